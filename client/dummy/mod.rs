@@ -61,7 +61,7 @@ pub fn run(listen_url: &str, server_url: &str) {
           &mut |_| { },
           &mut |_| { },
           &mut |_| { },
-          &mut |up| { server.talk.tell(&up) },
+          &mut |up| { server.talk.tell(up) },
           &mut |msg| {
             if let client_lib::terrain::Load::Voxels { time_requested: Some(_), .. } = msg {
               *loaded_count.lock().unwrap() += 1;
@@ -88,11 +88,12 @@ pub fn run(listen_url: &str, server_url: &str) {
 
 fn connect_client(listen_url: &str, server: &server::T) -> client::T {
   // TODO: Consider using RPCs to solidify the request-response patterns.
-  server.talk.tell(&protocol::ClientToServer::Init(listen_url.to_owned()));
+  server.talk.tell(protocol::ClientToServer::Init(listen_url.to_owned()));
   loop {
     match server.listen.wait() {
-      protocol::ServerToClient::LeaseId(client_id) => {
-        server.talk.tell(&protocol::ClientToServer::AddPlayer(client_id));
+      protocol::ServerToClient::LeaseId(client_id, secret) => {
+        server.talk.sign_in(client_id, secret);
+        server.talk.tell(protocol::ClientToServer::AddPlayer(client_id));
         let client_id = client_id;
         loop {
           match server.listen.wait() {
@@ -2,7 +2,7 @@
 
 /// lg(WIDTH)
 pub const LG_WIDTH: u16 = 3;
-/// The width of a chunk of terrain.
+/// The width of a chunk of terrain at the finest LOD.
 pub const WIDTH: u32 = 1 << LG_WIDTH;
 
 #[allow(missing_docs)]
@@ -13,26 +13,29 @@ pub mod position {
   use common::voxel;
 
   use chunk;
+  use lod;
 
   #[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
   /// Position of blocks on an "infinite" regular grid.
-  /// The position is implicitly in units of chunk::WIDTH.
+  /// The position is implicitly in units of the width of chunks at some LOD, which is
+  /// chunk::WIDTH unless stated otherwise.
   pub struct T(Point3<i32>);
 
-  /// chunk position that contains a given voxel
+  /// position of the chunk at `lod` that contains a given voxel
   #[inline(never)]
-  pub fn containing_voxel(bounds: &voxel::bounds::T) -> T {
+  pub fn containing_voxel(bounds: &voxel::bounds::T, lod: lod::T) -> T {
+    let lg_width = lod.lg_chunk_width();
     if bounds.lg_size < 0 {
       new(
-        (bounds.x >> -bounds.lg_size) >> chunk::LG_WIDTH,
-        (bounds.y >> -bounds.lg_size) >> chunk::LG_WIDTH,
-        (bounds.z >> -bounds.lg_size) >> chunk::LG_WIDTH,
+        (bounds.x >> -bounds.lg_size) >> lg_width,
+        (bounds.y >> -bounds.lg_size) >> lg_width,
+        (bounds.z >> -bounds.lg_size) >> lg_width,
       )
     } else {
       new(
-        (bounds.x << bounds.lg_size) >> chunk::LG_WIDTH,
-        (bounds.y << bounds.lg_size) >> chunk::LG_WIDTH,
-        (bounds.z << bounds.lg_size) >> chunk::LG_WIDTH,
+        (bounds.x << bounds.lg_size) >> lg_width,
+        (bounds.y << bounds.lg_size) >> lg_width,
+        (bounds.z << bounds.lg_size) >> lg_width,
       )
     }
   }
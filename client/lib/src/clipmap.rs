@@ -0,0 +1,305 @@
+//! Choose which terrain chunks to load around a point, and at what LOD.
+//!
+//! Each LOD covers a cube of its chunks around the center, with a hole in the middle that the next
+//! finer LOD fills. A hole is always exactly covered by a cube of the finer chunks inside it, so
+//! LODs don't overlap or leave gaps, and each LOD costs about as many chunks as the last but
+//! reaches twice as far.
+
+use cgmath::{Point3, Vector3};
+use std::collections::VecDeque;
+use stopwatch;
+
+use common::fnv_set;
+use common::surroundings_loader;
+
+use chunk;
+use lod;
+
+/// A chunk at some LOD. Its position is in units of that LOD's chunk width.
+pub type Chunk = (chunk::position::T, lod::T);
+
+#[allow(missing_docs)]
+/// The type of message emitted by `T`.
+pub enum LoadType {
+  Load,
+  Unload,
+}
+
+/// Iteratively loads and unloads chunks as the center moves.
+pub struct T {
+  lod_count   : u32,
+  /// How far each LOD's hole reaches from its center, in chunks of that LOD.
+  hole_radius : i32,
+  /// How far the coarsest LOD reaches, in its chunks.
+  radius      : i32,
+  center      : Option<Point3<i32>>,
+  /// The chunks we've said to load, and haven't said to unload since.
+  loaded      : fnv_set::T<Chunk>,
+  /// The chunks the clipmap covers around `center`.
+  wanted      : fnv_set::T<Chunk>,
+  to_load     : VecDeque<Chunk>,
+  to_unload   : VecDeque<Chunk>,
+}
+
+fn check(lod_count: u32, hole_radius: i32, radius: i32) {
+  assert!(lod_count >= 1 && lod_count as usize <= lod::COUNT);
+  assert!(hole_radius >= 0);
+  assert!(radius > hole_radius);
+}
+
+/// `lod_count` is how many LODs to use, starting from the finest. `hole_radius` is how far each
+/// LOD's hole reaches, and `radius` is how far the coarsest LOD reaches, both in chunks of that LOD;
+/// `radius` should be more than `hole_radius`.
+pub fn new(lod_count: u32, hole_radius: i32, radius: i32) -> T {
+  check(lod_count, hole_radius, radius);
+  T {
+    lod_count   : lod_count,
+    hole_radius : hole_radius,
+    radius      : radius,
+    center      : None,
+    loaded      : fnv_set::new(),
+    wanted      : fnv_set::new(),
+    to_load     : VecDeque::new(),
+    to_unload   : VecDeque::new(),
+  }
+}
+
+/// A position at `lod` from one at the finest LOD.
+fn at_lod(p: &Point3<i32>, lod: lod::T) -> Point3<i32> {
+  Point3::new(p.x >> lod.0, p.y >> lod.0, p.z >> lod.0)
+}
+
+/// The chunks to load around a finest-LOD chunk position, finest and then nearest first.
+pub fn chunks(center: &Point3<i32>, lod_count: u32, hole_radius: i32, radius: i32) -> Vec<Chunk> {
+  let mut chunks = Vec::new();
+  for lod in 0 .. lod_count {
+    let lod = lod::T(lod);
+    let center = at_lod(center, lod);
+
+    // The cube this LOD covers: either the whole area, or the coarser LOD's hole.
+    let (low, high) =
+      if lod.0 + 1 == lod_count {
+        (center + Vector3::new(-radius, -radius, -radius), center + Vector3::new(radius, radius, radius))
+      } else {
+        let coarser = at_lod(&center, lod::T(1));
+        let low = coarser + Vector3::new(-hole_radius, -hole_radius, -hole_radius);
+        let high = coarser + Vector3::new(hole_radius, hole_radius, hole_radius);
+        (Point3::new(2 * low.x, 2 * low.y, 2 * low.z), Point3::new(2 * high.x + 1, 2 * high.y + 1, 2 * high.z + 1))
+      };
+
+    let first = chunks.len();
+    for x in low.x .. high.x + 1 {
+    for y in low.y .. high.y + 1 {
+    for z in low.z .. high.z + 1 {
+      let p = Point3::new(x, y, z);
+      let in_hole = lod.0 > 0 && surroundings_loader::distance_between(&center, &p) <= hole_radius as u32;
+      if !in_hole {
+        chunks.push((chunk::position::of_pnt(&p), lod));
+      }
+    }}}
+
+    chunks[first ..].sort_by_key(|&(p, _)| surroundings_loader::distance_between(&center, p.as_pnt()));
+  }
+  chunks
+}
+
+impl T {
+  /// Change the shape of the clipmap. The chunks it no longer covers are unloaded by the next updates.
+  pub fn reconfigure(&mut self, lod_count: u32, hole_radius: i32, radius: i32) {
+    check(lod_count, hole_radius, radius);
+    self.lod_count = lod_count;
+    self.hole_radius = hole_radius;
+    self.radius = radius;
+    self.center = None;
+  }
+
+  /// Forget which chunks have been loaded, e.g. because they've all been unloaded, so that the
+  /// next updates load them all again.
+  pub fn clear(&mut self) {
+    self.loaded.clear();
+    self.wanted.clear();
+    self.to_load.clear();
+    self.to_unload.clear();
+    self.center = None;
+  }
+
+  /// Update the finest-LOD chunk position around which we load, and load some more chunks.
+  /// `is_meshed` says whether a chunk we've loaded has its mesh yet.
+  pub fn updates<'a, IsMeshed>(&'a mut self, center: &Point3<i32>, is_meshed: IsMeshed) -> Updates<'a, IsMeshed>
+    where IsMeshed: FnMut(&Chunk) -> bool
+  {
+    if self.center != Some(*center) {
+      stopwatch::time("clipmap.extend", || {
+        let wanted = chunks(center, self.lod_count, self.hole_radius, self.radius);
+        self.wanted = wanted.iter().cloned().collect();
+        self.to_load = wanted.into_iter().filter(|chunk| !self.loaded.contains(chunk)).collect();
+        let wanted = &self.wanted;
+        self.to_unload = self.loaded.iter().filter(|chunk| !wanted.contains(chunk)).cloned().collect();
+        self.center = Some(*center);
+      })
+    }
+
+    Updates {
+      clipmap   : self,
+      is_meshed : is_meshed,
+    }
+  }
+
+  /// The chunks we want that overlap `chunk`, which replace it when it's unloaded.
+  fn covering(&self, chunk: &Chunk) -> Vec<Chunk> {
+    let (position, lod) = *chunk;
+    let p = *position.as_pnt();
+
+    // At most one coarser chunk can contain this one.
+    for coarser in lod.0 + 1 .. lod::COUNT as u32 {
+      let shift = coarser - lod.0;
+      let coarser = (chunk::position::new(p.x >> shift, p.y >> shift, p.z >> shift), lod::T(coarser));
+      if self.wanted.contains(&coarser) {
+        return vec!(coarser)
+      }
+    }
+
+    // Otherwise it might be covered by finer chunks.
+    let mut covering = Vec::new();
+    let mut within = vec!(*chunk);
+    while let Some((position, lod)) = within.pop() {
+      let finer =
+        match lod.finer() {
+          None => continue,
+          Some(finer) => finer,
+        };
+      let p = position.as_pnt();
+      for x in 0 .. 2 {
+      for y in 0 .. 2 {
+      for z in 0 .. 2 {
+        let finer = (chunk::position::new(2 * p.x + x, 2 * p.y + y, 2 * p.z + z), finer);
+        if self.wanted.contains(&finer) {
+          covering.push(finer);
+        } else {
+          within.push(finer);
+        }
+      }}}
+    }
+    covering
+  }
+}
+
+/// Iterator for the updates from a T. Chunks are loaded before the ones they replace are unloaded,
+/// and aren't unloaded until the chunks replacing them are meshed, so there are no holes in between.
+pub struct Updates<'a, IsMeshed> {
+  clipmap   : &'a mut T,
+  is_meshed : IsMeshed,
+}
+
+impl<'a, IsMeshed> Iterator for Updates<'a, IsMeshed> where IsMeshed: FnMut(&Chunk) -> bool {
+  type Item = (Chunk, LoadType);
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if let Some(chunk) = self.clipmap.to_load.pop_front() {
+      self.clipmap.loaded.insert(chunk);
+      return Some((chunk, LoadType::Load))
+    }
+
+    // Look through the unloads once for one that's ready; the rest wait for the next updates.
+    for _ in 0 .. self.clipmap.to_unload.len() {
+      let chunk = self.clipmap.to_unload.pop_front().unwrap();
+      let is_meshed = &mut self.is_meshed;
+      if self.clipmap.covering(&chunk).iter().all(|chunk| is_meshed(chunk)) {
+        self.clipmap.loaded.remove(&chunk);
+        return Some((chunk, LoadType::Unload))
+      }
+      self.clipmap.to_unload.push_back(chunk);
+    }
+    None
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point3;
+  use std::collections::HashSet;
+
+  use lod;
+
+  use super::{chunks, LoadType};
+
+  /// Which finest-LOD chunk positions a chunk covers.
+  fn covered(chunk: &super::Chunk) -> Vec<Point3<i32>> {
+    let (p, lod) = *chunk;
+    let width = 1 << lod.0;
+    let low = Point3::new(p.as_pnt().x * width, p.as_pnt().y * width, p.as_pnt().z * width);
+    let mut covered = Vec::new();
+    for x in 0 .. width {
+    for y in 0 .. width {
+    for z in 0 .. width {
+      covered.push(Point3::new(low.x + x, low.y + y, low.z + z));
+    }}}
+    covered
+  }
+
+  #[test]
+  fn lods_tile() {
+    let hole_radius = 2;
+    let radius = hole_radius + 2;
+    for &center in &[Point3::new(0, 0, 0), Point3::new(-5, 3, 17)] {
+      let chunks = chunks(&center, 3, hole_radius, radius);
+      let mut seen = HashSet::new();
+      for chunk in &chunks {
+        for p in covered(chunk) {
+          assert!(seen.insert(p), "{:?} is covered twice", p);
+        }
+      }
+
+      // Together they cover exactly the coarsest LOD's cube.
+      let coarsest = lod::T(2);
+      let width = 1 << coarsest.0;
+      let center = Point3::new(center.x >> coarsest.0, center.y >> coarsest.0, center.z >> coarsest.0);
+      let low = Point3::new((center.x - radius) * width, (center.y - radius) * width, (center.z - radius) * width);
+      let cube_width = (2 * radius + 1) * width;
+      assert_eq!(seen.len() as i32, cube_width * cube_width * cube_width);
+      for p in &seen {
+        assert!(p.x >= low.x && p.y >= low.y && p.z >= low.z);
+        assert!(p.x < low.x + cube_width && p.y < low.y + cube_width && p.z < low.z + cube_width);
+      }
+    }
+  }
+
+  #[test]
+  fn unloads_wait_for_meshes() {
+    let (lod_count, hole_radius, radius) = (3, 1, 2);
+    let mut clipmap = super::new(lod_count, hole_radius, radius);
+    let mut loaded = HashSet::new();
+    let mut update = |chunk, load_type| {
+      match load_type {
+        LoadType::Load => assert!(loaded.insert(chunk)),
+        LoadType::Unload => assert!(loaded.remove(&chunk)),
+      }
+    };
+
+    let first = Point3::new(0, 0, 0);
+    for (chunk, load_type) in clipmap.updates(&first, |_| true) {
+      update(chunk, load_type);
+    }
+
+    let moved = Point3::new(5, 0, 0);
+    let wanted = chunks(&moved, lod_count, hole_radius, radius);
+    let wanted_points: HashSet<Point3<i32>> = wanted.iter().flat_map(|chunk| covered(chunk)).collect();
+
+    // Nothing new has been meshed, so only the chunks that nothing replaces can go.
+    let mut waiting = false;
+    for (chunk, load_type) in clipmap.updates(&moved, |_| false) {
+      if let LoadType::Unload = load_type {
+        assert!(covered(&chunk).iter().all(|p| !wanted_points.contains(p)), "{:?} was unloaded too early", chunk);
+      }
+      update(chunk, load_type);
+    }
+    for (chunk, load_type) in clipmap.updates(&moved, |_| true) {
+      waiting = true;
+      update(chunk, load_type);
+    }
+
+    // Once they're meshed, the rest go too.
+    assert!(waiting);
+    assert_eq!(loaded, wanted.into_iter().collect());
+  }
+}
@@ -7,6 +7,69 @@ use common::color::Color4;
 use vertex::{ColoredVertex};
 use view;
 
+/// Number of vertices in the player status display.
+pub const STATUS_VERTICES: usize = 3 * 6;
+
+/// What the HUD shows about the player.
+#[derive(Debug, Clone, Copy)]
+#[allow(missing_docs)]
+pub struct Status {
+  pub health     : u32,
+  pub max_health : u32,
+  pub dead       : bool,
+}
+
+impl Status {
+  #[allow(missing_docs)]
+  pub fn new() -> Status {
+    Status {
+      health     : 1,
+      max_health : 1,
+      dead       : false,
+    }
+  }
+}
+
+/// The health bar, plus a screen-covering overlay while the player is dead.
+pub fn status_triangles(status: &Status) -> Vec<ColoredVertex> {
+  let low = Point2 { x: -0.4, y: -0.95 };
+  let high = Point2 { x: 0.4, y: -0.9 };
+  let fraction =
+    if status.max_health == 0 {
+      0.0
+    } else {
+      f32::min(1.0, status.health as f32 / status.max_health as f32)
+    };
+
+  let background = ColoredVertex::square(low, high, Color4::of_rgba(0.0, 0.0, 0.0, 0.5));
+  let health =
+    ColoredVertex::square(
+      low,
+      Point2 { x: low.x + (high.x - low.x) * fraction, y: high.y },
+      Color4::of_rgba(0.8, 0.1, 0.1, 0.75),
+    );
+  // The HUD camera only sees a window-shaped region around the origin, so this just needs to be wide enough.
+  let death_screen =
+    ColoredVertex::square(
+      Point2 { x: -16.0, y: -1.0 },
+      Point2 { x: 16.0, y: 1.0 },
+      Color4::of_rgba(0.3, 0.0, 0.0, if status.dead { 0.6 } else { 0.0 }),
+    );
+
+  let mut triangles = Vec::with_capacity(STATUS_VERTICES);
+  triangles.extend_from_slice(&background);
+  triangles.extend_from_slice(&health);
+  triangles.extend_from_slice(&death_screen);
+  triangles
+}
+
+/// Rewrite the status display to match `view.status`.
+pub fn update_status<'a, 'b:'a>(view: &'a mut view::T<'b>) {
+  let triangles = status_triangles(&view.status);
+  view.status_triangles.buffer.byte_buffer.bind(&mut view.gl);
+  view.status_triangles.buffer.update(&mut view.gl, 0, triangles.as_ref());
+}
+
 /// Add HUD data into `view`.
 pub fn make_hud<'a, 'b:'a>(view: &'a mut view::T<'b>) {
   let cursor_color = Color4::of_rgba(0.0, 0.0, 0.0, 0.75);
@@ -20,4 +83,8 @@ pub fn make_hud<'a, 'b:'a>(view: &'a mut view::T<'b>) {
 
   view.hud_triangles.bind(&mut view.gl);
   view.hud_triangles.push(&mut view.gl, triangles.as_ref());
+
+  let triangles = status_triangles(&view.status);
+  view.status_triangles.bind(&mut view.gl);
+  view.status_triangles.push(&mut view.gl, triangles.as_ref());
 }
@@ -1,35 +1,18 @@
 //! Structs for keeping track of terrain level of detail.
-
-use num;
+//!
+//! Every chunk has the same number of samples, and coarser chunks are bigger: a chunk at LOD `n`
+//! is `2^n` times as wide as one at LOD 0. `clipmap` decides which chunks get which LOD.
 
 use chunk;
 
-// TODO: terrain_mesh is now chunk-agnostic. Some/all of these values should be moved.
 /// Number of LODs
-pub const COUNT: usize = 5;
-
-/// exhaustive list of all the LODs
-pub const ALL: [T; COUNT] = [T(0), T(1), T(2), T(3), T(4)];
+pub const COUNT: usize = 8;
 
 /// lg(EDGE_SAMPLES)
-// NOTE: If there are duplicates here, weird invariants will fail.
-// Just remove the LODs if you don't want duplicates.
-const LG_EDGE_SAMPLES: [u16; COUNT] = [3, 2, 1, 1, 0];
-
-/// The base-2 log of the width of a voxel within a chunk, indexed by LOD.
-const LG_SAMPLE_SIZE: [i16; COUNT] = [
-  chunk::LG_WIDTH as i16 - LG_EDGE_SAMPLES[0] as i16,
-  chunk::LG_WIDTH as i16 - LG_EDGE_SAMPLES[1] as i16,
-  chunk::LG_WIDTH as i16 - LG_EDGE_SAMPLES[2] as i16,
-  chunk::LG_WIDTH as i16 - LG_EDGE_SAMPLES[3] as i16,
-  chunk::LG_WIDTH as i16 - LG_EDGE_SAMPLES[4] as i16,
-];
+const LG_EDGE_SAMPLES: u16 = chunk::LG_WIDTH;
 
-/// max LOD where grass will display
-pub const MAX_GRASS_LOD: T = T(3);
-
-/// The distances at which LOD switches.
-pub const THRESHOLDS: [u32; COUNT-1] = [1, 15, 31, 47];
+/// max LOD where grass will display, unless it's been changed in the settings
+pub const MAX_GRASS_LOD: T = T(2);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 /// A strongly-typed index into various LOD-indexed arrays.
@@ -40,29 +23,49 @@ pub struct T(pub u32);
 impl T {
   /// base-2 log of voxel sample size for this LOD
   pub fn lg_sample_size(self) -> i16 {
-    LG_SAMPLE_SIZE[self.0 as usize]
+    self.lg_chunk_width() as i16 - LG_EDGE_SAMPLES as i16
   }
 
   /// base-2 log of the number of voxel samples per chunk for this LOD
   pub fn lg_edge_samples(self) -> u16 {
-    LG_EDGE_SAMPLES[self.0 as usize]
+    LG_EDGE_SAMPLES
   }
 
   /// the number of voxel samples per chunk for this LOD
   pub fn edge_samples(self) -> u16 {
     1 << self.lg_edge_samples()
   }
-}
 
-/// determine the LOD for a certain chunk distance away
-pub fn of_distance(distance: u32) -> T {
-  let mut lod = 0;
-  while
-    lod < THRESHOLDS.len()
-    && THRESHOLDS[lod] < distance
-  {
-    lod += 1;
+  /// base-2 log of the width of a chunk at this LOD
+  pub fn lg_chunk_width(self) -> u16 {
+    chunk::LG_WIDTH + self.0 as u16
+  }
+
+  /// The next coarser LOD, if there is one.
+  pub fn coarser(self) -> Option<T> {
+    if (self.0 as usize) + 1 < COUNT {
+      Some(T(self.0 + 1))
+    } else {
+      None
+    }
+  }
+
+  /// The next finer LOD, if there is one.
+  pub fn finer(self) -> Option<T> {
+    if self.0 > 0 {
+      Some(T(self.0 - 1))
+    } else {
+      None
+    }
   }
-  T(num::traits::FromPrimitive::from_usize(lod).unwrap())
 }
 
+/// The LOD that samples voxels of size `2^lg_sample_size`, if there is one.
+pub fn of_lg_sample_size(lg_sample_size: i16) -> Option<T> {
+  let lod = lg_sample_size - T(0).lg_sample_size();
+  if lod >= 0 && (lod as usize) < COUNT {
+    Some(T(lod as u32))
+  } else {
+    None
+  }
+}
@@ -0,0 +1,112 @@
+//! Threads that turn voxels into terrain polygons, so the update thread doesn't wait on dual contouring.
+
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use common::voxel;
+
+use chunk;
+use lod;
+use terrain_mesh;
+
+/// How many mesh threads to run.
+pub const THREAD_COUNT: usize = 3;
+
+/// A chunk to mesh, with a copy of the voxels it needs.
+pub struct Job {
+  /// Tells this job's mesh apart from older ones for the same chunk.
+  pub id             : u64,
+  #[allow(missing_docs)]
+  pub chunk_position : chunk::position::T,
+  #[allow(missing_docs)]
+  pub lod            : lod::T,
+  #[allow(missing_docs)]
+  pub seams          : Vec<terrain_mesh::Seam>,
+  #[allow(missing_docs)]
+  pub voxels         : voxel::tree::T,
+}
+
+/// A finished job.
+pub struct Mesh {
+  #[allow(missing_docs)]
+  pub id             : u64,
+  #[allow(missing_docs)]
+  pub chunk_position : chunk::position::T,
+  #[allow(missing_docs)]
+  pub lod            : lod::T,
+  #[allow(missing_docs)]
+  pub polygons       : Vec<terrain_mesh::Polygon>,
+  #[allow(missing_docs)]
+  pub skirts         : Vec<terrain_mesh::Polygon>,
+}
+
+/// Jobs waiting for a mesh thread, and meshes waiting for the update thread.
+pub struct T {
+  jobs       : Mutex<VecDeque<Job>>,
+  job_queued : Condvar,
+  meshes     : Mutex<VecDeque<Mesh>>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    jobs       : Mutex::new(VecDeque::new()),
+    job_queued : Condvar::new(),
+    meshes     : Mutex::new(VecDeque::new()),
+  }
+}
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn push(&self, job: Job) {
+    self.jobs.lock().unwrap().push_back(job);
+    self.job_queued.notify_one();
+  }
+
+  /// Take a finished mesh, if there is one.
+  pub fn pop(&self) -> Option<Mesh> {
+    self.meshes.lock().unwrap().pop_front()
+  }
+
+  /// The number of jobs waiting for a thread.
+  pub fn queued_job_count(&self) -> usize {
+    self.jobs.lock().unwrap().len()
+  }
+}
+
+/// Run jobs until we quit.
+pub fn mesh_thread(
+  quit   : &Mutex<bool>,
+  queues : &T,
+) {
+  loop {
+    let job;
+    {
+      let mut jobs = queues.jobs.lock().unwrap();
+      loop {
+        if *quit.lock().unwrap() {
+          return
+        }
+        if let Some(next) = jobs.pop_front() {
+          job = next;
+          break
+        }
+        // Wake up now and then to check whether we've quit.
+        jobs = queues.job_queued.wait_timeout(jobs, Duration::from_millis(100)).unwrap().0;
+      }
+    }
+
+    let polygons = terrain_mesh::polygons(&job.voxels, &job.chunk_position, job.lod);
+    let skirts = terrain_mesh::skirts(&polygons, &job.chunk_position, job.lod, &job.seams);
+    queues.meshes.lock().unwrap().push_back(
+      Mesh {
+        id             : job.id,
+        chunk_position : job.chunk_position,
+        lod            : job.lod,
+        polygons       : polygons,
+        skirts         : skirts,
+      }
+    );
+  }
+}
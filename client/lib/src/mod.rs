@@ -21,7 +21,6 @@ extern crate portaudio;
 extern crate rand;
 extern crate sdl2;
 extern crate stopwatch;
-extern crate thread_scoped;
 extern crate time;
 extern crate voxel_data;
 extern crate yaglw;
@@ -32,15 +31,19 @@ pub mod audio_thread;
 pub mod chunk;
 pub mod chunk_stats;
 pub mod client;
+pub mod clipmap;
 pub mod hud;
 pub mod lod;
+pub mod mesh_thread;
 pub mod process_event;
 pub mod record_book;
 pub mod run;
 pub mod server;
 pub mod server_update;
+pub mod settings;
 pub mod terrain;
 pub mod terrain_mesh;
+pub mod tool;
 pub mod update_thread;
 pub mod vertex;
 pub mod view;
@@ -11,6 +11,7 @@ use common::entity;
 use common::protocol;
 
 use client;
+use tool;
 use view;
 
 #[allow(missing_docs)]
@@ -24,28 +25,120 @@ pub fn process_event<UpdateServer>(
   match event {
     Event::KeyDown{keycode, repeat, ..} => {
       keycode.map(|keycode| {
-        if !repeat {
-          key_press(update_server, view, client, keycode);
+        if view.chat.is_typing() {
+          // Repeats are wanted here, e.g. to hold down backspace.
+          text_key_press(update_server, view, client, keycode);
+        } else if !repeat {
+          if keycode == Keycode::Return {
+            start_typing(update_server, view, client);
+          } else {
+            view.held_keys.insert(keycode);
+            key_press(update_server, view, client, keycode);
+          }
         }
       });
     },
     Event::KeyUp{keycode, repeat, ..} => {
       keycode.map(|keycode| {
-        if !repeat {
+        // Keys pressed while typing never got handled, so their releases shouldn't be either.
+        if !repeat && view.held_keys.remove(&keycode) {
           key_release(client.player_id, update_server, keycode);
         }
       });
     },
+    Event::TextInput{text, ..} => {
+      if view.chat.is_typing() {
+        view.chat.type_text(&view.gl, &text);
+      }
+    },
     Event::MouseMotion{xrel, yrel, ..} => {
       mouse_move(client.player_id, update_server, view, xrel, yrel);
     },
     Event::MouseButtonDown{mouse_btn, ..} => {
-      mouse_press(client.player_id, update_server, mouse_btn);
+      mouse_press(client.player_id, update_server, &view.tool, mouse_btn);
+    },
+    Event::MouseWheel{y, ..} => {
+      if !view.chat.is_typing() {
+        tool::resize(&mut view.tool, y as f32);
+        describe_tool(view);
+      }
     },
     _ => {},
   }
 }
 
+/// Tell the player what their brush is now.
+fn describe_tool(view: &mut view::T) {
+  let description = format!("Brush: {}", tool::describe(&view.tool));
+  view.chat.push(&view.gl, &description);
+}
+
+/// Tell the player how pastes will be turned.
+fn describe_placement(view: &mut view::T) {
+  let description =
+    format!(
+      "Paste: turned {} degrees{}",
+      90 * view.placement.quarter_turns as u32,
+      if view.placement.mirror { ", mirrored" } else { "" },
+    );
+  view.chat.push(&view.gl, &description);
+}
+
+/// Switch the keyboard over to typing into chat.
+fn start_typing<UpdateServer>(
+  update_server: &mut UpdateServer,
+  view: &mut view::T,
+  client: &client::T,
+) where UpdateServer: FnMut(protocol::ClientToServer)
+{
+  // Let go of everything first, so the player doesn't keep walking while we type.
+  for key in view.held_keys.drain() {
+    key_release(client.player_id, update_server, key);
+  }
+  view.chat.start_typing(&view.gl);
+}
+
+/// Handle a key press while typing into chat. The characters themselves arrive as `TextInput` events.
+fn text_key_press<UpdateServer>(
+  update_server: &mut UpdateServer,
+  view: &mut view::T,
+  client: &client::T,
+  key: Keycode,
+) where UpdateServer: FnMut(protocol::ClientToServer)
+{
+  stopwatch::time("event.text_key_press", || {
+    match key {
+      Keycode::Return => {
+        view.chat.stop_typing().map(|text| {
+          let command = text.trim();
+          if command == "/set" || command.starts_with("/set ") {
+            // Settings only matter to this client, so the server never sees them.
+            let reply = client.settings.lock().unwrap().command(&command["/set".len() ..]);
+            for line in reply {
+              view.chat.push(&view.gl, &line);
+            }
+          } else if !command.is_empty() {
+            update_server(protocol::ClientToServer::Chat(client.player_id, text.clone()));
+          }
+        });
+      },
+      Keycode::Escape => {
+        view.chat.stop_typing();
+      },
+      Keycode::Backspace => {
+        view.chat.backspace(&view.gl);
+      },
+      Keycode::PageUp => {
+        view.chat.scroll(1);
+      },
+      Keycode::PageDown => {
+        view.chat.scroll(-1);
+      },
+      _ => {},
+    }
+  })
+}
+
 fn key_press<UpdateServer>(
   update_server: &mut UpdateServer,
   view: &mut view::T,
@@ -120,6 +213,58 @@ fn key_press<UpdateServer>(
             view::InputMode::Sun => view::InputMode::Camera,
           };
       },
+      Keycode::Num1 | Keycode::Num2 | Keycode::Num3 | Keycode::Num4 => {
+        view.tool.shape =
+          match key {
+            Keycode::Num1 => protocol::BrushShape::Sphere,
+            Keycode::Num2 => protocol::BrushShape::Cube,
+            Keycode::Num3 => protocol::BrushShape::Cylinder,
+            _ => protocol::BrushShape::Tree,
+          };
+        describe_tool(view);
+      },
+      Keycode::Tab => {
+        tool::next_operation(&mut view.tool);
+        describe_tool(view);
+      },
+      Keycode::C => {
+        tool::next_material(&mut view.tool);
+        describe_tool(view);
+      },
+      Keycode::Minus => {
+        tool::resize(&mut view.tool, -1.0);
+        describe_tool(view);
+      },
+      Keycode::Equals => {
+        tool::resize(&mut view.tool, 1.0);
+        describe_tool(view);
+      },
+      Keycode::Z => {
+        update_server(Undo(client.player_id));
+      },
+      Keycode::Y => {
+        update_server(Redo(client.player_id));
+      },
+      Keycode::LeftBracket => {
+        update_server(Select(client.player_id, protocol::SelectionCorner::First));
+      },
+      Keycode::RightBracket => {
+        update_server(Select(client.player_id, protocol::SelectionCorner::Second));
+      },
+      Keycode::X => {
+        update_server(Copy(client.player_id));
+      },
+      Keycode::V => {
+        update_server(Paste(client.player_id, view.placement));
+      },
+      Keycode::R => {
+        view.placement.quarter_turns = (view.placement.quarter_turns + 1) % 4;
+        describe_placement(view);
+      },
+      Keycode::F => {
+        view.placement.mirror = !view.placement.mirror;
+        describe_placement(view);
+      },
       Keycode::P => {
         let mut load_position = client.load_position.lock().unwrap();
         match *load_position {
@@ -135,20 +280,17 @@ fn key_press<UpdateServer>(
 fn mouse_press<UpdateServer>(
   player_id: entity::id::Player,
   update_server: &mut UpdateServer,
+  tool: &protocol::Brush,
   mouse_btn: MouseButton,
 ) where UpdateServer: FnMut(protocol::ClientToServer)
 {
   stopwatch::time("event.mouse_press", || {
     match mouse_btn {
       MouseButton::Left => {
-        update_server(
-          protocol::ClientToServer::Add(player_id)
-        );
+        update_server(protocol::ClientToServer::Brush(player_id, *tool));
       },
       MouseButton::Right => {
-        update_server(
-          protocol::ClientToServer::Remove(player_id)
-        );
+        update_server(protocol::ClientToServer::Brush(player_id, tool::dig(tool)));
       },
       _ => {},
     }
@@ -4,13 +4,13 @@ use std;
 use std::io::Write;
 use std::sync::{Mutex};
 use stopwatch;
-use thread_scoped;
 
 use common::protocol;
 
 use audio_loader;
 use audio_thread;
 use client;
+use mesh_thread;
 use record_book;
 use server;
 use terrain;
@@ -31,33 +31,38 @@ pub fn run(listen_url: &str, server_url: &str) {
   let client = connect_client(&listen_url, &server);
   let client = &client;
 
-  {
-    let monitor_thread = {
-      unsafe {
-        thread_scoped::scoped(|| {
-          while !*quit.lock().unwrap() {
-            info!("Outstanding terrain requests: {}", *client.pending_terrain_requests.lock().unwrap());
-            info!("Outstanding voxel updates: {}", client.terrain.lock().unwrap().queued_update_count());
-            info!("Outstanding view0 updates: {}", view_updates0.lock().unwrap().len());
-            info!("Outstanding view1 updates: {}", view_updates1.lock().unwrap().len());
-            std::thread::sleep(std::time::Duration::from_secs(1));
-          }
-        })
-      }
-    };
+  std::thread::scope(|scope| {
+    let monitor_thread =
+      scope.spawn(|| {
+        while !*quit.lock().unwrap() {
+          info!("Outstanding terrain requests: {}", *client.pending_terrain_requests.lock().unwrap());
+          info!("Outstanding voxel updates: {}", client.terrain.lock().unwrap().queued_update_count());
+          info!("Outstanding mesh jobs: {}", client.mesh_threads.queued_job_count());
+          info!("Outstanding view0 updates: {}", view_updates0.lock().unwrap().len());
+          info!("Outstanding view1 updates: {}", view_updates1.lock().unwrap().len());
+          std::thread::sleep(std::time::Duration::from_secs(1));
+        }
+      });
 
     let audio_thread = {
       let audio_updates = &audio_updates;
-      unsafe {
-        thread_scoped::scoped(move || {
-          audio_thread::audio_thread(
-            quit,
-            &mut || { audio_updates.lock().unwrap().pop_front() },
-          );
-        })
-      }
+      scope.spawn(move || {
+        audio_thread::audio_thread(
+          quit,
+          &mut || { audio_updates.lock().unwrap().pop_front() },
+        );
+      })
     };
 
+    let mesh_threads: Vec<_> =
+      (0 .. mesh_thread::THREAD_COUNT)
+      .map(|_| {
+        scope.spawn(move || {
+          mesh_thread::mesh_thread(quit, &client.mesh_threads);
+        })
+      })
+      .collect();
+
     if (1 + 1) - 1 == 0 {
       audio_updates.lock().unwrap().push_back(audio_thread::Message::PlayLoop(audio_loader::SoundId::Rainforest));
     }
@@ -68,46 +73,44 @@ pub fn run(listen_url: &str, server_url: &str) {
       let view_updates1 = &view_updates1;
       let audio_updates = &audio_updates;
       let server = server.clone();
-      unsafe {
-        thread_scoped::scoped(move || {
-          update_thread(
-            quit,
-            client,
-            &mut || { server.listen.try() },
-            &mut |up| { view_updates0.lock().unwrap().push_back(up) },
-            &mut |up| { view_updates1.lock().unwrap().push_back(up) },
-            &mut |up| { audio_updates.lock().unwrap().push_back(up) },
-  	        &mut |up| { server.talk.tell(&up) },
-            &mut |msg| {
-              match msg {
-                terrain::Load::Voxels { time_requested: None, .. } => {},
-                terrain::Load::Voxels { time_requested: Some(_), .. } => {
-                  *client.pending_terrain_requests.lock().unwrap() -= 1;
-                }
-              };
-              client.terrain.lock().unwrap().enqueue(msg);
-            },
-          );
-
-          let mut recorded = record_book::thread_local::clone();
-          recorded.chunk_loads.sort_by(|x, y| x.loaded_time_ns.cmp(&y.loaded_time_ns));
-
-          let mut file = std::fs::File::create("chunk_loads.out").unwrap();
-
-          file.write_all(b"records = [").unwrap();
-          for (i, record) in recorded.chunk_loads.iter().enumerate() {
-            if i > 0 {
-              file.write_all(b", ").unwrap();
-            }
-            let record_book::ChunkLoad { time_requested_ns, response_time_ns, stored_time_ns, loaded_time_ns } = *record;
-            file.write_fmt(format_args!("[{}; {}; {}; {}]", time_requested_ns, response_time_ns, stored_time_ns, loaded_time_ns)).unwrap();
+      scope.spawn(move || {
+        update_thread(
+          quit,
+          client,
+          &mut || { server.listen.try() },
+          &mut |up| { view_updates0.lock().unwrap().push_back(up) },
+          &mut |up| { view_updates1.lock().unwrap().push_back(up) },
+          &mut |up| { audio_updates.lock().unwrap().push_back(up) },
+          &mut |up| { server.talk.tell(up) },
+          &mut |msg| {
+            match msg {
+              terrain::Load::Voxels { time_requested: None, .. } => {},
+              terrain::Load::Voxels { time_requested: Some(_), .. } => {
+                *client.pending_terrain_requests.lock().unwrap() -= 1;
+              }
+            };
+            client.terrain.lock().unwrap().enqueue(msg);
+          },
+        );
+
+        let mut recorded = record_book::thread_local::clone();
+        recorded.chunk_loads.sort_by(|x, y| x.loaded_time_ns.cmp(&y.loaded_time_ns));
+
+        let mut file = std::fs::File::create("chunk_loads.out").unwrap();
+
+        file.write_all(b"records = [").unwrap();
+        for (i, record) in recorded.chunk_loads.iter().enumerate() {
+          if i > 0 {
+            file.write_all(b", ").unwrap();
           }
-          file.write_all(b"];\n").unwrap();
-          file.write_fmt(format_args!("plot([1:{}], records);", recorded.chunk_loads.len())).unwrap();
+          let record_book::ChunkLoad { time_requested_ns, response_time_ns, stored_time_ns, loaded_time_ns } = *record;
+          file.write_fmt(format_args!("[{}; {}; {}; {}]", time_requested_ns, response_time_ns, stored_time_ns, loaded_time_ns)).unwrap();
+        }
+        file.write_all(b"];\n").unwrap();
+        file.write_fmt(format_args!("plot([1:{}], records);", recorded.chunk_loads.len())).unwrap();
 
-          stopwatch::clone()
-        })
-      }
+        stopwatch::clone()
+      })
     };
 
     {
@@ -117,7 +120,7 @@ pub fn run(listen_url: &str, server_url: &str) {
         client,
         &mut || { view_updates0.lock().unwrap().pop_front() },
         &mut || { view_updates1.lock().unwrap().pop_front() },
-        &mut |server_update| { server.talk.tell(&server_update) },
+        &mut |server_update| { server.talk.tell(server_update) },
       );
 
       stopwatch::clone().print();
@@ -126,22 +129,26 @@ pub fn run(listen_url: &str, server_url: &str) {
     // View thread returned, so we got a quit event.
     *quit.lock().unwrap() = true;
 
-    audio_thread.join();
-    monitor_thread.join();
+    audio_thread.join().unwrap();
+    monitor_thread.join().unwrap();
+    for mesh_thread in mesh_threads {
+      mesh_thread.join().unwrap();
+    }
 
-    let stopwatch = update_thread.join();
+    let stopwatch = update_thread.join().unwrap();
 
     stopwatch.print();
-  }
+  });
 }
 
 fn connect_client(listen_url: &str, server: &server::T) -> client::T {
   // TODO: Consider using RPCs to solidify the request-response patterns.
-  server.talk.tell(&protocol::ClientToServer::Init(listen_url.to_owned()));
+  server.talk.tell(protocol::ClientToServer::Init(listen_url.to_owned()));
   loop {
     match server.listen.wait() {
-      protocol::ServerToClient::LeaseId(client_id) => {
-        server.talk.tell(&protocol::ClientToServer::AddPlayer(client_id));
+      protocol::ServerToClient::LeaseId(client_id, secret) => {
+        server.talk.sign_in(client_id, secret);
+        server.talk.tell(protocol::ClientToServer::AddPlayer(client_id));
         let client_id = client_id;
         loop {
           match server.listen.wait() {
@@ -18,6 +18,8 @@ pub struct SSender {
   pub sender: Sender<Box<[u8]>>,
   // Please replace with AtomicU64 when it becomes stable
   pub bytes_sent: Arc<AtomicUsize>,
+  /// What messages are signed with, once the server has given us an ID.
+  sender_id: Arc<Mutex<Option<(protocol::ClientId, protocol::ClientSecret)>>>,
 }
 
 impl SSender {
@@ -26,12 +28,23 @@ impl SSender {
     SSender {
       sender: sender,
       bytes_sent: Arc::new(AtomicUsize::new(0)),
+      sender_id: Arc::new(Mutex::new(None)),
     }
   }
 
+  /// Sign every message sent from now on with the ID and secret the server leased us.
+  pub fn sign_in(&self, id: protocol::ClientId, secret: protocol::ClientSecret) {
+    *self.sender_id.lock().unwrap() = Some((id, secret));
+  }
+
   #[allow(missing_docs)]
-  pub fn tell(&self, msg: &protocol::ClientToServer) {
-    let msg = bincode::serialize(msg, bincode::Infinite).unwrap();
+  pub fn tell(&self, msg: protocol::ClientToServer) {
+    let msg =
+      protocol::Signed {
+        sender  : *self.sender_id.lock().unwrap(),
+        message : msg,
+      };
+    let msg = bincode::serialize(&msg, bincode::Infinite).unwrap();
     // We aren't reading this until long after the write, so we use `Relaxed`
     self.bytes_sent.fetch_add(msg.len() as usize, Ordering::Relaxed);
     self.sender.send(msg.into_boxed_slice()).unwrap();
@@ -37,7 +37,7 @@ pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueTerrain
 {
   stopwatch::time("apply_server_update", move || {
     match update {
-      protocol::ServerToClient::LeaseId(_) => {
+      protocol::ServerToClient::LeaseId(_, _) => {
         warn!("Client ID has already been leased.");
       },
       protocol::ServerToClient::Ping => {
@@ -46,6 +46,31 @@ pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueTerrain
       protocol::ServerToClient::PlayerAdded(id, _) => {
         warn!("Unexpected PlayerAdded event: {:?}.", id);
       },
+      protocol::ServerToClient::PlayerRespawned(player_id, position) => {
+        if player_id != client.player_id {
+          return
+        }
+
+        info!("Respawned at {:?}", position);
+        *client.player_position.lock().unwrap() = position;
+        *client.last_footstep.lock().unwrap() = position;
+        update_view(view::update::SetDead(false));
+      },
+      protocol::ServerToClient::PlayerDied(player_id) => {
+        if player_id != client.player_id {
+          return
+        }
+
+        info!("You died.");
+        update_view(view::update::SetDead(true));
+      },
+      protocol::ServerToClient::UpdateHealth { player, health, max_health } => {
+        if player != client.player_id {
+          return
+        }
+
+        update_view(view::update::SetHealth { health: health, max_health: max_health });
+      },
       protocol::ServerToClient::UpdatePlayer(player_id, bounds) => {
         let mesh = to_triangles(&bounds, &Color4::of_rgba(0.0, 0.0, 1.0, 1.0));
         update_view(view::update::UpdatePlayer(player_id, mesh));
@@ -64,10 +89,16 @@ pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueTerrain
         *client.player_position.lock().unwrap() = position;
         update_view(view::update::MoveCamera(position));
       },
+      protocol::ServerToClient::RemovePlayer(id) => {
+        update_view(view::update::RemovePlayer(id));
+      },
       protocol::ServerToClient::UpdateMob(id, bounds) => {
         let mesh = to_triangles(&bounds, &Color4::of_rgba(1.0, 0.0, 0.0, 1.0));
         update_view(view::update::UpdateMob(id, mesh));
       },
+      protocol::ServerToClient::RemoveMob(id) => {
+        update_view(view::update::RemoveMob(id));
+      },
       protocol::ServerToClient::UpdateSun(fraction) => {
         update_view(view::update::SetSun(
           view::light::Sun {
@@ -95,6 +126,18 @@ pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueTerrain
           }
         );
       },
+      protocol::ServerToClient::Chat { sender, text } => {
+        let name =
+          if sender == client.player_id {
+            String::from("you")
+          } else {
+            format!("player {}", sender.to_u32())
+          };
+        update_view(view::update::ChatLine(format!("<{}> {}", name, text)));
+      },
+      protocol::ServerToClient::Notice(text) => {
+        update_view(view::update::ChatLine(text));
+      },
       protocol::ServerToClient::Collision(collision_type) => {
         if let protocol::Collision::PlayerTerrain(..) = collision_type {
           let player_position = *client.player_position.lock().unwrap();
@@ -0,0 +1,258 @@
+//! Client settings that can be changed while playing, with `/set` in chat.
+
+use std::str::FromStr;
+
+use lod;
+use view;
+
+/// Any further and the clipmap would have too many chunks to keep up with.
+const MAX_LOD_RADIUS: i32 = 16;
+/// The least VRAM terrain or grass can be given, in bytes.
+const MIN_VRAM: usize = 1 << 20;
+
+/// What players can type after `/set`.
+const HELP: &'static str = "\
+/set                     show this, and the current settings
+/set terrain_vram BYTES  VRAM to use for terrain
+/set grass_vram BYTES    VRAM to use for grass
+/set load_distance N     how many chunks away to load terrain, if it fits in terrain_vram
+/set lod_radius N        how far each LOD reaches, in chunks of the next coarser LOD
+/set max_grass_lod LOD   the coarsest LOD that has grass, if it fits in grass_vram";
+
+/// How much to load, and how detailed to make it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct T {
+  /// Bytes of VRAM for terrain.
+  pub terrain_vram  : usize,
+  /// Bytes of VRAM for grass.
+  pub grass_vram    : usize,
+  /// How far to load terrain, in chunks of the finest LOD. Loading stops short of this if the
+  /// terrain wouldn't fit in `terrain_vram`.
+  pub load_distance : u32,
+  /// How far each LOD reaches from the center, in chunks of the next coarser LOD.
+  pub lod_radius    : i32,
+  /// The coarsest LOD that gets grass. Grass stops short of this if it wouldn't fit in `grass_vram`.
+  pub max_grass_lod : lod::T,
+}
+
+/// The default settings.
+pub fn new() -> T {
+  T {
+    terrain_vram  : 64_000_000,
+    grass_vram    : 64_000_000,
+    load_distance : 1 << 10,
+    lod_radius    : 2,
+    max_grass_lod : lod::MAX_GRASS_LOD,
+  }
+}
+
+fn parse<X: FromStr>(value: &str) -> Result<X, String> {
+  value.parse().map_err(|_| format!("{:?} isn't a valid value", value))
+}
+
+impl T {
+  /// How far the coarsest LOD reaches, in its chunks. This is about as far as the finer LODs reach
+  /// past their holes, so every LOD is about as thick.
+  pub fn clipmap_radius(&self) -> i32 {
+    2 * self.lod_radius + 1
+  }
+
+  /// About how many polygons the chunks of one LOD have between them.
+  fn polygons(&self, lod: lod::T) -> i64 {
+    // TODO: This should try to account for VRAM not used on a per-poly basis.
+    // Assume the terrain is about a chunk thick, so each LOD costs a square of chunks: a whole one
+    // for the finest, and one around the finer LOD's hole for the rest.
+    let width = 2 * self.clipmap_radius() as i64 + 1;
+    let hole_width = 2 * self.lod_radius as i64 + 1;
+    let quality = lod.edge_samples() as i64;
+    let polygons_per_chunk = quality * quality * 4;
+    let chunks =
+      if lod.0 == 0 {
+        width * width
+      } else {
+        width * width - hole_width * hole_width
+      };
+    chunks * polygons_per_chunk
+  }
+
+  /// The number of LODs to load: enough to reach `load_distance`, as long as they fit in
+  /// `terrain_vram`. `set` makes sure at least the finest one fits.
+  pub fn lod_count(&self) -> u32 {
+    let mut polygon_budget = view::terrain_buffers::polygon_budget(self.terrain_vram) as i64;
+    let mut lod_count = 0;
+    while (lod_count as usize) < lod::COUNT {
+      if lod_count > 0 && (self.clipmap_radius() as u32) << (lod_count - 1) >= self.load_distance {
+        break;
+      }
+
+      polygon_budget -= self.polygons(lod::T(lod_count));
+      if polygon_budget < 0 {
+        break;
+      }
+
+      lod_count += 1;
+    }
+
+    lod_count
+  }
+
+  /// The number of LODs, from the finest, to put grass on: the loaded ones up to `max_grass_lod`, as
+  /// long as their grass fits in `grass_vram`. This assumes every polygon could have a tuft.
+  pub fn grass_lod_count(&self) -> u32 {
+    let mut tuft_budget = view::grass_buffers::tuft_budget(self.grass_vram) as i64;
+    let mut grass_lod_count = 0;
+    while grass_lod_count < self.lod_count() && lod::T(grass_lod_count) <= self.max_grass_lod {
+      tuft_budget -= self.polygons(lod::T(grass_lod_count));
+      if tuft_budget < 0 {
+        break;
+      }
+
+      grass_lod_count += 1;
+    }
+
+    grass_lod_count
+  }
+
+  /// Change one setting. Settings that won't fit in VRAM are refused.
+  pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+    let mut changed = self.clone();
+    try!(changed.set_unchecked(name, value));
+
+    if changed.terrain_vram < MIN_VRAM || changed.grass_vram < MIN_VRAM {
+      return Err(format!("terrain_vram and grass_vram have to be at least {}", MIN_VRAM))
+    }
+    if changed.lod_count() == 0 {
+      let needed = changed.polygons(lod::T(0)) as usize * view::terrain_buffers::POLYGON_COST;
+      return Err(format!("lod_radius {} needs terrain_vram of at least {}", changed.lod_radius, needed))
+    }
+
+    *self = changed;
+    Ok(())
+  }
+
+  fn set_unchecked(&mut self, name: &str, value: &str) -> Result<(), String> {
+    match name {
+      "terrain_vram" => self.terrain_vram = try!(parse(value)),
+      "grass_vram" => self.grass_vram = try!(parse(value)),
+      "load_distance" => self.load_distance = try!(parse(value)),
+      "lod_radius" => {
+        let lod_radius: i32 = try!(parse(value));
+        if lod_radius < 1 || lod_radius > MAX_LOD_RADIUS {
+          return Err(format!("lod_radius has to be from 1 to {}", MAX_LOD_RADIUS))
+        }
+        self.lod_radius = lod_radius;
+      },
+      "max_grass_lod" => {
+        let max_grass_lod: u32 = try!(parse(value));
+        if max_grass_lod as usize >= lod::COUNT {
+          return Err(format!("max_grass_lod has to be less than {}", lod::COUNT))
+        }
+        self.max_grass_lod = lod::T(max_grass_lod);
+      },
+      _ => return Err(format!("There's no setting called {:?}", name)),
+    }
+    Ok(())
+  }
+
+  /// One line per setting, with its value.
+  pub fn describe(&self) -> Vec<String> {
+    vec!(
+      format!("terrain_vram = {}", self.terrain_vram),
+      format!("grass_vram = {}", self.grass_vram),
+      format!("load_distance = {} ({} LODs)", self.load_distance, self.lod_count()),
+      format!("lod_radius = {}", self.lod_radius),
+      format!("max_grass_lod = {} ({} LODs have grass)", self.max_grass_lod.0, self.grass_lod_count()),
+    )
+  }
+
+  /// Run what the player typed after `/set`, and return the lines of the reply.
+  pub fn command(&mut self, line: &str) -> Vec<String> {
+    let mut words = line.split_whitespace();
+    match (words.next(), words.next(), words.next()) {
+      (None, _, _) => {
+        let mut reply: Vec<String> = HELP.lines().map(String::from).collect();
+        reply.extend(self.describe());
+        reply
+      },
+      (Some(name), Some(value), None) => {
+        match self.set(name, value) {
+          Ok(()) => vec!(format!("{} = {}", name, value)),
+          Err(err) => vec!(err),
+        }
+      },
+      _ => vec!(String::from("Usage: /set NAME VALUE")),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use lod;
+  use view;
+
+  #[test]
+  fn lod_count() {
+    let mut settings = super::new();
+    assert_eq!(settings.lod_count(), lod::COUNT as u32);
+
+    // The coarsest LOD reaches 5 chunks, then 10, 20, ...
+    settings.load_distance = 20;
+    assert_eq!(settings.lod_count(), 3);
+    settings.load_distance = 21;
+    assert_eq!(settings.lod_count(), 4);
+
+    // LODs that don't fit aren't loaded.
+    settings.load_distance = super::new().load_distance;
+    let finest = settings.polygons(lod::T(0)) as usize * view::terrain_buffers::POLYGON_COST;
+    settings.terrain_vram = finest;
+    assert_eq!(settings.lod_count(), 1);
+    settings.terrain_vram = finest - 1;
+    assert_eq!(settings.lod_count(), 0);
+  }
+
+  #[test]
+  fn grass_lod_count() {
+    let mut settings = super::new();
+    assert_eq!(settings.grass_lod_count(), lod::MAX_GRASS_LOD.0 + 1);
+
+    settings.max_grass_lod = lod::T(0);
+    assert_eq!(settings.grass_lod_count(), 1);
+
+    settings.max_grass_lod = lod::MAX_GRASS_LOD;
+    let finest = settings.polygons(lod::T(0)) as usize;
+    settings.grass_vram = view::grass_buffers::TUFT_COST * finest;
+    assert_eq!(settings.grass_lod_count(), 1);
+    settings.grass_vram -= 1;
+    assert_eq!(settings.grass_lod_count(), 0);
+  }
+
+  #[test]
+  fn vram_minimums() {
+    let mut settings = super::new();
+    assert!(settings.set("terrain_vram", "0").is_err());
+    assert!(settings.set("grass_vram", "1").is_err());
+    assert_eq!(settings, super::new());
+
+    // The finest LOD has to fit.
+    let finest = settings.polygons(lod::T(0)) as usize * view::terrain_buffers::POLYGON_COST;
+    assert!(settings.set("terrain_vram", &(finest - 1).to_string()).is_err());
+    assert!(settings.set("terrain_vram", &finest.to_string()).is_ok());
+    assert!(settings.set("lod_radius", "3").is_err());
+    assert_eq!(settings.lod_radius, super::new().lod_radius);
+  }
+
+  #[test]
+  fn set() {
+    let mut settings = super::new();
+    assert!(settings.set("lod_radius", "3").is_ok());
+    assert_eq!(settings.lod_radius, 3);
+    assert!(settings.set("lod_radius", "0").is_err());
+    assert!(settings.set("max_grass_lod", &lod::COUNT.to_string()).is_err());
+    assert!(settings.set("load_distance", "far").is_err());
+    assert!(settings.set("view_distance", "10").is_err());
+    // Failures don't change anything.
+    assert_eq!(settings.lod_radius, 3);
+    assert_eq!(settings.max_grass_lod, super::new().max_grass_lod);
+    assert_eq!(settings.load_distance, super::new().load_distance);
+  }
+}
@@ -13,11 +13,29 @@ use common::voxel;
 
 use chunk;
 use chunk_stats;
+use clipmap;
 use lod;
+use mesh_thread;
 use record_book;
 use terrain_mesh;
 use view;
 
+/// A chunk's faces, and the offset to the neighbour across each.
+const FACES: [(terrain_mesh::Face, cgmath::Vector3<i32>); 6] = [
+  (terrain_mesh::Face::LowX, cgmath::Vector3 { x: -1, y: 0, z: 0 }),
+  (terrain_mesh::Face::LowY, cgmath::Vector3 { x: 0, y: -1, z: 0 }),
+  (terrain_mesh::Face::LowZ, cgmath::Vector3 { x: 0, y: 0, z: -1 }),
+  (terrain_mesh::Face::HighX, cgmath::Vector3 { x: 1, y: 0, z: 0 }),
+  (terrain_mesh::Face::HighY, cgmath::Vector3 { x: 0, y: 1, z: 0 }),
+  (terrain_mesh::Face::HighZ, cgmath::Vector3 { x: 0, y: 0, z: 1 }),
+];
+
+/// How long each tick can spend queueing edited chunks to be remeshed. The nearest chunk is always
+/// queued, so edits show up even when ticks are slow.
+const REMESH_BUDGET_NS: u64 = 1_000_000;
+/// How long each tick can spend loading finished meshes. At least one is always loaded.
+const LOAD_MESHES_BUDGET_NS: u64 = 1_000_000;
+
 #[allow(missing_docs)]
 #[derive(Debug, Clone)]
 pub enum Load {
@@ -29,35 +47,81 @@ pub enum Load {
   },
 }
 
+/// What to ask the server for to load a chunk.
+#[derive(Debug, Clone)]
+pub struct Request {
+  /// The voxels to fetch.
+  pub voxels : Vec<voxel::bounds::T>,
+  /// Whether the server should start sending us edits to them.
+  pub watch  : bool,
+}
+
 #[allow(missing_docs)]
 pub struct T {
   /// A record of all the chunks that have been loaded.
-  loaded_chunks       : fnv_map::T<chunk::position::T, (terrain_mesh::Ids, lod::T)>,
+  loaded_chunks       : fnv_map::T<clipmap::Chunk, terrain_mesh::Ids>,
+  /// Chunks waiting on a mesh thread, with the id of the latest job for each. Meshes from any
+  /// other job are stale.
+  meshing             : fnv_map::T<clipmap::Chunk, u64>,
+  next_mesh_id        : u64,
+  /// Chunks we've asked the server for voxels for, to load once they've all arrived.
+  requested_chunks    : fnv_set::T<clipmap::Chunk>,
+  /// Chunks the server sends us edits for: ones we've asked for, and haven't unloaded since. Our
+  /// cached voxels anywhere else might have missed edits.
+  watched_chunks      : fnv_set::T<clipmap::Chunk>,
   /// Map each chunk to the number of voxels inside it that we have.
-  chunk_voxels_loaded : fnv_map::T<(chunk::position::T, lod::T), u32>,
+  chunk_voxels_loaded : fnv_map::T<clipmap::Chunk, u32>,
+  /// Loaded chunks whose voxels have changed since they were meshed.
+  dirty_chunks        : fnv_set::T<clipmap::Chunk>,
+  /// Loaded chunks whose latest mesh the view didn't have room to show.
+  unshown_chunks      : fnv_set::T<clipmap::Chunk>,
   /// The voxels we have cached from the server.
   voxels              : voxel::tree::T,
-  max_load_distance   : u32,
   queue               : std::collections::VecDeque<Load>,
 }
 
 #[allow(missing_docs)]
-pub fn new(max_load_distance: u32) -> T {
+pub fn new() -> T {
   T {
     loaded_chunks       : fnv_map::new(),
+    meshing             : fnv_map::new(),
+    next_mesh_id        : 0,
+    requested_chunks    : fnv_set::new(),
+    watched_chunks      : fnv_set::new(),
     chunk_voxels_loaded : fnv_map::new(),
+    dirty_chunks        : fnv_set::new(),
+    unshown_chunks      : fnv_set::new(),
     voxels              : voxel::tree::new(),
-    max_load_distance   : max_load_distance,
     queue               : std::collections::VecDeque::new(),
   }
 }
 
 impl T {
-  /// return the LOD at which a chunk is loaded
-  pub fn load_state(&self, chunk_position: &chunk::position::T) -> Option<lod::T> {
-    self.loaded_chunks
-      .get(&chunk_position)
-      .map(|&(_, lod)| lod)
+  /// whether a chunk is loaded at a given LOD, or is being meshed at it
+  pub fn is_loaded(&self, chunk_position: &chunk::position::T, lod: lod::T) -> bool {
+    let chunk = (*chunk_position, lod);
+    self.meshing.contains_key(&chunk) || self.loaded_chunks.contains_key(&chunk)
+  }
+
+  /// Whether a chunk's mesh is showing in the view. Chunks being remeshed still have their old one.
+  pub fn is_meshed(&self, chunk_position: &chunk::position::T, lod: lod::T) -> bool {
+    let chunk = (*chunk_position, lod);
+    self.loaded_chunks.contains_key(&chunk) && !self.unshown_chunks.contains(&chunk)
+  }
+
+  /// Hear that the view didn't have room for a mesh, so its chunk isn't counted as meshed until it
+  /// gets another one. Meshes that have already been replaced are ignored.
+  pub fn mesh_not_shown(&mut self, ids: &terrain_mesh::Ids) {
+    let chunk =
+      self.loaded_chunks.iter()
+      .find(|&(_, loaded)| loaded.chunk_ids == ids.chunk_ids)
+      .map(|(&chunk, _)| chunk);
+    match chunk {
+      None => {},
+      Some(chunk) => {
+        self.unshown_chunks.insert(chunk);
+      },
+    }
   }
 
   /// get the count of queued messages
@@ -87,30 +151,38 @@ impl T {
     *chunk_voxels_loaded == samples as u32
   }
 
-  /// Iterate through some enqueued voxel loads and load any updated chunks.
+  /// Load any finished meshes, then iterate through some enqueued voxel loads and queue any
+  /// updated chunks for meshing.
   pub fn tick<Rng, UpdateView>(
     &mut self,
+    mesh_threads      : &mesh_thread::T,
     terrain_allocator : &std::sync::Mutex<id_allocator::T<view::entity::id::Terrain>>,
     grass_allocator   : &std::sync::Mutex<id_allocator::T<view::entity::id::Grass>>,
     rng               : &mut Rng,
     chunk_stats       : &mut chunk_stats::T,
     update_view       : &mut UpdateView,
     player_position   : &cgmath::Point3<f32>,
+    grass_lod_count   : u32,
   ) where
     UpdateView : FnMut(view::update::T),
     Rng        : rand::Rng,
   {
+    self.load_meshes(
+      mesh_threads,
+      terrain_allocator,
+      grass_allocator,
+      rng,
+      chunk_stats,
+      update_view,
+      grass_lod_count,
+    );
+
     let start = time::precise_time_ns();
     while let Some(msg) = self.queue.pop_front() {
       match msg {
         Load::Voxels { voxels, time_requested } => {
           self.load_voxels(
-            terrain_allocator,
-            grass_allocator,
-            rng,
-            chunk_stats,
-            update_view,
-            player_position,
+            mesh_threads,
             voxels,
             time_requested,
           );
@@ -121,210 +193,305 @@ impl T {
         break
       }
     }
+
+    self.remesh(mesh_threads, player_position);
+  }
+
+  /// Queue chunks with changed voxels for meshing, nearest to the player first, until this tick's
+  /// time is up.
+  #[inline(never)]
+  fn remesh(
+    &mut self,
+    mesh_threads    : &mesh_thread::T,
+    player_position : &cgmath::Point3<f32>,
+  ) {
+    if self.dirty_chunks.is_empty() {
+      return
+    }
+
+    let start = time::precise_time_ns();
+    let player_position = chunk::position::of_world_position(player_position);
+    let mut dirty_chunks: Vec<clipmap::Chunk> = self.dirty_chunks.iter().cloned().collect();
+    // Compare the chunks' low corners, in finest-LOD chunks.
+    dirty_chunks.sort_by_key(|&(chunk_position, lod)| {
+      let p = chunk_position.as_pnt();
+      let p = cgmath::Point3::new(p.x << lod.0, p.y << lod.0, p.z << lod.0);
+      surroundings_loader::distance_between(player_position.as_pnt(), &p)
+    });
+
+    for (chunk_position, lod) in dirty_chunks {
+      self.mesh_chunk(mesh_threads, &chunk_position, lod);
+
+      if time::precise_time_ns() - start >= REMESH_BUDGET_NS {
+        break
+      }
+    }
   }
 
+  /// Send a chunk to the mesh threads, with a copy of the voxels it needs.
   #[inline(never)]
-  fn force_load_chunk<Rng, UpdateView>(
+  fn mesh_chunk(
     &mut self,
+    mesh_threads   : &mesh_thread::T,
+    chunk_position : &chunk::position::T,
+    lod            : lod::T,
+  ) {
+    debug!("mesh {:?} at {:?}", chunk_position, lod);
+    self.dirty_chunks.remove(&(*chunk_position, lod));
+
+    // Our neighbours' skirts depend on our LOD, so they need redoing when we first show up.
+    if !self.is_loaded(chunk_position, lod) {
+      for neighbor in self.neighbors(chunk_position, lod) {
+        self.dirty_chunks.insert(neighbor);
+      }
+    }
+
+    let seams = self.seams(chunk_position, lod);
+    let mut voxels = voxel::tree::new();
+    for bounds in chunk_voxels(chunk_position, lod) {
+      if let Some(voxel) = self.voxels.get(&bounds) {
+        voxels.get_mut_or_create(&bounds).data = Some(voxel.clone());
+      }
+    }
+
+    let id = self.next_mesh_id;
+    self.next_mesh_id += 1;
+    self.meshing.insert((*chunk_position, lod), id);
+    mesh_threads.push(
+      mesh_thread::Job {
+        id             : id,
+        chunk_position : *chunk_position,
+        lod            : lod,
+        seams          : seams,
+        voxels         : voxels,
+      }
+    );
+  }
+
+  /// The LOD of the chunk loaded just across one of a chunk's faces, if any. Since LODs only meet
+  /// their neighbours in the clipmap, it's the chunk's own LOD or the one on either side.
+  fn lod_across(
+    &self,
+    chunk_position : &chunk::position::T,
+    lod            : lod::T,
+    delta          : cgmath::Vector3<i32>,
+  ) -> Option<lod::T> {
+    let neighbor = *chunk_position + delta;
+    if self.is_loaded(&neighbor, lod) {
+      return Some(lod)
+    }
+    if let Some(coarser) = lod.coarser() {
+      if self.is_loaded(&coarser_containing(&neighbor), coarser) {
+        return Some(coarser)
+      }
+    }
+    if let Some(finer) = lod.finer() {
+      if finer_across(chunk_position, delta).iter().any(|chunk| self.is_loaded(chunk, finer)) {
+        return Some(finer)
+      }
+    }
+    None
+  }
+
+  /// The loaded chunks across any of a chunk's faces, at any LOD.
+  fn neighbors(&self, chunk_position: &chunk::position::T, lod: lod::T) -> Vec<clipmap::Chunk> {
+    let mut neighbors = Vec::new();
+    for &(_, delta) in &FACES {
+      let neighbor = *chunk_position + delta;
+      neighbors.push((neighbor, lod));
+      if let Some(coarser) = lod.coarser() {
+        neighbors.push((coarser_containing(&neighbor), coarser));
+      }
+      if let Some(finer) = lod.finer() {
+        neighbors.extend(finer_across(chunk_position, delta).into_iter().map(|chunk| (chunk, finer)));
+      }
+    }
+    neighbors.retain(|&(neighbor, lod)| self.is_loaded(&neighbor, lod));
+    neighbors
+  }
+
+  /// The faces of a chunk whose neighbour across them samples voxels at a different size.
+  fn seams(&self, chunk_position: &chunk::position::T, lod: lod::T) -> Vec<terrain_mesh::Seam> {
+    FACES.iter()
+      .filter_map(|&(face, delta)| {
+        self.lod_across(chunk_position, lod, delta)
+          .and_then(|neighbor_lod| {
+            if neighbor_lod == lod {
+              None
+            } else {
+              Some(terrain_mesh::Seam { face: face, lg_sample_size: neighbor_lod.lg_sample_size() })
+            }
+          })
+      })
+      .collect()
+  }
+
+  /// Give finished meshes ids and send them to the view, dropping any that are stale because their
+  /// chunk was unloaded or meshed again since.
+  #[inline(never)]
+  fn load_meshes<Rng, UpdateView>(
+    &mut self,
+    mesh_threads      : &mesh_thread::T,
     terrain_allocator : &std::sync::Mutex<id_allocator::T<view::entity::id::Terrain>>,
     grass_allocator   : &std::sync::Mutex<id_allocator::T<view::entity::id::Grass>>,
     rng               : &mut Rng,
     chunk_stats       : &mut chunk_stats::T,
     update_view       : &mut UpdateView,
-    chunk_position    : &chunk::position::T,
-    lod               : lod::T,
+    grass_lod_count   : u32,
   ) where
     UpdateView : FnMut(view::update::T),
     Rng        : rand::Rng,
   {
-    debug!("generate {:?} at {:?}", chunk_position, lod);
-    let mesh_chunk: view::chunked_terrain::T =
-      terrain_mesh::generate(&self.voxels, chunk_stats, &chunk_position, lod, terrain_allocator, grass_allocator, rng);
+    let start = time::precise_time_ns();
+    while let Some(mesh) = mesh_threads.pop() {
+      if !self.take_mesh(&mesh) {
+        debug!("Dropping stale mesh of {:?} at {:?}", mesh.chunk_position, mesh.lod);
+        continue
+      }
+      let chunk = (mesh.chunk_position, mesh.lod);
 
-    let mut updates = Vec::new();
+      let mesh_chunk: view::chunked_terrain::T =
+        terrain_mesh::generate(mesh.polygons, mesh.skirts, mesh.lod, grass_lod_count, chunk_stats, terrain_allocator, grass_allocator, rng);
 
-    let ids =
-      terrain_mesh::Ids {
-        chunk_ids: mesh_chunk.ids.clone(),
-        grass_ids: mesh_chunk.grass.ids.clone(),
-      };
+      let mut updates = Vec::new();
 
-    use std::collections::hash_map::Entry::*;
-    // TODO: Rc instead of clone.
-    match self.loaded_chunks.entry(*chunk_position) {
-      Vacant(entry) => {
-        entry.insert((ids, lod));
-      },
-      Occupied(mut entry) => {
-        let (ids, _) = entry.insert((ids, lod));
+      let ids =
+        terrain_mesh::Ids {
+          chunk_ids: mesh_chunk.ids.clone(),
+          grass_ids: mesh_chunk.grass.ids.clone(),
+        };
+
+      // TODO: Rc instead of clone.
+      if let Some(ids) = self.loaded_chunks.insert(chunk, ids) {
         updates.push(view::update::UnloadMesh(ids));
-      },
-    };
+      }
+      self.unshown_chunks.remove(&chunk);
+
+      if !mesh_chunk.is_empty() {
+        updates.push(view::update::LoadMesh(Box::new(mesh_chunk)));
+      }
+
+      update_view(view::update::Atomic(updates));
 
-    if !mesh_chunk.is_empty() {
-      updates.push(view::update::LoadMesh(Box::new(mesh_chunk)));
+      if time::precise_time_ns() - start >= LOAD_MESHES_BUDGET_NS {
+        break
+      }
     }
+  }
 
-    update_view(view::update::Atomic(updates));
+  /// Whether a finished mesh is the latest one for a chunk we still want. If it is, the chunk
+  /// stops waiting for it.
+  fn take_mesh(&mut self, mesh: &mesh_thread::Mesh) -> bool {
+    let chunk = (mesh.chunk_position, mesh.lod);
+    if self.meshing.get(&chunk) != Some(&mesh.id) {
+      return false
+    }
+    self.meshing.remove(&chunk);
+    true
   }
 
   /// try to load a chunk into VRAM.
-  /// if some voxels are missing, returns an Err of all the voxels that need to be fetched from the server.
-  pub fn load_chunk<Rng, UpdateView>(
+  /// if some voxels are missing, or might have missed edits because we weren't watching them,
+  /// returns an Err of what to ask the server for, and the chunk is loaded once it arrives.
+  pub fn load_chunk(
     &mut self,
-    terrain_allocator : &std::sync::Mutex<id_allocator::T<view::entity::id::Terrain>>,
-    grass_allocator   : &std::sync::Mutex<id_allocator::T<view::entity::id::Grass>>,
-    rng               : &mut Rng,
-    chunk_stats       : &mut chunk_stats::T,
-    update_view       : &mut UpdateView,
-    chunk_position    : &chunk::position::T,
-    lod               : lod::T,
-  ) -> Result<(), Vec<voxel::bounds::T>> where
-    UpdateView : FnMut(view::update::T),
-    Rng        : rand::Rng,
-  {
+    mesh_threads   : &mesh_thread::T,
+    chunk_position : &chunk::position::T,
+    lod            : lod::T,
+  ) -> Result<(), Request> {
+    let chunk = (*chunk_position, lod);
     let all_voxels_loaded =
       self.all_voxels_loaded(
         *chunk_position,
         lod,
       );
-    if all_voxels_loaded {
-      self.force_load_chunk(
-        terrain_allocator,
-        grass_allocator,
-        rng,
-        chunk_stats,
-        update_view,
+    if all_voxels_loaded && self.watched_chunks.contains(&chunk) {
+      self.requested_chunks.remove(&chunk);
+      self.mesh_chunk(
+        mesh_threads,
         chunk_position,
         lod,
       );
       Ok(())
     } else {
-      let voxel_size = 1 << lod.lg_sample_size();
-      let voxels =
-        terrain_mesh::voxels_in(
-          &collision::Aabb3::new(
-            cgmath::Point3::new(
-              (chunk_position.as_pnt().x << chunk::LG_WIDTH) - voxel_size,
-              (chunk_position.as_pnt().y << chunk::LG_WIDTH) - voxel_size,
-              (chunk_position.as_pnt().z << chunk::LG_WIDTH) - voxel_size,
-            ),
-            cgmath::Point3::new(
-              ((chunk_position.as_pnt().x + 1) << chunk::LG_WIDTH) + voxel_size,
-              ((chunk_position.as_pnt().y + 1) << chunk::LG_WIDTH) + voxel_size,
-              ((chunk_position.as_pnt().z + 1) << chunk::LG_WIDTH) + voxel_size,
-            ),
-          ),
-          lod.lg_sample_size(),
-        );
-      Err(voxels)
+      self.requested_chunks.insert(chunk);
+      Err(
+        Request {
+          voxels : chunk_voxels(chunk_position, lod),
+          watch  : self.watched_chunks.insert(chunk),
+        }
+      )
     }
   }
 
+  /// Store a voxel, and mark which chunks need meshing because of it. Loaded chunks that sample
+  /// voxels this size are rebuilt later, a few at a time. The voxel can also finish a chunk we
+  /// asked for, which gets passed to `update_chunk`.
+  /// Edits come with the coarser samples they changed, so chunks at every LOD get rebuilt this way.
   #[inline(never)]
   fn load_voxel<UpdateChunk>(
     &mut self,
-    player_position  : &cgmath::Point3<f32>,
     voxel            : voxel::T,
     bounds           : &voxel::bounds::T,
     mut update_chunk : UpdateChunk,
   ) where
     UpdateChunk: FnMut(chunk::position::T, lod::T),
   {
-    let player_position = chunk::position::of_world_position(player_position);
-
     // Has a new voxel been loaded? (or did we change an existing voxel)
     let new_voxel_loaded;
+    // Voxels we asked for again can be the same as the cached ones, but they can still finish a chunk.
+    let changed;
     {
       let voxel = Some(voxel);
       let node = self.voxels.get_mut_or_create(bounds);
       let old_voxel = &mut node.data;
       new_voxel_loaded = old_voxel.is_none();
-      if *old_voxel == voxel {
-        return
-      }
+      changed = *old_voxel != voxel;
       *old_voxel = voxel;
     }
 
     trace!("voxel bounds {:?}", bounds);
 
-    // The LOD of the chunks that should be updated.
-    // This doesn't necessarily match the LOD they're loaded at.
-    let mut updated_lods = Vec::new();
-    for lod in 0..lod::COUNT as u32 {
-      let lod = lod::T(lod);
-
-      let lg_size = lod.lg_sample_size();
-      if lg_size == bounds.lg_size {
-        updated_lods.push(lod);
-      }
-    }
+    // The LOD of the chunks that sample voxels this size.
+    let lod =
+      match lod::of_lg_sample_size(bounds.lg_size) {
+        None => return,
+        Some(lod) => lod,
+      };
 
-    for chunk_position in updated_chunk_positions(&bounds) {
+    for chunk_position in updated_chunk_positions(&bounds, lod) {
       trace!("chunk_position {:?}", chunk_position);
+      let chunk = (chunk_position, lod);
       if new_voxel_loaded {
-        for &updated_lod in &updated_lods {
-          let chunk_voxels_loaded =
-            self.chunk_voxels_loaded.entry((chunk_position, updated_lod))
-            .or_insert_with(|| 0);
-          trace!("{:?} gets {:?}", chunk_position, bounds);
-          *chunk_voxels_loaded += 1;
-        }
-      }
-
-      let distance =
-        surroundings_loader::distance_between(
-          player_position.as_pnt(),
-          &chunk_position.as_pnt(),
-        );
-
-      if distance > self.max_load_distance {
-        debug!(
-          "Not loading {:?}: too far away from player at {:?}.",
-          bounds,
-          player_position,
-        );
-        continue;
+        let chunk_voxels_loaded =
+          self.chunk_voxels_loaded.entry(chunk)
+          .or_insert_with(|| 0);
+        trace!("{:?} gets {:?}", chunk_position, bounds);
+        *chunk_voxels_loaded += 1;
       }
 
-      let lod = lod::of_distance(distance as u32);
-      let lg_size = lod.lg_sample_size();
-      if lg_size != bounds.lg_size {
-        debug!(
-          "{:?} is not the desired LOD {:?}.",
-          bounds,
-          lod
-        );
-        continue;
-      }
-
-      if self.all_voxels_loaded(chunk_position, lod) {
+      if self.is_loaded(&chunk_position, lod) {
+        if changed {
+          self.dirty_chunks.insert(chunk);
+        }
+      } else if self.requested_chunks.contains(&chunk) && self.all_voxels_loaded(chunk_position, lod) {
         update_chunk(chunk_position, lod);
       }
     }
   }
 
   #[inline(never)]
-  fn load_voxels<Rng, UpdateView>(
+  fn load_voxels(
     &mut self,
-    terrain_allocator : &std::sync::Mutex<id_allocator::T<view::entity::id::Terrain>>,
-    grass_allocator   : &std::sync::Mutex<id_allocator::T<view::entity::id::Grass>>,
-    rng               : &mut Rng,
-    chunk_stats       : &mut chunk_stats::T,
-    update_view       : &mut UpdateView,
-    player_position   : &cgmath::Point3<f32>,
-    voxel_updates     : Vec<(voxel::bounds::T, voxel::T)>,
-    time_requested    : Option<u64>,
-  ) where
-    UpdateView : FnMut(view::update::T),
-    Rng        : rand::Rng,
-  {
+    mesh_threads    : &mesh_thread::T,
+    voxel_updates   : Vec<(voxel::bounds::T, voxel::T)>,
+    time_requested  : Option<u64>,
+  ) {
     let mut update_chunks = fnv_set::new();
     let response_time = time::precise_time_ns();
     for (bounds, voxel) in voxel_updates {
       trace!("Got voxel at {:?}", bounds);
       self.load_voxel(
-        player_position,
         voxel,
         &bounds,
         |chunk, lod| { update_chunks.insert((chunk, lod)); },
@@ -333,16 +500,7 @@ impl T {
 
     let processed_time = time::precise_time_ns();
     for (chunk, lod) in update_chunks {
-      let _ =
-        self.load_chunk(
-          terrain_allocator,
-          grass_allocator,
-          rng,
-          chunk_stats,
-          update_view,
-          &chunk,
-          lod,
-        );
+      let _ = self.load_chunk(mesh_threads, &chunk, lod);
     }
 
     let chunk_loaded = time::precise_time_ns();
@@ -362,37 +520,128 @@ impl T {
     }
   }
 
-  /// unload a chunk
+  /// unload a chunk.
+  /// Returns the voxels to tell the server to unwatch, if we were watching them.
   pub fn unload<UpdateView>(
     &mut self,
     update_view    : &mut UpdateView,
     chunk_position : &chunk::position::T,
-  ) where
+    lod            : lod::T,
+  ) -> Option<Vec<voxel::bounds::T>> where
     UpdateView : FnMut(view::update::T),
   {
-    match self.loaded_chunks.remove(chunk_position) {
+    let chunk = (*chunk_position, lod);
+    self.requested_chunks.remove(&chunk);
+    self.dirty_chunks.remove(&chunk);
+    self.unshown_chunks.remove(&chunk);
+    self.meshing.remove(&chunk);
+    match self.loaded_chunks.remove(&chunk) {
       None => {},
-      Some((ids, _)) => {
+      Some(ids) => {
         update_view(view::update::UnloadMesh(ids));
       },
     }
+
+    // Our neighbours' skirts depended on our LOD, and there might be a different one across from
+    // them now.
+    for neighbor in self.neighbors(chunk_position, lod) {
+      self.dirty_chunks.insert(neighbor);
+    }
+
+    if self.watched_chunks.remove(&chunk) {
+      Some(chunk_voxels(chunk_position, lod))
+    } else {
+      None
+    }
+  }
+
+  /// unload every chunk. The voxels stay cached.
+  /// Returns the voxels to tell the server to unwatch, a chunk at a time.
+  pub fn unload_all<UpdateView>(
+    &mut self,
+    update_view : &mut UpdateView,
+  ) -> Vec<Vec<voxel::bounds::T>> where
+    UpdateView : FnMut(view::update::T),
+  {
+    self.requested_chunks.clear();
+    self.dirty_chunks.clear();
+    self.unshown_chunks.clear();
+    self.meshing.clear();
+    for (_, ids) in self.loaded_chunks.drain() {
+      update_view(view::update::UnloadMesh(ids));
+    }
+    self.watched_chunks.drain()
+      .map(|(chunk_position, lod)| chunk_voxels(&chunk_position, lod))
+      .collect()
   }
 }
 
+/// The low and high corners of a chunk at `lod`, padded by `padding` all around.
+fn chunk_bounds(
+  chunk_position : &chunk::position::T,
+  lod            : lod::T,
+  padding        : i32,
+) -> (cgmath::Point3<i32>, cgmath::Point3<i32>) {
+  let lg_width = lod.lg_chunk_width();
+  let p = chunk_position.as_pnt();
+  (
+    cgmath::Point3::new(
+      (p.x << lg_width) - padding,
+      (p.y << lg_width) - padding,
+      (p.z << lg_width) - padding,
+    ),
+    cgmath::Point3::new(
+      ((p.x + 1) << lg_width) + padding,
+      ((p.y + 1) << lg_width) + padding,
+      ((p.z + 1) << lg_width) + padding,
+    ),
+  )
+}
+
+/// The chunk at the next coarser LOD that contains a chunk.
+fn coarser_containing(chunk_position: &chunk::position::T) -> chunk::position::T {
+  let p = chunk_position.as_pnt();
+  chunk::position::new(p.x >> 1, p.y >> 1, p.z >> 1)
+}
+
+/// The chunks at the next finer LOD just across one of a chunk's faces: the ones in the neighbour
+/// across `delta` that touch the face.
+fn finer_across(chunk_position: &chunk::position::T, delta: cgmath::Vector3<i32>) -> Vec<chunk::position::T> {
+  let p = (*chunk_position + delta).as_pnt().clone();
+  // Across a low face, it's the neighbour's high half that touches us, and vice versa.
+  let touches = |i: i32, d: i32| d == 0 || (d < 0) == (i == 1);
+  let mut chunks = Vec::new();
+  for x in 0 .. 2 {
+  for y in 0 .. 2 {
+  for z in 0 .. 2 {
+    if touches(x, delta.x) && touches(y, delta.y) && touches(z, delta.z) {
+      chunks.push(chunk::position::new(2 * p.x + x, 2 * p.y + y, 2 * p.z + z));
+    }
+  }}}
+  chunks
+}
+
+/// The voxels needed to mesh a chunk: the ones it covers, and one more all around.
+fn chunk_voxels(chunk_position: &chunk::position::T, lod: lod::T) -> Vec<voxel::bounds::T> {
+  let (low, high) = chunk_bounds(chunk_position, lod, 1 << lod.lg_sample_size());
+  terrain_mesh::voxels_in(&collision::Aabb3::new(low, high), lod.lg_sample_size())
+}
+
 #[inline(never)]
 fn updated_chunk_positions(
-  voxel: &voxel::bounds::T,
+  voxel : &voxel::bounds::T,
+  lod   : lod::T,
 ) -> Vec<chunk::position::T>
 {
-  let chunk = chunk::position::containing_voxel(voxel);
+  let chunk = chunk::position::containing_voxel(voxel, lod);
 
   macro_rules! tweak(($dim:ident) => {{
     let mut new_voxel = voxel.clone();
     new_voxel.$dim += 1;
-    if chunk::position::containing_voxel(&new_voxel) == chunk {
+    if chunk::position::containing_voxel(&new_voxel, lod) == chunk {
       let mut new_voxel = voxel.clone();
       new_voxel.$dim -= 1;
-      if chunk::position::containing_voxel(&new_voxel) == chunk {
+      if chunk::position::containing_voxel(&new_voxel, lod) == chunk {
         0
       } else {
         -1
@@ -427,3 +676,89 @@ fn updated_chunk_positions(
 
   chunks
 }
+
+#[cfg(test)]
+mod test {
+  use common::id_allocator;
+  use common::voxel;
+
+  use chunk;
+  use lod;
+  use mesh_thread;
+  use terrain_mesh;
+
+  fn mesh(id: u64, chunk_position: chunk::position::T, lod: lod::T) -> mesh_thread::Mesh {
+    mesh_thread::Mesh {
+      id             : id,
+      chunk_position : chunk_position,
+      lod            : lod,
+      polygons       : Vec::new(),
+      skirts         : Vec::new(),
+    }
+  }
+
+  #[test]
+  fn stale_meshes() {
+    let mut terrain = super::new();
+    let mesh_threads = mesh_thread::new();
+    let p = chunk::position::new(1, 2, 3);
+    let lod = lod::T(0);
+
+    // Only the latest job's mesh is taken.
+    terrain.mesh_chunk(&mesh_threads, &p, lod);
+    terrain.mesh_chunk(&mesh_threads, &p, lod);
+    assert!(!terrain.take_mesh(&mesh(0, p, lod)));
+    assert!(terrain.take_mesh(&mesh(1, p, lod)));
+    assert!(!terrain.take_mesh(&mesh(1, p, lod)));
+
+    // Or none, once the chunk's been unloaded.
+    terrain.mesh_chunk(&mesh_threads, &p, lod);
+    terrain.unload(&mut |_| {}, &p, lod);
+    assert!(!terrain.take_mesh(&mesh(2, p, lod)));
+  }
+
+  #[test]
+  fn edited_samples_dirty_their_chunks() {
+    let mut terrain = super::new();
+    let coarse = (chunk::position::new(0, 0, 0), lod::T(1));
+    let coarse_sample = voxel::bounds::new(1, 1, 1, lod::T(1).lg_sample_size());
+    terrain.voxels.get_mut_or_create(&coarse_sample).data = Some(voxel::Volume(voxel::Material::Terrain));
+    // Being meshed counts as loaded.
+    terrain.meshing.insert(coarse, 0);
+
+    // The same sample again doesn't change anything..
+    terrain.load_voxel(voxel::Volume(voxel::Material::Terrain), &coarse_sample, |_, _| {});
+    assert!(terrain.dirty_chunks.is_empty());
+
+    // .. but an edited one does.
+    terrain.load_voxel(voxel::Volume(voxel::Material::Empty), &coarse_sample, |_, _| {});
+    assert!(terrain.dirty_chunks.contains(&coarse));
+  }
+
+  #[test]
+  fn unshown_meshes() {
+    let mut terrain = super::new();
+    let mut allocator = id_allocator::new();
+    let p = chunk::position::new(1, 2, 3);
+    let lod = lod::T(0);
+    let ids = |allocator: &mut id_allocator::T<_>| {
+      terrain_mesh::Ids {
+        chunk_ids : vec!(allocator.allocate()),
+        grass_ids : Vec::new(),
+      }
+    };
+
+    let first = ids(&mut allocator);
+    terrain.loaded_chunks.insert((p, lod), first.clone());
+    assert!(terrain.is_meshed(&p, lod));
+
+    // A mesh that's been replaced since doesn't matter..
+    terrain.mesh_not_shown(&ids(&mut allocator));
+    assert!(terrain.is_meshed(&p, lod));
+
+    // .. but the chunk's latest one does.
+    terrain.mesh_not_shown(&first);
+    assert!(!terrain.is_meshed(&p, lod));
+    assert!(terrain.is_loaded(&p, lod));
+  }
+}
@@ -5,9 +5,11 @@ use collision::{Aabb, Aabb3};
 use isosurface_extraction::dual_contouring;
 use num::iter::range_inclusive;
 use rand;
+use std::cmp;
 use std::sync::Mutex;
 use stopwatch;
 
+use common::fnv_map;
 use common::id_allocator;
 use common::voxel;
 // TODO: Move the server-only parts to the server, like BLOCK_WIDTH and sample_info.
@@ -114,102 +116,248 @@ mod voxel_storage {
   }
 }
 
+/// A polygon out of dual contouring, before it has ids.
+#[derive(Debug, Clone)]
+pub struct Polygon {
+  #[allow(missing_docs)]
+  pub vertices : Triangle<Point3<f32>>,
+  #[allow(missing_docs)]
+  pub normals  : Triangle<Vector3<f32>>,
+  #[allow(missing_docs)]
+  pub material : voxel::Material,
+}
+
+/// One of a chunk's faces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(missing_docs)]
+pub enum Face {
+  LowX,
+  LowY,
+  LowZ,
+  HighX,
+  HighY,
+  HighZ,
+}
+
+/// A face whose neighbour is meshed at a different sample size, so the two meshes don't meet.
+/// Both chunks hang skirts along it to hide the crack (see `skirts`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Seam {
+  #[allow(missing_docs)]
+  pub face           : Face,
+  /// The neighbour's.
+  pub lg_sample_size : i16,
+}
+
+/// The corners of a chunk at `lod`, in units of samples of size `lg_sample_size`.
+fn chunk_samples(chunk_position: &chunk::position::T, lod: lod::T, lg_sample_size: i16) -> (Point3<i32>, Point3<i32>) {
+  let lg_edge_samples = lod.lg_chunk_width() as i16 - lg_sample_size;
+  let low = *chunk_position.as_pnt();
+  let high = low + (&Vector3::new(1, 1, 1));
+  let low =
+    Point3::new(
+      low.x << lg_edge_samples,
+      low.y << lg_edge_samples,
+      low.z << lg_edge_samples,
+    );
+  let high =
+    Point3::new(
+      high.x << lg_edge_samples,
+      high.y << lg_edge_samples,
+      high.z << lg_edge_samples,
+    );
+  (low, high)
+}
+
+/// Extract the polygons around a box of edges (inclusive) that point in one direction.
+fn extract_edges(
+  voxels    : &voxel::tree::T,
+  direction : dual_contouring::edge::Direction,
+  lg_size   : i16,
+  low       : Point3<i32>,
+  high      : Point3<i32>,
+  polygons  : &mut Vec<Polygon>,
+) {
+  for x in range_inclusive(low.x, high.x) {
+  for y in range_inclusive(low.y, high.y) {
+  for z in range_inclusive(low.z, high.z) {
+    trace!("edge: {:?} {:?}", direction, Point3::new(x, y, z));
+    let edge =
+      dual_contouring::edge::T {
+        low_corner: Point3::new(x, y, z),
+        direction: direction,
+        lg_size: lg_size,
+      };
+
+    let _ =
+      dual_contouring::edge::extract(
+        &mut voxel_storage::T { voxels: voxels },
+        &edge,
+        &mut |polygon: dual_contouring::polygon::T<voxel::Material>| {
+          polygons.push(Polygon {
+            vertices : tri(polygon.vertices[0], polygon.vertices[1], polygon.vertices[2]),
+            normals  : tri(polygon.normals[0], polygon.normals[1], polygon.normals[2]),
+            material : polygon.material,
+          });
+        }
+      );
+  }}}
+}
+
+/// Extract the polygons for a chunk. This only reads `voxels`, so it can run off the update thread.
+pub fn polygons(
+  voxels         : &voxel::tree::T,
+  chunk_position : &chunk::position::T,
+  lod            : lod::T,
+) -> Vec<Polygon>
+{
+  stopwatch::time("terrain_mesh::polygons", || {
+    let lg_sample_size = lod.lg_sample_size();
+    let (low, high) = chunk_samples(chunk_position, lod, lg_sample_size);
+
+    trace!("low {:?}", low);
+    trace!("high {:?}", high);
+
+    let mut polygons = Vec::new();
+    let high = high + Vector3::new(-1, -1, -1);
+    for &direction in &[
+      dual_contouring::edge::Direction::X,
+      dual_contouring::edge::Direction::Y,
+      dual_contouring::edge::Direction::Z,
+    ] {
+      extract_edges(voxels, direction, lg_sample_size, low, high, &mut polygons);
+    }
+    polygons
+  })
+}
+
+/// Whether a vertex is in the layer of samples along one of a chunk's faces, given the chunk's
+/// corners. Chunks mesh one sample past their low faces, so the low layers are outside the chunk.
+fn on_face(face: Face, low: &Point3<f32>, high: &Point3<f32>, sample_size: f32, v: &Point3<f32>) -> bool {
+  match face {
+    Face::LowX  => v.x <= low.x,
+    Face::LowY  => v.y <= low.y,
+    Face::LowZ  => v.z <= low.z,
+    Face::HighX => v.x >= high.x - sample_size,
+    Face::HighY => v.y >= high.y - sample_size,
+    Face::HighZ => v.z >= high.z - sample_size,
+  }
+}
+
+/// A vertex, as something we can hash. Polygons that share a vertex get it from the same voxel, so
+/// the floats are identical.
+fn vertex_key(v: &Point3<f32>) -> [u32; 3] {
+  [v.x.to_bits(), v.y.to_bits(), v.z.to_bits()]
+}
+
+/// Hang skirts from the open edges of a chunk's mesh along its seams. Each skirt reaches into the
+/// terrain (against the surface normals) as far as the coarser side's sample size, which covers the
+/// crack between the chunk's mesh and its neighbour's. This only needs the chunk's own polygons, so
+/// it doesn't wait on any of the neighbour's voxels.
+pub fn skirts(
+  polygons       : &[Polygon],
+  chunk_position : &chunk::position::T,
+  lod            : lod::T,
+  seams          : &[Seam],
+) -> Vec<Polygon>
+{
+  stopwatch::time("terrain_mesh::skirts", || {
+    let mut skirts = Vec::new();
+    if seams.is_empty() {
+      return skirts
+    }
+
+    // Count the polygons on each edge, keeping the first one's winding. The edges with only one
+    // are the mesh's boundary.
+    let mut edges = fnv_map::new();
+    for polygon in polygons {
+      let vertices = [polygon.vertices.v1, polygon.vertices.v2, polygon.vertices.v3];
+      let normals = [polygon.normals.v1, polygon.normals.v2, polygon.normals.v3];
+      for i in 0 .. 3 {
+        let j = (i + 1) % 3;
+        let (a, b) = (vertex_key(&vertices[i]), vertex_key(&vertices[j]));
+        let key = if a < b { (a, b) } else { (b, a) };
+        let edge = (vertices[i], vertices[j], normals[i], normals[j], polygon.material);
+        edges.entry(key).or_insert((0, edge)).0 += 1;
+      }
+    }
+
+    let lg_width = lod.lg_chunk_width();
+    let p = chunk_position.as_pnt();
+    let low = Point3::new((p.x << lg_width) as f32, (p.y << lg_width) as f32, (p.z << lg_width) as f32);
+    let width = (1 << lg_width) as f32;
+    let high = low + Vector3::new(width, width, width);
+    let sample_size = (1 << lod.lg_sample_size()) as f32;
+
+    for (_, (count, (v1, v2, n1, n2, material))) in edges {
+      if count > 1 {
+        continue
+      }
+      let depth =
+        seams.iter()
+        .filter(|seam| {
+          on_face(seam.face, &low, &high, sample_size, &v1) &&
+          on_face(seam.face, &low, &high, sample_size, &v2)
+        })
+        .map(|seam| (1 << cmp::max(lod.lg_sample_size(), seam.lg_sample_size)) as f32)
+        .fold(0.0, f32::max);
+      if depth == 0.0 {
+        continue
+      }
+
+      // The polygon is on the left of v1 -> v2, so this winding faces away from it.
+      let u1 = v1 + -n1 * depth;
+      let u2 = v2 + -n2 * depth;
+      skirts.push(Polygon { vertices: tri(v1, u2, v2), normals: tri(n1, n2, n2), material: material });
+      skirts.push(Polygon { vertices: tri(v1, u1, u2), normals: tri(n1, n1, n2), material: material });
+    }
+    skirts
+  })
+}
+
+/// Give a chunk's polygons ids, and add grass if the chunk is one of the finest `grass_lod_count` LODs. Skirts
+/// don't get grass, since they're under the surface.
 pub fn generate<Rng: rand::Rng>(
-  voxels          : &voxel::tree::T,
-  chunk_stats     : &mut chunk_stats::T,
-  chunk_position  : &chunk::position::T,
+  polygons        : Vec<Polygon>,
+  skirts          : Vec<Polygon>,
   lod             : lod::T,
+  grass_lod_count : u32,
+  chunk_stats     : &mut chunk_stats::T,
   chunk_allocator : &Mutex<id_allocator::T<view::entity::id::Terrain>>,
   grass_allocator : &Mutex<id_allocator::T<view::entity::id::Grass>>,
   rng             : &mut Rng,
 ) -> view::chunked_terrain::T
 {
   stopwatch::time("terrain_mesh::generate", || {
-    let lg_edge_samples = lod.lg_edge_samples();
-    let lg_sample_size = lod.lg_sample_size();
-
     let mut chunked_terrain = chunked_terrain::empty();
 
-    let low = *chunk_position.as_pnt();
-    let high = low + (&Vector3::new(1, 1, 1));
-    let low =
-      Point3::new(
-        low.x << lg_edge_samples,
-        low.y << lg_edge_samples,
-        low.z << lg_edge_samples,
-      );
-    let high =
-      Point3::new(
-        high.x << lg_edge_samples,
-        high.y << lg_edge_samples,
-        high.z << lg_edge_samples,
+    for polygon in polygons {
+      let grass =
+        if polygon.material == voxel::Material::Terrain && lod.0 < grass_lod_count {
+          Some(chunked_terrain::PushGrass {
+            tex_id : rng.gen_range(0, 9),
+            id     : grass_allocator.lock().unwrap().allocate(),
+          })
+        } else {
+          None
+        };
+
+      chunked_terrain.push(
+        &mut *chunk_allocator.lock().unwrap(),
+        polygon.vertices,
+        polygon.normals,
+        polygon.material as i32,
+        grass,
       );
+    }
 
-    trace!("low {:?}", low);
-    trace!("high {:?}", high);
-
-    {
-      let mut edges = |direction, low_x, high_x, low_y, high_y, low_z, high_z| {
-        for x in range_inclusive(low_x, high_x) {
-        for y in range_inclusive(low_y, high_y) {
-        for z in range_inclusive(low_z, high_z) {
-          trace!("edge: {:?} {:?}", direction, Point3::new(x, y, z));
-          let edge =
-            dual_contouring::edge::T {
-              low_corner: Point3::new(x, y, z),
-              direction: direction,
-              lg_size: lg_sample_size,
-            };
-
-          let _ =
-            dual_contouring::edge::extract(
-              &mut voxel_storage::T { voxels: voxels },
-              &edge,
-              &mut |polygon: dual_contouring::polygon::T<voxel::Material>| {
-                let vertices = tri(polygon.vertices[0], polygon.vertices[1], polygon.vertices[2]);
-                let normals = tri(polygon.normals[0], polygon.normals[1], polygon.normals[2]);
-                let material = polygon.material as i32;
-
-                let grass =
-                  if polygon.material == voxel::Material::Terrain && lod <= lod::MAX_GRASS_LOD {
-                    Some(chunked_terrain::PushGrass {
-                      tex_id : rng.gen_range(0, 9),
-                      id     : grass_allocator.lock().unwrap().allocate(),
-                    })
-                  } else {
-                    None
-                  };
-
-                chunked_terrain.push(
-                  &mut *chunk_allocator.lock().unwrap(),
-                  vertices,
-                  normals,
-                  material,
-                  grass,
-                );
-              }
-            );
-        }}}
-      };
-
-      edges(
-        dual_contouring::edge::Direction::X,
-        low.x, high.x - 1,
-        low.y, high.y - 1,
-        low.z, high.z - 1,
-      );
-      edges(
-        dual_contouring::edge::Direction::Y,
-        low.x, high.x - 1,
-        low.y, high.y - 1,
-        low.z, high.z - 1,
-      );
-      edges(
-        dual_contouring::edge::Direction::Z,
-        low.x, high.x - 1,
-        low.y, high.y - 1,
-        low.z, high.z - 1,
+    for polygon in skirts {
+      chunked_terrain.push(
+        &mut *chunk_allocator.lock().unwrap(),
+        polygon.vertices,
+        polygon.normals,
+        polygon.material as i32,
+        None,
       );
     }
 
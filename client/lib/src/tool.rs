@@ -0,0 +1,99 @@
+//! The brush the player edits terrain with.
+
+use common::protocol::{Brush, BrushShape, BrushOperation, MIN_BRUSH_SIZE, MAX_BRUSH_SIZE};
+use common::voxel::Material;
+
+const OPERATIONS: [BrushOperation; 4] = [
+  BrushOperation::Add,
+  BrushOperation::Remove,
+  BrushOperation::Replace,
+  BrushOperation::Smooth,
+];
+
+const MATERIALS: [Material; 5] = [
+  Material::Terrain,
+  Material::Stone,
+  Material::Marble,
+  Material::Bark,
+  Material::Leaves,
+];
+
+/// Digging with a tree brush digs a sphere this big, like right click did before there were brushes.
+const DIG_RADIUS: f32 = 8.0;
+
+/// Plant trees, like left click did before there were brushes.
+pub fn new() -> Brush {
+  Brush {
+    shape     : BrushShape::Tree,
+    size      : 8.0,
+    material  : Material::Terrain,
+    operation : BrushOperation::Add,
+  }
+}
+
+/// The option after `current` in `options`, wrapping around.
+fn next<T: Copy + PartialEq>(options: &[T], current: T) -> T {
+  let i = options.iter().position(|&option| option == current).unwrap_or(0);
+  options[(i + 1) % options.len()]
+}
+
+#[allow(missing_docs)]
+pub fn next_operation(brush: &mut Brush) {
+  brush.operation = next(&OPERATIONS, brush.operation);
+}
+
+#[allow(missing_docs)]
+pub fn next_material(brush: &mut Brush) {
+  brush.material = next(&MATERIALS, brush.material);
+}
+
+/// Grow or shrink the brush, within the sizes the server accepts.
+pub fn resize(brush: &mut Brush, delta: f32) {
+  brush.size = f32::max(MIN_BRUSH_SIZE, f32::min(MAX_BRUSH_SIZE, brush.size + delta));
+}
+
+/// What right click does: remove, with the brush's shape and size. Trees dig a sphere instead, so
+/// right click still digs the way it always has until the player picks another shape.
+pub fn dig(brush: &Brush) -> Brush {
+  match brush.shape {
+    BrushShape::Tree => {
+      Brush {
+        shape     : BrushShape::Sphere,
+        size      : DIG_RADIUS,
+        operation : BrushOperation::Remove,
+        .. *brush
+      }
+    },
+    _ => {
+      Brush {
+        operation : BrushOperation::Remove,
+        .. *brush
+      }
+    },
+  }
+}
+
+/// A short description, e.g. "add sphere, size 8, stone".
+pub fn describe(brush: &Brush) -> String {
+  format!("{:?} {:?}, size {}, {:?}", brush.operation, brush.shape, brush.size, brush.material).to_lowercase()
+}
+
+#[test]
+fn cycling() {
+  let mut brush = new();
+  for _ in 0 .. OPERATIONS.len() {
+    next_operation(&mut brush);
+  }
+  assert_eq!(brush.operation, BrushOperation::Add);
+
+  resize(&mut brush, 1000.0);
+  assert_eq!(brush.size, MAX_BRUSH_SIZE);
+  resize(&mut brush, -1000.0);
+  assert_eq!(brush.size, MIN_BRUSH_SIZE);
+
+  assert_eq!(describe(&brush), "add tree, size 1, terrain");
+
+  assert_eq!(describe(&dig(&brush)), "remove sphere, size 8, terrain");
+  brush.shape = BrushShape::Cube;
+  assert_eq!(describe(&dig(&brush)), "remove cube, size 1, terrain");
+}
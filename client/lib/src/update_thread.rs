@@ -5,15 +5,15 @@ use stopwatch;
 use time;
 
 use common::protocol;
-use common::surroundings_loader;
-use common::surroundings_loader::LoadType;
 
 use audio_thread;
 use chunk;
 use chunk_stats;
 use client;
+use clipmap::LoadType;
 use lod;
 use server_update::apply_server_update;
+use settings;
 use terrain;
 use view;
 
@@ -38,6 +38,8 @@ pub fn update_thread<RecvServer, UpdateView0, UpdateView1, UpdateAudio, UpdateSe
   EnqueueTerrainLoad : FnMut(terrain::Load),
 {
   let mut chunk_stats = chunk_stats::new();
+  // The settings the clipmap and view were set up with.
+  let mut settings = client.settings.lock().unwrap().clone();
 
   'update_loop: loop {
     let should_quit = *quit.lock().unwrap();
@@ -49,12 +51,16 @@ pub fn update_thread<RecvServer, UpdateView0, UpdateView1, UpdateAudio, UpdateSe
           process_server_updates(client, recv_server, update_view0, update_audio, update_server, enqueue_terrain_load);
         });
 
+        stopwatch::time("update_settings", || {
+          update_settings(client, &mut settings, update_view1, update_server);
+        });
+
         stopwatch::time("update_surroundings", || {
-          update_surroundings(client, &mut chunk_stats, update_view1, update_server);
+          update_surroundings(client, update_view1, update_server);
         });
 
         stopwatch::time("process_voxel_updates", || {
-          process_voxel_updates(client, &mut chunk_stats, update_view1);
+          process_voxel_updates(client, &mut chunk_stats, update_view1, settings.grass_lod_count());
         });
       })
     }
@@ -64,10 +70,55 @@ pub fn update_thread<RecvServer, UpdateView0, UpdateView1, UpdateAudio, UpdateSe
   chunk_stats.output_to("vram_chunk_loads.out");
 }
 
+/// Apply any changes to the client's settings since `applied`.
+#[inline(never)]
+fn update_settings<UpdateView, UpdateServer>(
+  client        : &client::T,
+  applied       : &mut settings::T,
+  update_view   : &mut UpdateView,
+  update_server : &mut UpdateServer,
+) where
+  UpdateView   : FnMut(view::update::T),
+  UpdateServer : FnMut(protocol::ClientToServer),
+{
+  let settings = client.settings.lock().unwrap().clone();
+  if settings == *applied {
+    return
+  }
+  info!("Applying {:?}", settings);
+
+  // The view can only replace its buffers once they're empty, and the loaded chunks have grass up to
+  // the old LOD, so either change means loading the terrain all over again.
+  let vram_changed =
+    settings.terrain_vram != applied.terrain_vram ||
+    settings.grass_vram != applied.grass_vram;
+  if vram_changed || settings.grass_lod_count() != applied.grass_lod_count() {
+    let unwatched = client.terrain.lock().unwrap().unload_all(update_view);
+    for voxels in unwatched {
+      update_server(protocol::ClientToServer::UnwatchVoxels { client_id: client.id, voxels: voxels });
+    }
+    client.clipmap.lock().unwrap().clear();
+    if vram_changed {
+      update_view(
+        view::update::SetVramBudgets {
+          terrain_vram : settings.terrain_vram,
+          grass_vram   : settings.grass_vram,
+        }
+      );
+    }
+  }
+
+  client.clipmap.lock().unwrap().reconfigure(
+    settings.lod_count(),
+    settings.lod_radius,
+    settings.clipmap_radius(),
+  );
+  *applied = settings;
+}
+
 #[inline(never)]
 fn update_surroundings<UpdateView, UpdateServer>(
   client        : &client::T,
-  chunk_stats   : &mut chunk_stats::T,
   update_view   : &mut UpdateView,
   update_server : &mut UpdateServer,
 ) where
@@ -81,8 +132,12 @@ fn update_surroundings<UpdateView, UpdateServer>(
     load_position.unwrap_or_else(|| *client.player_position.lock().unwrap())
   };
   let load_position = chunk::position::of_world_position(&load_position);
-  let mut surroundings_loader = client.surroundings_loader.lock().unwrap();
-  let mut updates = surroundings_loader.updates(load_position.as_pnt()) ;
+  let mut clipmap = client.clipmap.lock().unwrap();
+  let mut updates =
+    clipmap.updates(
+      load_position.as_pnt(),
+      |&(chunk_position, lod)| client.terrain.lock().unwrap().is_meshed(&chunk_position, lod),
+    );
   loop {
     if *client.pending_terrain_requests.lock().unwrap() >= MAX_OUTSTANDING_TERRAIN_REQUESTS {
       trace!("update loop breaking");
@@ -90,49 +145,34 @@ fn update_surroundings<UpdateView, UpdateServer>(
     }
 
     let chunk_position;
+    let lod;
     let load_type;
     match updates.next() {
       None => break,
-      Some((b, l)) => {
-        chunk_position = chunk::position::of_pnt(&b);
-        load_type = l;
+      Some(((p, l), t)) => {
+        chunk_position = p;
+        lod = l;
+        load_type = t;
       },
     }
 
     debug!("chunk surroundings");
-    let distance =
-      surroundings_loader::distance_between(
-        load_position.as_pnt(),
-        chunk_position.as_pnt(),
-      );
     match load_type {
       LoadType::Load => {
         stopwatch::time("update_thread.load_chunk", || {
-          trace!("Loading distance {}", distance);
-          let new_lod = lod::of_distance(distance as u32);
-          let load_state = client.terrain.lock().unwrap().load_state(&chunk_position);
-          if load_state == Some(new_lod) {
-            debug!("Not re-loading {:?} at {:?}", chunk_position, new_lod);
-          } else {
-            load_or_request_chunk(client, chunk_stats, update_server, update_view, &chunk_position, new_lod);
-          }
-        })
-      },
-      LoadType::Downgrade => {
-        stopwatch::time("update_thread.update_chunk", || {
-          let new_lod = lod::of_distance(distance as u32);
-          let load_state = client.terrain.lock().unwrap().load_state(&chunk_position);
-          let is_downgrade = load_state.map(|lod| new_lod < lod) == Some(true);
-          if is_downgrade {
-            load_or_request_chunk(client, chunk_stats, update_server, update_view, &chunk_position, new_lod);
+          if client.terrain.lock().unwrap().is_loaded(&chunk_position, lod) {
+            debug!("Not re-loading {:?} at {:?}", chunk_position, lod);
           } else {
-            trace!("Not updating {:?} at {:?}", chunk_position, new_lod);
+            load_or_request_chunk(client, update_server, &chunk_position, lod);
           }
         })
       },
       LoadType::Unload => {
         stopwatch::time("update_thread.unload", || {
-          client.terrain.lock().unwrap().unload(update_view, &chunk_position);
+          let unwatched = client.terrain.lock().unwrap().unload(update_view, &chunk_position, lod);
+          if let Some(voxels) = unwatched {
+            update_server(protocol::ClientToServer::UnwatchVoxels { client_id: client.id, voxels: voxels });
+          }
         })
       },
     };
@@ -147,37 +187,24 @@ fn update_surroundings<UpdateView, UpdateServer>(
   }
 }
 
-fn load_or_request_chunk<UpdateServer, UpdateView>(
+fn load_or_request_chunk<UpdateServer>(
   client         : &client::T,
-  chunk_stats    : &mut chunk_stats::T,
   update_server  : &mut UpdateServer,
-  update_view    : &mut UpdateView,
   chunk_position : &chunk::position::T,
   lod            : lod::T,
 ) where
   UpdateServer: FnMut(protocol::ClientToServer),
-  UpdateView: FnMut(view::update::T),
 {
-  let mut terrain = client.terrain.lock().unwrap();
-  let rng = &mut *client.rng.lock().unwrap();
-  let r =
-    terrain.load_chunk(
-      &client.terrain_allocator,
-      &client.grass_allocator,
-      &mut *rng,
-      chunk_stats,
-      update_view,
-      chunk_position,
-      lod,
-    );
+  let r = client.terrain.lock().unwrap().load_chunk(&client.mesh_threads, chunk_position, lod);
   match r {
     Ok(()) => {},
-    Err(voxels) => {
+    Err(request) => {
       update_server(
         protocol::ClientToServer::RequestVoxels {
           time_requested_ns : time::precise_time_ns(),
           client_id       : client.id,
-          voxels          : voxels,
+          voxels          : request.voxels,
+          watch           : request.watch,
         }
       );
       *client.pending_terrain_requests.lock().unwrap() += 1;
@@ -187,21 +214,24 @@ fn load_or_request_chunk<UpdateServer, UpdateView>(
 
 #[inline(never)]
 fn process_voxel_updates<UpdateView>(
-  client      : &client::T,
-  chunk_stats : &mut chunk_stats::T,
-  update_view : &mut UpdateView,
+  client          : &client::T,
+  chunk_stats     : &mut chunk_stats::T,
+  update_view     : &mut UpdateView,
+  grass_lod_count : u32,
 ) where
   UpdateView: FnMut(view::update::T),
 {
   let terrain = &mut *client.terrain.lock().unwrap();
   let rng = &mut *client.rng.lock().unwrap();
   terrain.tick(
+    &client.mesh_threads,
     &client.terrain_allocator,
     &client.grass_allocator,
     rng,
     chunk_stats,
     update_view,
     &*client.player_position.lock().unwrap(),
+    grass_lod_count,
   );
 }
 
@@ -0,0 +1,254 @@
+//! The chat log, and the line the player types into.
+
+use cgmath::{Point2, Point3, Vector2};
+use gl;
+use gl::types::*;
+use std::collections::VecDeque;
+use time;
+use yaglw::gl_context::GLContext;
+use yaglw::texture::{Texture2D, TextureUnit};
+use yaglw::vertex_buffer::{GLArray, GLBuffer, VertexAttribData};
+use yaglw::vertex_buffer::{DrawMode, GLType};
+
+use common::color::Color4;
+use common::protocol;
+
+use vertex::TextureVertex;
+use view;
+use view::fontloader::FontLoader;
+
+/// How many lines are kept to scroll back through.
+const LOG_LENGTH: usize = 64;
+/// How many log lines are on screen at once.
+const VISIBLE_LINES: usize = 8;
+/// How long a line stays on screen after it arrives, unless the player is typing.
+const LINE_LIFETIME_NS: u64 = 10_000_000_000;
+
+const VERTICES_PER_LINE: usize = 6;
+/// The log lines, plus the input line.
+const MAX_LINES_DRAWN: usize = VISIBLE_LINES + 1;
+
+/// A line of text, rendered to a texture.
+struct Line<'a> {
+  texture     : Texture2D<'a>,
+  /// Size in pixels.
+  size        : Vector2<i32>,
+  received_ns : u64,
+}
+
+fn render_line<'a, 'b>(
+  fonts: &FontLoader,
+  gl: &'b GLContext,
+  text: &str,
+  color: Color4<u8>,
+) -> Line<'a> where
+  'a: 'b,
+{
+  // SDL_ttf can't render empty strings.
+  let text = if text.is_empty() { " " } else { text };
+  let (w, h) = fonts.sans.size(text);
+  Line {
+    texture     : fonts.sans.render(gl, text, color),
+    size        : Vector2::new(w, h),
+    received_ns : time::precise_time_ns(),
+  }
+}
+
+/// Two textured triangles covering the rectangle from `low` to `high`.
+fn quad(low: Point2<f32>, high: Point2<f32>) -> [TextureVertex; VERTICES_PER_LINE] {
+  // Text textures are stored top row first.
+  let vtx = |x, y, u, v| {
+    TextureVertex {
+      world_position   : Point3::new(x, y, 0.0),
+      texture_position : Vector2::new(u, v),
+    }
+  };
+
+  [
+    vtx(low.x, low.y, 0.0, 1.0), vtx(high.x, high.y, 1.0, 0.0), vtx(low.x, high.y, 0.0, 0.0),
+    vtx(low.x, low.y, 0.0, 1.0), vtx(high.x, low.y, 1.0, 1.0), vtx(high.x, high.y, 1.0, 0.0),
+  ]
+}
+
+/// The chat log, and the line the player types into.
+pub struct T<'a> {
+  fonts      : FontLoader,
+  /// Oldest first.
+  log        : VecDeque<Line<'a>>,
+  /// How many lines back from the newest one the log is scrolled.
+  scroll     : usize,
+  /// What the player has typed, while they're typing.
+  input      : Option<String>,
+  input_line : Option<Line<'a>>,
+  triangles  : GLArray<'a, TextureVertex>,
+}
+
+#[allow(missing_docs)]
+pub fn new<'a, 'b>(
+  gl: &'b mut GLContext,
+  shader: &view::shaders::texture::T<'a>,
+) -> T<'a> where
+  'a: 'b,
+{
+  let buffer = GLBuffer::new(gl, MAX_LINES_DRAWN * VERTICES_PER_LINE);
+  let mut triangles =
+    GLArray::new(
+      gl,
+      &shader.shader,
+      &[
+        VertexAttribData { name: "position", size: 3, unit: GLType::Float, divisor: 0 },
+        VertexAttribData { name: "texture_position", size: 2, unit: GLType::Float, divisor: 0 },
+      ],
+      DrawMode::Triangles,
+      buffer,
+    );
+
+  // Fill the buffer up front, so drawing can just overwrite it.
+  let empty = quad(Point2::new(0.0, 0.0), Point2::new(0.0, 0.0));
+  triangles.buffer.byte_buffer.bind(gl);
+  for _ in 0 .. MAX_LINES_DRAWN {
+    triangles.push(gl, &empty);
+  }
+
+  T {
+    fonts      : FontLoader::new(),
+    log        : VecDeque::new(),
+    scroll     : 0,
+    input      : None,
+    input_line : None,
+    triangles  : triangles,
+  }
+}
+
+impl<'a> T<'a> {
+  /// Add a line to the bottom of the log.
+  pub fn push<'b>(&mut self, gl: &'b GLContext, text: &str) where 'a: 'b {
+    self.log.push_back(render_line(&self.fonts, gl, text, Color4::of_rgba(0xFF, 0xFF, 0xFF, 0xFF)));
+    if self.log.len() > LOG_LENGTH {
+      self.log.pop_front();
+    }
+    // Keep showing the same lines if the log's scrolled back.
+    if self.scroll > 0 {
+      self.scroll += 1;
+    }
+    self.clamp_scroll();
+  }
+
+  #[allow(missing_docs)]
+  pub fn is_typing(&self) -> bool {
+    self.input.is_some()
+  }
+
+  #[allow(missing_docs)]
+  pub fn start_typing<'b>(&mut self, gl: &'b GLContext) where 'a: 'b {
+    self.input = Some(String::new());
+    self.render_input(gl);
+  }
+
+  /// Stop typing, and return what was typed.
+  pub fn stop_typing(&mut self) -> Option<String> {
+    self.input_line = None;
+    self.scroll = 0;
+    self.input.take()
+  }
+
+  /// Add text to the end of the input line, up to the longest message the server accepts.
+  pub fn type_text<'b>(&mut self, gl: &'b GLContext, text: &str) where 'a: 'b {
+    match self.input {
+      None => return,
+      Some(ref mut input) => {
+        let room = protocol::MAX_CHAT_LENGTH.saturating_sub(input.chars().count());
+        input.extend(text.chars().filter(|c| !c.is_control()).take(room));
+      },
+    }
+    self.render_input(gl);
+  }
+
+  /// Delete the last character of the input line.
+  pub fn backspace<'b>(&mut self, gl: &'b GLContext) where 'a: 'b {
+    match self.input {
+      None => return,
+      Some(ref mut input) => {
+        input.pop();
+      },
+    }
+    self.render_input(gl);
+  }
+
+  /// Scroll the log back (positive) or forward (negative) by some lines.
+  pub fn scroll(&mut self, lines: isize) {
+    if lines < 0 {
+      self.scroll = self.scroll.saturating_sub((-lines) as usize);
+    } else {
+      self.scroll += lines as usize;
+    }
+    self.clamp_scroll();
+  }
+
+  fn clamp_scroll(&mut self) {
+    let max_scroll = self.log.len().saturating_sub(VISIBLE_LINES);
+    if self.scroll > max_scroll {
+      self.scroll = max_scroll;
+    }
+  }
+
+  fn render_input<'b>(&mut self, gl: &'b GLContext) where 'a: 'b {
+    self.input_line =
+      self.input.as_ref().map(|input| {
+        let text = format!("> {}_", input);
+        render_line(&self.fonts, gl, &text, Color4::of_rgba(0xFF, 0xFF, 0xA0, 0xFF))
+      });
+  }
+
+  /// Draw the log in the bottom-left corner of the screen, with the input line underneath it.
+  /// N.B. This expects the texture shader to be bound, with the HUD camera.
+  pub fn draw(&mut self, gl: &mut GLContext, texture_unit: &TextureUnit, window_size: Vector2<i32>) {
+    let now = time::precise_time_ns();
+
+    // Bottom line first.
+    let mut lines: Vec<&Line> = Vec::with_capacity(MAX_LINES_DRAWN);
+    match self.input_line {
+      None => {
+        lines.extend(
+          self.log.iter().rev()
+          .take(VISIBLE_LINES)
+          .take_while(|line| now - line.received_ns < LINE_LIFETIME_NS)
+        );
+      },
+      Some(ref input_line) => {
+        lines.push(input_line);
+        lines.extend(self.log.iter().rev().skip(self.scroll).take(VISIBLE_LINES));
+      },
+    }
+
+    if lines.is_empty() {
+      return
+    }
+
+    // The HUD camera shows [-aspect, aspect] x [-1, 1].
+    let pixel = 2.0 / window_size.y as f32;
+    let left = -(window_size.x as f32 / window_size.y as f32) + 0.02;
+    // Leave room for the health bar.
+    let mut bottom = -0.85;
+    let mut vertices = Vec::with_capacity(lines.len() * VERTICES_PER_LINE);
+    for line in &lines {
+      let top = bottom + line.size.y as f32 * pixel;
+      vertices.extend_from_slice(&quad(
+        Point2::new(left, bottom),
+        Point2::new(left + line.size.x as f32 * pixel, top),
+      ));
+      bottom = top;
+    }
+
+    self.triangles.buffer.byte_buffer.bind(gl);
+    self.triangles.buffer.update(gl, 0, &vertices);
+    self.triangles.bind(gl);
+    unsafe {
+      gl::ActiveTexture(texture_unit.gl_id());
+      for (i, line) in lines.iter().enumerate() {
+        gl::BindTexture(gl::TEXTURE_2D, line.texture.handle.gl_id);
+        gl::DrawArrays(gl::TRIANGLES, (i * VERTICES_PER_LINE) as GLint, VERTICES_PER_LINE as GLsizei);
+      }
+    }
+  }
+}
@@ -1,7 +1,7 @@
 //! Font loading data structure and functions.
 
 use std::path::Path;
-use ttf;
+use view::ttf;
 
 #[allow(missing_docs)]
 pub struct FontLoader {
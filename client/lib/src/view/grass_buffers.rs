@@ -14,10 +14,13 @@ use common::fnv_map;
 use super::entity;
 use super::terrain_buffers;
 
-// VRAM bytes
-const BYTE_BUDGET: usize = 64_000_000;
-const TUFT_COST: usize = 8;
-const TUFT_BUDGET: usize = BYTE_BUDGET / TUFT_COST;
+/// VRAM bytes per tuft.
+pub const TUFT_COST: usize = 8;
+
+/// Maximum number of tufts that fit in a VRAM budget of `byte_budget` bytes.
+pub fn tuft_budget(byte_budget: usize) -> usize {
+  byte_budget / TUFT_COST
+}
 
 #[derive(Debug, Clone)]
 #[repr(C)]
@@ -37,6 +40,9 @@ pub struct T<'a> {
   to_polygon_idx: fnv_map::T<entity::id::Grass, u32>,
   of_polygon_idx: fnv_map::T<u32, entity::id::Grass>,
 
+  /// How many tufts fit.
+  capacity: usize,
+
   gl_array: yaglw::vertex_buffer::ArrayHandle<'a>,
   _instance_vertices: yaglw::vertex_buffer::GLBuffer<'a, Vertex>,
   per_tuft: yaglw::vertex_buffer::GLBuffer<'a, Entry>,
@@ -54,17 +60,19 @@ struct Vertex {
   pub model_translation : cgmath::Vector3<f32>,
 }
 
-#[allow(missing_docs)]
+/// Allocate buffers that use at most `byte_budget` bytes of VRAM.
 pub fn new<'a, 'b:'a>(
   gl: &'a mut GLContext,
   shader: &yaglw::shader::Shader<'a>,
+  byte_budget: usize,
 ) -> T<'b>
 {
   use yaglw::vertex_buffer;
 
   let gl_array = vertex_buffer::ArrayHandle::new(gl);
   let mut instance_vertices = vertex_buffer::GLBuffer::new(gl, 18);
-  let per_tuft = vertex_buffer::GLBuffer::new(gl, TUFT_BUDGET);
+  let capacity = tuft_budget(byte_budget);
+  let per_tuft = vertex_buffer::GLBuffer::new(gl, capacity);
 
   let mut vertices = Vec::new();
   {
@@ -168,6 +176,8 @@ pub fn new<'a, 'b:'a>(
     to_polygon_idx: fnv_map::new(),
     of_polygon_idx: fnv_map::new(),
 
+    capacity: capacity,
+
     gl_array: gl_array,
     _instance_vertices: instance_vertices,
     per_tuft: per_tuft,
@@ -175,20 +185,23 @@ pub fn new<'a, 'b:'a>(
 }
 
 impl<'a> T<'a> {
-  /// Add a series of entites into VRAM.
+  /// Add a series of entites into VRAM. If they don't all fit, none are added and this returns false.
   pub fn push(
     &mut self,
     gl: &mut GLContext,
     grass: &[Entry],
     grass_ids: &[entity::id::Grass],
-  ) {
+  ) -> bool {
     assert!(grass.len() == grass_ids.len());
 
+    if self.index_to_id.len() + grass.len() > self.capacity {
+      return false
+    }
+
     self.per_tuft.byte_buffer.bind(gl);
     let success: bool = self.per_tuft.push(gl, grass);
-    if !success {
-      panic!("Ran out of VRAM for grass");
-    }
+    // There's room; that was checked above.
+    assert!(success);
 
     for id in grass_ids {
       let previous = self.id_to_index.insert(*id, self.index_to_id.len());
@@ -203,13 +216,18 @@ impl<'a> T<'a> {
       let previous = self.of_polygon_idx.insert(grass.polygon_idx, *id);
       assert!(previous.is_none());
     }
+
+    true
   }
 
   // TODO: Make this take many ids as a parameter, to reduce `bind`s.
-  // Note: `id` must be present in the buffers.
-  /// Remove some entity from VRAM.
+  /// Remove some entity from VRAM. Grass that didn't fit when it was pushed is ignored.
   pub fn swap_remove(&mut self, gl: &mut GLContext, id: entity::id::Grass) {
-    let idx = (*self).id_to_index[&id];
+    let idx =
+      match self.id_to_index.get(&id) {
+        None => return,
+        Some(&idx) => idx,
+      };
     let swapped_id = self.index_to_id[self.index_to_id.len() - 1];
     self.index_to_id.swap_remove(idx);
     self.id_to_index.remove(&id);
@@ -1,9 +1,11 @@
 //! The state associated with perceiving the world state.
 
 mod camera;
+pub mod chat;
 pub mod chunked_terrain;
-mod grass_buffers;
+pub mod grass_buffers;
 pub mod entity;
+pub mod fontloader;
 pub mod light;
 mod mob_buffers;
 mod player_buffers;
@@ -11,6 +13,7 @@ mod render;
 pub mod shaders;
 pub mod terrain_buffers;
 pub mod thread;
+pub mod ttf;
 pub mod update;
 
 pub use self::render::render;
@@ -20,6 +23,7 @@ use gl;
 use gl::types::*;
 use image;
 use image::GenericImage;
+use sdl2::keyboard::Keycode;
 use std;
 use yaglw::gl_context::GLContext;
 use yaglw;
@@ -27,6 +31,9 @@ use yaglw::vertex_buffer::{GLArray, GLBuffer, GLType, DrawMode, VertexAttribData
 use yaglw::texture::{TextureUnit};
 
 use common::id_allocator;
+use hud;
+use terrain_mesh;
+use tool;
 use vertex::{ColoredVertex};
 
 /// FOV in radians
@@ -55,6 +62,8 @@ pub struct T<'a> {
   pub terrain_buffers: terrain_buffers::T<'a>,
   /// The OpenGL buffers for grass render data
   pub grass_buffers: grass_buffers::T<'a>,
+  /// Terrain meshes there wasn't room for in `terrain_buffers`, for the client to hear about.
+  pub unshown_meshes: Vec<terrain_mesh::Ids>,
   /// The OpenGL texture to sample for grass
   pub grass_texture: yaglw::texture::Texture2D<'a>,
   /// OpenGL buffers for mob render data
@@ -63,6 +72,16 @@ pub struct T<'a> {
   pub player_buffers: player_buffers::T<'a>,
   /// Hud triangles for non-text.
   pub hud_triangles: GLArray<'a, ColoredVertex>,
+  /// Hud triangles for the health bar and death screen.
+  pub status_triangles: GLArray<'a, ColoredVertex>,
+  /// What the HUD shows about the player.
+  pub status: hud::Status,
+  /// The chat log, and what the player's typing.
+  pub chat: chat::T<'a>,
+  /// The brush clicks edit terrain with.
+  pub tool: common::protocol::Brush,
+  /// How to turn the clipboard when pasting it.
+  pub placement: common::protocol::Placement,
 
   #[allow(missing_docs)]
   pub sun: light::Sun,
@@ -75,6 +94,8 @@ pub struct T<'a> {
 
   /// Whether to render HUD elements
   pub input_mode: InputMode,
+  /// Keys being held down, whose releases still need handling.
+  pub held_keys: std::collections::HashSet<Keycode>,
 
   /// Distance to near clip plane
   pub near_clip: f32,
@@ -114,16 +135,18 @@ fn load_grass_texture<'a, 'b:'a>(
   Ok(grass_texture)
 }
 
-#[allow(missing_docs)]
+/// `terrain_vram` and `grass_vram` are the bytes of VRAM to use for terrain and grass.
 pub fn new<'a>(
   mut gl: GLContext,
   window_size: cgmath::Vector2<i32>,
+  terrain_vram: usize,
+  grass_vram: usize,
 ) -> T<'a> {
   let mut texture_unit_alloc = id_allocator::new();
 
   let mut shaders = shaders::new(&mut gl, window_size);
 
-  let terrain_buffers = terrain_buffers::new(&mut gl);
+  let mut terrain_buffers = terrain_buffers::new(&mut gl, terrain_vram);
   terrain_buffers.bind_vertex_positions(
     &mut gl,
     &mut texture_unit_alloc,
@@ -167,6 +190,22 @@ pub fn new<'a>(
     )
   };
 
+  let buffer = GLBuffer::new(&mut gl, hud::STATUS_VERTICES);
+  let status_triangles = {
+    GLArray::new(
+      &mut gl,
+      &shaders.hud_color_shader.shader,
+      &[
+        VertexAttribData { name: "position", size: 3, unit: GLType::Float, divisor: 0 },
+        VertexAttribData { name: "in_color", size: 4, unit: GLType::Float, divisor: 0 },
+      ],
+      DrawMode::Triangles,
+      buffer,
+    )
+  };
+
+  let chat = chat::new(&mut gl, &shaders.texture_shader);
+
   let misc_texture_unit = texture_unit_alloc.allocate();
 
   unsafe {
@@ -203,7 +242,7 @@ pub fn new<'a>(
     gl::Uniform1i(texture_in, misc_texture_unit.glsl_id as GLint);
   }
 
-  let grass_buffers = grass_buffers::new(&mut gl, &shaders.grass_billboard.shader);
+  let grass_buffers = grass_buffers::new(&mut gl, &shaders.grass_billboard.shader, grass_vram);
   let grass_texture = load_grass_texture(&mut gl).unwrap();
 
   let empty_gl_array = yaglw::vertex_buffer::ArrayHandle::new(&gl);
@@ -217,10 +256,16 @@ pub fn new<'a>(
 
     terrain_buffers: terrain_buffers,
     grass_buffers: grass_buffers,
+    unshown_meshes: Vec::new(),
     grass_texture: grass_texture,
     mob_buffers: mob_buffers,
     player_buffers: player_buffers,
     hud_triangles: hud_triangles,
+    status_triangles: status_triangles,
+    status: hud::Status::new(),
+    chat: chat,
+    tool: tool::new(),
+    placement: common::protocol::Placement { quarter_turns: 0, mirror: false },
 
     empty_gl_array: empty_gl_array,
     misc_texture_unit: misc_texture_unit,
@@ -246,6 +291,7 @@ pub fn new<'a>(
 
     show_hud: true,
     input_mode: InputMode::Camera,
+    held_keys: std::collections::HashSet::new(),
 
     near_clip: near_clip,
     far_clip: far_clip,
@@ -113,7 +113,24 @@ pub fn render(
 
   if rndr.show_hud {
     rndr.shaders.hud_color_shader.shader.use_shader(&mut rndr.gl);
+    // HUD elements are drawn in order, on top of everything else.
+    unsafe {
+      gl::Disable(gl::DEPTH_TEST);
+    }
+    rndr.status_triangles.bind(&mut rndr.gl);
+    rndr.status_triangles.draw(&mut rndr.gl);
     rndr.hud_triangles.bind(&mut rndr.gl);
     rndr.hud_triangles.draw(&mut rndr.gl);
+
+    rndr.shaders.texture_shader.shader.use_shader(&mut rndr.gl);
+    let alpha_threshold_uniform =
+      rndr.shaders.texture_shader.shader.get_uniform_location("alpha_threshold");
+    unsafe {
+      gl::Uniform1f(alpha_threshold_uniform, 0.0);
+    }
+    rndr.chat.draw(&mut rndr.gl, &rndr.misc_texture_unit, rndr.window_size);
+    unsafe {
+      gl::Enable(gl::DEPTH_TEST);
+    }
   }
 }
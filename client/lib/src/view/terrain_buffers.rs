@@ -22,16 +22,21 @@ use std::mem;
 
 const VERTICES_PER_TRIANGLE: usize = 3;
 
-/// Maximum number of bytes to be used in VRAM
-pub const BYTE_BUDGET: usize = 64_000_000;
-const POLYGON_COST: usize = 100;
-/// Maximum number of polygons to be used in VRAM
-pub const POLYGON_BUDGET: usize = BYTE_BUDGET / POLYGON_COST;
+/// VRAM bytes per polygon.
+pub const POLYGON_COST: usize = 100;
 
 /// Number of elements in a chunk in vram.
 pub const CHUNK_LENGTH: usize = 1 << 5;
-/// The number of polygons loaded contiguously into VRAM.
-const CHUNK_BUDGET: usize = POLYGON_BUDGET / CHUNK_LENGTH;
+
+/// Maximum number of polygons that fit in a VRAM budget of `byte_budget` bytes.
+pub fn polygon_budget(byte_budget: usize) -> usize {
+  byte_budget / POLYGON_COST
+}
+
+/// The number of chunks of polygons that fit in a VRAM budget of `byte_budget` bytes.
+fn chunk_budget(byte_budget: usize) -> usize {
+  polygon_budget(byte_budget) / CHUNK_LENGTH
+}
 /// Instead of storing individual vertices, normals, etc. in VRAM, store them in chunks.
 /// This makes it much faster to unload things.
 pub struct Chunk<V>(pub [V; CHUNK_LENGTH]);
@@ -51,12 +56,20 @@ pub struct T<'a> {
   // TODO: Use yaglw's ArrayHandle.
   empty_array: GLuint,
   length: u32,
+  /// How many chunks fit.
+  capacity: usize,
 
   // Per-triangle buffers
 
   vertex_positions: BufferTexture<'a, Chunk<Triangle<Point3<GLfloat>>>>,
   normals: BufferTexture<'a, Chunk<Triangle<Vector3<GLfloat>>>>,
   materials: BufferTexture<'a, Chunk<GLint>>,
+
+  // The texture units each buffer is bound to, so the buffers can be replaced.
+
+  vertex_position_units: Vec<TextureUnit>,
+  normal_units: Vec<TextureUnit>,
+  material_units: Vec<TextureUnit>,
 }
 
 /// Phantom type for this buffer.
@@ -80,9 +93,10 @@ fn correct_size() {
   assert!(mem::size_of::<Vector3<GLfloat>>() == 3 * mem::size_of::<GLfloat>());
 }
 
-#[allow(missing_docs)]
+/// Allocate buffers that use at most `byte_budget` bytes of VRAM.
 pub fn new<'a, 'b>(
   gl: &'b mut GLContext,
+  byte_budget: usize,
 ) -> T<'a> where
   'a: 'b,
 {
@@ -95,9 +109,21 @@ pub fn new<'a, 'b>(
       empty_array
     },
     length: 0,
-    vertex_positions: BufferTexture::new(gl, gl::R32F, CHUNK_BUDGET),
-    normals: BufferTexture::new(gl, gl::R32F, CHUNK_BUDGET),
-    materials: BufferTexture::new(gl, gl::R32UI, CHUNK_BUDGET),
+    capacity: chunk_budget(byte_budget),
+    vertex_positions: BufferTexture::new(gl, gl::R32F, chunk_budget(byte_budget)),
+    normals: BufferTexture::new(gl, gl::R32F, chunk_budget(byte_budget)),
+    materials: BufferTexture::new(gl, gl::R32UI, chunk_budget(byte_budget)),
+    vertex_position_units: Vec::new(),
+    normal_units: Vec::new(),
+    material_units: Vec::new(),
+  }
+}
+
+/// Bind a buffer texture to a texture unit.
+fn bind_texture(unit: &TextureUnit, id: u32) {
+  unsafe {
+    gl::ActiveTexture(unit.gl_id());
+    gl::BindTexture(gl::TEXTURE_BUFFER, id);
   }
 }
 
@@ -111,57 +137,89 @@ impl<'a> T<'a> {
   }
 
   fn bind(
-    &self,
     texture_unit_alloc: &mut id_allocator::T<TextureUnit>,
     shader: &mut yaglw::shader::Shader,
     name: &'static str,
     id: u32,
-  ) {
+  ) -> TextureUnit {
     let unit = texture_unit_alloc.allocate();
-    unsafe {
-      gl::ActiveTexture(unit.gl_id());
-      gl::BindTexture(gl::TEXTURE_BUFFER, id);
-    }
+    bind_texture(&unit, id);
     let loc = shader.get_uniform_location(name);
     unsafe {
       gl::Uniform1i(loc, unit.glsl_id as GLint);
     }
+    unit
   }
 
   #[allow(missing_docs)]
   pub fn bind_vertex_positions(
-    &self,
+    &mut self,
     gl: &mut GLContext,
     texture_unit_alloc: &mut id_allocator::T<TextureUnit>,
     shader: &mut yaglw::shader::Shader,
   ) {
     shader.use_shader(gl);
-    self.bind(texture_unit_alloc, shader, "positions", self.vertex_positions.handle.gl_id);
+    let unit = T::bind(texture_unit_alloc, shader, "positions", self.vertex_positions.handle.gl_id);
+    self.vertex_position_units.push(unit);
   }
 
   #[allow(missing_docs)]
   pub fn bind_normals(
-    &self,
+    &mut self,
     gl: &mut GLContext,
     texture_unit_alloc: &mut id_allocator::T<TextureUnit>,
     shader: &mut yaglw::shader::Shader,
   ) {
     shader.use_shader(gl);
-    self.bind(texture_unit_alloc, shader, "normals", self.normals.handle.gl_id);
+    let unit = T::bind(texture_unit_alloc, shader, "normals", self.normals.handle.gl_id);
+    self.normal_units.push(unit);
   }
 
   #[allow(missing_docs)]
   pub fn bind_materials(
-    &self,
+    &mut self,
     gl: &mut GLContext,
     texture_unit_alloc: &mut id_allocator::T<TextureUnit>,
     shader: &mut yaglw::shader::Shader,
   ) {
     shader.use_shader(gl);
-    self.bind(texture_unit_alloc, shader, "materials", self.materials.handle.gl_id);
+    let unit = T::bind(texture_unit_alloc, shader, "materials", self.materials.handle.gl_id);
+    self.material_units.push(unit);
   }
 
-  /// Add a series of entites into VRAM.
+  /// Replace the buffers with empty ones that use at most `byte_budget` bytes of VRAM, bound to the
+  /// same texture units. Everything must have been removed first.
+  pub fn reallocate<'b>(
+    &mut self,
+    gl: &'b mut GLContext,
+    byte_budget: usize,
+  ) where
+    'a: 'b,
+  {
+    assert!(self.length == 0, "Reallocating terrain buffers that are in use");
+
+    self.capacity = chunk_budget(byte_budget);
+    self.vertex_positions = BufferTexture::new(gl, gl::R32F, chunk_budget(byte_budget));
+    self.normals = BufferTexture::new(gl, gl::R32F, chunk_budget(byte_budget));
+    self.materials = BufferTexture::new(gl, gl::R32UI, chunk_budget(byte_budget));
+
+    for unit in &self.vertex_position_units {
+      bind_texture(unit, self.vertex_positions.handle.gl_id);
+    }
+    for unit in &self.normal_units {
+      bind_texture(unit, self.normals.handle.gl_id);
+    }
+    for unit in &self.material_units {
+      bind_texture(unit, self.materials.handle.gl_id);
+    }
+  }
+
+  /// How many more chunks fit.
+  pub fn room(&self) -> usize {
+    self.capacity - self.length as usize
+  }
+
+  /// Add a chunk of polygons into VRAM. If it doesn't fit, it isn't added and this returns false.
   pub fn push(
     &mut self,
     gl        : &mut GLContext,
@@ -169,7 +227,11 @@ impl<'a> T<'a> {
     vertices  : &Chunk<Triangle<Point3<GLfloat>>>,
     normals   : &Chunk<Triangle<Vector3<GLfloat>>>,
     materials : &Chunk<GLint>,
-  ) {
+  ) -> bool {
+    if self.room() == 0 {
+      return false
+    }
+
     debug!("Insert {:?}", chunk_id);
 
     let vertices  = unsafe { std::slice::from_raw_parts(vertices.as_ptr()  as *const _, 1) };
@@ -194,9 +256,10 @@ impl<'a> T<'a> {
     assert!(success);
 
     self.length += 1;
+    true
   }
 
-  /// Remove some entity from VRAM.
+  /// Remove some entity from VRAM. Chunks that didn't fit when they were pushed are ignored.
   /// Returns the swapped ID and its VRAM index, if any.
   pub fn swap_remove(
     &mut self,
@@ -204,7 +267,11 @@ impl<'a> T<'a> {
     id: entity::id::Terrain,
   ) -> Option<(ChunkIndex, ChunkIndex)>
   {
-    let idx = self.id_to_index[&id];
+    let idx =
+      match self.id_to_index.get(&id) {
+        None => return None,
+        Some(&idx) => idx,
+      };
     let swapped_idx = self.index_to_id.len() - 1;
     let swapped_id = self.index_to_id[swapped_idx];
     self.index_to_id.swap_remove(idx);
@@ -82,7 +82,10 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
     Vector2::new(w as i32, h as i32)
   };
 
-  let mut view = view::new(gl, window_size);
+  let mut view = {
+    let settings = client.settings.lock().unwrap();
+    view::new(gl, window_size, settings.terrain_vram, settings.grass_vram)
+  };
 
   sdl.mouse().set_relative_mouse_mode(true);
 
@@ -148,6 +151,13 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
               break
             }
           }
+
+          if !view.unshown_meshes.is_empty() {
+            let mut terrain = client.terrain.lock().unwrap();
+            for ids in view.unshown_meshes.drain(..) {
+              terrain.mesh_not_shown(&ids);
+            }
+          }
         });
 
         let renders = render_timer.update(time::precise_time_ns());
@@ -2,9 +2,9 @@
 
 use std::ffi::CString;
 use std::path::Path;
-use sdl2_sys::pixels::{SDL_Color,SDL_PIXELFORMAT_ARGB8888};
-use sdl2_sys::surface::SDL_Surface;
-use sdl2_sys::surface;
+use sdl2::sys::pixels::{SDL_Color,SDL_PIXELFORMAT_ARGB8888};
+use sdl2::sys::surface::SDL_Surface;
+use sdl2::sys::surface;
 use gl;
 
 use common::color::Color4;
@@ -18,8 +18,8 @@ use yaglw::texture::Texture2D;
 pub mod ffi {
   extern crate libc;
 
-  use sdl2_sys::pixels::SDL_Color;
-  use sdl2_sys::surface::SDL_Surface;
+  use sdl2::sys::pixels::SDL_Color;
+  use sdl2::sys::surface::SDL_Surface;
 
   pub use self::libc::{c_int, c_char, c_void, c_long};
 
@@ -99,6 +99,18 @@ impl Font {
     Font { p: p }
   }
 
+  /// The size, in pixels, that `txt` renders at.
+  pub fn size(&self, txt: &str) -> (i32, i32) {
+    let c_str = CString::new(txt.as_bytes()).unwrap();
+    let ptr = c_str.as_ptr() as *const i8;
+    let mut w = 0;
+    let mut h = 0;
+    unsafe {
+      assert_eq!(ffi::TTF_SizeUTF8(self.p, ptr, &mut w, &mut h), 0);
+    }
+    (w, h)
+  }
+
   /// Color is rgba
   pub fn render<'a, 'b:'a>(
     &self,
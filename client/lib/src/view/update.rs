@@ -1,8 +1,10 @@
 //! Define the updates passed from the client to the view.
 
 use cgmath::Point3;
+use gl;
 use stopwatch;
 
+use hud;
 use terrain_mesh;
 use vertex::ColoredVertex;
 use view;
@@ -11,6 +13,7 @@ use common::index;
 
 use super::chunked_terrain;
 use super::entity;
+use super::grass_buffers;
 use super::light;
 use super::mob_buffers::VERTICES_PER_MOB;
 use super::player_buffers::VERTICES_PER_PLAYER;
@@ -22,16 +25,40 @@ pub enum T {
 
   /// Update a player mesh.
   UpdatePlayer(entity::id::Player, [ColoredVertex; VERTICES_PER_PLAYER]),
+  /// Remove a player mesh.
+  RemovePlayer(entity::id::Player),
   /// Update a mob mesh.
   UpdateMob(entity::id::Mob, [ColoredVertex; VERTICES_PER_MOB]),
+  /// Remove a mob mesh.
+  RemoveMob(entity::id::Mob),
 
   /// Update the sun.
   SetSun(light::Sun),
 
+  /// Update the player's health bar.
+  SetHealth {
+    /// The player's current health.
+    health     : u32,
+    /// The most health the player can have.
+    max_health : u32,
+  },
+  /// Show or hide the death screen.
+  SetDead(bool),
+  /// Add a line to the chat log.
+  ChatLine(String),
+
   /// Add a terrain chunk to the view.
   LoadMesh (Box<chunked_terrain::T>),
   /// Remove a terrain entity.
   UnloadMesh(terrain_mesh::Ids),
+  /// Replace the terrain and grass buffers with ones of a different size. All the terrain must have
+  /// been unloaded first.
+  SetVramBudgets {
+    /// Bytes of VRAM for terrain.
+    terrain_vram : usize,
+    /// Bytes of VRAM for grass.
+    grass_vram   : usize,
+  },
   /// Treat a series of updates as an atomic operation.
   Atomic(Vec<T>),
 }
@@ -47,9 +74,27 @@ pub fn apply_client_to_view(view: &mut view::T, up: T) {
     T::UpdateMob(id, triangles) => {
       view.mob_buffers.insert(&mut view.gl, id, &triangles);
     },
+    T::RemoveMob(id) => {
+      view.mob_buffers.remove(&mut view.gl, id);
+    },
     T::UpdatePlayer(id, triangles) => {
       view.player_buffers.insert(&mut view.gl, id, &triangles);
     },
+    T::RemovePlayer(id) => {
+      view.player_buffers.remove(&mut view.gl, id);
+    },
+    T::SetHealth { health, max_health } => {
+      view.status.health = health;
+      view.status.max_health = max_health;
+      hud::update_status(view);
+    },
+    T::SetDead(dead) => {
+      view.status.dead = dead;
+      hud::update_status(view);
+    },
+    T::ChatLine(text) => {
+      view.chat.push(&view.gl, &text);
+    },
     T::SetSun(sun) => {
       match view.input_mode {
         view::InputMode::Sun => {},
@@ -61,14 +106,29 @@ pub fn apply_client_to_view(view: &mut view::T, up: T) {
     T::LoadMesh(mesh) => {
       stopwatch::time("add_chunk", move || {
         let mesh = *mesh;
-        for i in 0 .. mesh.chunk_count() {
-          view.terrain_buffers.push(
-            &mut view.gl,
-            mesh.ids[i],
-            &mesh.vertex_coordinates[i],
-            &mesh.normals[i],
-            &mesh.materials[i],
+        // Settings keep the loaded LODs inside the budgets, so this shouldn't happen, but a full buffer
+        // shouldn't take the client down if it does. The client hears the mesh wasn't shown, so it
+        // doesn't count the chunk as meshed. The unload for this mesh ignores the ids it never got.
+        if view.terrain_buffers.room() < mesh.chunk_count() {
+          warn!("Out of terrain VRAM; not showing {} chunks", mesh.chunk_count());
+          view.unshown_meshes.push(
+            terrain_mesh::Ids {
+              chunk_ids: mesh.ids,
+              grass_ids: mesh.grass.ids,
+            }
           );
+          return
+        }
+        for i in 0 .. mesh.chunk_count() {
+          let success =
+            view.terrain_buffers.push(
+              &mut view.gl,
+              mesh.ids[i],
+              &mesh.vertex_coordinates[i],
+              &mesh.normals[i],
+              &mesh.materials[i],
+            );
+          assert!(success);
         }
         let mut grass_entries = Vec::with_capacity(mesh.grass.len());
         for i in 0 .. mesh.grass.len() {
@@ -83,11 +143,15 @@ pub fn apply_client_to_view(view: &mut view::T, up: T) {
             }
           );
         }
-        view.grass_buffers.push(
-          &mut view.gl,
-          grass_entries.as_ref(),
-          mesh.grass.ids.as_ref(),
-        );
+        let success =
+          view.grass_buffers.push(
+            &mut view.gl,
+            grass_entries.as_ref(),
+            mesh.grass.ids.as_ref(),
+          );
+        if !success {
+          warn!("Out of grass VRAM; showing {} chunks without grass", mesh.chunk_count());
+        }
       })
     },
     T::UnloadMesh(terrain_mesh::Ids { chunk_ids, grass_ids }) => {
@@ -110,6 +174,14 @@ pub fn apply_client_to_view(view: &mut view::T, up: T) {
         }
       }
     },
+    T::SetVramBudgets { terrain_vram, grass_vram } => {
+      view.terrain_buffers.reallocate(&mut view.gl, terrain_vram);
+      view.grass_buffers = grass_buffers::new(&mut view.gl, &view.shaders.grass_billboard.shader, grass_vram);
+      // Rebinding the terrain buffers changed the active texture unit.
+      unsafe {
+        gl::ActiveTexture(view.misc_texture_unit.gl_id());
+      }
+    },
     T::Atomic(updates) => {
       for up in updates {
         apply_client_to_view(view, up);
@@ -3,10 +3,7 @@
 /// Phantom types to use with `id`.
 mod types {
   #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
-  pub struct Player;
-
-  #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
-  pub struct Mob;
+  pub struct Entity;
 }
 
 #[allow(missing_docs)]
@@ -20,7 +17,7 @@ pub mod id {
     value: u32,
   }
 
-  fn of_u32<U>(value: u32) -> T<U> {
+  pub fn of_u32<U>(value: u32) -> T<U> {
     T {
       value: value,
       units: std::marker::PhantomData,
@@ -47,6 +44,10 @@ pub mod id {
     }
   }
 
-  pub type Player = T<super::types::Player>;
-  pub type Mob = T<super::types::Mob>;
+  /// Players, mobs and everything else in the world share one ID space, on the server and the client.
+  pub type Entity = T<super::types::Entity>;
+  /// An entity that's a player.
+  pub type Player = Entity;
+  /// An entity that's a mob.
+  pub type Mob = Entity;
 }
@@ -15,6 +15,11 @@ impl IntervalTimer {
     }
   }
 
+  /// When the next interval will have elapsed.
+  pub fn next(&self) -> u64 {
+    self.next
+  }
+
   #[inline]
   /// Returns the number of intervals that have elapsed since last `update`.
   pub fn update(&mut self, current: u64) -> u64 {
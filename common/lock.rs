@@ -0,0 +1,229 @@
+//! Mutexes that have to be locked in a fixed order.
+//!
+//! Every `Mutex` here has a `Level`. A thread may only lock a mutex whose level ranks higher than
+//! every level it already holds, so no two threads can ever wait on each other's locks.
+//! Debug builds check this on every lock, and panic with the stack traces of both acquisitions
+//! when the order is broken. Release builds skip the check.
+
+use backtrace::Backtrace;
+use std;
+use std::cell::{Cell, RefCell};
+use std::ops::{Deref, DerefMut};
+use std::sync::{LockResult, PoisonError};
+use std::time::Duration;
+
+/// Where a lock sits in the hierarchy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Level {
+  /// Locks with a lower rank have to be taken first.
+  pub rank : u32,
+  /// What the lock is called in violation reports.
+  pub name : &'static str,
+}
+
+struct Held {
+  token    : u64,
+  level    : Level,
+  acquired : Backtrace,
+}
+
+thread_local! {
+  static HELD: RefCell<Vec<Held>> = RefCell::new(Vec::new());
+  static NEXT_TOKEN: Cell<u64> = Cell::new(0);
+}
+
+/// Check that this thread can take a lock at `level`, and remember that it has.
+/// Returns a token to forget it by.
+fn acquire(level: Level) -> u64 {
+  if !cfg!(debug_assertions) {
+    return 0
+  }
+
+  let acquired = Backtrace::new_unresolved();
+  HELD.with(|held| {
+    let mut held = held.borrow_mut();
+    for other in held.iter_mut() {
+      if other.level.rank >= level.rank {
+        other.acquired.resolve();
+        let mut now = acquired.clone();
+        now.resolve();
+        panic!(
+          "Lock order violation: locking {:?} (rank {}) while holding {:?} (rank {}).\n\
+           {:?} was locked at:\n{:?}\n\
+           {:?} is being locked at:\n{:?}",
+          level.name, level.rank, other.level.name, other.level.rank,
+          other.level.name, other.acquired,
+          level.name, now,
+        );
+      }
+    }
+
+    let token = NEXT_TOKEN.with(|next| { let token = next.get(); next.set(token + 1); token });
+    held.push(Held { token: token, level: level, acquired: acquired });
+    token
+  })
+}
+
+fn release(token: u64) {
+  if !cfg!(debug_assertions) {
+    return
+  }
+
+  HELD.with(|held| {
+    let mut held = held.borrow_mut();
+    // Guards can be dropped in any order.
+    match held.iter().position(|held| held.token == token) {
+      None => {},
+      Some(i) => { held.remove(i); },
+    }
+  })
+}
+
+/// A `std::sync::Mutex` with a place in the lock hierarchy.
+pub struct Mutex<T> {
+  level : Level,
+  inner : std::sync::Mutex<T>,
+}
+
+/// Unlocks its `Mutex` when dropped.
+pub struct MutexGuard<'a, T: 'a> {
+  // Taken out by `Condvar::wait_timeout`.
+  inner : Option<std::sync::MutexGuard<'a, T>>,
+  level : Level,
+  token : u64,
+}
+
+fn wrap<'a, T>(
+  level : Level,
+  token : u64,
+  inner : LockResult<std::sync::MutexGuard<'a, T>>,
+) -> LockResult<MutexGuard<'a, T>> {
+  let guard = |inner| MutexGuard { inner: Some(inner), level: level, token: token };
+  match inner {
+    Ok(inner) => Ok(guard(inner)),
+    Err(err) => Err(PoisonError::new(guard(err.into_inner()))),
+  }
+}
+
+impl<T> Mutex<T> {
+  #[allow(missing_docs)]
+  pub fn new(level: Level, t: T) -> Mutex<T> {
+    Mutex {
+      level : level,
+      inner : std::sync::Mutex::new(t),
+    }
+  }
+
+  /// Block until the lock is ours. Same as `std::sync::Mutex::lock`, except that debug builds panic
+  /// if this thread holds a lock it should have taken after this one.
+  pub fn lock(&self) -> LockResult<MutexGuard<T>> {
+    let token = acquire(self.level);
+    wrap(self.level, token, self.inner.lock())
+  }
+}
+
+/// A `std::sync::Condvar`, for this module's `Mutex`es.
+pub struct Condvar {
+  inner : std::sync::Condvar,
+}
+
+impl Condvar {
+  #[allow(missing_docs)]
+  pub fn new() -> Condvar {
+    Condvar {
+      inner : std::sync::Condvar::new(),
+    }
+  }
+
+  /// Wake up one thread waiting on this.
+  pub fn notify_one(&self) {
+    self.inner.notify_one();
+  }
+
+  /// Unlock `guard`'s mutex and wait until this is notified, or `timeout` has passed, and lock it again.
+  pub fn wait_timeout<'a, T>(&self, mut guard: MutexGuard<'a, T>, timeout: Duration) -> LockResult<MutexGuard<'a, T>> {
+    let level = guard.level;
+    let inner = guard.inner.take().unwrap();
+    // While we wait, we don't hold the lock.
+    drop(guard);
+
+    let result = self.inner.wait_timeout(inner, timeout);
+    let token = acquire(level);
+    let inner =
+      match result {
+        Ok((inner, _)) => Ok(inner),
+        Err(err) => Err(PoisonError::new(err.into_inner().0)),
+      };
+    wrap(level, token, inner)
+  }
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+  type Target = T;
+  fn deref(&self) -> &T {
+    self.inner.as_ref().unwrap().deref()
+  }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+  fn deref_mut(&mut self) -> &mut T {
+    self.inner.as_mut().unwrap().deref_mut()
+  }
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+  fn drop(&mut self) {
+    release(self.token);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  const OUTER: Level = Level { rank: 0, name: "outer" };
+  const INNER: Level = Level { rank: 1, name: "inner" };
+
+  #[test]
+  fn in_order() {
+    let outer = Mutex::new(OUTER, 1);
+    let inner = Mutex::new(INNER, 2);
+    let a = outer.lock().unwrap();
+    let b = inner.lock().unwrap();
+    assert_eq!(*a + *b, 3);
+  }
+
+  #[test]
+  fn released_out_of_order() {
+    let outer = Mutex::new(OUTER, ());
+    let inner = Mutex::new(INNER, ());
+    let a = outer.lock().unwrap();
+    let b = inner.lock().unwrap();
+    drop(a);
+    drop(b);
+    // Nothing is held any more, so starting from the inner lock is fine.
+    let _b = inner.lock().unwrap();
+  }
+
+  #[cfg(debug_assertions)]
+  #[test]
+  #[should_panic(expected = "Lock order violation")]
+  fn out_of_order() {
+    let outer = Mutex::new(OUTER, ());
+    let inner = Mutex::new(INNER, ());
+    let _b = inner.lock().unwrap();
+    let _a = outer.lock().unwrap();
+  }
+
+  #[test]
+  fn wait_releases() {
+    let outer = Mutex::new(OUTER, ());
+    let inner = Mutex::new(INNER, ());
+    let condvar = Condvar::new();
+    let b = inner.lock().unwrap();
+    let b = condvar.wait_timeout(b, Duration::from_millis(1)).unwrap();
+    drop(b);
+    // The wait didn't leave a stale record of the inner lock behind.
+    let _a = outer.lock().unwrap();
+  }
+}
@@ -3,6 +3,7 @@
 #![deny(missing_docs)]
 #![deny(warnings)]
 
+extern crate backtrace;
 extern crate cgmath;
 extern crate collision;
 extern crate fnv;
@@ -17,7 +18,6 @@ extern crate stopwatch;
 extern crate time;
 extern crate voxel_data;
 
-pub mod closure_series;
 pub mod color;
 pub mod cube_shell;
 pub mod entity;
@@ -26,8 +26,10 @@ pub mod fnv_set;
 pub mod id_allocator;
 pub mod index;
 pub mod interval_timer;
+pub mod lock;
 pub mod protocol;
 pub mod range_abs;
+pub mod ray;
 pub mod socket;
 pub mod surroundings_loader;
 pub mod voxel;
@@ -8,10 +8,29 @@ use std::ops::Add;
 use entity;
 use voxel;
 
+/// The longest chat message the server accepts, in characters.
+pub const MAX_CHAT_LENGTH: usize = 256;
+/// The smallest brush size the server accepts.
+pub const MIN_BRUSH_SIZE: f32 = 1.0;
+/// The biggest brush size the server accepts.
+pub const MAX_BRUSH_SIZE: f32 = 16.0;
+
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
 /// Unique client ID.
 pub struct ClientId(u32);
 
+impl ClientId {
+  #[allow(missing_docs)]
+  pub fn of_u32(value: u32) -> ClientId {
+    ClientId(value)
+  }
+
+  #[allow(missing_docs)]
+  pub fn to_u32(self) -> u32 {
+    self.0
+  }
+}
+
 impl Default for ClientId {
   fn default() -> ClientId {
     ClientId(0)
@@ -27,8 +46,22 @@ impl Add<u32> for ClientId {
   }
 }
 
+/// Given to each client along with its ID, so the server can tell that the client's messages
+/// really come from it. Client IDs are easy to guess; secrets aren't.
+pub type ClientSecret = u64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// What clients actually send: a message, and who it's from.
+pub struct Signed {
+  /// None until the server has leased the client an ID.
+  pub sender  : Option<(ClientId, ClientSecret)>,
+  #[allow(missing_docs)]
+  pub message : ClientToServer,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 /// Messages the client sends to the server.
+/// The server ignores messages about clients or players that aren't the sender's own.
 pub enum ClientToServer {
   /// Notify the server that the client exists, and provide a "return address".
   Init(String),
@@ -52,11 +85,86 @@ pub enum ClientToServer {
     client_id       : ClientId,
     /// The bounds of the voxels to fetch.
     voxels          : Vec<voxel::bounds::T>,
+    /// Whether to keep sending the client edits around these voxels, until it unwatches them.
+    watch           : bool,
   },
-  /// Brush-remove where the player's looking.
-  Add(entity::id::Player),
-  /// Brush-add at where the player's looking.
-  Remove(entity::id::Player),
+  /// Stop sending edits around voxels the client asked to watch, unless it's still watching others nearby.
+  UnwatchVoxels {
+    #[allow(missing_docs)]
+    client_id : ClientId,
+    /// The same voxels the client asked to watch.
+    voxels    : Vec<voxel::bounds::T>,
+  },
+  /// Apply a brush where the player's looking.
+  Brush(entity::id::Player, Brush),
+  /// Undo the player's latest terrain edit.
+  Undo(entity::id::Player),
+  /// Redo the player's latest undone terrain edit.
+  Redo(entity::id::Player),
+  /// Set a corner of the player's selection to the voxel they're looking at.
+  Select(entity::id::Player, SelectionCorner),
+  /// Copy the player's selection to their clipboard.
+  Copy(entity::id::Player),
+  /// Paste the player's clipboard where they're looking.
+  Paste(entity::id::Player, Placement),
+  /// Say something in chat. Messages starting with `/` are commands for the server.
+  Chat(entity::id::Player, String),
+}
+
+/// The shape of a brush, centered where it's applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[allow(missing_docs)]
+pub enum BrushShape {
+  Sphere,
+  Cube,
+  Cylinder,
+  /// A trunk, branches and leaves, growing up from where it's applied.
+  /// Adding a tree uses its own materials instead of the brush's.
+  Tree,
+}
+
+/// What a brush does to the terrain inside its shape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BrushOperation {
+  /// Fill with the brush's material.
+  Add,
+  /// Dig out.
+  Remove,
+  /// Change the material of what's already solid, without changing its shape.
+  Replace,
+  /// Round off bumps and fill in dips.
+  Smooth,
+}
+
+/// A configurable terrain-editing tool.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Brush {
+  #[allow(missing_docs)]
+  pub shape     : BrushShape,
+  /// The radius (or half-width) of the shape, in voxels.
+  /// Must be between `MIN_BRUSH_SIZE` and `MAX_BRUSH_SIZE`.
+  pub size      : f32,
+  /// The material to add or replace with.
+  pub material  : voxel::Material,
+  #[allow(missing_docs)]
+  pub operation : BrushOperation,
+}
+
+/// The two opposite corners of a selected box of voxels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[allow(missing_docs)]
+pub enum SelectionCorner {
+  First,
+  Second,
+}
+
+/// How to turn a clipboard before pasting it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Placement {
+  /// Quarter turns counterclockwise around the y axis, seen from above.
+  pub quarter_turns : u8,
+  /// Flip along the x axis, before turning.
+  pub mirror        : bool,
 }
 
 /// Why a block is being sent to a client.
@@ -82,18 +190,35 @@ pub enum Collision {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 /// Messages the server sends to the client.
 pub enum ServerToClient {
-  /// Provide the client a unique id to tag its messages.
-  LeaseId(ClientId),
+  /// Provide the client a unique id to tag its messages, and the secret to sign them with.
+  LeaseId(ClientId, ClientSecret),
   /// Ping
   Ping,
 
   /// Complete an AddPlayer request.
   PlayerAdded(entity::id::Player, Point3<f32>),
+  /// A player was put back at the given position, after dying or leaving the world.
+  PlayerRespawned(entity::id::Player, Point3<f32>),
+  /// A player died. They'll be respawned after a delay.
+  PlayerDied(entity::id::Player),
+  /// A player's health changed.
+  UpdateHealth {
+    /// The player whose health changed.
+    player     : entity::id::Player,
+    /// The player's current health. Zero means dead.
+    health     : u32,
+    /// The most health the player can have.
+    max_health : u32,
+  },
 
   /// Update a player's position.
   UpdatePlayer(entity::id::Player, Aabb3<f32>),
+  /// A player has left the world.
+  RemovePlayer(entity::id::Player),
   /// Update the client's view of a mob with a given mesh.
   UpdateMob(entity::id::Mob, Aabb3<f32>),
+  /// A mob has left the world.
+  RemoveMob(entity::id::Mob),
   /// The sun as a [0, 1) portion of its cycle.
   UpdateSun(f32),
 
@@ -106,4 +231,14 @@ pub enum ServerToClient {
   },
   /// A collision happened.
   Collision(Collision),
+
+  /// A player said something in chat.
+  Chat {
+    /// The player who said it.
+    sender : entity::id::Player,
+    /// What they said.
+    text   : String,
+  },
+  /// A line of text from the server to just this client, e.g. a command's reply.
+  Notice(String),
 }
@@ -0,0 +1,86 @@
+//! Ray intersection tests.
+
+use cgmath::{Vector3, InnerSpace};
+use collision::{Aabb3, Ray3};
+use std;
+
+/// Where a ray passes through a box.
+/// Distances are in units of the ray's direction vector.
+#[derive(Debug, Clone, Copy)]
+pub struct AabbHit {
+  /// Where the ray enters the box. This is zero if the ray starts inside the box.
+  pub enter: f32,
+  /// Where the ray leaves the box.
+  pub exit: f32,
+  /// The outward normal of the face the ray enters through.
+  pub normal: Vector3<f32>,
+}
+
+/// Cast a ray against a box, using the slab method.
+pub fn cast_aabb(ray: &Ray3<f32>, aabb: &Aabb3<f32>) -> Option<AabbHit> {
+  let origin = [ray.origin.x, ray.origin.y, ray.origin.z];
+  let direction = [ray.direction.x, ray.direction.y, ray.direction.z];
+  let low = [aabb.min.x, aabb.min.y, aabb.min.z];
+  let high = [aabb.max.x, aabb.max.y, aabb.max.z];
+  let axis = |i| {
+    match i {
+      0 => Vector3::new(1.0, 0.0, 0.0),
+      1 => Vector3::new(0.0, 1.0, 0.0),
+      _ => Vector3::new(0.0, 0.0, 1.0),
+    }
+  };
+
+  let mut enter = 0.0;
+  let mut exit = std::f32::INFINITY;
+  let mut normal = -ray.direction.normalize();
+  for i in 0 .. 3 {
+    if direction[i].abs() < 1e-12 {
+      if origin[i] < low[i] || origin[i] > high[i] {
+        return None
+      }
+      continue
+    }
+
+    let mut t1 = (low[i] - origin[i]) / direction[i];
+    let mut t2 = (high[i] - origin[i]) / direction[i];
+    let mut face = -axis(i);
+    if t1 > t2 {
+      std::mem::swap(&mut t1, &mut t2);
+      face = axis(i);
+    }
+
+    if t1 > enter {
+      enter = t1;
+      normal = face;
+    }
+    exit = f32::min(exit, t2);
+
+    if enter > exit {
+      return None
+    }
+  }
+
+  Some(AabbHit {
+    enter: enter,
+    exit: exit,
+    normal: normal,
+  })
+}
+
+#[test]
+fn hit_and_miss() {
+  use cgmath::Point3;
+
+  let aabb = Aabb3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+
+  let hit = cast_aabb(&Ray3::new(Point3::new(0.5, 4.0, 0.5), Vector3::new(0.0, -1.0, 0.0)), &aabb).unwrap();
+  assert_eq!(hit.enter, 3.0);
+  assert_eq!(hit.exit, 4.0);
+  assert_eq!(hit.normal, Vector3::new(0.0, 1.0, 0.0));
+
+  let hit = cast_aabb(&Ray3::new(Point3::new(0.5, 0.5, 0.5), Vector3::new(1.0, 0.0, 0.0)), &aabb).unwrap();
+  assert_eq!(hit.enter, 0.0);
+
+  assert!(cast_aabb(&Ray3::new(Point3::new(2.0, 4.0, 0.5), Vector3::new(0.0, -1.0, 0.0)), &aabb).is_none());
+  assert!(cast_aabb(&Ray3::new(Point3::new(0.5, 4.0, 0.5), Vector3::new(0.0, 1.0, 0.0)), &aabb).is_none());
+}
@@ -7,6 +7,7 @@ pub use voxel_data::bounds;
 pub use voxel_data::impls::surface_vertex::T::*;
 pub use voxel_data::impls::surface_vertex::of_field;
 pub use voxel_data::impls::surface_vertex::unwrap;
+pub use voxel_data::impls::surface_vertex::{SurfaceStruct, Vertex, Normal};
 
 #[allow(missing_docs)]
 pub type T = voxel_data::impls::surface_vertex::T<Material>;
@@ -23,6 +24,14 @@ pub enum Material {
   Marble = 5,
 }
 
+/// The material at a voxel's low corner.
+pub fn material_of(voxel: &T) -> Material {
+  match *voxel {
+    Volume(material) => material,
+    Surface(ref voxel) => voxel.corner,
+  }
+}
+
 #[allow(missing_docs)]
 pub mod tree {
   use voxel_data;
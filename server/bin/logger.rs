@@ -0,0 +1,66 @@
+//! Logging that takes its filters from `RUST_LOG`, like `env_logger`, but whose level can be
+//! changed from the console while the server runs.
+
+use env_logger;
+use log;
+use log::{LogLevelFilter, LogMetadata, LogRecord, MaxLogLevelFilter};
+use std;
+use std::sync::{Arc, Mutex};
+
+struct Logger {
+  env   : env_logger::Logger,
+  /// Overrides `env`'s filters once it's been set from the console.
+  level : Arc<Mutex<Option<LogLevelFilter>>>,
+}
+
+impl log::Log for Logger {
+  fn enabled(&self, metadata: &LogMetadata) -> bool {
+    match *self.level.lock().unwrap() {
+      None => self.env.enabled(metadata),
+      Some(level) => metadata.level() <= level,
+    }
+  }
+
+  fn log(&self, record: &LogRecord) {
+    match *self.level.lock().unwrap() {
+      None => self.env.log(record),
+      Some(level) => {
+        if record.level() <= level {
+          eprintln!("{}:{}: {}", record.level(), record.location().module_path(), record.args());
+        }
+      },
+    }
+  }
+}
+
+/// Changes the level of the installed logger.
+pub struct Handle {
+  level : Arc<Mutex<Option<LogLevelFilter>>>,
+  max   : MaxLogLevelFilter,
+}
+
+impl Handle {
+  /// Log everything at `level` and above, from every module.
+  pub fn set_level(&self, level: LogLevelFilter) {
+    *self.level.lock().unwrap() = Some(level);
+    self.max.set(level);
+  }
+}
+
+/// Install the logger.
+pub fn init() -> Handle {
+  let mut builder = env_logger::LogBuilder::new();
+  if let Ok(filters) = std::env::var("RUST_LOG") {
+    builder.parse(&filters);
+  }
+  let env = builder.build();
+
+  let level = Arc::new(Mutex::new(None));
+  let mut handle = None;
+  log::set_logger(|max| {
+    max.set(env.filter());
+    handle = Some(Handle { level: level.clone(), max: max });
+    Box::new(Logger { env: env, level: level.clone() })
+  }).unwrap();
+  handle.unwrap()
+}
@@ -7,50 +7,131 @@ extern crate env_logger;
 extern crate nanomsg;
 #[macro_use]
 extern crate log;
-extern crate thread_scoped;
 
 extern crate server_lib;
 
+mod logger;
+
 use std::borrow::Borrow;
 use std::env;
+use std::io::BufRead;
 use std::sync::Mutex;
+use std::sync::mpsc;
+
+use server_lib::admin;
+use server_lib::control;
+
+/// Commands the console handles itself, on top of `admin::HELP`.
+const HELP: &'static str = "\
+log <level>                  log at <level> (off, error, warn, info, debug, trace) and above
+help                         show this
+quit                         stop the server";
 
 fn main() {
-  env_logger::init().unwrap();
+  let logger = logger::init();
 
+  let mut listen_url = None;
+  let mut control_address = None;
   let mut args = env::args();
   args.next().unwrap();
-  let listen_url = args.next().unwrap_or_else(|| String::from("ipc:///tmp/server.ipc"));
-  assert!(args.next().is_none());
+  while let Some(arg) = args.next() {
+    if arg == "--control" {
+      control_address = Some(args.next().expect("--control needs an address, e.g. 127.0.0.1:4444"));
+    } else {
+      assert!(listen_url.is_none(), "unexpected argument {:?}", arg);
+      listen_url = Some(arg);
+    }
+  }
+  let listen_url = listen_url.unwrap_or_else(|| String::from("ipc:///tmp/server.ipc"));
 
   info!("Listening on {}.", listen_url);
 
   let quit_signal = Mutex::new(false);
+  let (admin_requests, admin_inbox) = mpsc::channel();
 
-  let _quit_thread =
-    unsafe {
-      let quit_signal = &quit_signal;
-      thread_scoped::scoped(move || {
-        wait_for_quit();
+  std::thread::scope(|scope| {
+    let quit_signal = &quit_signal;
+    let logger = &logger;
+    scope.spawn(move || {
+      if console(logger, &admin_requests) {
         *quit_signal.lock().unwrap() = true;
         // Close all sockets.
         nanomsg::Socket::terminate();
-      })
-    };
+      }
+    });
 
-  server_lib::run(listen_url.borrow(), &quit_signal);
+    let log = |level: &str| set_log_level(logger, level);
+    server_lib::run(
+      listen_url.borrow(),
+      quit_signal,
+      admin_inbox,
+      control_address.as_ref().map(|address| control::Config { address: address, log: &log }),
+    );
+  });
 }
 
-fn wait_for_quit() {
-  loop {
-    let mut line = String::new();
-    std::io::stdin().read_line(&mut line).unwrap();
+/// Handle `log <level>`, from the console or the control socket.
+fn set_log_level(logger: &logger::Handle, level: &str) -> admin::Reply {
+  match level.parse() {
+    Ok(level) => {
+      logger.set_level(level);
+      Ok(vec!(vec!(("log", format!("{}", level).to_lowercase()))))
+    },
+    Err(_) => Err(format!("bad log level: {:?}", level)),
+  }
+}
 
-    if line == "quit\n" {
-      println!("Quitting");
-      return
-    } else {
-      println!("Unrecognized command: {:?}", line);
-    }
+/// Run commands from stdin, printing each reply in the `admin` format.
+/// Returns true if we were told to quit, and false if stdin closed or the server stopped taking commands.
+fn console(logger: &logger::Handle, admin_requests: &mpsc::Sender<admin::Request>) -> bool {
+  let stdin = std::io::stdin();
+  for line in stdin.lock().lines() {
+    let line =
+      match line {
+        Ok(line) => line,
+        Err(err) => {
+          warn!("Error reading from stdin: {:?}", err);
+          return false
+        },
+      };
+
+    let mut words = line.split_whitespace();
+    let reply =
+      match (words.next(), words.next(), words.next()) {
+        (None, _, _) => continue,
+        (Some("quit"), None, _) => {
+          println!("ok");
+          return true
+        },
+        (Some("help"), None, _) => {
+          println!("{}\n{}\nok", admin::HELP, HELP);
+          continue
+        },
+        (Some("log"), Some(level), None) => set_log_level(logger, level),
+        _ => {
+          match admin::parse(&line) {
+            Err(err) => Err(err),
+            Ok(command) => {
+              let (reply, replies) = mpsc::channel();
+              let reply =
+                admin_requests.send(admin::Request { command: command, reply: reply })
+                .map_err(|_| ())
+                .and_then(|()| replies.recv().map_err(|_| ()));
+              match reply {
+                Ok(reply) => reply,
+                Err(()) => {
+                  // The server's stopped taking commands, so there's nothing left to do here.
+                  error!("The server isn't answering commands any more.");
+                  println!("{}", admin::format(&Err(String::from("the server has stopped"))));
+                  return false
+                },
+              }
+            },
+          }
+        },
+      };
+    println!("{}", admin::format(&reply));
   }
+
+  false
 }
@@ -0,0 +1,553 @@
+//! Commands for whoever runs the server.
+//!
+//! Commands are lines of text, like `teleport 3 0 64 0`. Every reply is a list of records, one per
+//! line, each made of `key=value` fields, followed by a line that's either `ok` or `error: ...`.
+//! That way scripts can read the output of any command the same way.
+
+use cgmath::{Point3};
+use std::sync::mpsc;
+use std::sync::atomic::Ordering;
+
+use common::entity;
+use common::protocol;
+
+use mob;
+use permissions;
+use player;
+use run;
+use server;
+use spawn;
+use update_gaia;
+use world;
+use world::center;
+
+/// What the server's commands do.
+pub const HELP: &'static str = "\
+clients                      list connected clients
+players                      list players
+kick <client>                disconnect a client and remove its players
+teleport <player> <x> <y> <z> move a player to the nearest safe spot to a point
+time <fraction>              move the sun to a [0, 1) fraction of its cycle (0 is sunrise)
+save                         save the terrain now
+spawn <archetype> [<player>] spawn a mob near a player, or at the world spawn
+kill <mob>|all               remove a mob, or every mob
+stats                        show world, terrain loader and gaia queue stats
+metrics                      show tick timing, terrain size and memory, and player positions
+role <player> <role>         make a player a visitor, builder or admin
+default-role <role>          set the role new players get
+protect <name> <x0> <y0> <z0> <x1> <y1> <z1>
+                             only let admins edit voxels between two corners, inclusive
+unprotect <name>             remove a protected region
+regions                      list protected regions";
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(missing_docs)]
+pub enum Command {
+  Clients,
+  Players,
+  Kick(protocol::ClientId),
+  Teleport(entity::id::Player, Point3<f32>),
+  SetTime(f32),
+  Save,
+  Spawn {
+    archetype : String,
+    near      : Option<entity::id::Player>,
+  },
+  /// Kill one mob, or all of them.
+  Kill(Option<entity::id::Mob>),
+  Stats,
+  Metrics,
+  SetRole(entity::id::Player, permissions::Role),
+  SetDefaultRole(permissions::Role),
+  Protect(permissions::Region),
+  Unprotect(String),
+  Regions,
+}
+
+/// One line of a reply: a list of named fields.
+pub type Record = Vec<(&'static str, String)>;
+
+/// What a command did, or why it couldn't.
+pub type Reply = Result<Vec<Record>, String>;
+
+/// A command to run on the server, and where to send its reply.
+pub struct Request {
+  #[allow(missing_docs)]
+  pub command : Command,
+  #[allow(missing_docs)]
+  pub reply   : mpsc::Sender<Reply>,
+}
+
+fn parse_u32(word: Option<&str>, what: &str) -> Result<u32, String> {
+  match word {
+    None => Err(format!("missing {}", what)),
+    Some(word) => word.parse().map_err(|_| format!("bad {}: {:?}", what, word)),
+  }
+}
+
+fn parse_i32(word: Option<&str>, what: &str) -> Result<i32, String> {
+  match word {
+    None => Err(format!("missing {}", what)),
+    Some(word) => word.parse().map_err(|_| format!("bad {}: {:?}", what, word)),
+  }
+}
+
+fn parse_role(word: Option<&str>) -> Result<permissions::Role, String> {
+  match word {
+    None => Err(String::from("missing role")),
+    Some(word) => permissions::Role::parse(word),
+  }
+}
+
+fn parse_f32(word: Option<&str>, what: &str) -> Result<f32, String> {
+  match word {
+    None => Err(format!("missing {}", what)),
+    Some(word) => word.parse().map_err(|_| format!("bad {}: {:?}", what, word)),
+  }
+}
+
+/// Parse a line of input into a command.
+pub fn parse(line: &str) -> Result<Command, String> {
+  let mut words = line.split_whitespace();
+  let command =
+    match words.next() {
+      None => return Err(String::from("empty command")),
+      Some(command) => command,
+    };
+
+  let command =
+    match command {
+      "clients" => Command::Clients,
+      "players" => Command::Players,
+      "kick" => Command::Kick(protocol::ClientId::of_u32(try!(parse_u32(words.next(), "client")))),
+      "teleport" => {
+        let player = entity::id::of_u32(try!(parse_u32(words.next(), "player")));
+        let x = try!(parse_f32(words.next(), "x"));
+        let y = try!(parse_f32(words.next(), "y"));
+        let z = try!(parse_f32(words.next(), "z"));
+        Command::Teleport(player, Point3::new(x, y, z))
+      },
+      "time" => {
+        let fraction = try!(parse_f32(words.next(), "fraction"));
+        if fraction < 0.0 || fraction >= 1.0 {
+          return Err(format!("time has to be in [0, 1), not {}", fraction))
+        }
+        Command::SetTime(fraction)
+      },
+      "save" => Command::Save,
+      "spawn" => {
+        let archetype =
+          match words.next() {
+            None => return Err(String::from("missing archetype")),
+            Some(archetype) => String::from(archetype),
+          };
+        let near =
+          match words.next() {
+            None => None,
+            Some(player) => Some(entity::id::of_u32(try!(parse_u32(Some(player), "player")))),
+          };
+        Command::Spawn { archetype: archetype, near: near }
+      },
+      "kill" => {
+        match words.next() {
+          Some("all") => Command::Kill(None),
+          mob => Command::Kill(Some(entity::id::of_u32(try!(parse_u32(mob, "mob"))))),
+        }
+      },
+      "stats" => Command::Stats,
+      "metrics" => Command::Metrics,
+      "role" => {
+        let player = entity::id::of_u32(try!(parse_u32(words.next(), "player")));
+        Command::SetRole(player, try!(parse_role(words.next())))
+      },
+      "default-role" => Command::SetDefaultRole(try!(parse_role(words.next()))),
+      "protect" => {
+        let name =
+          match words.next() {
+            None => return Err(String::from("missing name")),
+            Some(name) => String::from(name),
+          };
+        let mut corner = || -> Result<Point3<i32>, String> {
+          let x = try!(parse_i32(words.next(), "x"));
+          let y = try!(parse_i32(words.next(), "y"));
+          let z = try!(parse_i32(words.next(), "z"));
+          Ok(Point3::new(x, y, z))
+        };
+        let a = try!(corner());
+        let b = try!(corner());
+        Command::Protect(permissions::Region {
+          name : name,
+          low  : Point3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)),
+          high : Point3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)),
+        })
+      },
+      "unprotect" => {
+        match words.next() {
+          None => return Err(String::from("missing name")),
+          Some(name) => Command::Unprotect(String::from(name)),
+        }
+      },
+      "regions" => Command::Regions,
+      command => return Err(format!("unknown command {:?}", command)),
+    };
+
+  match words.next() {
+    None => Ok(command),
+    Some(word) => Err(format!("unexpected {:?}", word)),
+  }
+}
+
+/// Write a reply out the way the console prints it.
+pub fn format(reply: &Reply) -> String {
+  let mut out = String::new();
+  match *reply {
+    Ok(ref records) => {
+      for record in records {
+        let fields: Vec<String> =
+          record.iter()
+          .map(|&(key, ref value)| {
+            if value.is_empty() || value.contains(char::is_whitespace) {
+              format!("{}={:?}", key, value)
+            } else {
+              format!("{}={}", key, value)
+            }
+          })
+          .collect();
+        out.push_str(&fields.join(" "));
+        out.push('\n');
+      }
+      out.push_str("ok");
+    },
+    Err(ref err) => {
+      out.push_str("error: ");
+      out.push_str(err);
+    },
+  }
+  out
+}
+
+fn position_fields(record: &mut Record, position: &Point3<f32>) {
+  record.push(("x", format!("{:.2}", position.x)));
+  record.push(("y", format!("{:.2}", position.y)));
+  record.push(("z", format!("{:.2}", position.z)));
+}
+
+fn region_record(region: &permissions::Region) -> Record {
+  vec!(
+    ("region", region.name.clone()),
+    ("low", format!("{},{},{}", region.low.x, region.low.y, region.low.z)),
+    ("high", format!("{},{},{}", region.high.x, region.high.y, region.high.z)),
+  )
+}
+
+/// Run a command.
+pub fn execute(
+  server       : &server::T,
+  gaia_updates : &update_gaia::Queue,
+  command      : Command,
+) -> Reply {
+  match command {
+    Command::Clients => {
+      let world = server.world.lock().unwrap();
+      let clients = server.clients.lock().unwrap();
+      let mut ids: Vec<protocol::ClientId> = clients.keys().cloned().collect();
+      ids.sort();
+      Ok(ids.into_iter().map(|id| {
+        let mut players: Vec<u32> =
+          world.players.iter()
+          .filter(|&(_, player)| player.client == id)
+          .map(|(&player, _)| player.to_u32())
+          .collect();
+        players.sort();
+        let players: Vec<String> = players.into_iter().map(|id| id.to_string()).collect();
+        vec!(
+          ("client", id.to_u32().to_string()),
+          ("url", clients[&id].url.clone()),
+          ("players", players.join(",")),
+        )
+      }).collect())
+    },
+    Command::Players => {
+      let world = server.world.lock().unwrap();
+      let mut ids = world.players.ids();
+      ids.sort();
+      Ok(ids.into_iter().map(|id| {
+        let player = world.players.get(id).unwrap();
+        let mut record = vec!(
+          ("player", id.to_u32().to_string()),
+          ("client", player.client.to_u32().to_string()),
+        );
+        position_fields(&mut record, world.positions.get(id).unwrap());
+        record.push(("role", String::from(player.role.name())));
+        record.push(("health", player.health.to_string()));
+        record.push(("dead", player.is_dead().to_string()));
+        record
+      }).collect())
+    },
+    Command::Kick(client) => {
+      let mut world = server.world.lock().unwrap();
+      if !server.clients.lock().unwrap().contains_key(&client) {
+        return Err(format!("no client {}", client.to_u32()))
+      }
+
+      let players: Vec<entity::id::Player> =
+        world.players.iter()
+        .filter(|&(_, player)| player.client == client)
+        .map(|(&id, _)| id)
+        .collect();
+      let mut records = Vec::new();
+      for id in players {
+        player::remove(server, &mut world, id);
+        records.push(vec!(("removed_player", id.to_u32().to_string())));
+      }
+
+      // Dropping the client closes its socket.
+      server.clients.lock().unwrap().remove(&client);
+      info!("Kicked client {:?}", client);
+      records.push(vec!(("kicked", client.to_u32().to_string())));
+      Ok(records)
+    },
+    Command::Teleport(id, target) => {
+      let size =
+        match server.world.lock().unwrap().bounds(server, id) {
+          None => return Err(format!("no player {}", id.to_u32())),
+          Some(bounds) => bounds.max - bounds.min,
+        };
+      let bounds =
+        match spawn::find_or_fallback(server, &target, &size) {
+          None => return Err(format!("nowhere safe near {:?}", target)),
+          Some(bounds) => bounds,
+        };
+
+      let mut world = server.world.lock().unwrap();
+      if world.players.get(id).is_none() {
+        return Err(format!("no player {}", id.to_u32()))
+      }
+      if !world::teleport(server, &mut world, id, &bounds) {
+        return Err(format!("{:?} is outside the world", target))
+      }
+
+      let mut record = vec!(("player", id.to_u32().to_string()));
+      position_fields(&mut record, &center(&bounds));
+      Ok(vec!(record))
+    },
+    Command::SetTime(fraction) => {
+      let mut sun = server.sun.lock().unwrap();
+      sun.set_fraction(fraction);
+      Ok(vec!(vec!(
+        ("time", format!("{:.3}", sun.fraction())),
+        ("day", sun.is_day().to_string()),
+      )))
+    },
+    Command::Save => {
+      let path = run::terrain_path();
+      match run::save_terrain(&server.terrain_loader.terrain, &path) {
+        Ok(()) => Ok(vec!(vec!(("saved", path.display().to_string())))),
+        Err(err) => Err(format!("couldn't save terrain to {}: {}", path.display(), err)),
+      }
+    },
+    Command::Spawn { archetype, near } => {
+      let archetype_id =
+        match server.mob_archetypes.iter().position(|a| a.name == archetype) {
+          None => {
+            let names: Vec<&str> = server.mob_archetypes.iter().map(|a| &a.name[..]).collect();
+            return Err(format!("no archetype {:?}; try one of {}", archetype, names.join(", ")))
+          },
+          Some(id) => id,
+        };
+
+      let near =
+        match near {
+          None => server.spawn.lock().unwrap().world_spawn,
+          Some(player) => {
+            let world = server.world.lock().unwrap();
+            match world.players.get(player) {
+              None => return Err(format!("no player {}", player.to_u32())),
+              Some(_) => *world.positions.get(player).unwrap(),
+            }
+          },
+        };
+
+      let size = server.mob_archetypes[archetype_id].size;
+      let bounds =
+        match spawn::find_or_fallback(server, &near, &size) {
+          None => return Err(format!("nowhere safe near {:?}", near)),
+          Some(bounds) => bounds,
+        };
+      match mob::add(server, &mut server.world.lock().unwrap(), archetype_id, &bounds) {
+        None => Err(format!("{:?} is outside the world", near)),
+        Some(id) => {
+          let mut record = vec!(
+            ("mob", id.to_u32().to_string()),
+            ("archetype", archetype),
+          );
+          position_fields(&mut record, &center(&bounds));
+          Ok(vec!(record))
+        },
+      }
+    },
+    Command::Kill(mob) => {
+      let mut world = server.world.lock().unwrap();
+      let mobs =
+        match mob {
+          None => world.mob_ids(),
+          Some(id) => {
+            if world.appearances.get(id) != Some(&world::Appearance::Mob) {
+              return Err(format!("no mob {}", id.to_u32()))
+            }
+            vec!(id)
+          },
+        };
+      Ok(mobs.into_iter().map(|id| {
+        mob::remove(server, &mut world, id);
+        vec!(("killed", id.to_u32().to_string()))
+      }).collect())
+    },
+    Command::Stats => {
+      let (players, mobs) = {
+        let world = server.world.lock().unwrap();
+        (world.players.ids().len(), world.mob_ids().len())
+      };
+      let clients = server.clients.lock().unwrap().len();
+      let loader = &server.terrain_loader;
+      let placeholder_blocks = loader.in_progress_terrain.lock().unwrap().blocks.len();
+      let loaded_blocks = loader.loaded.lock().unwrap().len();
+      Ok(vec!(vec!(
+        ("clients", clients.to_string()),
+        ("players", players.to_string()),
+        ("mobs", mobs.to_string()),
+        ("loaded_blocks", loaded_blocks.to_string()),
+        ("placeholder_blocks", placeholder_blocks.to_string()),
+        ("gaia_queue", gaia_updates.len().to_string()),
+      )))
+    },
+    Command::Metrics => {
+      let mut records = Vec::new();
+
+      {
+        let tick_timing = server.tick_timing.lock().unwrap();
+        let ms = |ns: u64| format!("{:.3}", ns as f64 / 1e6);
+        records.push(vec!(
+          ("ticks", tick_timing.ticks.to_string()),
+          ("tick_last_ms", ms(tick_timing.last_ns)),
+          ("tick_mean_ms", ms(tick_timing.mean_ns())),
+          ("tick_max_ms", ms(tick_timing.max_ns)),
+          ("gaia_queue", gaia_updates.len().to_string()),
+          ("terrain_voxels", server.terrain_loader.terrain.voxel_count().to_string()),
+          // Measured every `run::TERRAIN_RAM_INTERVAL`, since it walks the whole voxel tree.
+          ("terrain_bytes", server.terrain_bytes.load(Ordering::Relaxed).to_string()),
+        ));
+      }
+
+      let world = server.world.lock().unwrap();
+      let mut ids = world.players.ids();
+      ids.sort();
+      for id in ids {
+        let mut record = vec!(("player", id.to_u32().to_string()));
+        position_fields(&mut record, world.positions.get(id).unwrap());
+        records.push(record);
+      }
+
+      Ok(records)
+    },
+    Command::SetRole(id, role) => {
+      let mut world = server.world.lock().unwrap();
+      // Edits are checked with `permissions` locked (see `permissions::edit`), so don't change
+      // roles without it.
+      let mut permissions = server.permissions.lock().unwrap();
+      let player =
+        match world.players.get_mut(id) {
+          None => return Err(format!("no player {}", id.to_u32())),
+          Some(player) => player,
+        };
+      player.role = role;
+      let url = server.clients.lock().unwrap().get(&player.client).map(|client| client.url.clone());
+      url.map(|url| permissions.roles.insert(url, role));
+      try!(permissions.save());
+      info!("Player {} is now a {}", id.to_u32(), role.name());
+      Ok(vec!(vec!(("player", id.to_u32().to_string()), ("role", String::from(role.name())))))
+    },
+    Command::SetDefaultRole(role) => {
+      let mut permissions = server.permissions.lock().unwrap();
+      permissions.default_role = role;
+      try!(permissions.save());
+      Ok(vec!(vec!(("default_role", String::from(role.name())))))
+    },
+    Command::Protect(region) => {
+      let mut permissions = server.permissions.lock().unwrap();
+      let record = region_record(&region);
+      permissions.protect(region);
+      try!(permissions.save());
+      Ok(vec!(record))
+    },
+    Command::Unprotect(name) => {
+      let mut permissions = server.permissions.lock().unwrap();
+      if !permissions.unprotect(&name) {
+        return Err(format!("no region {:?}", name))
+      }
+      try!(permissions.save());
+      Ok(vec!(vec!(("unprotected", name))))
+    },
+    Command::Regions => {
+      let permissions = server.permissions.lock().unwrap();
+      let mut records = vec!(vec!(("default_role", String::from(permissions.default_role.name()))));
+      records.extend(permissions.regions.iter().map(region_record));
+      Ok(records)
+    },
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point3;
+
+  use common::entity;
+  use common::protocol;
+
+  use permissions;
+
+  use super::{format, parse, Command};
+
+  #[test]
+  fn parse_commands() {
+    assert_eq!(parse("  players "), Ok(Command::Players));
+    assert_eq!(parse("kick 3"), Ok(Command::Kick(protocol::ClientId::of_u32(3))));
+    assert_eq!(
+      parse("teleport 1 2 -3.5 4"),
+      Ok(Command::Teleport(entity::id::of_u32(1), Point3::new(2.0, -3.5, 4.0))),
+    );
+    assert_eq!(parse("kill all"), Ok(Command::Kill(None)));
+    assert_eq!(
+      parse("spawn tagger"),
+      Ok(Command::Spawn { archetype: String::from("tagger"), near: None }),
+    );
+    assert!(parse("time 1.5").is_err());
+    assert!(parse("kick").is_err());
+    assert!(parse("save now").is_err());
+    assert!(parse("dance").is_err());
+    assert_eq!(
+      parse("role 4 admin"),
+      Ok(Command::SetRole(entity::id::of_u32(4), permissions::Role::Admin)),
+    );
+    assert!(parse("role 4 king").is_err());
+    assert_eq!(
+      parse("protect castle 10 0 -5 0 20 5"),
+      Ok(Command::Protect(permissions::Region {
+        name : String::from("castle"),
+        low  : Point3::new(0, 0, -5),
+        high : Point3::new(10, 20, 5),
+      })),
+    );
+    assert!(parse("protect castle 1 2 3").is_err());
+  }
+
+  #[test]
+  fn format_replies() {
+    let reply = Ok(vec!(
+      vec!(("player", String::from("1")), ("url", String::from("a b"))),
+      vec!(("player", String::from("2")), ("url", String::new())),
+    ));
+    assert_eq!(format(&reply), "player=1 url=\"a b\"\nplayer=2 url=\"\"\nok");
+    assert_eq!(format(&Err(String::from("nope"))), "error: nope");
+  }
+}
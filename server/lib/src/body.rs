@@ -0,0 +1,296 @@
+//! Kinematic bodies: things that fall, slide to a stop, jump, and step up small ledges.
+//! Players and mobs both move this way.
+
+use cgmath::{Vector3, ElementWise};
+use collision::{Aabb3};
+use std::ops::DerefMut;
+
+use common::lock::Mutex;
+
+use entity;
+use physics;
+
+/// Downward acceleration, in world units per tick per tick.
+pub const GRAVITY: f32 = 0.1;
+/// Upward acceleration while jumping.
+const JUMP_ACCEL: f32 = 0.3;
+/// How many ticks a jump keeps pushing upward for.
+const MAX_JUMP_FUEL: u32 = 4;
+/// Bodies climb ledges up to this tall without jumping.
+pub const MAX_STEP_HEIGHT: f32 = 1.0;
+/// How much of its speed a body keeps from one tick to the next.
+const FRICTION: Vector3<f32> = Vector3 { x: 0.7, y: 0.99, z: 0.7 };
+/// How far above the terrain a body stops when it steps or lands, so rounding doesn't leave it
+/// stuck inside.
+const STEP_CLEARANCE: f32 = 1e-4;
+
+#[derive(Debug, Clone)]
+#[allow(missing_docs)]
+pub enum Collision {
+  Terrain(entity::id::Terrain),
+  Misc(entity::id::Misc),
+  /// The body tried to leave the world.
+  OutOfBounds,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Units are world coordinates per tick.
+  pub speed    : Vector3<f32>,
+  /// Acceleration on top of gravity and jumping, e.g. from walking.
+  pub accel    : Vector3<f32>,
+  /// Was the body standing on something at the end of its last step?
+  pub grounded : bool,
+  /// How much of its speed the body keeps from one tick to the next.
+  friction     : Vector3<f32>,
+  // this is depleted as we jump and replenished as we stand.
+  jump_fuel    : u32,
+  // are we currently trying to jump? (e.g. holding the key).
+  jumping      : bool,
+}
+
+/// What happened to a body in one step.
+pub struct Step {
+  /// Where the body ended up.
+  pub bounds        : Aabb3<f32>,
+  /// Everything the body ran into.
+  pub collisions    : Vec<Collision>,
+  /// If the body landed on something, how fast it was falling.
+  pub landing_speed : Option<f32>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    speed     : Vector3::new(0.0, 0.0, 0.0),
+    accel     : Vector3::new(0.0, 0.0, 0.0),
+    grounded  : false,
+    friction  : FRICTION,
+    jump_fuel : 0,
+    jumping   : false,
+  }
+}
+
+/// A body that keeps its speed until something changes it, like a mob's behavior.
+pub fn frictionless() -> T {
+  T {
+    friction : Vector3::new(1.0, 1.0, 1.0),
+    .. new()
+  }
+}
+
+impl T {
+  /// Start pushing upward. Only works from the ground, and stops by itself after a few ticks.
+  pub fn start_jump(&mut self) {
+    self.jumping = true;
+  }
+
+  /// Stop pushing upward, for a shorter jump.
+  pub fn stop_jump(&mut self) {
+    self.jumping = false;
+  }
+
+  /// Bring the body to rest.
+  pub fn stop(&mut self) {
+    self.speed = Vector3::new(0.0, 0.0, 0.0);
+    self.jumping = false;
+    self.jump_fuel = 0;
+  }
+
+  /// Move the physics object `id` by this body's speed, then speed it up by `accel`, gravity and
+  /// jumping, and slow it down by its friction.
+  pub fn step(
+    &mut self,
+    physics : &Mutex<physics::T>,
+    id      : entity::id::Misc,
+  ) -> Step {
+    let mut jump_accel = 0.0;
+    if self.jumping {
+      if self.jump_fuel > 0 {
+        self.jump_fuel -= 1;
+        jump_accel = JUMP_ACCEL;
+      } else {
+        self.jumping = false;
+      }
+    }
+
+    let mut step =
+      Step {
+        bounds        : *physics.lock().unwrap().get_bounds(id).unwrap(),
+        collisions    : Vec::new(),
+        landing_speed : None,
+      };
+
+    // TODO: This logic is dumb (isolating along components shouldn't be a thing). Change it.
+    let delta_p = self.speed;
+    if delta_p.x != 0.0 {
+      self.translate(physics, id, Vector3::new(delta_p.x, 0.0, 0.0), &mut step);
+    }
+    if delta_p.y != 0.0 {
+      self.translate(physics, id, Vector3::new(0.0, delta_p.y, 0.0), &mut step);
+    }
+    if delta_p.z != 0.0 {
+      self.translate(physics, id, Vector3::new(0.0, 0.0, delta_p.z), &mut step);
+    }
+
+    self.speed += self.accel + Vector3::new(0.0, jump_accel - GRAVITY, 0.0);
+    self.speed.mul_assign_element_wise(self.friction);
+
+    step
+  }
+
+  /// Translates the body by a vector.
+  /// If the body walks into terrain no more than `MAX_STEP_HEIGHT` above its feet, it steps up
+  /// onto it. If it falls into terrain, it lands on top of it.
+  fn translate(
+    &mut self,
+    physics         : &Mutex<physics::T>,
+    id              : entity::id::Misc,
+    requested_shift : Vector3<f32>,
+    step            : &mut Step,
+  ) {
+    let mut physics = physics.lock().unwrap();
+    let physics = physics.deref_mut();
+    let init_bounds = *physics.get_bounds(id).unwrap();
+    let requested_bounds =
+      Aabb3::new(
+        init_bounds.min + requested_shift,
+        init_bounds.max + requested_shift,
+      );
+
+    let mut collided = false;
+    match physics.translate_misc(id, requested_shift) {
+      None => {},
+      Some((_, physics::Collision::Misc(other))) => {
+        collided = true;
+        step.collisions.push(Collision::Misc(other));
+      },
+      Some((_, physics::Collision::OutOfBounds)) => {
+        collided = true;
+        step.collisions.push(Collision::OutOfBounds);
+      },
+      Some((_, physics::Collision::Terrain(terrain))) => {
+        step.collisions.push(Collision::Terrain(terrain));
+        collided = true;
+
+        // Walking can climb as far as a step, and falling can land anywhere short of the full fall,
+        // but nothing lifts a body higher than where it started.
+        let max_lift =
+          if requested_shift.y == 0.0 {
+            MAX_STEP_HEIGHT
+          } else {
+            f32::max(0.0, -requested_shift.y)
+          };
+
+        // Lift the body once, onto the top of the terrain under it.
+        physics.terrain_top(&requested_bounds).map(|top| {
+          let lift = top + STEP_CLEARANCE - requested_bounds.min.y;
+          if lift > 0.0 && lift <= max_lift {
+            // If there's no room up there either, stay put.
+            let _ = physics.translate_misc(id, requested_shift + Vector3::new(0.0, lift, 0.0));
+          }
+        });
+      },
+    }
+
+    step.bounds = *physics.get_bounds(id).unwrap();
+
+    if collided {
+      if requested_shift.y < 0.0 {
+        self.grounded = true;
+        self.jump_fuel = MAX_JUMP_FUEL;
+        step.landing_speed = Some(-requested_shift.y);
+      }
+
+      self.speed.y -= requested_shift.y;
+    } else {
+      if requested_shift.y < 0.0 {
+        self.grounded = false;
+        self.jump_fuel = 0;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3};
+  use collision::{Aabb3};
+
+  use common::lock::Mutex;
+  use common::voxel;
+
+  use entity;
+  use lock_order;
+  use physics;
+  use terrain_collision;
+  use triangle;
+
+  /// A physics world with one terrain triangle and one body-sized box, standing on the triangle
+  /// at the origin.
+  fn on(triangle: triangle::T) -> Mutex<physics::T> {
+    let mut world =
+      physics::T::new(Aabb3::new(Point3::new(-64.0, -64.0, -64.0), Point3::new(64.0, 64.0, 64.0)));
+    world.insert_terrain(
+      entity::id::of_u32(0),
+      &terrain_collision::Shape::Triangle(triangle),
+      voxel::Material::Terrain,
+    );
+    let bounds = Aabb3::new(Point3::new(0.0, 0.6, -0.5), Point3::new(1.0, 2.6, 0.5));
+    assert!(world.insert_misc(entity::id::of_u32(0), &bounds, physics::MiscKind::Placeholder));
+    Mutex::new(lock_order::PHYSICS, world)
+  }
+
+  #[test]
+  fn walk_up_slope() {
+    // Rises one unit for every two along x.
+    let world =
+      on(triangle::new(
+        Point3::new(-10.0, -5.0, -30.0),
+        Point3::new(30.0, 15.0, 0.0),
+        Point3::new(-10.0, -5.0, 30.0),
+      ));
+    let id = entity::id::of_u32(0);
+
+    let mut body = super::new();
+    body.accel = Vector3::new(0.1, 0.0, 0.0);
+    let mut bounds = *world.lock().unwrap().get_bounds(id).unwrap();
+    for _ in 0 .. 40 {
+      bounds = body.step(&world, id).bounds;
+      // Never lifted above the slope, the way stepping to the top of the whole triangle would.
+      assert!(bounds.min.y < bounds.max.x / 2.0 + 0.2, "{:?}", bounds);
+    }
+    // ... and never stopped by it.
+    assert!(bounds.min.x > 5.0, "{:?}", bounds);
+    assert!(body.grounded);
+  }
+
+  #[test]
+  fn friction() {
+    let floor =
+      triangle::new(
+        Point3::new(-30.0, 0.5, -30.0),
+        Point3::new(30.0, 0.5, 0.0),
+        Point3::new(-30.0, 0.5, 30.0),
+      );
+    let id = entity::id::of_u32(0);
+
+    let world = on(floor);
+    let mut body = super::new();
+    body.speed.x = 0.2;
+    for _ in 0 .. 10 {
+      body.step(&world, id);
+    }
+    assert!(body.speed.x < 0.01, "{:?}", body.speed);
+
+    // Mobs keep going until their behavior says otherwise.
+    let world = on(floor);
+    let mut body = super::frictionless();
+    body.speed.x = 0.2;
+    for _ in 0 .. 10 {
+      body.step(&world, id);
+    }
+    assert_eq!(body.speed.x, 0.2);
+    assert!(body.grounded);
+  }
+}
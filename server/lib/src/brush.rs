@@ -0,0 +1,602 @@
+//! Turn the brushes players send into voxel brushes.
+//!
+//! Adding and removing fill a shape with a solid material. Replacing changes the materials of the
+//! voxels already there, and leaves their shape alone. Smoothing depends on what's already there
+//! too, so it snapshots the terrain under the brush and works out every voxel's new material up
+//! front. Edits that set voxels directly, like replacing and pasting, are followed by a brush that
+//! brings the coarser voxels over them up to date.
+
+use cgmath::{Point3, Vector3, EuclideanSpace, InnerSpace};
+use collision::{Aabb3};
+use rand;
+use rand::distributions::IndependentSample;
+use std::ops::DerefMut;
+
+use common::protocol;
+use common::voxel;
+
+use entity;
+use server;
+use terrain;
+use voxel_data;
+use voxel_data::field;
+use voxel_data::mosaic;
+
+#[allow(missing_docs)]
+pub type Mosaic = Box<mosaic::T<voxel::Material> + Send>;
+
+/// What a stroke does to the terrain.
+pub enum Edit {
+  /// Reshape the terrain.
+  Brush(voxel_data::brush::T<Mosaic>),
+  /// Set voxels as they are.
+  Voxels(Vec<(voxel::bounds::T, voxel::T)>),
+}
+
+/// A brush, and where it's being applied.
+#[derive(Debug, Clone, Copy)]
+pub struct Stroke {
+  /// Who's editing.
+  pub player : entity::id::Player,
+  #[allow(missing_docs)]
+  pub center : Point3<f32>,
+  #[allow(missing_docs)]
+  pub brush  : protocol::Brush,
+}
+
+/// Check that a brush is one the server is willing to apply.
+pub fn validate(brush: &protocol::Brush) -> Result<(), String> {
+  // This is also false for NaN.
+  if !(brush.size >= protocol::MIN_BRUSH_SIZE && brush.size <= protocol::MAX_BRUSH_SIZE) {
+    return Err(format!(
+      "Brush size must be between {} and {}.",
+      protocol::MIN_BRUSH_SIZE,
+      protocol::MAX_BRUSH_SIZE,
+    ))
+  }
+
+  match (brush.shape, brush.operation) {
+    (protocol::BrushShape::Tree, protocol::BrushOperation::Replace) |
+    (protocol::BrushShape::Tree, protocol::BrushOperation::Smooth) => {
+      Err(String::from("Tree brushes can only add or remove."))
+    },
+    _ => Ok(()),
+  }
+}
+
+mod shape {
+  use cgmath::{Point3, Vector3, EuclideanSpace, InnerSpace};
+
+  use voxel_data::field;
+
+  /// An axis-aligned cube, centered on the origin.
+  pub struct Cube {
+    pub half_width: f32,
+  }
+
+  impl field::T for Cube {
+    fn density(&mut self, p: &Point3<f32>) -> f32 {
+      self.half_width - p.x.abs().max(p.y.abs()).max(p.z.abs())
+    }
+
+    fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+      let (x, y, z) = (p.x.abs(), p.y.abs(), p.z.abs());
+      if x >= y && x >= z {
+        Vector3::new(p.x.signum(), 0.0, 0.0)
+      } else if y >= z {
+        Vector3::new(0.0, p.y.signum(), 0.0)
+      } else {
+        Vector3::new(0.0, 0.0, p.z.signum())
+      }
+    }
+  }
+
+  /// An upright cylinder as tall as it is wide, centered on the origin.
+  pub struct Cylinder {
+    pub radius: f32,
+  }
+
+  impl Cylinder {
+    fn side_distance(&self, p: &Point3<f32>) -> f32 {
+      self.radius - Vector3::new(p.x, 0.0, p.z).magnitude()
+    }
+  }
+
+  impl field::T for Cylinder {
+    fn density(&mut self, p: &Point3<f32>) -> f32 {
+      self.side_distance(p).min(self.radius - p.y.abs())
+    }
+
+    fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+      if self.side_distance(p) < self.radius - p.y.abs() {
+        let mut p = *p;
+        p.y = 0.0;
+        p.to_vec().normalize()
+      } else {
+        Vector3::new(0.0, p.y.signum(), 0.0)
+      }
+    }
+  }
+
+  /// The shapes that don't bring their own materials.
+  pub enum T {
+    Sphere(field::sphere::T),
+    Cube(Cube),
+    Cylinder(Cylinder),
+  }
+
+  impl field::T for T {
+    fn density(&mut self, p: &Point3<f32>) -> f32 {
+      match *self {
+        T::Sphere(ref mut shape) => shape.density(p),
+        T::Cube(ref mut shape) => shape.density(p),
+        T::Cylinder(ref mut shape) => shape.density(p),
+      }
+    }
+
+    fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+      match *self {
+        T::Sphere(ref mut shape) => shape.normal(p),
+        T::Cube(ref mut shape) => shape.normal(p),
+        T::Cylinder(ref mut shape) => shape.normal(p),
+      }
+    }
+  }
+}
+
+/// The materials of a box of voxels, by their low corners.
+#[derive(Clone)]
+pub struct Snapshot {
+  #[allow(missing_docs)]
+  pub low       : Point3<i32>,
+  #[allow(missing_docs)]
+  pub size      : Vector3<i32>,
+  /// Ordered by x, then y, then z.
+  pub materials : Vec<voxel::Material>,
+}
+
+impl Snapshot {
+  fn index(&self, p: &Point3<i32>) -> Option<usize> {
+    let d = *p - self.low;
+    if d.x < 0 || d.y < 0 || d.z < 0 || d.x >= self.size.x || d.y >= self.size.y || d.z >= self.size.z {
+      return None
+    }
+    Some(((d.x * self.size.y + d.y) * self.size.z + d.z) as usize)
+  }
+
+  /// Everything outside the snapshot counts as empty.
+  pub fn get(&self, p: &Point3<i32>) -> voxel::Material {
+    match self.index(p) {
+      None => voxel::Material::Empty,
+      Some(i) => self.materials[i],
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn set(&mut self, p: &Point3<i32>, material: voxel::Material) {
+    let i = self.index(p).unwrap();
+    self.materials[i] = material;
+  }
+
+  fn is_solid(&self, p: &Point3<i32>) -> bool {
+    self.get(p) != voxel::Material::Empty
+  }
+
+  /// How solid the terrain is at a point, from 0 to 1, interpolated between voxel corners.
+  fn occupancy(&self, p: &Point3<f32>) -> f32 {
+    let low = Point3::new(p.x.floor(), p.y.floor(), p.z.floor());
+    let f = *p - low;
+    let low = Point3::new(low.x as i32, low.y as i32, low.z as i32);
+    let mut total = 0.0;
+    for &(dx, wx) in &[(0, 1.0 - f.x), (1, f.x)] {
+    for &(dy, wy) in &[(0, 1.0 - f.y), (1, f.y)] {
+    for &(dz, wz) in &[(0, 1.0 - f.z), (1, f.z)] {
+      if self.is_solid(&(low + Vector3::new(dx, dy, dz))) {
+        total += wx * wy * wz;
+      }
+    }}}
+    total
+  }
+
+  /// Every voxel in the snapshot, in the same order as `materials`.
+  pub fn points(&self) -> Vec<Point3<i32>> {
+    let mut points = Vec::with_capacity(self.materials.len());
+    for x in 0 .. self.size.x {
+    for y in 0 .. self.size.y {
+    for z in 0 .. self.size.z {
+      points.push(self.low + Vector3::new(x, y, z));
+    }}}
+    points
+  }
+}
+
+/// Read the terrain's materials between `low` and `high`, inclusive.
+pub fn snapshot(terrain: &terrain::T, low: Point3<i32>, high: Point3<i32>) -> Snapshot {
+  let mut snapshot =
+    Snapshot {
+      low       : low,
+      size      : high - low + Vector3::new(1, 1, 1),
+      materials : Vec::new(),
+    };
+  for p in snapshot.points() {
+    let voxel = terrain.load(&voxel::bounds::new(p.x, p.y, p.z, 0));
+    snapshot.materials.push(voxel::material_of(&voxel));
+  }
+  snapshot
+}
+
+/// Change the material of every solid voxel in `bounds` for which `inside` is true, keeping its
+/// shape. Surface voxels only have a material at their low corner, so that's what changes.
+fn replace<Load, Inside>(
+  mut load   : Load,
+  bounds     : &Aabb3<i32>,
+  material   : voxel::Material,
+  mut inside : Inside,
+) -> Vec<(voxel::bounds::T, voxel::T)>
+  where
+    Load: FnMut(&voxel::bounds::T) -> voxel::T,
+    Inside: FnMut(&Point3<i32>) -> bool,
+{
+  let mut replaced = Vec::new();
+  for x in bounds.min.x .. bounds.max.x + 1 {
+  for y in bounds.min.y .. bounds.max.y + 1 {
+  for z in bounds.min.z .. bounds.max.z + 1 {
+    if !inside(&Point3::new(x, y, z)) {
+      continue
+    }
+    let voxel_bounds = voxel::bounds::new(x, y, z, 0);
+    let voxel =
+      match load(&voxel_bounds) {
+        voxel::Volume(voxel::Material::Empty) => continue,
+        voxel::Volume(_) => voxel::Volume(material),
+        voxel::Surface(mut surface) => {
+          if surface.corner == voxel::Material::Empty {
+            continue
+          }
+          surface.corner = material;
+          voxel::Surface(surface)
+        },
+      };
+    replaced.push((voxel_bounds, voxel));
+  }}}
+  replaced
+}
+
+/// For every voxel for which `inside` is true, make it solid if most of its neighbors are, and
+/// empty otherwise. New solid voxels take their neighbors' most common material.
+fn smooth<Inside>(before: &Snapshot, mut inside: Inside) -> Snapshot
+  where Inside: FnMut(&Point3<i32>) -> bool,
+{
+  let mut after = before.clone();
+  for p in before.points() {
+    if !inside(&p) {
+      continue
+    }
+
+    let mut solid_neighbors = 0;
+    let mut counts: Vec<(voxel::Material, u32)> = Vec::new();
+    for dx in -1 .. 2 {
+    for dy in -1 .. 2 {
+    for dz in -1 .. 2 {
+      let material = before.get(&(p + Vector3::new(dx, dy, dz)));
+      if material == voxel::Material::Empty {
+        continue
+      }
+      solid_neighbors += 1;
+      match counts.iter().position(|&(m, _)| m == material) {
+        None => counts.push((material, 1)),
+        Some(i) => counts[i].1 += 1,
+      }
+    }}}
+
+    // Out of 27, counting the voxel itself.
+    let should_be_solid = solid_neighbors >= 14;
+    if should_be_solid && !before.is_solid(&p) {
+      let &(material, _) = counts.iter().max_by_key(|&&(_, count)| count).unwrap();
+      after.set(&p, material);
+    } else if !should_be_solid && before.is_solid(&p) {
+      after.set(&p, voxel::Material::Empty);
+    }
+  }
+  after
+}
+
+/// Sets voxels to precomputed materials, and leaves the rest alone.
+pub struct Remap {
+  #[allow(missing_docs)]
+  pub before : Snapshot,
+  #[allow(missing_docs)]
+  pub after  : Snapshot,
+}
+
+impl field::T for Remap {
+  fn density(&mut self, p: &Point3<f32>) -> f32 {
+    self.after.occupancy(p) - 0.5
+  }
+
+  fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+    let d = 0.5;
+    let gradient =
+      Vector3::new(
+        self.after.occupancy(&(*p + Vector3::new(d, 0.0, 0.0))) - self.after.occupancy(&(*p + Vector3::new(-d, 0.0, 0.0))),
+        self.after.occupancy(&(*p + Vector3::new(0.0, d, 0.0))) - self.after.occupancy(&(*p + Vector3::new(0.0, -d, 0.0))),
+        self.after.occupancy(&(*p + Vector3::new(0.0, 0.0, d))) - self.after.occupancy(&(*p + Vector3::new(0.0, 0.0, -d))),
+      );
+    if gradient.magnitude2() < 1e-6 {
+      Vector3::new(0.0, 1.0, 0.0)
+    } else {
+      // Density increases inward, and normals point out.
+      -gradient.normalize()
+    }
+  }
+}
+
+impl mosaic::T<voxel::Material> for Remap {
+  fn material(&mut self, p: &Point3<f32>) -> Option<voxel::Material> {
+    let p = Point3::new(p.x.round() as i32, p.y.round() as i32, p.z.round() as i32);
+    let after = self.after.get(&p);
+    if after == self.before.get(&p) {
+      None
+    } else {
+      Some(after)
+    }
+  }
+}
+
+/// A brush that brings the coarser voxels over some changed voxels up to date with them, and
+/// leaves the changed voxels as they are. Each change is a voxel's bounds, what it was, and what it
+/// is now. Returns None if none of them are the finest size.
+pub fn coarsen(
+  terrain : &terrain::T,
+  changes : &[(voxel::bounds::T, voxel::T, voxel::T)],
+) -> Option<voxel_data::brush::T<Mosaic>> {
+  let changed: Vec<_> =
+    changes.iter()
+    .filter(|&&(ref bounds, _, _)| bounds.lg_size == 0)
+    .map(|&(ref bounds, ref old, _)| (Point3::new(bounds.x, bounds.y, bounds.z), voxel::material_of(old)))
+    .collect();
+  if changed.is_empty() {
+    return None
+  }
+
+  let mut bounds = Aabb3::new(changed[0].0, changed[0].0);
+  for &(p, _) in &changed {
+    bounds.min = Point3::new(bounds.min.x.min(p.x), bounds.min.y.min(p.y), bounds.min.z.min(p.z));
+    bounds.max = Point3::new(bounds.max.x.max(p.x), bounds.max.y.max(p.y), bounds.max.z.max(p.z));
+  }
+
+  // Like smoothing, each voxel's shape depends on the materials around it.
+  let after = snapshot(terrain, bounds.min + Vector3::new(-1, -1, -1), bounds.max + Vector3::new(1, 1, 1));
+  let mut before = after.clone();
+  for &(p, old) in &changed {
+    before.set(&p, old);
+  }
+
+  Some(voxel_data::brush::T {
+    bounds: bounds,
+    mosaic: Box::new(Remap { before: before, after: after }),
+    min_lg_size: 1,
+  })
+}
+
+/// The voxel-aligned box around a sphere.
+fn bounds_around(center: Point3<f32>, r: f32) -> Aabb3<i32> {
+  let low = center + (&-Vector3::new(r, r, r));
+  let high = center + (&Vector3::new(r, r, r));
+  Aabb3::new(
+    Point3::new(low.x.floor() as i32, low.y.floor() as i32, low.z.floor() as i32),
+    Point3::new(high.x.ceil() as i32, high.y.ceil() as i32, high.z.ceil() as i32),
+  )
+}
+
+/// Trunks are at most this many times as tall as they are wide.
+const MAX_TRUNK_HEIGHT: f32 = 12.0;
+
+fn tree_trunk_radius(leaf_radius: f32) -> f32 {
+  f32::max(1.0, leaf_radius / 4.0)
+}
+
+fn tree_bounds(bottom: Point3<f32>, trunk_height: f32, leaf_radius: f32) -> Aabb3<i32> {
+  let center = bottom + Vector3::new(0.0, trunk_height / 2.0, 0.0);
+  bounds_around(center, trunk_height / 2.0 + leaf_radius + 20.0)
+}
+
+/// The bounds of a shape brush of a given size.
+fn shape_bounds(center: Point3<f32>, size: f32) -> Aabb3<i32> {
+  // The corners of a cube stick out of its bounding sphere.
+  bounds_around(center, size * 3.0f32.sqrt() + 1.0)
+}
+
+/// Everything a stroke might change, without building its brush.
+pub fn reach(stroke: &Stroke) -> Aabb3<i32> {
+  match stroke.brush.shape {
+    protocol::BrushShape::Tree => {
+      let trunk_height = MAX_TRUNK_HEIGHT * tree_trunk_radius(stroke.brush.size);
+      tree_bounds(stroke.center, trunk_height, stroke.brush.size)
+    },
+    _ => shape_bounds(stroke.center, stroke.brush.size),
+  }
+}
+
+fn tree(server: &server::T, stroke: &Stroke) -> voxel_data::brush::T<Mosaic> {
+  let mut rng = server.rng.lock().unwrap();
+  let rng = rng.deref_mut();
+
+  let leaf_radius = stroke.brush.size;
+  let trunk_radius = tree_trunk_radius(leaf_radius);
+
+  let trunk_height =
+    rand::distributions::normal::Normal::new(8.0 * trunk_radius as f64, 2.0 * trunk_radius as f64)
+    .ind_sample(rng) as f32;
+  let trunk_height =
+    f32::max(4.0 * trunk_radius, f32::min(MAX_TRUNK_HEIGHT * trunk_radius, trunk_height));
+
+  let bottom = stroke.center;
+  let tree = terrain::tree::new(rng, trunk_height, trunk_radius, leaf_radius);
+  let mosaic: Mosaic =
+    match stroke.brush.operation {
+      protocol::BrushOperation::Remove => {
+        Box::new(mosaic::solid::T {
+          field: field::translation::T {
+            translation: bottom.to_vec(),
+            field: tree,
+          },
+          material: voxel::Material::Empty,
+        })
+      },
+      // `validate` only lets trees add or remove.
+      _ => {
+        Box::new(mosaic::translation::T {
+          translation: bottom.to_vec(),
+          mosaic: tree,
+        })
+      },
+    };
+
+  voxel_data::brush::T {
+    bounds: tree_bounds(bottom, trunk_height, leaf_radius),
+    mosaic: mosaic,
+    min_lg_size: 0,
+  }
+}
+
+/// Work out what a stroke does. This reads the terrain, so it should happen just before the edit
+/// is applied.
+pub fn build(server: &server::T, stroke: &Stroke) -> Edit {
+  let size = stroke.brush.size;
+  let mut shape =
+    match stroke.brush.shape {
+      protocol::BrushShape::Sphere => shape::T::Sphere(field::sphere::T { radius: size }),
+      protocol::BrushShape::Cube => shape::T::Cube(shape::Cube { half_width: size }),
+      protocol::BrushShape::Cylinder => shape::T::Cylinder(shape::Cylinder { radius: size }),
+      protocol::BrushShape::Tree => return Edit::Brush(tree(server, stroke)),
+    };
+
+  let center = stroke.center;
+  let bounds = shape_bounds(center, size);
+  let terrain = &server.terrain_loader.terrain;
+
+  let solid = |shape, material| -> Mosaic {
+    Box::new(mosaic::solid::T {
+      field: field::translation::T {
+        translation: center.to_vec(),
+        field: shape,
+      },
+      material: material,
+    })
+  };
+
+  let mosaic =
+    match stroke.brush.operation {
+      protocol::BrushOperation::Add => solid(shape, stroke.brush.material),
+      protocol::BrushOperation::Remove => solid(shape, voxel::Material::Empty),
+      operation => {
+        let inside = |p: &Point3<i32>| {
+          let p = Point3::new(p.x as f32, p.y as f32, p.z as f32) - center.to_vec();
+          field::T::density(&mut shape, &p) >= 0.0
+        };
+        if operation == protocol::BrushOperation::Replace {
+          return Edit::Voxels(replace(|bounds| terrain.load(bounds), &bounds, stroke.brush.material, inside))
+        }
+        // Smoothing looks at each voxel's neighbors.
+        let before = snapshot(terrain, bounds.min + Vector3::new(-1, -1, -1), bounds.max + Vector3::new(1, 1, 1));
+        let after = smooth(&before, inside);
+        Box::new(Remap { before: before, after: after }) as Mosaic
+      },
+    };
+
+  Edit::Brush(voxel_data::brush::T {
+    bounds: bounds,
+    mosaic: mosaic,
+    min_lg_size: 0,
+  })
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3};
+  use collision::{Aabb3};
+
+  use common::voxel;
+  use common::voxel::Material;
+
+  use common::lock;
+
+  use terrain;
+
+  use super::{Snapshot, coarsen, replace, smooth};
+
+  fn filled(material: Material) -> Snapshot {
+    Snapshot {
+      low       : Point3::new(0, 0, 0),
+      size      : Vector3::new(3, 3, 3),
+      materials : vec!(material; 27),
+    }
+  }
+
+  #[test]
+  fn smoothing() {
+    let center = Point3::new(1, 1, 1);
+    let only_center = |p: &Point3<i32>| *p == center;
+
+    // A lone voxel gets worn away..
+    let mut lone = filled(Material::Empty);
+    lone.set(&center, Material::Stone);
+    assert_eq!(smooth(&lone, only_center).get(&center), Material::Empty);
+
+    // .. and a lone hole gets filled in with what's around it.
+    let mut hole = filled(Material::Stone);
+    hole.set(&Point3::new(0, 0, 0), Material::Bark);
+    hole.set(&center, Material::Empty);
+    assert_eq!(smooth(&hole, only_center).get(&center), Material::Stone);
+  }
+
+  #[test]
+  fn replacing() {
+    let surface =
+      voxel::SurfaceStruct {
+        surface_vertex : voxel::Vertex::of_world_vertex_in_voxel(&Point3::new(1.5, 0.25, 0.5), &voxel::bounds::new(1, 0, 0, 0)),
+        normal         : voxel::Normal::of_float_normal(&Vector3::new(0.0, 1.0, 0.0)),
+        corner         : Material::Terrain,
+      };
+    let load = |bounds: &voxel::bounds::T| {
+      match (bounds.x, bounds.y, bounds.z) {
+        (1, 0, 0) => voxel::Surface(surface),
+        (2, 0, 0) => voxel::Volume(Material::Terrain),
+        _ => voxel::Volume(Material::Empty),
+      }
+    };
+    let bounds = Aabb3::new(Point3::new(0, 0, 0), Point3::new(2, 2, 2));
+    let replaced = replace(load, &bounds, Material::Marble, |p| p.x < 2);
+    // Empty voxels stay empty, and the surface keeps its shape.
+    assert_eq!(
+      replaced,
+      vec!((voxel::bounds::new(1, 0, 0, 0), voxel::Surface(voxel::SurfaceStruct { corner: Material::Marble, .. surface })))
+    );
+  }
+
+  #[test]
+  fn coarsening() {
+    let terrain =
+      terrain::T::new(
+        terrain::Seed::new(0),
+        lock::Level { rank: 1, name: "voxels" },
+        lock::Level { rank: 2, name: "mosaic" },
+      );
+    let empty = voxel::Volume(Material::Empty);
+    let stone = voxel::Volume(Material::Stone);
+
+    // Only changes to the finest voxels need the coarser ones brushing.
+    assert!(coarsen(&terrain, &[]).is_none());
+    assert!(coarsen(&terrain, &[(voxel::bounds::new(0, 0, 0, 1), empty, stone)]).is_none());
+
+    let changes = [
+      (voxel::bounds::new(4, -2, 1, 0), empty, stone),
+      (voxel::bounds::new(1, 3, 2, 0), empty, stone),
+    ];
+    let brush = coarsen(&terrain, &changes).unwrap();
+    assert_eq!(brush.bounds, Aabb3::new(Point3::new(1, -2, 1), Point3::new(4, 3, 2)));
+    // The changed voxels are left as they are.
+    assert_eq!(brush.min_lg_size, 1);
+  }
+}
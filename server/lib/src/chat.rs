@@ -0,0 +1,244 @@
+//! Chat between players: what they're allowed to say, how often, and slash commands.
+
+use std::cmp::min;
+use time;
+
+use common::protocol;
+
+use admin;
+use entity;
+use permissions;
+use server;
+use update_gaia;
+
+/// A player can send this many messages in a burst,
+const BURST: u32 = 5;
+/// and then gets one more back every this many nanoseconds.
+const REFILL_NS: u64 = 2_000_000_000;
+
+/// What players can type after a `/`.
+const HELP: &'static str = "\
+/help             show this
+/players          list players (admins also see where they are)
+/stats            show world, terrain loader and gaia queue stats
+/prefab save NAME save your clipboard as a prefab
+/prefab load NAME load a prefab into your clipboard
+Admins can also run any server console command.";
+
+/// How many more messages a player can send right now.
+pub struct RateLimit {
+  allowance   : u32,
+  refilled_ns : u64,
+}
+
+#[allow(missing_docs)]
+pub fn rate_limit(now_ns: u64) -> RateLimit {
+  RateLimit {
+    allowance   : BURST,
+    refilled_ns : now_ns,
+  }
+}
+
+impl RateLimit {
+  /// Use up one message. Returns false if there weren't any left.
+  pub fn take(&mut self, now_ns: u64) -> bool {
+    let refills = now_ns.saturating_sub(self.refilled_ns) / REFILL_NS;
+    if refills > 0 {
+      self.allowance = min(BURST as u64, self.allowance as u64 + refills) as u32;
+      self.refilled_ns += refills * REFILL_NS;
+    }
+
+    if self.allowance == 0 {
+      return false
+    }
+    self.allowance -= 1;
+    true
+  }
+}
+
+/// Turn tabs and newlines into spaces, drop other control characters, and trim whitespace.
+fn clean(text: &str) -> String {
+  let text: String =
+    text.chars()
+    .filter_map(|c| {
+      if !c.is_control() {
+        Some(c)
+      } else if c.is_whitespace() {
+        Some(' ')
+      } else {
+        None
+      }
+    })
+    .collect();
+  String::from(text.trim())
+}
+
+/// The admin commands players who aren't admins can run from chat.
+fn player_can_run(command: &admin::Command) -> bool {
+  match *command {
+    admin::Command::Players | admin::Command::Stats => true,
+    _ => false,
+  }
+}
+
+/// Cut a reply down to what players who aren't admins can see. `players` is just the list of
+/// players, without where they are or which clients they're on.
+fn for_players(command: &admin::Command, reply: admin::Reply) -> admin::Reply {
+  match *command {
+    admin::Command::Players => {
+      reply.map(|records| {
+        records.into_iter()
+        .map(|record| record.into_iter().filter(|&(key, _)| key == "player").collect())
+        .collect()
+      })
+    },
+    _ => reply,
+  }
+}
+
+/// Run a slash command, and return the lines of its reply.
+fn command(
+  server       : &server::T,
+  gaia_updates : &update_gaia::Queue,
+  player_id    : entity::id::Player,
+  role         : permissions::Role,
+  line         : &str,
+) -> Vec<String> {
+  let mut words = line.split_whitespace();
+  let reply =
+    match words.next() {
+      None | Some("help") => return HELP.lines().map(String::from).collect(),
+      Some("prefab") => {
+        let (save, prefab) =
+          match (words.next(), words.next(), words.next()) {
+            (Some("save"), Some(prefab), None) => (true, String::from(prefab)),
+            (Some("load"), Some(prefab), None) => (false, String::from(prefab)),
+            _ => return vec!(String::from("Usage: /prefab save NAME, or /prefab load NAME")),
+          };
+        // Saving writes to the server's disk.
+        if save && role < permissions::Role::Builder {
+          return vec!(format!("A {} can't save prefabs.", role.name()))
+        }
+        // These touch the clipboard, so they go in order with copies and pastes.
+        gaia_updates.push(
+          if save {
+            update_gaia::Message::SavePrefab(player_id, prefab)
+          } else {
+            update_gaia::Message::LoadPrefab(player_id, prefab)
+          }
+        );
+        return Vec::new()
+      },
+      Some(name) => {
+        match admin::parse(line) {
+          Err(err) => Err(err),
+          Ok(command) => {
+            if role == permissions::Role::Admin {
+              admin::execute(server, gaia_updates, command)
+            } else if player_can_run(&command) {
+              let reply = admin::execute(server, gaia_updates, command.clone());
+              for_players(&command, reply)
+            } else {
+              Err(format!("/{} is only for admins", name))
+            }
+          },
+        }
+      },
+    };
+  admin::format(&reply).lines().map(String::from).collect()
+}
+
+/// Send a line of text to just one client.
+pub fn notice(server: &server::T, client: protocol::ClientId, text: String) {
+  server.clients.lock().unwrap()
+    .get_mut(&client)
+    .map(|client| client.send(protocol::ServerToClient::Notice(text)));
+}
+
+/// Handle a chat message from a player: broadcast it, or run it if it's a command.
+pub fn receive(
+  server       : &server::T,
+  gaia_updates : &update_gaia::Queue,
+  player_id    : entity::id::Player,
+  text         : String,
+) {
+  let (client, role, allowed) = {
+    let mut world = server.world.lock().unwrap();
+    // The player might have been kicked.
+    let player =
+      match world.players.get_mut(player_id) {
+        None => return,
+        Some(player) => player,
+      };
+    (player.client, player.role, player.chat.take(time::precise_time_ns()))
+  };
+
+  if !allowed {
+    notice(server, client, String::from("You're sending messages too fast."));
+    return
+  }
+
+  let text = clean(&text);
+  if text.is_empty() {
+    return
+  }
+  if text.chars().count() > protocol::MAX_CHAT_LENGTH {
+    notice(server, client, format!("Messages can be at most {} characters long.", protocol::MAX_CHAT_LENGTH));
+    return
+  }
+
+  if text.starts_with('/') {
+    info!("player {} ran {:?}", player_id.to_u32(), text);
+    for line in command(server, gaia_updates, player_id, role, &text[1..]) {
+      notice(server, client, line);
+    }
+    return
+  }
+
+  info!("<player {}> {}", player_id.to_u32(), text);
+  for (_, client) in server.clients.lock().unwrap().iter_mut() {
+    client.send(protocol::ServerToClient::Chat { sender: player_id, text: text.clone() });
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use admin;
+
+  use super::{BURST, REFILL_NS, clean, for_players, rate_limit};
+
+  #[test]
+  fn rate_limit_refills() {
+    let mut limit = rate_limit(0);
+    for _ in 0 .. BURST {
+      assert!(limit.take(0));
+    }
+    assert!(!limit.take(REFILL_NS - 1));
+    assert!(limit.take(REFILL_NS));
+    assert!(!limit.take(REFILL_NS));
+
+    // Waiting a long time doesn't bank more than a burst.
+    let later = 100 * REFILL_NS;
+    for _ in 0 .. BURST {
+      assert!(limit.take(later));
+    }
+    assert!(!limit.take(later));
+  }
+
+  #[test]
+  fn cleaning() {
+    assert_eq!(clean("  hi\tthere\u{7} \n"), "hi there");
+    assert_eq!(clean("two\nlines"), "two lines");
+    assert_eq!(clean("\r\n"), "");
+  }
+
+  #[test]
+  fn players_see_names_only() {
+    let record = vec!(("player", String::from("3")), ("client", String::from("1")), ("x", String::from("0.50")));
+    assert_eq!(
+      for_players(&admin::Command::Players, Ok(vec!(record.clone()))),
+      Ok(vec!(vec!(("player", String::from("3"))))),
+    );
+    assert_eq!(for_players(&admin::Command::Stats, Ok(vec!(record.clone()))), Ok(vec!(record)));
+  }
+}
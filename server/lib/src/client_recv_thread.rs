@@ -1,240 +1,227 @@
-use cgmath::{Point3, Vector3, EuclideanSpace};
-use collision::{Aabb3};
-use rand;
-use rand::distributions::IndependentSample;
+use cgmath::Point3;
 use std::convert::AsRef;
-use std::f32::consts::PI;
-use std::ops::DerefMut;
 use std::time::Duration;
+use rand;
 use stopwatch;
 
+use common::fnv_map;
 use common::protocol;
 use common::socket::SendSocket;
-use common::voxel;
 
+use brush;
+use chat;
 use entity;
-use player;
+use raycast;
 use server;
 use server::Client;
-use terrain;
-use voxel_data;
+use spawn;
 use update_gaia;
 use update_gaia::LoadDestination;
+use voxel_updates;
 
-fn center(bounds: &Aabb3<f32>) -> Point3<f32> {
-  (bounds.min + bounds.max.to_vec()) * 0.5
-}
+/// How far away players can use tools.
+const MAX_REACH: f32 = 512.0;
 
 fn cast(
   server: &server::T,
   player_id: entity::id::Player,
-) -> Option<voxel::bounds::T> {
+) -> Option<raycast::Hit> {
   let ray;
+  let physics_id;
   {
-    let players = server.players.lock().unwrap();
-    let player = players.get(&player_id).unwrap();
-    ray = player.forward_ray();
+    let world = server.world.lock().unwrap();
+    // The player might have been kicked.
+    let player =
+      match world.players.get(player_id) {
+        None => return None,
+        Some(player) => player,
+      };
+    ray = player.forward_ray(world.positions.get(player_id).unwrap());
+    physics_id = *world.physics_ids.get(player_id).unwrap();
   }
 
-  server.terrain_loader.terrain.voxels.lock().unwrap().cast_ray(
-    &ray,
-    &mut |bounds, voxel| {
-      match voxel {
-        &voxel::Volume(voxel::Material::Empty) => None,
-        _ => Some(bounds),
-      }
+  raycast::cast(server, &ray, MAX_REACH, &[raycast::Layer::Terrain], Some(physics_id))
+}
+
+/// The voxel containing a point.
+fn voxel_at(p: Point3<f32>) -> Point3<i32> {
+  Point3::new(p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32)
+}
+
+/// The client that signed a message, if it signed it with the right secret.
+fn sender(
+  server: &server::T,
+  signature: Option<(protocol::ClientId, protocol::ClientSecret)>,
+) -> Option<protocol::ClientId> {
+  signature.and_then(|(id, secret)| {
+    match server.clients.lock().unwrap().get(&id) {
+      Some(client) if client.secret == secret => Some(id),
+      _ => None,
     }
-  )
+  })
 }
 
-pub fn apply_client_update<UpdateGaia>(
+/// Whether `sender` can send `update`: clients can only speak for themselves and their own players.
+fn speaks_for(
   server: &server::T,
-  update_gaia: &mut UpdateGaia,
-  update: protocol::ClientToServer,
-) where
-  UpdateGaia: FnMut(update_gaia::Message),
-{
+  sender: Option<protocol::ClientId>,
+  update: &protocol::ClientToServer,
+) -> bool {
+  use common::protocol::ClientToServer::*;
+
+  let owns_player = |player| {
+    sender.is_some() &&
+    server.world.lock().unwrap().players.get(player).map(|player| player.client) == sender
+  };
+
+  match *update {
+    Init(_) => true,
+    Ping(client_id) |
+    AddPlayer(client_id) |
+    RequestVoxels { client_id, .. } |
+    UnwatchVoxels { client_id, .. } => sender == Some(client_id),
+    Walk(player_id, _) |
+    RotatePlayer(player_id, _) |
+    StartJump(player_id) |
+    StopJump(player_id) |
+    Brush(player_id, _) |
+    Undo(player_id) |
+    Redo(player_id) |
+    Select(player_id, _) |
+    Copy(player_id) |
+    Paste(player_id, _) |
+    Chat(player_id, _) => owns_player(player_id),
+  }
+}
+
+/// Tell a player why they can't do something.
+fn refuse(server: &server::T, player_id: entity::id::Player, why: String) {
+  let client = server.world.lock().unwrap().players.get(player_id).map(|player| player.client);
+  client.map(|client| chat::notice(server, client, why));
+}
+
+pub fn apply_client_update(
+  server: &server::T,
+  gaia_updates: &update_gaia::Queue,
+  signed: protocol::Signed,
+) {
   stopwatch::time("apply_client_update", move || {
+    let sender = sender(server, signed.sender);
+    let update = signed.message;
+    if !speaks_for(server, sender, &update) {
+      warn!("Dropping a message {:?} sent on someone else's behalf: {:?}", sender, update);
+      return
+    }
+
     match update {
       protocol::ClientToServer::Init(client_url) => {
         info!("Sending to {}.", client_url);
 
         let mut client =
           Client {
-            socket: SendSocket::new(client_url.as_ref(), Some(Duration::from_secs(30))),
+            socket   : SendSocket::new(client_url.as_ref(), Some(Duration::from_secs(30))),
+            url      : client_url,
+            secret   : rand::random(),
+            interest : fnv_map::new(),
           };
 
         let client_id = server.client_allocator.lock().unwrap().allocate();
-        client.send(protocol::ServerToClient::LeaseId(client_id));
+        client.send(protocol::ServerToClient::LeaseId(client_id, client.secret));
 
         server.clients.lock().unwrap().insert(client_id, client);
       },
+      // Clients can be kicked, so messages can show up from clients and players that are gone.
       protocol::ClientToServer::Ping(client_id) => {
         server.clients.lock().unwrap()
           .get_mut(&client_id)
-          .unwrap()
-          .send(protocol::ServerToClient::Ping);
+          .map(|client| client.send(protocol::ServerToClient::Ping));
       },
       protocol::ClientToServer::AddPlayer(client_id) => {
-        let mut player =
-          player::new(
-            server.player_allocator.lock().unwrap().allocate(),
-            server.misc_allocator.lock().unwrap().allocate(),
-            &server.owner_allocator,
-          );
-
-        // TODO: shift upward until outside terrain
-        let min = Point3::new(0.0, 64.0, 4.0);
-        let max = min + (&Vector3::new(1.0, 2.0, 1.0));
-        let bounds = Aabb3::new(min, max);
-        server.physics.lock().unwrap().insert_misc(player.physics_id, &bounds);
-
-        player.position = center(&bounds);
-        player.rotate_lateral(PI / 2.0);
-
-        let id = player.entity_id;
-        let pos = player.position;
-
-        server.players.lock().unwrap().insert(id, player);
-
-        let mut clients = server.clients.lock().unwrap();
-        let client = clients.get_mut(&client_id).unwrap();
-        client.send(
-          protocol::ServerToClient::PlayerAdded(id, pos)
-        );
+        if !server.clients.lock().unwrap().contains_key(&client_id) {
+          return
+        }
+        spawn::request(server, spawn::Request::Join(client_id), &mut |message| gaia_updates.push(message));
       },
       protocol::ClientToServer::StartJump(player_id) => {
-        let mut players = server.players.lock().unwrap();
-        let player = players.get_mut(&player_id).unwrap();
-        if !player.is_jumping {
-          player.is_jumping = true;
-          // this 0.3 is duplicated in a few places
-          player.accel.y = player.accel.y + 0.3;
-        }
+        let mut world = server.world.lock().unwrap();
+        world.bodies.get_mut(player_id).map(|body| body.start_jump());
       },
       protocol::ClientToServer::StopJump(player_id) => {
-        let mut players = server.players.lock().unwrap();
-        let player = players.get_mut(&player_id).unwrap();
-        if player.is_jumping {
-          player.is_jumping = false;
-          // this 0.3 is duplicated in a few places
-          player.accel.y = player.accel.y - 0.3;
-        }
+        let mut world = server.world.lock().unwrap();
+        world.bodies.get_mut(player_id).map(|body| body.stop_jump());
       },
       protocol::ClientToServer::Walk(player_id, v) => {
-        let mut players = server.players.lock().unwrap();
-        let player = players.get_mut(&player_id).unwrap();
-        player.walk(v);
+        let mut world = server.world.lock().unwrap();
+        world.players.get_mut(player_id).map(|player| player.walk(v));
       },
       protocol::ClientToServer::RotatePlayer(player_id, v) => {
-        let mut players = server.players.lock().unwrap();
-        let player = players.get_mut(&player_id).unwrap();
-        player.rotate_lateral(v.x);
-        player.rotate_vertical(v.y);
-      },
-      protocol::ClientToServer::RequestVoxels { time_requested_ns, client_id, voxels } => {
-        update_gaia(update_gaia::Message::Load(time_requested_ns, voxels, LoadDestination::Client(client_id)));
-      },
-      protocol::ClientToServer::Add(player_id) => {
-        let bounds = cast(server, player_id);
-
-        bounds.map(|bounds| {
-          let mut rng = server.rng.lock().unwrap();
-          let rng = rng.deref_mut();
-
-          let trunk_radius =
-            rand::distributions::normal::Normal::new(2.0, 0.5)
-            .ind_sample(rng);
-          let trunk_radius =
-            f64::max(1.0, f64::min(3.0, trunk_radius));
-
-          let trunk_height =
-            rand::distributions::normal::Normal::new(8.0 * trunk_radius, 2.0 * trunk_radius)
-            .ind_sample(rng);
-          let trunk_height =
-            f64::max(4.0 * trunk_radius, f64::min(12.0 * trunk_radius, trunk_height));
-
-          let leaf_radius =
-            rand::distributions::normal::Normal::new(4.0 * trunk_radius, trunk_radius)
-            .ind_sample(rng);
-          let leaf_radius =
-            f64::max(2.0 * trunk_radius, f64::min(6.0 * trunk_radius, leaf_radius));
-
-          let (low, high) = bounds.corners();
-          let mut bottom = (low + high.to_vec()) / 2.0;
-          bottom.y = low.y;
-
-          let trunk_height = trunk_height as f32;
-          let trunk_radius = trunk_radius as f32;
-          let leaf_radius = leaf_radius as f32;
-
-          let tree =
-            voxel_data::mosaic::translation::T {
-              translation: bottom.to_vec(),
-              mosaic: terrain::tree::new(rng, trunk_height, trunk_radius, leaf_radius),
-            };
-
-          let center =
-            bottom + (&Vector3::new(0.0, trunk_height / 2.0, 0.0));
-          let r = trunk_height / 2.0 + leaf_radius + 20.0;
-          let brush =
-            voxel_data::brush::T {
-              bounds:
-                Aabb3::new(
-                  {
-                    let low = center + (&-Vector3::new(r, r, r));
-                    Point3::new(low.x.floor() as i32, low.y.floor() as i32, low.z.floor() as i32)
-                  },
-                  {
-                    let high = center + (&Vector3::new(r, r, r));
-                    Point3::new(high.x.ceil() as i32, high.y.ceil() as i32, high.z.ceil() as i32)
-                  },
-                ),
-              mosaic: Box::new(tree) as Box<voxel_data::mosaic::T<voxel::Material> + Send>,
-              min_lg_size: 0,
-            };
-
-          update_gaia(update_gaia::Message::Brush(brush));
+        let mut world = server.world.lock().unwrap();
+        world.players.get_mut(player_id).map(|player| {
+          player.rotate_lateral(v.x);
+          player.rotate_vertical(v.y);
+        });
+      },
+      protocol::ClientToServer::RequestVoxels { time_requested_ns, client_id, voxels, watch } => {
+        if watch {
+          server.clients.lock().unwrap()
+            .get_mut(&client_id)
+            .map(|client| voxel_updates::watch(&mut client.interest, &voxels));
+        }
+        gaia_updates.push(update_gaia::Message::Load(time_requested_ns, voxels, LoadDestination::Client(client_id)));
+      },
+      protocol::ClientToServer::UnwatchVoxels { client_id, voxels } => {
+        server.clients.lock().unwrap()
+          .get_mut(&client_id)
+          .map(|client| voxel_updates::unwatch(&mut client.interest, &voxels));
+      },
+      protocol::ClientToServer::Brush(player_id, brush) => {
+        if let Err(err) = brush::validate(&brush) {
+          refuse(server, player_id, err);
+          return
+        }
+
+        cast(server, player_id).map(|hit| {
+          debug!("{:?} at {:?}", brush, hit.point);
+          // Whether the player can make the edit is checked as it's made.
+          let stroke = brush::Stroke { player: player_id, center: hit.point, brush: brush };
+          gaia_updates.push(update_gaia::Message::Brush(stroke));
+        });
+      },
+      protocol::ClientToServer::Undo(player_id) => {
+        gaia_updates.push(update_gaia::Message::Undo(player_id));
+      },
+      protocol::ClientToServer::Redo(player_id) => {
+        gaia_updates.push(update_gaia::Message::Redo(player_id));
+      },
+      protocol::ClientToServer::Select(player_id, corner) => {
+        cast(server, player_id).map(|hit| {
+          // The voxel that was hit, not the one in front of it.
+          let voxel = voxel_at(hit.point + -hit.normal * 0.5);
+          let client = {
+            let mut world = server.world.lock().unwrap();
+            world.players.get_mut(player_id).map(|player| {
+              player.clipboard.select(corner, voxel);
+              player.client
+            })
+          };
+          client.map(|client| {
+            chat::notice(server, client, format!("{:?} corner at ({}, {}, {}).", corner, voxel.x, voxel.y, voxel.z));
+          });
         });
       },
-      protocol::ClientToServer::Remove(player_id) => {
-        let bounds = cast(server, player_id);
-
-        bounds.map(|bounds| {
-          debug!("remove bounds {:?}", bounds);
-          let center = bounds.center();
-          let r = 8.0;
-          let sphere =
-            voxel_data::mosaic::solid::T {
-              field: voxel_data::field::translation::T {
-                translation: center.to_vec(),
-                field: voxel_data::field::sphere::T {
-                  radius: r,
-                },
-              },
-              material: voxel::Material::Empty,
-            };
-          let r = sphere.field.field.radius + 1.0;
-          let brush =
-            voxel_data::brush::T {
-              bounds:
-                Aabb3::new(
-                  {
-                    let low = sphere.field.translation + (&-Vector3::new(r, r, r));
-                    Point3::new(low.x.floor() as i32, low.y.floor() as i32, low.z.floor() as i32)
-                  },
-                  {
-                    let high = sphere.field.translation + (&Vector3::new(r, r, r));
-                    Point3::new(high.x.ceil() as i32, high.y.ceil() as i32, high.z.ceil() as i32)
-                  },
-                ),
-              mosaic: Box::new(sphere) as Box<voxel_data::mosaic::T<voxel::Material> + Send>,
-              min_lg_size: 0,
-            };
-          let brush: voxel_data::brush::T<Box<voxel_data::mosaic::T<voxel::Material> + Send>> = brush;
-          update_gaia(update_gaia::Message::Brush(brush));
+      protocol::ClientToServer::Copy(player_id) => {
+        gaia_updates.push(update_gaia::Message::Copy(player_id));
+      },
+      protocol::ClientToServer::Paste(player_id, placement) => {
+        // Where it'll land depends on the clipboard, so `update_gaia` checks it once it knows.
+        cast(server, player_id).map(|hit| {
+          let target = voxel_at(hit.point + hit.normal * 0.5);
+          gaia_updates.push(update_gaia::Message::Paste(player_id, target, placement));
         });
       },
+      protocol::ClientToServer::Chat(player_id, text) => {
+        chat::receive(server, gaia_updates, player_id, text);
+      },
     };
   })
 }
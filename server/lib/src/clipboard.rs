@@ -0,0 +1,435 @@
+//! Copying boxes of terrain, pasting them elsewhere, and saving them as prefab files.
+//!
+//! A clipboard holds the copied voxels themselves, surfaces and all, so pastes keep the shape they
+//! were copied with. Turning and mirroring move each surface vertex and normal along with its voxel.
+//! Pasting sets the finest voxels, and the coarser ones over them are brushed to match.
+
+use cgmath::{Point3, Vector3, EuclideanSpace};
+use collision::{Aabb3};
+use serde_json;
+use serde_json::Value;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::path::PathBuf;
+
+use common::protocol;
+use common::voxel;
+
+use data;
+use entity;
+use server;
+
+/// Where prefabs are saved, in the data directory.
+const PREFAB_DIR: &'static str = "prefabs";
+const PREFAB_EXTENSION: &'static str = "json";
+const NO_PLAYER: &'static str = "No such player.";
+const NOTHING_COPIED: &'static str = "Copy something first.";
+/// The most voxels a selection can hold.
+pub const MAX_VOLUME: i32 = 64 * 64 * 64;
+
+/// A copied box of voxels, with its low corner at the origin.
+#[derive(Debug, Clone)]
+pub struct Copied {
+  /// In voxels.
+  pub size : Vector3<i32>,
+  /// The voxels in the box, and one more layer past its high sides. The extra layer is only there
+  /// for the materials at its voxels' low corners, which are the corners on the box's high sides.
+  /// Ordered by x, then y, then z.
+  voxels   : Vec<voxel::T>,
+}
+
+impl Copied {
+  /// The number of voxels along each side, including the extra layer.
+  fn extent(&self) -> Vector3<i32> {
+    self.size + Vector3::new(1, 1, 1)
+  }
+
+  fn index(&self, p: &Point3<i32>) -> usize {
+    let extent = self.extent();
+    assert!(p.x >= 0 && p.y >= 0 && p.z >= 0 && p.x < extent.x && p.y < extent.y && p.z < extent.z);
+    ((p.x * extent.y + p.y) * extent.z + p.z) as usize
+  }
+
+  #[allow(missing_docs)]
+  pub fn get(&self, p: &Point3<i32>) -> &voxel::T {
+    &self.voxels[self.index(p)]
+  }
+
+  fn set(&mut self, p: &Point3<i32>, voxel: voxel::T) {
+    let i = self.index(p);
+    self.voxels[i] = voxel;
+  }
+
+  /// Every voxel in the box (without the extra layer), in the same order as `voxels`.
+  pub fn points(&self) -> Vec<Point3<i32>> {
+    points_in(&self.size)
+  }
+}
+
+/// The points from the origin up to (but not including) `size`, ordered by x, then y, then z.
+fn points_in(size: &Vector3<i32>) -> Vec<Point3<i32>> {
+  let mut points = Vec::new();
+  for x in 0 .. size.x {
+  for y in 0 .. size.y {
+  for z in 0 .. size.z {
+    points.push(Point3::new(x, y, z));
+  }}}
+  points
+}
+
+/// The bounds of the voxel at `p`.
+fn voxel_at(p: &Point3<i32>) -> voxel::bounds::T {
+  voxel::bounds::new(p.x, p.y, p.z, 0)
+}
+
+/// A player's selection, and what they've copied.
+pub struct T {
+  corners : [Option<Point3<i32>>; 2],
+  copied  : Option<Copied>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    corners : [None, None],
+    copied  : None,
+  }
+}
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn select(&mut self, corner: protocol::SelectionCorner, voxel: Point3<i32>) {
+    let i =
+      match corner {
+        protocol::SelectionCorner::First => 0,
+        protocol::SelectionCorner::Second => 1,
+      };
+    self.corners[i] = Some(voxel);
+  }
+
+  /// The low and high corners of the selection, inclusive.
+  fn selection(&self) -> Result<(Point3<i32>, Point3<i32>), String> {
+    match (self.corners[0], self.corners[1]) {
+      (Some(a), Some(b)) => {
+        let low = Point3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
+        let high = Point3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z));
+        try!(check_size(&(high - low + Vector3::new(1, 1, 1))));
+        Ok((low, high))
+      },
+      _ => Err(String::from("Select both corners first.")),
+    }
+  }
+}
+
+fn check_size(size: &Vector3<i32>) -> Result<(), String> {
+  let volume = size.x as i64 * size.y as i64 * size.z as i64;
+  if size.x <= 0 || size.y <= 0 || size.z <= 0 || volume > MAX_VOLUME as i64 {
+    return Err(format!("Selections can be at most {} voxels.", MAX_VOLUME))
+  }
+  Ok(())
+}
+
+/// Where a placement takes the points in a box with its low corner at the origin.
+struct Transform {
+  placement : protocol::Placement,
+  size      : Vector3<i32>,
+}
+
+impl Transform {
+  fn quarter_turns(&self) -> u8 {
+    self.placement.quarter_turns % 4
+  }
+
+  /// The size of the box once it's placed.
+  fn placed_size(&self) -> Vector3<i32> {
+    if self.quarter_turns() % 2 == 0 {
+      self.size
+    } else {
+      Vector3::new(self.size.z, self.size.y, self.size.x)
+    }
+  }
+
+  /// Flip, and then turn so that +z goes to +x and +x goes to -z, keeping the low corner at the origin.
+  fn point(&self, p: &Point3<f32>) -> Point3<f32> {
+    let mut size = Vector3::new(self.size.x as f32, self.size.y as f32, self.size.z as f32);
+    let mut p = *p;
+    if self.placement.mirror {
+      p.x = size.x - p.x;
+    }
+    for _ in 0 .. self.quarter_turns() {
+      p = Point3::new(p.z, p.y, size.x - p.x);
+      size = Vector3::new(size.z, size.y, size.x);
+    }
+    p
+  }
+
+  /// Like `point`, but for a corner between voxels.
+  fn corner(&self, p: &Point3<i32>) -> Point3<i32> {
+    let p = self.point(&Point3::new(p.x as f32, p.y as f32, p.z as f32));
+    Point3::new(p.x.round() as i32, p.y.round() as i32, p.z.round() as i32)
+  }
+
+  fn normal(&self, n: &Vector3<f32>) -> Vector3<f32> {
+    let mut n = *n;
+    if self.placement.mirror {
+      n.x = -n.x;
+    }
+    for _ in 0 .. self.quarter_turns() {
+      n = Vector3::new(n.z, n.y, -n.x);
+    }
+    n
+  }
+}
+
+/// `copied`, flipped and then turned, with its low corner still at the origin.
+fn place(copied: &Copied, placement: &protocol::Placement) -> Copied {
+  let transform = Transform { placement: *placement, size: copied.size };
+  let mut placed =
+    Copied {
+      size   : transform.placed_size(),
+      voxels : Vec::new(),
+    };
+  let extent = placed.extent();
+  placed.voxels = vec!(voxel::Volume(voxel::Material::Empty); (extent.x * extent.y * extent.z) as usize);
+
+  // A surface voxel only knows the material at its low corner, but turning and flipping moves
+  // other corners there, so move each corner's material on its own first.
+  let mut corners = vec!(voxel::Material::Empty; placed.voxels.len());
+  for p in points_in(&copied.extent()) {
+    corners[placed.index(&transform.corner(&p))] = voxel::material_of(copied.get(&p));
+  }
+  for p in points_in(&extent) {
+    placed.set(&p, voxel::Volume(corners[placed.index(&p)]));
+  }
+
+  for p in copied.points() {
+    let (a, b) = (transform.corner(&p), transform.corner(&(p + Vector3::new(1, 1, 1))));
+    let to = Point3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
+    let voxel =
+      match *copied.get(&p) {
+        voxel::Volume(material) => voxel::Volume(material),
+        voxel::Surface(ref surface) => {
+          let vertex = transform.point(&surface.surface_vertex.to_world_vertex(&voxel_at(&p)));
+          voxel::Surface(voxel::SurfaceStruct {
+            surface_vertex : voxel::Vertex::of_world_vertex_in_voxel(&vertex, &voxel_at(&to)),
+            normal         : voxel::Normal::of_float_normal(&transform.normal(&surface.normal.to_float_normal())),
+            corner         : corners[placed.index(&to)],
+          })
+        },
+      };
+    placed.set(&to, voxel);
+  }
+
+  placed
+}
+
+/// Run `f` on a player's clipboard. Returns None if they've left.
+fn with_clipboard<F, R>(server: &server::T, player: entity::id::Player, f: F) -> Option<R>
+  where F: FnOnce(&mut T) -> R,
+{
+  let mut world = server.world.lock().unwrap();
+  world.players.get_mut(player).map(|player| f(&mut player.clipboard))
+}
+
+/// Copy a player's selection from the terrain. This reads the terrain, so it should happen in
+/// order with edits.
+pub fn copy(server: &server::T, player: entity::id::Player) -> Result<String, String> {
+  let (low, high) =
+    match with_clipboard(server, player, |clipboard| clipboard.selection()) {
+      None => return Err(String::from(NO_PLAYER)),
+      Some(selection) => try!(selection),
+    };
+
+  let mut copied =
+    Copied {
+      size   : high - low + Vector3::new(1, 1, 1),
+      voxels : Vec::new(),
+    };
+  let terrain = &server.terrain_loader.terrain;
+  copied.voxels = points_in(&copied.extent()).iter().map(|p| terrain.load(&voxel_at(&(low + p.to_vec())))).collect();
+
+  let size = copied.size;
+  with_clipboard(server, player, |clipboard| clipboard.copied = Some(copied));
+  Ok(format!("Copied {}x{}x{} voxels.", size.x, size.y, size.z))
+}
+
+/// The voxels to paste from a player's clipboard, with its bottom middle at `target`, and the box
+/// they fill.
+pub fn paste(
+  server    : &server::T,
+  player    : entity::id::Player,
+  target    : Point3<i32>,
+  placement : &protocol::Placement,
+) -> Result<(Aabb3<i32>, Vec<(voxel::bounds::T, voxel::T)>), String> {
+  let pasted =
+    match with_clipboard(server, player, |clipboard| clipboard.copied.as_ref().map(|copied| place(copied, placement))) {
+      None => return Err(String::from(NO_PLAYER)),
+      Some(None) => return Err(String::from(NOTHING_COPIED)),
+      Some(Some(pasted)) => pasted,
+    };
+
+  let low = target + Vector3::new(-pasted.size.x / 2, 0, -pasted.size.z / 2);
+  let voxels =
+    pasted.points().into_iter()
+    .map(|p| (voxel_at(&(low + p.to_vec())), *pasted.get(&p)))
+    .collect();
+  Ok((Aabb3::new(low, low + pasted.size), voxels))
+}
+
+fn prefab_path(name: &str) -> Result<PathBuf, String> {
+  // This also keeps the path inside `PREFAB_DIR`.
+  let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+  if name.is_empty() || name.len() > 64 || !name.chars().all(valid) {
+    return Err(String::from("Prefab names can only have letters, digits, - and _."))
+  }
+  let mut path = data::path(PREFAB_DIR);
+  path.push(name);
+  path.set_extension(PREFAB_EXTENSION);
+  Ok(path)
+}
+
+/// Save a copied box as JSON: its size, and runs of voxels in `Copied` order.
+fn encode(copied: &Copied) -> Value {
+  let mut runs: Vec<(voxel::T, u32)> = Vec::new();
+  for voxel in &copied.voxels {
+    let same_as_last =
+      match runs.last() {
+        None => false,
+        Some(&(ref last, _)) => last == voxel,
+      };
+    if same_as_last {
+      runs.last_mut().unwrap().1 += 1;
+    } else {
+      runs.push((*voxel, 1));
+    }
+  }
+
+  let mut prefab = serde_json::Map::new();
+  prefab.insert(String::from("size"), Value::from(vec!(copied.size.x, copied.size.y, copied.size.z)));
+  prefab.insert(String::from("runs"), serde_json::to_value(&runs).unwrap());
+  Value::Object(prefab)
+}
+
+fn decode(prefab: Value) -> Result<Copied, String> {
+  let field = |name: &str| {
+    prefab.get(name).cloned().ok_or_else(|| format!("The prefab has no {:?}.", name))
+  };
+  let size: [i32; 3] = try!(serde_json::from_value(try!(field("size"))).map_err(|err| err.to_string()));
+  let runs: Vec<(voxel::T, u32)> =
+    try!(serde_json::from_value(try!(field("runs"))).map_err(|err| err.to_string()));
+
+  let size = Vector3::new(size[0], size[1], size[2]);
+  try!(check_size(&size));
+  let mut copied =
+    Copied {
+      size   : size,
+      voxels : Vec::new(),
+    };
+  let extent = copied.extent();
+  let count = (extent.x * extent.y * extent.z) as usize;
+  for (voxel, run) in runs {
+    if copied.voxels.len() + run as usize > count {
+      break
+    }
+    copied.voxels.extend(::std::iter::repeat(voxel).take(run as usize));
+  }
+  if copied.voxels.len() != count {
+    return Err(format!("The prefab should have {} voxels.", count))
+  }
+
+  Ok(copied)
+}
+
+/// Save a player's clipboard as a prefab. Existing prefabs aren't overwritten.
+pub fn save(server: &server::T, player: entity::id::Player, name: &str) -> Result<String, String> {
+  let path = try!(prefab_path(name));
+  let prefab =
+    match with_clipboard(server, player, |clipboard| clipboard.copied.as_ref().map(encode)) {
+      None => return Err(String::from(NO_PLAYER)),
+      Some(None) => return Err(String::from(NOTHING_COPIED)),
+      Some(Some(prefab)) => prefab,
+    };
+
+  let write = || -> ::std::io::Result<()> {
+    try!(::std::fs::create_dir_all(data::path(PREFAB_DIR)));
+    let mut file = try!(OpenOptions::new().write(true).create_new(true).open(&path));
+    file.write_all(prefab.to_string().as_bytes())
+  };
+  match write() {
+    Ok(()) => {},
+    Err(ref err) if err.kind() == ErrorKind::AlreadyExists => {
+      return Err(format!("There's already a prefab called {}.", name))
+    },
+    Err(err) => return Err(format!("Couldn't save {}: {}", name, err)),
+  }
+  info!("player {} saved prefab {:?}", player.to_u32(), name);
+  Ok(format!("Saved prefab {}.", name))
+}
+
+/// Load a prefab into a player's clipboard.
+pub fn load(server: &server::T, player: entity::id::Player, name: &str) -> Result<String, String> {
+  let path = try!(prefab_path(name));
+  let mut contents = String::new();
+  try!(
+    File::open(&path)
+    .and_then(|mut file| file.read_to_string(&mut contents))
+    .map_err(|err| format!("Couldn't load {}: {}", name, err))
+  );
+  let prefab = try!(serde_json::from_str(&contents).map_err(|err| format!("Couldn't load {}: {}", name, err)));
+  let copied = try!(decode(prefab).map_err(|err| format!("Couldn't load {}: {}", name, err)));
+
+  with_clipboard(server, player, |clipboard| clipboard.copied = Some(copied));
+  Ok(format!("Loaded prefab {}.", name))
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3};
+
+  use common::protocol::Placement;
+  use common::voxel;
+  use common::voxel::Material;
+
+  use super::{Copied, decode, encode, place};
+
+  /// A 3x1x2 box with one stone voxel, in the low x, high z corner.
+  fn corner() -> Copied {
+    let mut copied =
+      Copied {
+        size   : Vector3::new(3, 1, 2),
+        voxels : vec!(voxel::Volume(Material::Empty); 4 * 2 * 3),
+      };
+    copied.set(&Point3::new(0, 0, 1), voxel::Volume(Material::Stone));
+    copied
+  }
+
+  fn stone_at(copied: &Copied) -> Vec<Point3<i32>> {
+    copied.points().into_iter().filter(|p| voxel::material_of(copied.get(p)) == Material::Stone).collect()
+  }
+
+  #[test]
+  fn placing() {
+    let turned = place(&corner(), &Placement { quarter_turns: 1, mirror: false });
+    assert_eq!(turned.size, Vector3::new(2, 1, 3));
+    // +z turns to +x, and +x turns to -z.
+    assert_eq!(stone_at(&turned), vec!(Point3::new(1, 0, 2)));
+
+    let mirrored = place(&corner(), &Placement { quarter_turns: 0, mirror: true });
+    assert_eq!(stone_at(&mirrored), vec!(Point3::new(2, 0, 1)));
+
+    let around = place(&corner(), &Placement { quarter_turns: 4, mirror: false });
+    assert_eq!(around.size, corner().size);
+    assert_eq!(stone_at(&around), stone_at(&corner()));
+  }
+
+  #[test]
+  fn prefab_round_trip() {
+    let decoded = decode(encode(&corner())).unwrap();
+    assert_eq!(decoded.size, corner().size);
+    assert_eq!(decoded.voxels, corner().voxels);
+
+    let mut truncated = encode(&corner());
+    truncated["runs"] = super::serde_json::to_value(&[(voxel::Volume(Material::Empty), 2)]).unwrap();
+    assert!(decode(truncated).is_err());
+  }
+}
@@ -0,0 +1,350 @@
+//! A local socket that tools can send admin commands to, without attaching to the server's console.
+//!
+//! The protocol is one JSON object per line each way. A request looks like
+//! `{"id": 1, "token": "...", "command": "teleport 3 0 64 0"}`, where `command` is anything
+//! `admin::parse` takes, or `log <level>`. The reply echoes the request's `id` (if it had one), and
+//! is either `{"id": 1, "ok": true, "records": [{"player": "3", "x": "0.50", ...}]}` or
+//! `{"id": 1, "ok": false, "error": "no player 3"}`. Record fields are the same strings the console
+//! prints.
+//!
+//! The socket only listens on loopback addresses, and serves one connection at a time. Every
+//! request has to carry the `token` the server writes to `control_token` in the data directory when
+//! it starts, which only the user running the server can read. A connection is closed if a request
+//! line is longer than `MAX_LINE` bytes, or if it goes `IDLE_TIMEOUT` without finishing one, so no
+//! one can hold the socket before the token is checked.
+
+use rand::{OsRng, Rng};
+use serde_json;
+use serde_json::Value;
+use std;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use admin;
+use data;
+use server;
+use update_gaia;
+
+/// Where the token is written, in the data directory.
+const TOKEN_FILE: &'static str = "control_token";
+/// The longest request line, in bytes, including its newline.
+const MAX_LINE: u64 = 4096;
+/// How long a connection can go without finishing a request before it's closed.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How to run the control socket.
+pub struct Config<'a> {
+  /// Where to listen, e.g. `127.0.0.1:4444`.
+  pub address : &'a str,
+  /// Handles `log <level>` requests, since the logger belongs to whoever's running the server.
+  pub log     : &'a (Fn(&str) -> admin::Reply + Sync),
+}
+
+#[derive(Debug, PartialEq)]
+enum Request {
+  Log(String),
+  Admin(admin::Command),
+}
+
+/// Compare tokens without stopping at the first difference, so the time taken doesn't say how
+/// much of a guess was right.
+fn same_token(a: &str, b: &str) -> bool {
+  a.len() == b.len() &&
+  a.bytes().zip(b.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Read a request line. Returns the request's `id`, if it has one, and what it asks for.
+fn parse_request(line: &str, token: &str) -> (Option<Value>, Result<Request, String>) {
+  let request: Value =
+    match serde_json::from_str(line) {
+      Ok(request) => request,
+      Err(err) => return (None, Err(format!("bad JSON: {}", err))),
+    };
+  let id = request.get("id").cloned();
+  match request.get("token").and_then(|token| token.as_str()) {
+    Some(given) if same_token(given, token) => {},
+    _ => return (id, Err(String::from("missing or wrong \"token\""))),
+  }
+  let command =
+    match request.get("command").and_then(|command| command.as_str()) {
+      None => Err(String::from("missing \"command\" string")),
+      Some(command) => {
+        let mut words = command.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+          (Some("log"), Some(level), None) => Ok(Request::Log(String::from(level))),
+          _ => admin::parse(command).map(Request::Admin),
+        }
+      },
+    };
+  (id, command)
+}
+
+/// Only let the owner read files opened with `options`.
+#[cfg(unix)]
+fn private(options: &mut OpenOptions) {
+  use std::os::unix::fs::OpenOptionsExt;
+  options.mode(0o600);
+}
+
+#[cfg(not(unix))]
+fn private(_: &mut OpenOptions) {}
+
+/// Make a new token, and write it where only this user can read it.
+fn new_token(path: &Path) -> std::io::Result<String> {
+  let mut rng = try!(OsRng::new());
+  let token = format!("{:016x}{:016x}", rng.gen::<u64>(), rng.gen::<u64>());
+
+  // Start afresh, so an old file's permissions aren't kept.
+  match fs::remove_file(path) {
+    Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {},
+    Err(err) => return Err(err),
+    Ok(()) => {},
+  }
+  let mut options = OpenOptions::new();
+  options.write(true).create_new(true);
+  private(&mut options);
+  let mut file = try!(options.open(path));
+  try!(writeln!(file, "{}", token));
+  Ok(token)
+}
+
+/// Write a reply as a response object.
+fn response(id: Option<Value>, reply: admin::Reply) -> Value {
+  let mut response = serde_json::Map::new();
+  if let Some(id) = id {
+    response.insert(String::from("id"), id);
+  }
+  match reply {
+    Ok(records) => {
+      let records =
+        records.into_iter()
+        .map(|record| {
+          let fields =
+            record.into_iter()
+            .map(|(key, value)| (String::from(key), Value::String(value)))
+            .collect();
+          Value::Object(fields)
+        })
+        .collect();
+      response.insert(String::from("ok"), Value::Bool(true));
+      response.insert(String::from("records"), Value::Array(records));
+    },
+    Err(err) => {
+      response.insert(String::from("ok"), Value::Bool(false));
+      response.insert(String::from("error"), Value::String(err));
+    },
+  }
+  Value::Object(response)
+}
+
+/// What came of reading a request line.
+#[derive(Debug, PartialEq)]
+enum LineRead {
+  /// `line` holds a whole line.
+  Line,
+  /// Only part of a line arrived before the read timed out; it's kept in `line`.
+  Partial,
+  /// The line got to `MAX_LINE` bytes without ending.
+  TooLong,
+  /// The connection closed.
+  Closed,
+}
+
+/// Read the rest of a line into `line`, without reading past `MAX_LINE` bytes of it.
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> std::io::Result<LineRead> {
+  let limit = MAX_LINE.saturating_sub(line.len() as u64);
+  match reader.by_ref().take(limit).read_line(line) {
+    Ok(_) if line.ends_with('\n') => Ok(LineRead::Line),
+    Ok(_) if line.len() as u64 >= MAX_LINE => Ok(LineRead::TooLong),
+    Ok(0) => Ok(LineRead::Closed),
+    Ok(_) => Ok(LineRead::Partial),
+    Err(ref err)
+      if err.kind() == std::io::ErrorKind::WouldBlock || err.kind() == std::io::ErrorKind::TimedOut => {
+      Ok(LineRead::Partial)
+    },
+    Err(err) => Err(err),
+  }
+}
+
+/// Answer requests on one connection until it closes, or it's time to quit.
+fn serve<Quit>(
+  server       : &server::T,
+  quit         : &Quit,
+  gaia_updates : &update_gaia::Queue,
+  config       : &Config,
+  token        : &str,
+  stream       : TcpStream,
+  poll_interval: Duration,
+) -> std::io::Result<()> where
+  Quit: Fn() -> bool,
+{
+  try!(stream.set_nonblocking(false));
+  try!(stream.set_read_timeout(Some(poll_interval)));
+  let mut writer = try!(stream.try_clone());
+  let mut reader = BufReader::new(stream);
+
+  let mut line = String::new();
+  let mut last_request = Instant::now();
+  while !quit() {
+    match try!(read_line(&mut reader, &mut line)) {
+      LineRead::Line => {},
+      LineRead::Partial => {
+        // Keep whatever we've read of the line so far, and check whether to quit.
+        if last_request.elapsed() >= IDLE_TIMEOUT {
+          return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "idle for too long"))
+        }
+        continue
+      },
+      LineRead::TooLong => {
+        // There's no telling where the next request starts, so give up on the connection.
+        let error = format!("request longer than {} bytes", MAX_LINE);
+        try!(writeln!(writer, "{}", response(None, Err(error.clone()))));
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, error))
+      },
+      LineRead::Closed => return Ok(()),
+    }
+    last_request = Instant::now();
+
+    if !line.trim().is_empty() {
+      let (id, request) = parse_request(line.trim(), token);
+      let reply =
+        request.and_then(|request| {
+          match request {
+            Request::Log(level) => (config.log)(&level),
+            Request::Admin(command) => admin::execute(server, gaia_updates, command),
+          }
+        });
+      try!(writeln!(writer, "{}", response(id, reply)));
+    }
+    line.clear();
+  }
+
+  Ok(())
+}
+
+/// Listen on `config.address` and serve requests until it's time to quit.
+pub fn run<Quit>(
+  server        : &server::T,
+  quit          : &Quit,
+  gaia_updates  : &update_gaia::Queue,
+  config        : &Config,
+  poll_interval : Duration,
+) where
+  Quit: Fn() -> bool,
+{
+  let listener =
+    match TcpListener::bind(config.address) {
+      Ok(listener) => listener,
+      Err(err) => {
+        error!("Couldn't open the control socket on {}: {}", config.address, err);
+        return
+      },
+    };
+  let local_address = listener.local_addr().unwrap();
+  if !local_address.ip().is_loopback() {
+    error!("Not opening the control socket on {}, which isn't a loopback address", local_address);
+    return
+  }
+
+  let token_path = data::path(TOKEN_FILE);
+  let token =
+    match new_token(&token_path) {
+      Ok(token) => token,
+      Err(err) => {
+        error!("Not opening the control socket, since its token couldn't be written to {}: {}", token_path.display(), err);
+        return
+      },
+    };
+
+  listener.set_nonblocking(true).unwrap();
+  info!("Control socket listening on {}, with its token in {}", local_address, token_path.display());
+
+  while !quit() {
+    match listener.accept() {
+      Ok((stream, peer)) => {
+        debug!("Control connection from {}", peer);
+        match serve(server, quit, gaia_updates, config, &token, stream, poll_interval) {
+          Ok(()) => {},
+          Err(err) => warn!("Control connection from {} failed: {}", peer, err),
+        }
+      },
+      Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+        std::thread::sleep(poll_interval);
+      },
+      Err(err) => {
+        warn!("Error accepting a control connection: {}", err);
+        std::thread::sleep(poll_interval);
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use serde_json;
+
+  use admin;
+
+  use std::io::Cursor;
+
+  use super::{LineRead, MAX_LINE, Request, parse_request, read_line, response};
+
+  #[test]
+  fn requests() {
+    let (id, request) = parse_request("{\"id\": 7, \"token\": \"abc\", \"command\": \"stats\"}", "abc");
+    assert_eq!(id, Some(serde_json::Value::from(7)));
+    assert_eq!(request, Ok(Request::Admin(admin::Command::Stats)));
+
+    let (_, request) = parse_request("{\"token\": \"abc\", \"command\": \"log debug\"}", "abc");
+    assert_eq!(request, Ok(Request::Log(String::from("debug"))));
+
+    let (id, request) = parse_request("{\"id\": \"a\", \"token\": \"abc\"}", "abc");
+    assert_eq!(id, Some(serde_json::Value::from("a")));
+    assert!(request.is_err());
+
+    let (id, request) = parse_request("not json", "abc");
+    assert_eq!(id, None);
+    assert!(request.is_err());
+  }
+
+  #[test]
+  fn tokens() {
+    assert!(parse_request("{\"command\": \"stats\"}", "abc").1.is_err());
+    assert!(parse_request("{\"token\": \"abd\", \"command\": \"stats\"}", "abc").1.is_err());
+    assert!(parse_request("{\"token\": \"ab\", \"command\": \"stats\"}", "abc").1.is_err());
+  }
+
+  #[test]
+  fn long_lines() {
+    let mut line = String::new();
+    let mut reader = Cursor::new("stats\nmore");
+    assert_eq!(read_line(&mut reader, &mut line).unwrap(), LineRead::Line);
+    assert_eq!(line, "stats\n");
+    line.clear();
+    assert_eq!(read_line(&mut reader, &mut line).unwrap(), LineRead::Partial);
+    assert_eq!(read_line(&mut reader, &mut line).unwrap(), LineRead::Closed);
+    assert_eq!(line, "more");
+
+    let long = vec!(b'a'; 2 * MAX_LINE as usize);
+    let mut reader = Cursor::new(long);
+    let mut line = String::new();
+    assert_eq!(read_line(&mut reader, &mut line).unwrap(), LineRead::TooLong);
+    assert_eq!(line.len() as u64, MAX_LINE);
+  }
+
+  #[test]
+  fn responses() {
+    let reply = Ok(vec!(vec!(("player", String::from("3")))));
+    assert_eq!(
+      response(Some(serde_json::Value::from(1)), reply).to_string(),
+      "{\"id\":1,\"ok\":true,\"records\":[{\"player\":\"3\"}]}",
+    );
+    assert_eq!(
+      response(None, Err(String::from("nope"))).to_string(),
+      "{\"error\":\"nope\",\"ok\":false}",
+    );
+  }
+}
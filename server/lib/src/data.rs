@@ -0,0 +1,31 @@
+//! Where the server keeps its files: permissions, the world spawn, prefabs, mob archetypes and scripts, and the control socket's token.
+
+use std::env;
+use std::path::PathBuf;
+
+/// Names the data directory, if it's set.
+const VAR: &'static str = "PLAYFORM_DATA";
+
+/// The directory the server's files go in: `$PLAYFORM_DATA` if it's set, and otherwise the
+/// directory the server's executable is in. It doesn't depend on the working directory.
+pub fn dir() -> PathBuf {
+  if let Some(dir) = env::var_os(VAR) {
+    return PathBuf::from(dir)
+  }
+  match env::current_exe() {
+    Ok(exe) => {
+      match exe.parent() {
+        Some(dir) => return dir.to_path_buf(),
+        None => {},
+      }
+    },
+    Err(err) => warn!("Couldn't find the server executable; set {} to choose a data directory: {}", VAR, err),
+  }
+  PathBuf::from(".")
+}
+
+/// A file in the data directory. Anything in `name` that came from a player has to be checked
+/// first, so it can't lead out of the directory.
+pub fn path(name: &str) -> PathBuf {
+  dir().join(name)
+}
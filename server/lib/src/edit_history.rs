@@ -0,0 +1,172 @@
+//! Each player's terrain edits, so they can be undone and redone.
+
+use std::collections::VecDeque;
+
+use common::voxel;
+
+/// How many edits a player can undo (or redo) in a row.
+pub const MAX_EDITS: usize = 32;
+/// How many changed voxels a player's undo (or redo) edits can hold between them. The oldest edits
+/// are forgotten to stay under this, so a few huge edits can't eat the server's memory.
+pub const MAX_VOXELS: usize = 1 << 18;
+
+/// The voxels an edit changed: each one's bounds, what it was, and what the edit made it. Undoing
+/// (or redoing) puts back what they were, unless they've been changed again since.
+pub type Edit = Vec<(voxel::bounds::T, voxel::T, voxel::T)>;
+
+/// Edits, oldest first.
+struct Stack {
+  edits  : VecDeque<Edit>,
+  /// How many voxels are in `edits` altogether.
+  voxels : usize,
+}
+
+impl Stack {
+  fn new() -> Stack {
+    Stack {
+      edits  : VecDeque::new(),
+      voxels : 0,
+    }
+  }
+
+  /// Add an edit, and forget the oldest ones if there are too many, or they're too big. An edit
+  /// bigger than `MAX_VOXELS` on its own isn't kept at all.
+  fn push(&mut self, edit: Edit) {
+    if edit.is_empty() {
+      return
+    }
+    self.voxels += edit.len();
+    self.edits.push_back(edit);
+    while self.edits.len() > MAX_EDITS || self.voxels > MAX_VOXELS {
+      let oldest = self.edits.pop_front().unwrap();
+      self.voxels -= oldest.len();
+    }
+  }
+
+  fn pop(&mut self) -> Option<Edit> {
+    self.edits.pop_back().map(|edit| {
+      self.voxels -= edit.len();
+      edit
+    })
+  }
+
+  fn clear(&mut self) {
+    self.edits.clear();
+    self.voxels = 0;
+  }
+}
+
+/// One player's undo and redo stacks.
+pub struct T {
+  undo : Stack,
+  redo : Stack,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    undo : Stack::new(),
+    redo : Stack::new(),
+  }
+}
+
+impl T {
+  /// Remember a new edit. Anything that was undone can't be redone after this.
+  pub fn record(&mut self, edit: Edit) {
+    if edit.is_empty() {
+      return
+    }
+    self.redo.clear();
+    self.undo.push(edit);
+  }
+
+  /// Take the latest edit, to revert.
+  pub fn undo(&mut self) -> Option<Edit> {
+    self.undo.pop()
+  }
+
+  /// Remember the changes undoing an edit made, so they can be reverted to redo it.
+  pub fn undone(&mut self, edit: Edit) {
+    self.redo.push(edit);
+  }
+
+  /// Take the latest undone edit's changes, to revert.
+  pub fn redo(&mut self) -> Option<Edit> {
+    self.redo.pop()
+  }
+
+  /// Remember the changes redoing an edit made, so it can be undone again.
+  pub fn redone(&mut self, edit: Edit) {
+    self.undo.push(edit);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use common::voxel;
+
+  use super::{Edit, MAX_EDITS, MAX_VOXELS, new};
+
+  fn edit(x: i32) -> Edit {
+    vec!((voxel::bounds::new(x, 0, 0, 0), voxel::Volume(voxel::Material::Empty), voxel::Volume(voxel::Material::Stone)))
+  }
+
+  #[test]
+  fn undo_redo() {
+    let mut history = new();
+    history.record(edit(1));
+    history.record(edit(2));
+
+    let undone = history.undo().unwrap();
+    assert_eq!(undone[0].0, edit(2)[0].0);
+    history.undone(edit(20));
+
+    let redone = history.redo().unwrap();
+    assert_eq!(redone[0].0, edit(20)[0].0);
+    history.redone(edit(2));
+    assert!(history.redo().is_none());
+
+    // A new edit forgets what could have been redone.
+    history.undo().unwrap();
+    history.undone(edit(20));
+    history.record(edit(3));
+    assert!(history.redo().is_none());
+  }
+
+  #[test]
+  fn bounded() {
+    let mut history = new();
+    for x in 0 .. 2 * MAX_EDITS as i32 {
+      history.record(edit(x));
+    }
+    history.record(Vec::new());
+
+    let mut count = 0;
+    while history.undo().is_some() {
+      count += 1;
+    }
+    assert_eq!(count, MAX_EDITS);
+  }
+
+  #[test]
+  fn bounded_by_voxels() {
+    let big = |x: i32, len: usize| -> Edit { (0 .. len).map(|_| edit(x).pop().unwrap()).collect() };
+    let mut history = new();
+    for x in 0 .. 6 {
+      history.record(big(x, MAX_VOXELS / 4));
+    }
+    // Four fit exactly. One more voxel pushes the oldest of them out.
+    history.record(edit(6));
+
+    assert_eq!(history.undo().unwrap()[0].0, edit(6)[0].0);
+    let mut count = 0;
+    while history.undo().is_some() {
+      count += 1;
+    }
+    assert_eq!(count, 3);
+
+    // An edit too big to keep on its own isn't kept.
+    history.record(big(7, MAX_VOXELS + 1));
+    assert!(history.undo().is_none());
+  }
+}
@@ -1,8 +1,8 @@
 use collision::{Aabb3};
-use std::sync::Mutex;
 
 use common::fnv_map;
 use common::id_allocator;
+use common::lock::Mutex;
 use common::voxel;
 
 use entity;
@@ -37,7 +37,12 @@ impl T {
         entry.insert(id);
 
         let (low, high) = block_position.corners();
-        physics.lock().unwrap().insert_misc(id, &Aabb3::new(low, high));
+        let in_world =
+          physics.lock().unwrap().insert_misc(id, &Aabb3::new(low, high), physics::MiscKind::Placeholder);
+        if !in_world {
+          // Nothing can get there to need the placeholder, and `remove` copes without one.
+          debug!("No placeholder for {:?}, outside the world", block_position);
+        }
         true
       }
     }
@@ -0,0 +1,95 @@
+//! Keep the terrain around an entity loaded while it's there.
+
+use cgmath::{Point3};
+
+use common::id_allocator;
+use common::lock::Mutex;
+use common::surroundings_loader;
+use common::surroundings_loader::LoadType;
+use common::voxel;
+
+use lod;
+use server;
+use update_gaia;
+
+/// A claim on the terrain around an entity.
+pub struct T {
+  surroundings       : surroundings_loader::T,
+  surroundings_owner : lod::OwnerId,
+  // Nearby blocks are solid until they're loaded, so the entity doesn't fall through them.
+  solid_boundary     : surroundings_loader::T,
+  solid_owner        : lod::OwnerId,
+}
+
+/// Load terrain up to `distance` blocks around an entity.
+pub fn new(owner_allocator: &Mutex<id_allocator::T<lod::OwnerId>>, distance: u32) -> T {
+  T {
+    surroundings       : surroundings_loader::new(distance, Vec::new()),
+    surroundings_owner : owner_allocator.lock().unwrap().allocate(),
+    solid_boundary     : surroundings_loader::new(distance, Vec::new()),
+    solid_owner        : owner_allocator.lock().unwrap().allocate(),
+  }
+}
+
+impl T {
+  /// Load and unload terrain for an entity that's now at `position`.
+  pub fn update<RequestBlock>(
+    &mut self,
+    server        : &server::T,
+    request_block : &mut RequestBlock,
+    position      : &Point3<f32>,
+  ) where
+    RequestBlock: FnMut(update_gaia::Message),
+  {
+    let position = Point3::new(position.x as i32, position.y as i32, position.z as i32);
+
+    let owner = self.surroundings_owner;
+    for (block, load_type) in self.surroundings.updates(&position) {
+      let block = voxel::bounds::new(block.x, block.y, block.z, 0);
+      load(server, request_block, owner, &block, lod::Full, load_type);
+    }
+
+    let owner = self.solid_owner;
+    for (block, load_type) in self.solid_boundary.updates(&position) {
+      let block = voxel::bounds::new(block.x, block.y, block.z, 0);
+      load(server, request_block, owner, &block, lod::Placeholder, load_type);
+    }
+  }
+
+  /// Let go of all the terrain this loader was keeping loaded.
+  pub fn release(self, server: &server::T) {
+    server.terrain_loader.unload_owner(&server.physics, self.surroundings_owner);
+    server.terrain_loader.unload_owner(&server.physics, self.solid_owner);
+  }
+}
+
+fn load<RequestBlock>(
+  server        : &server::T,
+  request_block : &mut RequestBlock,
+  owner         : lod::OwnerId,
+  block         : &voxel::bounds::T,
+  level         : lod::T,
+  load_type     : LoadType,
+) where
+  RequestBlock: FnMut(update_gaia::Message),
+{
+  match load_type {
+    LoadType::Load | LoadType::Downgrade => {
+      server.terrain_loader.load(
+        &server.misc_allocator,
+        &server.physics,
+        block,
+        level,
+        owner,
+        request_block,
+      );
+    },
+    LoadType::Unload => {
+      server.terrain_loader.unload(
+        &server.physics,
+        block,
+        owner,
+      );
+    },
+  }
+}
@@ -0,0 +1,42 @@
+//! The order the server's locks have to be taken in, outermost first. See `common::lock`.
+//!
+//! A thread holding one of these can only lock the ones below it. Ranks are spaced out so new
+//! locks can be slotted in between.
+
+use common::lock::Level;
+
+macro_rules! levels {
+  ($($name:ident = $rank:expr,)*) => {
+    $(
+      #[allow(missing_docs)]
+      pub const $name: Level = Level { rank: $rank, name: stringify!($name) };
+    )*
+  }
+}
+
+levels! {
+  UPDATE_TIMER        = 20,
+  TICK_TIMING         = 25,
+  WORLD               = 30,
+  PERMISSIONS         = 35,
+  MOB_SCRIPTS         = 40,
+  MOB_SPAWNER         = 50,
+  SUN                 = 60,
+  RNG                 = 70,
+  SPAWN               = 80,
+  // Terrain loading goes lod_map -> in_progress_terrain -> loaded, and then into physics.
+  LOD_MAP             = 90,
+  IN_PROGRESS_TERRAIN = 100,
+  LOADED              = 110,
+  TERRAIN_VOXELS      = 120,
+  TERRAIN_MOSAIC      = 130,
+  TERRAIN_ALLOCATOR   = 140,
+  MISC_ALLOCATOR      = 150,
+  OWNER_ALLOCATOR     = 160,
+  CLIENT_ALLOCATOR    = 170,
+  PHYSICS             = 180,
+  VOXEL_UPDATES       = 185,
+  CLIENTS             = 190,
+  // Anything that requests terrain can be holding almost anything else.
+  GAIA_UPDATES        = 200,
+}
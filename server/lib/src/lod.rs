@@ -60,6 +60,14 @@ impl Map {
     })
   }
 
+  /// Every position `owner` has a handle on.
+  pub fn owned_by(&self, owner: OwnerId) -> Vec<voxel::bounds::T> {
+    self.loaded.iter()
+      .filter(|&(_, bls)| bls.owner_lods.iter().any(|&(o, _)| o == owner))
+      .map(|(position, _)| *position)
+      .collect()
+  }
+
   // TODO: Can probably get rid of the LODChange returns; we only assert with em.
 
   /// Acquire/update an owner's handle in `position`.
@@ -0,0 +1,286 @@
+//! Kinds of mobs, described as data.
+//!
+//! Archetypes are read from `mob_archetypes.json` in the data directory, or, if there isn't one, from
+//! the copy built into the server. The file is a list of archetypes, like
+//!
+//! ```text
+//! { "name": "critter", "size": [0.5, 0.5, 0.5], "speed": 0.4,
+//!   "spawn": { "ground": ["Terrain"], "light": "day", "weight": 3 },
+//!   "states": [
+//!     { "name": "graze", "action": "stop",
+//!       "transitions": [{ "when": "player-within", "distance": 6.0, "to": "flee" }] },
+//!     ... ] }
+//! ```
+//!
+//! `script` is optional. Mobs start in the first state. Actions are `continue`, `stop`, `chase` and
+//! `flee`, and transitions are taken `when` there are `no-players`, or the nearest player is
+//! `player-within` or `player-beyond` a `distance`.
+
+use cgmath::Vector3;
+use serde_json;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+
+use common::voxel;
+
+use data;
+use mob::behavior;
+
+/// Where archetypes are loaded from, in the data directory.
+const FILE: &'static str = "mob_archetypes.json";
+/// Used when there's no archetypes file.
+const DEFAULTS: &'static str = include_str!("../../../../mob_archetypes.json");
+
+/// Index of an archetype in the server's list of archetypes.
+pub type Id = usize;
+
+/// When a mob can spawn, in terms of sunlight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum Light {
+  Any,
+  Day,
+  Night,
+}
+
+impl Light {
+  #[allow(missing_docs)]
+  pub fn parse(name: &str) -> Result<Light, String> {
+    match name {
+      "any" => Ok(Light::Any),
+      "day" => Ok(Light::Day),
+      "night" => Ok(Light::Night),
+      _ => Err(format!("bad light {:?}; try any, day or night", name)),
+    }
+  }
+
+  /// Can a mob spawn under this sky?
+  pub fn allows(&self, is_day: bool) -> bool {
+    match *self {
+      Light::Any => true,
+      Light::Day => is_day,
+      Light::Night => !is_day,
+    }
+  }
+}
+
+/// Where and when a mob can spawn.
+#[derive(Debug, Clone)]
+pub struct SpawnRules {
+  /// What the mob can spawn standing on. Our biomes are told apart by their materials.
+  pub ground : Vec<voxel::Material>,
+  #[allow(missing_docs)]
+  pub light  : Light,
+  /// How likely this mob is to be picked, relative to the others that can spawn.
+  pub weight : u32,
+}
+
+/// A kind of mob.
+#[derive(Debug, Clone)]
+pub struct T {
+  #[allow(missing_docs)]
+  pub name     : String,
+  /// The size of the mob's bounding box.
+  pub size     : Vector3<f32>,
+  /// Top horizontal speed, in world units per tick.
+  pub speed    : f32,
+  /// What the mob does, unless it has a working script.
+  pub behavior : behavior::T,
+  /// The name of the script that drives this mob, if any. See `mob::script`.
+  pub script   : Option<String>,
+  #[allow(missing_docs)]
+  pub spawn    : SpawnRules,
+}
+
+/// Load the archetypes file, falling back to the built-in archetypes if there isn't one.
+pub fn load() -> Vec<T> {
+  let path = data::path(FILE);
+  let mut contents = String::new();
+  match File::open(&path).and_then(|mut file| file.read_to_string(&mut contents)) {
+    Err(ref err) if err.kind() == ErrorKind::NotFound => return defaults(),
+    Err(err) => {
+      warn!("Couldn't read {}, using the built-in mobs: {}", path.display(), err);
+      return defaults()
+    },
+    Ok(_) => {},
+  }
+
+  match parse(&contents) {
+    Ok(archetypes) => archetypes,
+    Err(err) => {
+      warn!("Couldn't parse {}, using the built-in mobs: {}", path.display(), err);
+      defaults()
+    },
+  }
+}
+
+fn defaults() -> Vec<T> {
+  parse(DEFAULTS).unwrap()
+}
+
+fn parse(contents: &str) -> Result<Vec<T>, String> {
+  let records: Value = try!(serde_json::from_str(contents).map_err(|err| err.to_string()));
+  let records =
+    match records.as_array() {
+      None => return Err(String::from("archetypes should be a list")),
+      Some(records) => records,
+    };
+  let mut archetypes = Vec::new();
+  for record in records {
+    let archetype = try!(decode(record));
+    if archetypes.iter().any(|other: &T| other.name == archetype.name) {
+      return Err(format!("{:?} is defined twice", archetype.name))
+    }
+    archetypes.push(archetype);
+  }
+  Ok(archetypes)
+}
+
+fn field<'a>(record: &'a Value, name: &str) -> Result<&'a Value, String> {
+  record.get(name).ok_or_else(|| format!("missing {:?}", name))
+}
+
+fn string<'a>(record: &'a Value, name: &str) -> Result<&'a str, String> {
+  try!(field(record, name)).as_str().ok_or_else(|| format!("{:?} should be a string", name))
+}
+
+fn number(record: &Value, name: &str) -> Result<f32, String> {
+  try!(field(record, name)).as_f64().map(|x| x as f32).ok_or_else(|| format!("{:?} should be a number", name))
+}
+
+fn list<'a>(record: &'a Value, name: &str) -> Result<&'a Vec<Value>, String> {
+  try!(field(record, name)).as_array().ok_or_else(|| format!("{:?} should be a list", name))
+}
+
+fn decode(record: &Value) -> Result<T, String> {
+  let name = String::from(try!(string(record, "name")));
+  let in_archetype = |err: String| format!("{}: {}", name, err);
+
+  let size: [f32; 3] =
+    try!(serde_json::from_value(try!(field(record, "size").map_err(&in_archetype)).clone())
+      .map_err(|err| in_archetype(format!("bad size: {}", err))));
+  let speed = try!(number(record, "speed").map_err(&in_archetype));
+  let script =
+    match record.get("script") {
+      None | Some(&Value::Null) => None,
+      Some(script) => {
+        // This also keeps the script inside the scripts directory.
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        match script.as_str() {
+          Some(script) if !script.is_empty() && script.chars().all(valid) => Some(String::from(script)),
+          _ => return Err(in_archetype(String::from("\"script\" should be a name made of letters, digits, - and _"))),
+        }
+      },
+    };
+  let spawn = try!(field(record, "spawn").and_then(decode_spawn).map_err(&in_archetype));
+  let behavior = try!(decode_behavior(try!(list(record, "states").map_err(&in_archetype))).map_err(&in_archetype));
+
+  Ok(T {
+    name     : name.clone(),
+    size     : Vector3::new(size[0], size[1], size[2]),
+    speed    : speed,
+    behavior : behavior,
+    script   : script,
+    spawn    : spawn,
+  })
+}
+
+fn decode_spawn(record: &Value) -> Result<SpawnRules, String> {
+  let ground: Vec<voxel::Material> =
+    try!(serde_json::from_value(try!(field(record, "ground")).clone()).map_err(|err| format!("bad ground: {}", err)));
+  let light = try!(Light::parse(try!(string(record, "light"))));
+  let weight =
+    try!(try!(field(record, "weight")).as_u64().ok_or_else(|| String::from("\"weight\" should be a whole number")));
+  if weight > u32::max_value() as u64 {
+    return Err(String::from("\"weight\" is too big"))
+  }
+  Ok(SpawnRules {
+    ground : ground,
+    light  : light,
+    weight : weight as u32,
+  })
+}
+
+fn decode_behavior(records: &[Value]) -> Result<behavior::T, String> {
+  if records.is_empty() {
+    return Err(String::from("a mob needs at least one state"))
+  }
+  let names = try!(records.iter().map(|record| string(record, "name")).collect::<Result<Vec<&str>, String>>());
+  let state_id = |name: &str| {
+    names.iter().position(|&other| other == name).ok_or_else(|| format!("no state {:?}", name))
+  };
+
+  let mut states = Vec::new();
+  for (record, name) in records.iter().zip(names.iter()) {
+    let in_state = |err: String| format!("state {:?}: {}", name, err);
+    let action = try!(string(record, "action").and_then(behavior::Action::parse).map_err(&in_state));
+    let mut transitions = Vec::new();
+    for record in try!(list(record, "transitions").map_err(&in_state)) {
+      let when =
+        match try!(string(record, "when").map_err(&in_state)) {
+          "no-players" => behavior::Condition::NoPlayers,
+          "player-within" => behavior::Condition::PlayerWithin(try!(number(record, "distance").map_err(&in_state))),
+          "player-beyond" => behavior::Condition::PlayerBeyond(try!(number(record, "distance").map_err(&in_state))),
+          when => return Err(in_state(format!("bad condition {:?}; try no-players, player-within or player-beyond", when))),
+        };
+      let to = try!(string(record, "to").and_then(&state_id).map_err(&in_state));
+      transitions.push(behavior::Transition { when: when, to: to });
+    }
+    states.push(behavior::State { action: action, transitions: transitions });
+  }
+
+  Ok(behavior::T { states: states })
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Vector3;
+
+  use mob::behavior::Observations;
+
+  use super::{defaults, parse};
+
+  #[test]
+  fn built_in() {
+    let archetypes = defaults();
+    let names: Vec<&str> = archetypes.iter().map(|archetype| &archetype.name[..]).collect();
+    assert_eq!(names, vec!("tagger", "critter", "lurker"));
+    assert_eq!(archetypes[2].script, Some(String::from("lurker")));
+  }
+
+  #[test]
+  fn bad_files() {
+    assert!(parse("{}").is_err());
+    assert!(parse("[{\"name\": \"a\"}]").is_err());
+    let unknown_state =
+      "[{\"name\": \"a\", \"size\": [1, 1, 1], \"speed\": 1,
+         \"spawn\": {\"ground\": [\"Stone\"], \"light\": \"any\", \"weight\": 1},
+         \"states\": [{\"name\": \"s\", \"action\": \"stop\",
+                       \"transitions\": [{\"when\": \"no-players\", \"to\": \"t\"}]}]}]";
+    assert!(parse(unknown_state).is_err());
+    assert!(parse(&unknown_state.replace("\"to\": \"t\"", "\"to\": \"s\"")).is_ok());
+  }
+
+  #[test]
+  fn tag_round() {
+    let behavior = defaults().into_iter().find(|archetype| archetype.name == "tagger").unwrap().behavior;
+    let at = |d: f32| Observations { to_player: Some(Vector3::new(d, 0.0, 0.0)), path_to_player: None };
+
+    let mut state = 0;
+    state = behavior.next_state(state, &at(10.0));
+    assert_eq!(state, 0);
+    // Tagged.
+    state = behavior.next_state(state, &at(1.0));
+    assert_eq!(state, 1);
+    // The player ran off; give chase.
+    state = behavior.next_state(state, &at(9.0));
+    assert_eq!(state, 2);
+    assert_eq!(behavior.act(state, &at(9.0), 10.0, Vector3::new(0.0, -0.5, 0.0)), Vector3::new(4.5, -0.5, 0.0));
+    // Tag them back.
+    state = behavior.next_state(state, &at(1.0));
+    assert_eq!(state, 3);
+    state = behavior.next_state(state, &Observations { to_player: None, path_to_player: None });
+    assert_eq!(state, 0);
+  }
+}
@@ -0,0 +1,137 @@
+//! Mob behavior, as a state machine driven by what the mob can see of the world.
+
+use cgmath::{Vector3, InnerSpace};
+
+/// Index of a state in a `T`.
+pub type StateId = usize;
+
+/// Something a mob can notice about the world.
+#[derive(Debug, Clone, Copy)]
+pub enum Condition {
+  /// There are no players around.
+  NoPlayers,
+  /// The nearest player is closer than this.
+  PlayerWithin(f32),
+  /// The nearest player is at least this far away.
+  PlayerBeyond(f32),
+}
+
+/// What a mob does while it's in a state.
+#[derive(Debug, Clone, Copy)]
+pub enum Action {
+  /// Keep moving the same way.
+  Continue,
+  /// Stand still.
+  Stop,
+  /// Head toward the nearest player.
+  Chase,
+  /// Head away from the nearest player.
+  Flee,
+}
+
+#[derive(Debug, Clone)]
+#[allow(missing_docs)]
+pub struct Transition {
+  pub when : Condition,
+  pub to   : StateId,
+}
+
+#[derive(Debug, Clone)]
+#[allow(missing_docs)]
+pub struct State {
+  pub action      : Action,
+  /// Checked in order; the first one whose condition holds is taken.
+  pub transitions : Vec<Transition>,
+}
+
+/// A mob behavior. Mobs start in the first state.
+#[derive(Debug, Clone)]
+#[allow(missing_docs)]
+pub struct T {
+  pub states: Vec<State>,
+}
+
+/// What a mob knows about the world on a given tick.
+#[derive(Debug, Clone, Copy)]
+pub struct Observations {
+  /// The vector from the mob to the nearest player.
+  pub to_player      : Option<Vector3<f32>>,
+  /// Which way to walk to follow a path to the nearest player, if there is one.
+  pub path_to_player : Option<Vector3<f32>>,
+}
+
+impl Action {
+  #[allow(missing_docs)]
+  pub fn parse(name: &str) -> Result<Action, String> {
+    match name {
+      "continue" => Ok(Action::Continue),
+      "stop" => Ok(Action::Stop),
+      "chase" => Ok(Action::Chase),
+      "flee" => Ok(Action::Flee),
+      _ => Err(format!("bad action {:?}; try continue, stop, chase or flee", name)),
+    }
+  }
+}
+
+impl Condition {
+  #[allow(missing_docs)]
+  pub fn holds(&self, observations: &Observations) -> bool {
+    match (*self, observations.to_player) {
+      (Condition::NoPlayers, to_player) => to_player.is_none(),
+      (_, None) => false,
+      (Condition::PlayerWithin(d), Some(to_player)) => to_player.magnitude() < d,
+      (Condition::PlayerBeyond(d), Some(to_player)) => to_player.magnitude() >= d,
+    }
+  }
+}
+
+impl T {
+  /// The state to be in after observing the world from `state`.
+  pub fn next_state(&self, state: StateId, observations: &Observations) -> StateId {
+    self.states[state].transitions.iter()
+      .find(|transition| transition.when.holds(observations))
+      .map(|transition| transition.to)
+      .unwrap_or(state)
+  }
+
+  /// A mob's new speed after acting out `state`.
+  /// Only horizontal speed is changed; the mob's body takes care of the rest.
+  pub fn act(
+    &self,
+    state        : StateId,
+    observations : &Observations,
+    max_speed    : f32,
+    speed        : Vector3<f32>,
+  ) -> Vector3<f32> {
+    let horizontal = |v: Vector3<f32>| Vector3::new(v.x, 0.0, v.z);
+    let with_horizontal = |v: Vector3<f32>| Vector3::new(v.x, speed.y, v.z);
+
+    match (self.states[state].action, observations.to_player) {
+      (Action::Continue, _) => speed,
+      (Action::Stop, _) => with_horizontal(Vector3::new(0.0, 0.0, 0.0)),
+      (_, None) => speed,
+      (Action::Chase, Some(to_player)) => {
+        let v = horizontal(to_player) * 0.5;
+        let v =
+          match observations.path_to_player {
+            // Follow the path around obstacles, but slow down near the player just the same.
+            Some(direction) => direction * v.magnitude(),
+            None => v,
+          };
+        if v.magnitude() > max_speed {
+          with_horizontal(v.normalize_to(max_speed))
+        } else {
+          with_horizontal(v)
+        }
+      },
+      (Action::Flee, Some(to_player)) => {
+        let away = -horizontal(to_player);
+        if away.magnitude2() == 0.0 {
+          speed
+        } else {
+          with_horizontal(away.normalize_to(max_speed))
+        }
+      },
+    }
+  }
+}
@@ -0,0 +1,153 @@
+//! Non-player creatures.
+
+pub mod archetype;
+pub mod behavior;
+pub mod script;
+pub mod spawner;
+
+use cgmath::{Point3, InnerSpace};
+use collision::{Aabb3};
+
+use common::fnv_map;
+use common::protocol;
+
+use body;
+use entity;
+use loader;
+use navigation;
+use physics;
+use server;
+use world;
+use world::center;
+
+/// How far around itself a mob loads terrain.
+const LOAD_DISTANCE: u32 = 2;
+
+/// A mob's mind. Its body lives in the other components of its entity.
+pub struct Mob {
+  pub archetype     : archetype::Id,
+  pub state         : behavior::StateId,
+  pub navigator     : navigation::Follower,
+  pub script_memory : script::Memory,
+}
+
+fn feet(bounds: &Aabb3<f32>) -> Point3<f32> {
+  Point3::new((bounds.min.x + bounds.max.x) * 0.5, bounds.min.y, (bounds.min.z + bounds.max.z) * 0.5)
+}
+
+/// The bounds of the player nearest to `bounds`, if there is one.
+fn nearest_player(bounds: &Aabb3<f32>, players: &[Aabb3<f32>]) -> Option<Aabb3<f32>> {
+  let position = center(bounds);
+  players.iter()
+    .fold(None, |nearest: Option<Aabb3<f32>>, &player| {
+      match nearest {
+        Some(nearest) if (center(&nearest) - position).magnitude2() <= (center(&player) - position).magnitude2() =>
+          Some(nearest),
+        _ => Some(player),
+      }
+    })
+}
+
+/// What a mob can sense of the world around it.
+pub struct Surroundings<'a> {
+  /// The mob's own bounds.
+  pub bounds     : Aabb3<f32>,
+  #[allow(missing_docs)]
+  pub physics_id : entity::id::Misc,
+  /// The bounds of all the living players.
+  pub players    : &'a [Aabb3<f32>],
+}
+
+/// Run a mob's behavior (or its script, if it has one) for one tick.
+pub fn behave(server: &server::T, mob: &mut Mob, body: &mut body::T, surroundings: &Surroundings) {
+  let archetype = &server.mob_archetypes[mob.archetype];
+  let mob_bounds = surroundings.bounds;
+  let player_bounds = nearest_player(&mob_bounds, surroundings.players);
+
+  match script::run(server, mob, body, surroundings, player_bounds) {
+    None => {},
+    Some(intents) => {
+      match intents.walk {
+        None => {},
+        Some(walk) => {
+          let walk =
+            if walk.magnitude() > archetype.speed {
+              walk.normalize_to(archetype.speed)
+            } else {
+              walk
+            };
+          body.speed.x = walk.x;
+          body.speed.z = walk.z;
+        },
+      }
+      if intents.jump {
+        body.start_jump();
+      }
+      return
+    },
+  }
+
+  let mut observations =
+    behavior::Observations {
+      to_player      : player_bounds.map(|bounds| center(&bounds) - center(&mob_bounds)),
+      path_to_player : None,
+    };
+  mob.state = archetype.behavior.next_state(mob.state, &observations);
+
+  let mut jump = false;
+  match (archetype.behavior.states[mob.state].action, player_bounds) {
+    (behavior::Action::Chase, Some(player_bounds)) => {
+      let steering =
+        mob.navigator.steer(
+          &server.terrain_loader.terrain,
+          &navigation::Rules::for_size(&archetype.size),
+          &feet(&mob_bounds),
+          &feet(&player_bounds),
+        );
+      observations.path_to_player = steering.map(|steering| steering.direction);
+      jump = steering.map_or(false, |steering| steering.jump);
+    },
+    _ => {},
+  }
+
+  body.speed = archetype.behavior.act(mob.state, &observations, archetype.speed, body.speed);
+  if jump {
+    body.start_jump();
+  }
+}
+
+/// Put a new mob into the world.
+pub fn add(
+  server    : &server::T,
+  world     : &mut world::T,
+  archetype : archetype::Id,
+  bounds    : &Aabb3<f32>,
+) -> Option<entity::id::Mob> {
+  let id = world.create();
+  if !world::embody(server, world, id, bounds, physics::MiscKind::Mob(id)) {
+    return None
+  }
+  // Mobs set their own speed, and keep it until their behavior changes it.
+  world.bodies.insert(id, body::frictionless());
+
+  let mob =
+    Mob {
+      archetype     : archetype,
+      state         : 0,
+      navigator     : navigation::follower(),
+      script_memory : fnv_map::new(),
+    };
+  world.mobs.insert(id, mob);
+  world.appearances.insert(id, world::Appearance::Mob);
+  world.loaders.insert(id, loader::new(&server.owner_allocator, LOAD_DISTANCE));
+  Some(id)
+}
+
+/// Take a mob out of the world, and let go of the terrain it was keeping loaded.
+pub fn remove(server: &server::T, world: &mut world::T, id: entity::id::Mob) {
+  world::destroy(server, world, id);
+
+  for (_, client) in server.clients.lock().unwrap().iter_mut() {
+    client.send(protocol::ServerToClient::RemoveMob(id));
+  }
+}
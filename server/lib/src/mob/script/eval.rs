@@ -0,0 +1,532 @@
+//! Evaluate mob scripts.
+//!
+//! Scripts can't loop or define functions, and every step costs fuel, so a script always finishes
+//! (or runs dry) quickly. Some of the host's functions can take a while, so scripts also have a
+//! deadline. The only way a script can touch the world is through its `Host`.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+use time;
+
+use common::fnv_map;
+
+use mob::script::Error;
+use mob::script::parse::Expr;
+
+/// How much fuel it costs to evaluate one expression.
+const STEP_COST: u32 = 1;
+/// How much fuel a raycast costs.
+const RAYCAST_COST: u32 = 20;
+/// How much fuel asking for a path costs, since it might run a search.
+const PATH_COST: u32 = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(missing_docs)]
+pub enum Value {
+  Nil,
+  Bool(bool),
+  Number(f32),
+  Vector(Vector3<f32>),
+}
+
+impl Value {
+  fn is_true(&self) -> bool {
+    match *self {
+      Value::Nil | Value::Bool(false) => false,
+      _ => true,
+    }
+  }
+}
+
+/// Values a script keeps between ticks, with `set` and `get`.
+pub type Memory = fnv_map::T<String, Value>;
+
+/// What the script wants its mob to do this tick.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Intents {
+  /// Which way to walk, if the script said. The speed is capped by the mob's top speed.
+  pub walk : Option<Vector3<f32>>,
+  #[allow(missing_docs)]
+  pub jump : bool,
+}
+
+/// The view of the world a script gets.
+pub trait Host {
+  #[allow(missing_docs)]
+  fn position(&mut self) -> Point3<f32>;
+  #[allow(missing_docs)]
+  fn speed(&mut self) -> Vector3<f32>;
+  /// The vector to the nearest player.
+  fn nearest_player(&mut self) -> Option<Vector3<f32>>;
+  /// How many players are within `distance`.
+  fn players_within(&mut self, distance: f32) -> usize;
+  /// The distance to the terrain along `direction`, if it's within `max_distance`.
+  fn raycast(&mut self, direction: &Vector3<f32>, max_distance: f32) -> Option<f32>;
+  /// Which way to walk to follow a path to the nearest player, and whether a jump is needed.
+  fn path_to_player(&mut self) -> Option<(Vector3<f32>, bool)>;
+}
+
+struct Context<'a, H: Host + 'a> {
+  host        : &'a mut H,
+  memory      : &'a mut Memory,
+  intents     : Intents,
+  fuel        : u32,
+  deadline_ns : u64,
+  bindings    : Vec<(String, Value)>,
+}
+
+/// Run a script for one tick, with `fuel` to spend, and until `time::precise_time_ns()` reaches `deadline_ns`.
+pub fn run<H: Host>(
+  program     : &[Expr],
+  host        : &mut H,
+  memory      : &mut Memory,
+  fuel        : u32,
+  deadline_ns : u64,
+) -> Result<Intents, Error> {
+  let mut context =
+    Context {
+      host        : host,
+      memory      : memory,
+      intents     : Intents { walk: None, jump: false },
+      fuel        : fuel,
+      deadline_ns : deadline_ns,
+      bindings    : Vec::new(),
+    };
+  for expr in program {
+    try!(context.eval(expr));
+  }
+  Ok(context.intents)
+}
+
+fn number(name: &str, value: Value) -> Result<f32, Error> {
+  match value {
+    Value::Number(x) => Ok(x),
+    value => Err(Error::BadArguments(format!("{} expected a number, got {:?}", name, value))),
+  }
+}
+
+fn vector(name: &str, value: Value) -> Result<Vector3<f32>, Error> {
+  match value {
+    Value::Vector(v) => Ok(v),
+    value => Err(Error::BadArguments(format!("{} expected a vector, got {:?}", name, value))),
+  }
+}
+
+fn arity(name: &str, args: &[Value], n: usize) -> Result<(), Error> {
+  if args.len() == n {
+    Ok(())
+  } else {
+    Err(Error::BadArguments(format!("{} takes {} arguments, got {}", name, n, args.len())))
+  }
+}
+
+fn symbol<'e>(name: &str, expr: &'e Expr) -> Result<&'e str, Error> {
+  match *expr {
+    Expr::Symbol(ref symbol) => Ok(symbol),
+    ref expr => Err(Error::BadArguments(format!("{} expected a name, got {:?}", name, expr))),
+  }
+}
+
+/// Catch values that overflowed or came out of nonsense like `(* 0 huge)`, before anything uses them.
+fn finite(name: &str, value: Value) -> Result<Value, Error> {
+  let is_finite =
+    match value {
+      Value::Number(x) => x.is_finite(),
+      Value::Vector(v) => v.x.is_finite() && v.y.is_finite() && v.z.is_finite(),
+      Value::Nil | Value::Bool(_) => true,
+    };
+  if is_finite {
+    Ok(value)
+  } else {
+    Err(Error::NotFinite(String::from(name)))
+  }
+}
+
+fn compare<F: Fn(f32, f32) -> bool>(name: &str, args: &[Value], f: F) -> Result<Value, Error> {
+  try!(arity(name, args, 2));
+  Ok(Value::Bool(f(try!(number(name, args[0])), try!(number(name, args[1])))))
+}
+
+impl<'a, H: Host> Context<'a, H> {
+  fn burn(&mut self, fuel: u32) -> Result<(), Error> {
+    if self.fuel < fuel {
+      self.fuel = 0;
+      return Err(Error::OutOfFuel)
+    }
+    self.fuel -= fuel;
+    if time::precise_time_ns() > self.deadline_ns {
+      return Err(Error::OutOfTime)
+    }
+    Ok(())
+  }
+
+  fn eval(&mut self, expr: &Expr) -> Result<Value, Error> {
+    try!(self.burn(STEP_COST));
+    match *expr {
+      Expr::Number(x) => Ok(Value::Number(x)),
+      Expr::Symbol(ref name) => self.lookup(name),
+      Expr::List(ref list) => {
+        let (head, rest) =
+          match list.split_first() {
+            None => return Ok(Value::Nil),
+            Some(split) => split,
+          };
+        let name = try!(symbol("call", head));
+        match self.special_form(name, rest) {
+          Some(result) => result,
+          None => {
+            let mut args = Vec::with_capacity(rest.len());
+            for arg in rest {
+              args.push(try!(self.eval(arg)));
+            }
+            self.call(name, &args).and_then(|value| finite(name, value))
+          },
+        }
+      },
+    }
+  }
+
+  fn lookup(&mut self, name: &str) -> Result<Value, Error> {
+    match name {
+      "nil" => return Ok(Value::Nil),
+      "true" => return Ok(Value::Bool(true)),
+      "false" => return Ok(Value::Bool(false)),
+      _ => {},
+    }
+    self.bindings.iter().rev()
+      .find(|&&(ref bound, _)| bound == name)
+      .map(|&(_, value)| value)
+      .ok_or_else(|| Error::UnboundSymbol(String::from(name)))
+  }
+
+  fn eval_body(&mut self, body: &[Expr]) -> Result<Value, Error> {
+    let mut last = Value::Nil;
+    for expr in body {
+      last = try!(self.eval(expr));
+    }
+    Ok(last)
+  }
+
+  /// Forms that don't evaluate all their arguments up front. Returns None if `name` isn't one.
+  fn special_form(&mut self, name: &str, args: &[Expr]) -> Option<Result<Value, Error>> {
+    let result =
+      match name {
+        "if" => self.eval_if(args),
+        "do" => self.eval_body(args),
+        "let" => self.eval_let(args),
+        "and" => {
+          let mut last = Value::Bool(true);
+          for arg in args {
+            last = match self.eval(arg) { Ok(v) => v, Err(err) => return Some(Err(err)) };
+            if !last.is_true() {
+              break
+            }
+          }
+          Ok(last)
+        },
+        "or" => {
+          let mut last = Value::Bool(false);
+          for arg in args {
+            last = match self.eval(arg) { Ok(v) => v, Err(err) => return Some(Err(err)) };
+            if last.is_true() {
+              break
+            }
+          }
+          Ok(last)
+        },
+        "set" => self.eval_set(args),
+        "get" => {
+          if args.len() != 1 {
+            return Some(Err(Error::BadArguments(String::from("get takes a name"))))
+          }
+          symbol(name, &args[0]).map(|key| self.memory.get(key).cloned().unwrap_or(Value::Nil))
+        },
+        _ => return None,
+      };
+    Some(result)
+  }
+
+  fn eval_if(&mut self, args: &[Expr]) -> Result<Value, Error> {
+    if args.len() < 2 || args.len() > 3 {
+      return Err(Error::BadArguments(String::from("if takes a condition, a consequent and maybe an alternative")))
+    }
+    if try!(self.eval(&args[0])).is_true() {
+      self.eval(&args[1])
+    } else if args.len() == 3 {
+      self.eval(&args[2])
+    } else {
+      Ok(Value::Nil)
+    }
+  }
+
+  fn eval_set(&mut self, args: &[Expr]) -> Result<Value, Error> {
+    if args.len() != 2 {
+      return Err(Error::BadArguments(String::from("set takes a name and a value")))
+    }
+    let key = String::from(try!(symbol("set", &args[0])));
+    let value = try!(self.eval(&args[1]));
+    self.memory.insert(key, value);
+    Ok(value)
+  }
+
+  fn eval_binding(&mut self, binding: &Expr) -> Result<(String, Value), Error> {
+    match *binding {
+      Expr::List(ref pair) if pair.len() == 2 => {
+        let name = String::from(try!(symbol("let", &pair[0])));
+        let value = try!(self.eval(&pair[1]));
+        Ok((name, value))
+      },
+      ref binding => Err(Error::BadArguments(format!("let expected (name value), got {:?}", binding))),
+    }
+  }
+
+  // (let ((name value) ...) body...)
+  fn eval_let(&mut self, args: &[Expr]) -> Result<Value, Error> {
+    let bindings =
+      match args.first() {
+        Some(&Expr::List(ref bindings)) => bindings,
+        _ => return Err(Error::BadArguments(String::from("let expects a list of bindings"))),
+      };
+
+    let depth = self.bindings.len();
+    let mut result = Ok(Value::Nil);
+    for binding in bindings {
+      match self.eval_binding(binding) {
+        Ok(bound) => self.bindings.push(bound),
+        Err(err) => {
+          result = Err(err);
+          break
+        },
+      }
+    }
+    if result.is_ok() {
+      result = self.eval_body(&args[1..]);
+    }
+    self.bindings.truncate(depth);
+    result
+  }
+
+  fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, Error> {
+    match name {
+      "+" => {
+        let mut sum =
+          match args.first() {
+            Some(&Value::Vector(_)) => Value::Vector(Vector3::new(0.0, 0.0, 0.0)),
+            _ => Value::Number(0.0),
+          };
+        for &arg in args {
+          sum =
+            match (sum, arg) {
+              (Value::Number(a), Value::Number(b)) => Value::Number(a + b),
+              (Value::Vector(a), Value::Vector(b)) => Value::Vector(a + b),
+              (_, arg) => return Err(Error::BadArguments(format!("can't add {:?}", arg))),
+            };
+        }
+        Ok(sum)
+      },
+      "-" => {
+        match (args.len(), args.get(0).cloned(), args.get(1).cloned()) {
+          (1, Some(Value::Number(a)), _) => Ok(Value::Number(-a)),
+          (1, Some(Value::Vector(a)), _) => Ok(Value::Vector(-a)),
+          (2, Some(Value::Number(a)), Some(Value::Number(b))) => Ok(Value::Number(a - b)),
+          (2, Some(Value::Vector(a)), Some(Value::Vector(b))) => Ok(Value::Vector(a - b)),
+          _ => Err(Error::BadArguments(format!("can't subtract {:?}", args))),
+        }
+      },
+      "*" => {
+        try!(arity(name, args, 2));
+        match (args[0], args[1]) {
+          (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a * b)),
+          (Value::Vector(a), Value::Number(b)) | (Value::Number(b), Value::Vector(a)) => Ok(Value::Vector(a * b)),
+          _ => Err(Error::BadArguments(format!("can't multiply {:?}", args))),
+        }
+      },
+      "/" => {
+        try!(arity(name, args, 2));
+        match (args[0], args[1]) {
+          (Value::Number(a), Value::Number(b)) if b != 0.0 => Ok(Value::Number(a / b)),
+          (Value::Vector(a), Value::Number(b)) if b != 0.0 => Ok(Value::Vector(a / b)),
+          _ => Err(Error::BadArguments(format!("can't divide {:?}", args))),
+        }
+      },
+      "<" => compare(name, args, |a, b| a < b),
+      ">" => compare(name, args, |a, b| a > b),
+      "<=" => compare(name, args, |a, b| a <= b),
+      ">=" => compare(name, args, |a, b| a >= b),
+      "=" => {
+        try!(arity(name, args, 2));
+        Ok(Value::Bool(args[0] == args[1]))
+      },
+      "not" => {
+        try!(arity(name, args, 1));
+        Ok(Value::Bool(!args[0].is_true()))
+      },
+      "nil?" => {
+        try!(arity(name, args, 1));
+        Ok(Value::Bool(args[0] == Value::Nil))
+      },
+
+      "vec" => {
+        try!(arity(name, args, 3));
+        Ok(Value::Vector(Vector3::new(try!(number(name, args[0])), try!(number(name, args[1])), try!(number(name, args[2])))))
+      },
+      "x" | "y" | "z" => {
+        try!(arity(name, args, 1));
+        let v = try!(vector(name, args[0]));
+        Ok(Value::Number(match name { "x" => v.x, "y" => v.y, _ => v.z }))
+      },
+      "length" => {
+        try!(arity(name, args, 1));
+        Ok(Value::Number(try!(vector(name, args[0])).magnitude()))
+      },
+      // Nil for the zero vector, which has no direction.
+      "normalize" => {
+        try!(arity(name, args, 1));
+        let v = try!(vector(name, args[0]));
+        if v.magnitude2() == 0.0 {
+          Ok(Value::Nil)
+        } else {
+          Ok(Value::Vector(v.normalize()))
+        }
+      },
+
+      "position" => {
+        try!(arity(name, args, 0));
+        let p = self.host.position();
+        Ok(Value::Vector(Vector3::new(p.x, p.y, p.z)))
+      },
+      "speed" => {
+        try!(arity(name, args, 0));
+        Ok(Value::Vector(self.host.speed()))
+      },
+      "nearest-player" => {
+        try!(arity(name, args, 0));
+        Ok(self.host.nearest_player().map_or(Value::Nil, Value::Vector))
+      },
+      "players-within" => {
+        try!(arity(name, args, 1));
+        let distance = try!(number(name, args[0]));
+        Ok(Value::Number(self.host.players_within(distance) as f32))
+      },
+      "raycast" => {
+        try!(arity(name, args, 2));
+        let direction = try!(vector(name, args[0]));
+        let max_distance = try!(number(name, args[1]));
+        try!(self.burn(RAYCAST_COST));
+        Ok(self.host.raycast(&direction, max_distance).map_or(Value::Nil, Value::Number))
+      },
+      // The direction of the path, and a jump intent if the path needs one.
+      "path-to-player" => {
+        try!(arity(name, args, 0));
+        try!(self.burn(PATH_COST));
+        match self.host.path_to_player() {
+          None => Ok(Value::Nil),
+          Some((direction, jump)) => {
+            self.intents.jump |= jump;
+            Ok(Value::Vector(direction))
+          },
+        }
+      },
+
+      "walk" => {
+        try!(arity(name, args, 1));
+        let v = try!(vector(name, args[0]));
+        self.intents.walk = Some(Vector3::new(v.x, 0.0, v.z));
+        Ok(Value::Nil)
+      },
+      "stop" => {
+        try!(arity(name, args, 0));
+        self.intents.walk = Some(Vector3::new(0.0, 0.0, 0.0));
+        Ok(Value::Nil)
+      },
+      "jump" => {
+        try!(arity(name, args, 0));
+        self.intents.jump = true;
+        Ok(Value::Nil)
+      },
+
+      name => Err(Error::UnknownFunction(String::from(name))),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3};
+
+  use common::fnv_map;
+
+  use mob::script::Error;
+  use mob::script::parse::parse;
+
+  use super::{run, Host, Intents, Memory, Value};
+
+  struct Fake {
+    to_player: Option<Vector3<f32>>,
+  }
+
+  impl Host for Fake {
+    fn position(&mut self) -> Point3<f32> { Point3::new(0.0, 0.0, 0.0) }
+    fn speed(&mut self) -> Vector3<f32> { Vector3::new(0.0, 0.0, 0.0) }
+    fn nearest_player(&mut self) -> Option<Vector3<f32>> { self.to_player }
+    fn players_within(&mut self, _: f32) -> usize { 0 }
+    fn raycast(&mut self, _: &Vector3<f32>, _: f32) -> Option<f32> { None }
+    fn path_to_player(&mut self) -> Option<(Vector3<f32>, bool)> { None }
+  }
+
+  const CHASE: &'static str = "
+    (let ((to-player (nearest-player)))
+      (if (nil? to-player)
+        (stop)
+        (do
+          (set chases (+ 1 (or (get chases) 0)))
+          (walk to-player))))";
+
+  #[test]
+  fn intents_and_memory() {
+    let program = parse(CHASE).unwrap();
+    let mut memory: Memory = fnv_map::new();
+    let mut host = Fake { to_player: Some(Vector3::new(3.0, 1.0, 4.0)) };
+
+    let intents = run(&program, &mut host, &mut memory, 100, u64::max_value()).unwrap();
+    assert_eq!(intents, Intents { walk: Some(Vector3::new(3.0, 0.0, 4.0)), jump: false });
+    run(&program, &mut host, &mut memory, 100, u64::max_value()).unwrap();
+    assert_eq!(memory.get("chases"), Some(&Value::Number(2.0)));
+
+    host.to_player = None;
+    let intents = run(&program, &mut host, &mut memory, 100, u64::max_value()).unwrap();
+    assert_eq!(intents.walk, Some(Vector3::new(0.0, 0.0, 0.0)));
+  }
+
+  #[test]
+  fn runs_out_of_fuel() {
+    let program = parse(CHASE).unwrap();
+    let mut memory: Memory = fnv_map::new();
+    let mut host = Fake { to_player: Some(Vector3::new(3.0, 1.0, 4.0)) };
+    match run(&program, &mut host, &mut memory, 5, u64::max_value()) {
+      Err(Error::OutOfFuel) => {},
+      result => panic!("expected to run out of fuel, got {:?}", result),
+    }
+  }
+
+  #[test]
+  fn runs_out_of_time() {
+    let program = parse(CHASE).unwrap();
+    let mut memory: Memory = fnv_map::new();
+    let mut host = Fake { to_player: Some(Vector3::new(3.0, 1.0, 4.0)) };
+    match run(&program, &mut host, &mut memory, 100, 0) {
+      Err(Error::OutOfTime) => {},
+      result => panic!("expected to run out of time, got {:?}", result),
+    }
+  }
+
+  #[test]
+  fn overflow() {
+    let program = parse("(walk (vec (* 3e38 10) 0 0))").unwrap();
+    let mut memory: Memory = fnv_map::new();
+    let mut host = Fake { to_player: None };
+    match run(&program, &mut host, &mut memory, 100, u64::max_value()) {
+      Err(Error::NotFinite(_)) => {},
+      result => panic!("expected an overflow, got {:?}", result),
+    }
+  }
+}
@@ -0,0 +1,249 @@
+//! Mob behaviors written as scripts.
+//!
+//! Scripts live in `SCRIPT_DIR` in the data directory and are reloaded when they change. Each
+//! tick, a scripted mob's script is run with a fixed amount of fuel and a little wall time, and all
+//! the scripts together get a slice of wall time; scripts that break are switched off (until they
+//! change again) and the mob falls back to its archetype's built-in behavior.
+
+mod eval;
+mod parse;
+
+use cgmath::{Point3, Vector3, InnerSpace};
+use collision::{Aabb3, Ray3};
+use std;
+use std::io::Read;
+use std::time::SystemTime;
+use time;
+
+use common::fnv_map;
+use common::interval_timer::IntervalTimer;
+
+use body;
+use data;
+use entity;
+use mob::{archetype, center, feet, Mob, Surroundings};
+use navigation;
+use raycast;
+use server;
+
+pub use self::eval::{Intents, Memory};
+
+/// Where scripts are loaded from, in the data directory.
+const SCRIPT_DIR: &'static str = "mob_scripts";
+const SCRIPT_EXTENSION: &'static str = "mob";
+/// How often we check whether scripts have changed.
+const RELOAD_INTERVAL_NS: u64 = 1000000000;
+/// How much fuel each mob's script gets per tick.
+const FUEL_PER_TICK: u32 = 2000;
+/// How much wall time one mob's script gets per tick, for when the host's functions are slow.
+const SCRIPT_NS_PER_TICK: u64 = 200000;
+/// How much wall time all the scripts together get per tick. Mobs whose turn comes after that
+/// just keep moving the way they were.
+const BUDGET_NS_PER_TICK: u64 = 2000000;
+/// Scripts can't see further than this with a raycast.
+const MAX_RAYCAST_DISTANCE: f32 = 32.0;
+
+/// Something wrong with a script.
+#[derive(Debug, Clone)]
+#[allow(missing_docs)]
+pub enum Error {
+  Parse(String),
+  UnknownFunction(String),
+  UnboundSymbol(String),
+  BadArguments(String),
+  /// Arithmetic in the script overflowed or came out as nan.
+  NotFinite(String),
+  /// The script did too much work in one tick.
+  OutOfFuel,
+  /// The script took too long in one tick.
+  OutOfTime,
+}
+
+struct Script {
+  modified : Option<SystemTime>,
+  /// None if the script couldn't be loaded, or was switched off because it broke.
+  program  : Option<Vec<parse::Expr>>,
+}
+
+/// The scripts for all the mob archetypes.
+pub struct T {
+  scripts      : fnv_map::T<String, Script>,
+  reload_timer : IntervalTimer,
+  deadline_ns  : u64,
+}
+
+fn path(name: &str) -> std::path::PathBuf {
+  data::path(SCRIPT_DIR).join(name).with_extension(SCRIPT_EXTENSION)
+}
+
+fn modified(name: &str) -> Option<SystemTime> {
+  std::fs::metadata(path(name)).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn load(name: &str) -> Option<Vec<parse::Expr>> {
+  let mut source = String::new();
+  let read = std::fs::File::open(path(name)).and_then(|mut file| file.read_to_string(&mut source));
+  if let Err(err) = read {
+    warn!("Couldn't read mob script {:?}: {:?}", path(name), err);
+    return None
+  }
+
+  match parse::parse(&source) {
+    Ok(program) => {
+      info!("Loaded mob script {:?}", path(name));
+      Some(program)
+    },
+    Err(err) => {
+      warn!("Couldn't parse mob script {:?}: {:?}", path(name), err);
+      None
+    },
+  }
+}
+
+/// Load the scripts used by `archetypes`.
+pub fn new(archetypes: &[archetype::T]) -> T {
+  let now = time::precise_time_ns();
+  let mut scripts = fnv_map::new();
+  for name in archetypes.iter().filter_map(|archetype| archetype.script.as_ref()) {
+    scripts.insert(name.clone(), Script { modified: modified(name), program: load(name) });
+  }
+  T {
+    scripts      : scripts,
+    reload_timer : IntervalTimer::new(RELOAD_INTERVAL_NS, now),
+    deadline_ns  : now,
+  }
+}
+
+impl T {
+  /// Reload scripts that have changed, if it's time to check, and start the clock on this tick's budget.
+  pub fn start_tick(&mut self) {
+    let now = time::precise_time_ns();
+    if self.reload_timer.update(now) > 0 {
+      for (name, script) in self.scripts.iter_mut() {
+        let modified = modified(name);
+        if modified != script.modified {
+          script.modified = modified;
+          script.program = load(name);
+        }
+      }
+    }
+    self.deadline_ns = time::precise_time_ns() + BUDGET_NS_PER_TICK;
+  }
+}
+
+/// A script's view of the world, from one mob.
+struct MobHost<'a> {
+  server        : &'a server::T,
+  physics_id    : entity::id::Misc,
+  bounds        : Aabb3<f32>,
+  speed         : Vector3<f32>,
+  players       : &'a [Aabb3<f32>],
+  player_bounds : Option<Aabb3<f32>>,
+  rules         : navigation::Rules,
+  navigator     : &'a mut navigation::Follower,
+}
+
+impl<'a> eval::Host for MobHost<'a> {
+  fn position(&mut self) -> Point3<f32> {
+    center(&self.bounds)
+  }
+
+  fn speed(&mut self) -> Vector3<f32> {
+    self.speed
+  }
+
+  fn nearest_player(&mut self) -> Option<Vector3<f32>> {
+    self.player_bounds.map(|bounds| center(&bounds) - center(&self.bounds))
+  }
+
+  fn players_within(&mut self, distance: f32) -> usize {
+    let position = center(&self.bounds);
+    self.players.iter()
+      .filter(|bounds| (center(bounds) - position).magnitude() < distance)
+      .count()
+  }
+
+  fn raycast(&mut self, direction: &Vector3<f32>, max_distance: f32) -> Option<f32> {
+    let ray = Ray3::new(center(&self.bounds), *direction);
+    raycast::cast(
+      self.server,
+      &ray,
+      f32::min(max_distance, MAX_RAYCAST_DISTANCE),
+      &[raycast::Layer::Terrain],
+      Some(self.physics_id),
+    )
+    .map(|hit| hit.distance)
+  }
+
+  fn path_to_player(&mut self) -> Option<(Vector3<f32>, bool)> {
+    let player_bounds =
+      match self.player_bounds {
+        None => return None,
+        Some(bounds) => bounds,
+      };
+    self.navigator.steer(
+      &self.server.terrain_loader.terrain,
+      &self.rules,
+      &feet(&self.bounds),
+      &feet(&player_bounds),
+    )
+    .map(|steering| (steering.direction, steering.jump))
+  }
+}
+
+/// Run a mob's script for this tick.
+/// Returns None if the mob doesn't have a working script, or if the scripts are out of time this tick.
+pub fn run(
+  server        : &server::T,
+  mob           : &mut Mob,
+  body          : &body::T,
+  surroundings  : &Surroundings,
+  player_bounds : Option<Aabb3<f32>>,
+) -> Option<Intents> {
+  let archetype = &server.mob_archetypes[mob.archetype];
+  let name =
+    match archetype.script {
+      None => return None,
+      Some(ref name) => name,
+    };
+
+  let mut scripts = server.mob_scripts.lock().unwrap();
+  let now = time::precise_time_ns();
+  if now > scripts.deadline_ns {
+    return Some(Intents { walk: None, jump: false })
+  }
+  let deadline_ns = std::cmp::min(now + SCRIPT_NS_PER_TICK, scripts.deadline_ns);
+  let script =
+    match scripts.scripts.get_mut(name) {
+      None => return None,
+      Some(script) => script,
+    };
+
+  let result =
+    match script.program {
+      None => return None,
+      Some(ref program) => {
+        let mut host =
+          MobHost {
+            server        : server,
+            physics_id    : surroundings.physics_id,
+            bounds        : surroundings.bounds,
+            speed         : body.speed,
+            players       : surroundings.players,
+            player_bounds : player_bounds,
+            rules         : navigation::Rules::for_size(&archetype.size),
+            navigator     : &mut mob.navigator,
+          };
+        eval::run(program, &mut host, &mut mob.script_memory, FUEL_PER_TICK, deadline_ns)
+      },
+    };
+
+  match result {
+    Ok(intents) => Some(intents),
+    Err(err) => {
+      warn!("Mob script {:?} failed: {:?}. Switching it off until it changes.", path(name), err);
+      script.program = None;
+      None
+    },
+  }
+}
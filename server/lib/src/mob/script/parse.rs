@@ -0,0 +1,138 @@
+//! Read mob scripts into expressions.
+//!
+//! Scripts are s-expressions: numbers, symbols and parenthesized lists. `;` starts a comment.
+
+use mob::script::Error;
+
+/// Lists can't be nested deeper than this, which keeps evaluation from overflowing the stack.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(missing_docs)]
+pub enum Expr {
+  Number(f32),
+  Symbol(String),
+  List(Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+  Open,
+  Close,
+  Atom(String),
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+  let mut tokens = Vec::new();
+  let mut atom = String::new();
+  let mut in_comment = false;
+
+  for c in source.chars() {
+    if in_comment {
+      in_comment = c != '\n';
+      continue
+    }
+
+    let ends_atom = c == '(' || c == ')' || c == ';' || c.is_whitespace();
+    if ends_atom && !atom.is_empty() {
+      tokens.push(Token::Atom(atom));
+      atom = String::new();
+    }
+
+    match c {
+      '(' => tokens.push(Token::Open),
+      ')' => tokens.push(Token::Close),
+      ';' => in_comment = true,
+      c if c.is_whitespace() => {},
+      c => atom.push(c),
+    }
+  }
+  if !atom.is_empty() {
+    tokens.push(Token::Atom(atom));
+  }
+
+  tokens
+}
+
+/// Parse a whole script into its top-level expressions.
+pub fn parse(source: &str) -> Result<Vec<Expr>, Error> {
+  let tokens = tokenize(source);
+  let mut tokens = tokens.into_iter();
+
+  // Lists being built, innermost last. The outermost one collects the top-level expressions.
+  let mut stack: Vec<Vec<Expr>> = vec!(Vec::new());
+  while let Some(token) = tokens.next() {
+    match token {
+      Token::Open => {
+        if stack.len() > MAX_DEPTH {
+          return Err(Error::Parse(format!("lists nested deeper than {}", MAX_DEPTH)))
+        }
+        stack.push(Vec::new());
+      },
+      Token::Close => {
+        if stack.len() == 1 {
+          return Err(Error::Parse(String::from("unexpected )")))
+        }
+        let list = stack.pop().unwrap();
+        stack.last_mut().unwrap().push(Expr::List(list));
+      },
+      Token::Atom(atom) => {
+        let expr =
+          match atom.parse::<f32>() {
+            Ok(number) => {
+              // This catches "nan" and "inf" as well as numbers too big for an f32.
+              if !number.is_finite() {
+                return Err(Error::Parse(format!("{} isn't a finite number", atom)))
+              }
+              Expr::Number(number)
+            },
+            Err(_) => Expr::Symbol(atom),
+          };
+        stack.last_mut().unwrap().push(expr);
+      },
+    }
+  }
+
+  if stack.len() > 1 {
+    return Err(Error::Parse(String::from("missing )")))
+  }
+  Ok(stack.pop().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+  use super::{parse, Expr};
+
+  #[test]
+  fn nested_lists() {
+    let parsed = parse("; chase\n(walk (vec 1 0 -2.5)) stop").unwrap();
+    assert_eq!(
+      parsed,
+      vec!(
+        Expr::List(vec!(
+          Expr::Symbol(String::from("walk")),
+          Expr::List(vec!(
+            Expr::Symbol(String::from("vec")),
+            Expr::Number(1.0),
+            Expr::Number(0.0),
+            Expr::Number(-2.5),
+          )),
+        )),
+        Expr::Symbol(String::from("stop")),
+      ),
+    );
+  }
+
+  #[test]
+  fn unbalanced() {
+    assert!(parse("(walk (vec 1 0 0)").is_err());
+    assert!(parse("stop)").is_err());
+  }
+
+  #[test]
+  fn not_finite() {
+    assert!(parse("(walk (vec nan 0 0))").is_err());
+    assert!(parse("(walk (vec 1 -inf 0))").is_err());
+    assert!(parse("(walk (vec 1 0 1e50))").is_err());
+  }
+}
@@ -0,0 +1,183 @@
+//! Keep mobs around the players, and clear away mobs nobody is near.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+use collision::{Aabb3};
+use rand;
+use rand::Rng;
+use std::f32::consts::PI;
+use time;
+
+use common::interval_timer::IntervalTimer;
+
+use entity;
+use mob;
+use server;
+use spawn;
+
+/// How often the spawner runs.
+const SPAWN_INTERVAL_NS: u64 = 1000000000;
+/// How many mobs we try to keep within `SPAWN_RADIUS` of each player.
+const MOBS_PER_PLAYER: usize = 6;
+/// Mobs aren't spawned closer than this to a player, so they don't appear out of nowhere.
+const MIN_SPAWN_DISTANCE: f32 = 16.0;
+/// Mobs are spawned at most this far from a player.
+const SPAWN_RADIUS: f32 = 48.0;
+/// How far above or below a player we look for ground to spawn a mob on.
+const SPAWN_HEIGHT_RANGE: i32 = 32;
+/// Mobs this far from every player are despawned.
+const DESPAWN_DISTANCE: f32 = 96.0;
+
+#[allow(missing_docs)]
+pub struct T {
+  timer: IntervalTimer,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    timer: IntervalTimer::new(SPAWN_INTERVAL_NS, time::precise_time_ns()),
+  }
+}
+
+/// Spawn and despawn mobs, if it's time to.
+pub fn update(server: &server::T) {
+  if server.mob_spawner.lock().unwrap().timer.update(time::precise_time_ns()) == 0 {
+    return
+  }
+
+  let mut spawn_near = Vec::new();
+  {
+    let mut world = server.world.lock().unwrap();
+    let world = &mut *world;
+
+    let players: Vec<Point3<f32>> =
+      world.players.ids().into_iter()
+      .filter(|&id| world.is_living_player(id))
+      .filter_map(|id| world.positions.get(id).cloned())
+      .collect();
+    let mobs: Vec<(entity::id::Mob, Point3<f32>)> =
+      world.mob_ids().into_iter()
+      .filter_map(|id| world.positions.get(id).map(|&position| (id, position)))
+      .collect();
+
+    let distance_to_players = |p: &Point3<f32>| {
+      players.iter()
+        .map(|player| (*player - *p).magnitude())
+        .fold(None, |min: Option<f32>, d| Some(min.map_or(d, |min| f32::min(min, d))))
+    };
+
+    for &(id, position) in &mobs {
+      let far_away =
+        match distance_to_players(&position) {
+          None => true,
+          Some(d) => d > DESPAWN_DISTANCE,
+        };
+      if far_away {
+        debug!("Despawning mob {:?} at {:?}", id, position);
+        mob::remove(server, world, id);
+      }
+    }
+
+    for player in &players {
+      let nearby =
+        mobs.iter()
+        .filter(|&&(_, position)| (position - *player).magnitude() <= SPAWN_RADIUS)
+        .count();
+      if nearby < MOBS_PER_PLAYER {
+        spawn_near.push(*player);
+      }
+    }
+  }
+
+  // Look for spots without holding the world, and only take it again to add what we found.
+  for player in &spawn_near {
+    try_spawn_near(server, player).map(|(archetype, bounds)| {
+      match mob::add(server, &mut server.world.lock().unwrap(), archetype, &bounds) {
+        None => {},
+        Some(id) => debug!("Spawned {} {:?} at {:?}", server.mob_archetypes[archetype].name, id, bounds.min),
+      }
+    });
+  }
+}
+
+/// Try to find a random spot around `player`, and a mob to spawn there.
+fn try_spawn_near(server: &server::T, player: &Point3<f32>) -> Option<(mob::archetype::Id, Aabb3<f32>)> {
+  let (angle, distance): (f32, f32) = {
+    let mut rng = server.rng.lock().unwrap();
+    (rng.gen_range(0.0, 2.0 * PI), rng.gen_range(MIN_SPAWN_DISTANCE, SPAWN_RADIUS))
+  };
+  let x = (player.x + distance * angle.cos()).floor() as i32;
+  let z = (player.z + distance * angle.sin()).floor() as i32;
+  let top = player.y as i32 + SPAWN_HEIGHT_RANGE;
+  let bottom = player.y as i32 - SPAWN_HEIGHT_RANGE;
+
+  let is_day = server.sun.lock().unwrap().is_day();
+  // Only look at terrain that's already there, so spawning mobs doesn't generate any.
+  let mut search = spawn::search(&server.terrain_loader.terrain);
+
+  // Find the ground with a unit box, then see which mobs fit there.
+  let (_, ground) =
+    match spawn::on_ground(&mut search, x, z, top, bottom, &Vector3::new(1.0, 1.0, 1.0)) {
+      None => return None,
+      Some(found) => found,
+    };
+
+  let candidates: Vec<(mob::archetype::Id, u32)> =
+    server.mob_archetypes.iter()
+    .enumerate()
+    .filter(|&(_, archetype)| {
+      archetype.spawn.light.allows(is_day) && archetype.spawn.ground.contains(&ground)
+    })
+    .map(|(id, archetype)| (id, archetype.spawn.weight))
+    .collect();
+
+  let archetype = {
+    let mut rng = server.rng.lock().unwrap();
+    match pick(&mut *rng, &candidates) {
+      None => return None,
+      Some(archetype) => archetype,
+    }
+  };
+
+  let size = server.mob_archetypes[archetype].size;
+  spawn::on_ground(&mut search, x, z, top, bottom, &size).map(|(bounds, _)| (archetype, bounds))
+}
+
+/// Pick one of `candidates`, weighted by their spawn weights.
+fn pick<R: rand::Rng>(
+  rng        : &mut R,
+  candidates : &[(mob::archetype::Id, u32)],
+) -> Option<mob::archetype::Id> {
+  let total: u64 = candidates.iter().map(|&(_, weight)| weight as u64).sum();
+  if total == 0 {
+    return None
+  }
+
+  let mut roll = rng.gen_range(0, total);
+  for &(id, weight) in candidates {
+    if roll < weight as u64 {
+      return Some(id)
+    }
+    roll -= weight as u64;
+  }
+  // The roll is always less than the total, but don't bet the server on it.
+  candidates.last().map(|&(id, _)| id)
+}
+
+#[cfg(test)]
+mod test {
+  use rand;
+
+  use super::pick;
+
+  #[test]
+  fn picks() {
+    let mut rng = rand::thread_rng();
+    assert_eq!(pick(&mut rng, &[]), None);
+    assert_eq!(pick(&mut rng, &[(0, 0), (1, 0)]), None);
+    for _ in 0 .. 100 {
+      assert_eq!(pick(&mut rng, &[(0, 0), (1, 3), (2, 0)]), Some(1));
+      assert!(pick(&mut rng, &[(0, u32::max_value()), (1, u32::max_value())]).is_some());
+    }
+  }
+}
@@ -7,31 +7,50 @@ extern crate bincode;
 extern crate cgmath;
 extern crate collision;
 extern crate common;
+extern crate isosurface_extraction;
 #[macro_use]
 extern crate log;
 extern crate nanomsg;
 extern crate num;
 extern crate rand;
+extern crate serde_json;
 extern crate stopwatch;
 extern crate terrain;
-extern crate thread_scoped;
 extern crate time;
 extern crate voxel_data;
 
+pub mod admin;
+mod body;
+mod brush;
+mod chat;
 mod client_recv_thread;
+mod clipboard;
+pub mod control;
+mod data;
+mod edit_history;
 mod entity;
 mod in_progress_terrain;
-mod init_mobs;
+mod loader;
+mod lock_order;
 mod lod;
 mod mob;
+mod navigation;
 mod octree;
+mod permissions;
 mod physics;
 mod player;
+mod raycast;
 mod run;
 pub mod server;
+mod spawn;
 mod sun;
+mod terrain_collision;
 mod terrain_loader;
+mod tick_timing;
+mod triangle;
 pub mod update_gaia;
 mod update_world;
+mod voxel_updates;
+mod world;
 
 pub use run::run;
@@ -0,0 +1,352 @@
+//! Pathfinding over the voxel terrain.
+//!
+//! The world is treated as a grid of unit cells. A cell is walkable if the voxel beneath it is
+//! non-empty and there's enough empty space above it for the walker to stand in.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use common::fnv_map;
+use common::voxel;
+
+use body;
+use terrain;
+
+/// The most cells a single search will expand.
+const SEARCH_BUDGET: usize = 512;
+/// Cells further than this (horizontally) from the start of a search are never expanded.
+const SEARCH_RADIUS: i32 = 32;
+/// How many ticks a path is followed before it's replanned.
+const REPLAN_TICKS: u32 = 15;
+/// How far the goal can move before we replan.
+const REPLAN_DISTANCE: i32 = 2;
+
+const WALK_COST: u32 = 10;
+const JUMP_COST: u32 = 10;
+const DROP_COST: u32 = 2;
+
+/// What a walker is capable of.
+#[derive(Debug, Clone, Copy)]
+pub struct Rules {
+  /// How many cells tall the walker is.
+  pub height   : i32,
+  /// How high the walker can step up without jumping.
+  pub max_step : i32,
+  /// How high the walker can jump up.
+  pub max_jump : i32,
+  /// How far the walker is willing to drop down.
+  pub max_drop : i32,
+}
+
+impl Rules {
+  /// Rules for something of the given size that can step like a player and jump a couple cells.
+  pub fn for_size(size: &Vector3<f32>) -> Rules {
+    Rules {
+      height   : size.y.ceil() as i32,
+      max_step : body::MAX_STEP_HEIGHT.floor() as i32,
+      max_jump : 2,
+      max_drop : 3,
+    }
+  }
+}
+
+type Cell = (i32, i32, i32);
+
+fn point(cell: Cell) -> Point3<i32> {
+  Point3::new(cell.0, cell.1, cell.2)
+}
+
+/// Is there a walkable cell at `cell`? `solid` says which voxels block movement.
+fn walkable<Solid: FnMut(Cell) -> bool>(solid: &mut Solid, rules: &Rules, cell: Cell) -> bool {
+  let (x, y, z) = cell;
+  solid((x, y - 1, z)) && (0 .. rules.height).all(|dy| !solid((x, y + dy, z)))
+}
+
+/// Is there room to stand `extra` cells higher than `cell`? Needed to jump out of it.
+fn headroom<Solid: FnMut(Cell) -> bool>(solid: &mut Solid, rules: &Rules, cell: Cell, extra: i32) -> bool {
+  let (x, y, z) = cell;
+  (rules.height .. rules.height + extra).all(|dy| !solid((x, y + dy, z)))
+}
+
+#[derive(PartialEq, Eq)]
+struct Open {
+  estimate : u32,
+  cost     : u32,
+  cell     : Cell,
+}
+
+// Lowest estimate first, for the max-heap.
+impl Ord for Open {
+  fn cmp(&self, other: &Open) -> Ordering {
+    match other.estimate.cmp(&self.estimate) {
+      Ordering::Equal => self.cost.cmp(&other.cost),
+      ordering => ordering,
+    }
+  }
+}
+
+impl PartialOrd for Open {
+  fn partial_cmp(&self, other: &Open) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+fn heuristic(cell: Cell, goal: Cell) -> u32 {
+  ((cell.0 - goal.0).abs() + (cell.2 - goal.2).abs()) as u32 * WALK_COST
+}
+
+/// A* from `start` to `goal`, both walkable cells, expanding at most `budget` cells.
+/// If the goal isn't reached within budget, the path leads to the explored cell closest to it.
+/// The returned path starts with `start`.
+fn search<Solid: FnMut(Cell) -> bool>(
+  solid  : &mut Solid,
+  rules  : &Rules,
+  start  : Cell,
+  goal   : Cell,
+  budget : usize,
+) -> Vec<Cell> {
+  let mut came_from: fnv_map::T<Cell, Cell> = fnv_map::new();
+  let mut costs: fnv_map::T<Cell, u32> = fnv_map::new();
+  let mut open = BinaryHeap::new();
+
+  costs.insert(start, 0);
+  open.push(Open { estimate: heuristic(start, goal), cost: 0, cell: start });
+
+  let mut closest = (heuristic(start, goal), start);
+  let mut expanded = 0;
+  while let Some(Open { cost, cell, .. }) = open.pop() {
+    if costs.get(&cell).map_or(false, |&c| c < cost) {
+      // Stale entry.
+      continue
+    }
+
+    let h = heuristic(cell, goal);
+    if h < closest.0 {
+      closest = (h, cell);
+    }
+    if cell == goal {
+      closest = (0, cell);
+      break
+    }
+
+    if expanded >= budget {
+      break
+    }
+    expanded += 1;
+
+    let (x, y, z) = cell;
+    for &(dx, dz) in &[(1, 0), (-1, 0), (0, 1), (0, -1)] {
+      let (nx, nz) = (x + dx, z + dz);
+      if (nx - start.0).abs() > SEARCH_RADIUS || (nz - start.2).abs() > SEARCH_RADIUS {
+        continue
+      }
+
+      // Try the highest landing first, so we walk over things instead of dropping beside them.
+      let mut dy = rules.max_jump;
+      while dy >= -rules.max_drop {
+        let next = (nx, y + dy, nz);
+        if walkable(solid, rules, next) && (dy <= 0 || headroom(solid, rules, cell, dy)) {
+          let step_cost =
+            WALK_COST
+            + if dy > rules.max_step { JUMP_COST } else { 0 }
+            + if dy < 0 { (-dy) as u32 * DROP_COST } else { 0 };
+          let next_cost = cost + step_cost;
+          if costs.get(&next).map_or(true, |&c| next_cost < c) {
+            costs.insert(next, next_cost);
+            came_from.insert(next, cell);
+            open.push(Open { estimate: next_cost + heuristic(next, goal), cost: next_cost, cell: next });
+          }
+          break
+        }
+        dy -= 1;
+      }
+    }
+  }
+
+  let mut path = vec!(closest.1);
+  while let Some(&previous) = came_from.get(path.last().unwrap()) {
+    path.push(previous);
+  }
+  path.reverse();
+  path
+}
+
+/// Find a walkable cell near a position, looking a little above and below it.
+fn nearest_walkable<Solid: FnMut(Cell) -> bool>(
+  solid : &mut Solid,
+  rules : &Rules,
+  feet  : &Point3<f32>,
+) -> Option<Cell> {
+  let (x, y, z) = (feet.x.floor() as i32, feet.y.floor() as i32, feet.z.floor() as i32);
+  for &dy in &[0, 1, -1, 2, -2] {
+    if walkable(solid, rules, (x, y + dy, z)) {
+      return Some((x, y + dy, z))
+    }
+  }
+  None
+}
+
+/// Look up voxels from the terrain, remembering what we've seen.
+fn terrain_solid<'a>(terrain: &'a terrain::T) -> Box<FnMut(Cell) -> bool + 'a> {
+  let mut seen: fnv_map::T<Cell, bool> = fnv_map::new();
+  Box::new(move |cell: Cell| {
+    *seen.entry(cell).or_insert_with(|| {
+      match terrain.load(&voxel::bounds::new(cell.0, cell.1, cell.2, 0)) {
+        voxel::Volume(voxel::Material::Empty) => false,
+        _ => true,
+      }
+    })
+  })
+}
+
+/// Find a path between two positions (of the walker's feet) over the terrain.
+/// Returns the cells to walk through, starting at the one nearest `from`.
+pub fn find_path(
+  terrain : &terrain::T,
+  rules   : &Rules,
+  from    : &Point3<f32>,
+  to      : &Point3<f32>,
+) -> Option<Vec<Point3<i32>>> {
+  let mut solid = terrain_solid(terrain);
+  let start = match nearest_walkable(&mut solid, rules, from) {
+    None => return None,
+    Some(start) => start,
+  };
+  let goal =
+    nearest_walkable(&mut solid, rules, to)
+    .unwrap_or((to.x.floor() as i32, to.y.floor() as i32, to.z.floor() as i32));
+  let path = search(&mut solid, rules, start, goal, SEARCH_BUDGET);
+  Some(path.into_iter().map(point).collect())
+}
+
+/// Which way to go to follow a path.
+#[derive(Debug, Clone, Copy)]
+pub struct Steering {
+  /// Horizontal unit vector toward the next waypoint.
+  pub direction : Vector3<f32>,
+  /// The next waypoint is too high to step up to.
+  pub jump      : bool,
+}
+
+/// Follows paths toward a (possibly moving) goal, replanning as it goes.
+pub struct Follower {
+  path : Vec<Point3<i32>>,
+  next : usize,
+  goal : Option<Point3<i32>>,
+  age  : u32,
+}
+
+#[allow(missing_docs)]
+pub fn follower() -> Follower {
+  Follower {
+    path : Vec::new(),
+    next : 0,
+    goal : None,
+    age  : 0,
+  }
+}
+
+impl Follower {
+  /// Steer a walker whose feet are at `feet` toward `goal`, for one tick.
+  /// Returns None if there's nowhere useful to go.
+  pub fn steer(
+    &mut self,
+    terrain : &terrain::T,
+    rules   : &Rules,
+    feet    : &Point3<f32>,
+    goal    : &Point3<f32>,
+  ) -> Option<Steering> {
+    let goal_cell = Point3::new(goal.x.floor() as i32, goal.y.floor() as i32, goal.z.floor() as i32);
+    let goal_moved =
+      match self.goal {
+        None => true,
+        Some(old) => {
+          (old.x - goal_cell.x).abs() > REPLAN_DISTANCE ||
+          (old.y - goal_cell.y).abs() > REPLAN_DISTANCE ||
+          (old.z - goal_cell.z).abs() > REPLAN_DISTANCE
+        },
+      };
+    if goal_moved || self.age >= REPLAN_TICKS || self.next >= self.path.len() {
+      self.path = find_path(terrain, rules, feet, goal).unwrap_or(Vec::new());
+      // The first cell is where we already are.
+      self.next = 1;
+      self.goal = Some(goal_cell);
+      self.age = 0;
+    }
+    self.age += 1;
+
+    let current_y = feet.y.floor() as i32;
+    while self.next < self.path.len() {
+      let waypoint = self.path[self.next];
+      let to_waypoint =
+        Vector3::new(
+          waypoint.x as f32 + 0.5 - feet.x,
+          0.0,
+          waypoint.z as f32 + 0.5 - feet.z,
+        );
+      if to_waypoint.magnitude() < 0.25 {
+        self.next += 1;
+        continue
+      }
+
+      return Some(Steering {
+        direction : to_waypoint.normalize(),
+        jump      : waypoint.y - current_y > rules.max_step,
+      })
+    }
+
+    None
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{search, Cell, Rules};
+
+  const RULES: Rules = Rules { height: 2, max_step: 1, max_jump: 2, max_drop: 3 };
+
+  // Flat ground at y = 0, plus whatever's in `blocks`.
+  fn world(blocks: &'static [Cell]) -> Box<FnMut(Cell) -> bool> {
+    Box::new(move |cell: Cell| cell.1 < 0 || blocks.contains(&cell))
+  }
+
+  #[test]
+  fn straight_line() {
+    let path = search(&mut world(&[]), &RULES, (0, 0, 0), (3, 0, 0), 100);
+    assert_eq!(path, vec!((0, 0, 0), (1, 0, 0), (2, 0, 0), (3, 0, 0)));
+  }
+
+  #[test]
+  fn around_a_wall() {
+    // Too tall to jump.
+    let wall: &'static [Cell] = &[
+      (1, 0, -1), (1, 1, -1), (1, 2, -1), (1, 3, -1),
+      (1, 0, 0), (1, 1, 0), (1, 2, 0), (1, 3, 0),
+      (1, 0, 1), (1, 1, 1), (1, 2, 1), (1, 3, 1),
+    ];
+    let path = search(&mut world(wall), &RULES, (0, 0, 0), (2, 0, 0), 100);
+    assert_eq!(path.first(), Some(&(0, 0, 0)));
+    assert_eq!(path.last(), Some(&(2, 0, 0)));
+    assert!(path.iter().all(|&(x, _, z)| x != 1 || z.abs() > 1));
+  }
+
+  #[test]
+  fn step_and_jump() {
+    // A one-high step, then a two-high ledge.
+    let steps: &'static [Cell] = &[
+      (1, 0, 0),
+      (2, 0, 0), (2, 1, 0), (2, 2, 0),
+    ];
+    let path = search(&mut world(steps), &RULES, (0, 0, 0), (2, 3, 0), 100);
+    assert_eq!(path, vec!((0, 0, 0), (1, 1, 0), (2, 3, 0)));
+  }
+
+  #[test]
+  fn out_of_budget() {
+    let path = search(&mut world(&[]), &RULES, (0, 0, 0), (20, 0, 0), 5);
+    assert_eq!(path.first(), Some(&(0, 0, 0)));
+    assert!(path.len() > 1);
+    assert!(path.last() != Some(&(20, 0, 0)));
+  }
+}
@@ -1,8 +1,10 @@
 use cgmath::{Point3};
-use collision::{Aabb3};
+use collision::{Aabb3, Ray3};
 use std::fmt::Debug;
 use std::ptr;
 
+use common::ray;
+
 pub const MIN_CELL_WIDTH: f32 = 0.1;
 
 fn aabb_overlap(aabb1: &Aabb3<f32>, aabb2: &Aabb3<f32>) -> bool {
@@ -227,30 +229,72 @@ impl<V: Debug + Copy + Eq + PartialOrd> Octree<V> {
   // this/child trees. Uses equality comparison on V to ignore "same" objects.
   // Returns the value associated with the first object intersected.
   pub fn intersect(&self, bounds: &Aabb3<f32>, self_v: Option<V>) -> Option<(Aabb3<f32>, V)> {
+    self.intersect_by(bounds, |_, v| Some(v) != self_v)
+  }
+
+  // Like intersect, but only objects accepted by `accept` count as intersecting.
+  // `accept` is passed the (possibly split) bounds stored in the tree.
+  pub fn intersect_by<F>(&self, bounds: &Aabb3<f32>, mut accept: F) -> Option<(Aabb3<f32>, V)>
+    where F: FnMut(&Aabb3<f32>, V) -> bool
+  {
+    self.intersect_by_inner(bounds, &mut accept)
+  }
+
+  fn intersect_by_inner<F>(&self, bounds: &Aabb3<f32>, accept: &mut F) -> Option<(Aabb3<f32>, V)>
+    where F: FnMut(&Aabb3<f32>, V) -> bool
+  {
     match self.contents {
       OctreeContents::Leaf(ref vs) => {
         vs.iter()
-          .find(|&&(ref bs, ref v)| Some(*v) != self_v && aabb_overlap(bounds, bs))
+          .find(|&&(ref bs, v)| aabb_overlap(bounds, bs) && accept(bs, v))
           .map(|&(bounds, v)| (bounds, v))
       },
       OctreeContents::Branch(ref b) => {
         let mid = middle(&self.bounds, self.dimension);
         let (low_bounds, high_bounds) = split(mid, self.dimension, bounds);
-        let high = |high_bounds| {
-          match high_bounds {
+        let low =
+          match low_bounds {
             None => None,
-            Some(bs) => b.high_tree.intersect(&bs, self_v),
-          }
-        };
-        match low_bounds {
-          None => high(high_bounds),
-          Some(bs) =>
-            match b.low_tree.intersect(&bs, self_v) {
-              None => high(high_bounds),
-              r => r,
+            Some(bs) => b.low_tree.intersect_by_inner(&bs, accept),
+          };
+        match low {
+          Some(r) => Some(r),
+          None => {
+            match high_bounds {
+              None => None,
+              Some(bs) => b.high_tree.intersect_by_inner(&bs, accept),
             }
+          },
+        }
+      },
+    }
+  }
+
+  // Call `f` on every object in the cells that a ray passes through before `max_t`
+  // (in units of the ray's direction). Objects aren't visited in any particular order,
+  // and objects split across cells can be visited more than once.
+  pub fn cast_ray<F>(&self, ray: &Ray3<f32>, max_t: f32, f: &mut F)
+    where F: FnMut(V)
+  {
+    match ray::cast_aabb(ray, &self.bounds) {
+      None => return,
+      Some(hit) => {
+        if hit.enter > max_t {
+          return
+        }
+      },
+    }
+
+    match self.contents {
+      OctreeContents::Leaf(ref vs) => {
+        for &(_, v) in vs {
+          f(v);
         }
       },
+      OctreeContents::Branch(ref b) => {
+        b.low_tree.cast_ray(ray, max_t, f);
+        b.high_tree.cast_ray(ray, max_t, f);
+      },
     }
   }
 
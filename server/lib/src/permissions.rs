@@ -0,0 +1,346 @@
+//! Who can change what.
+//!
+//! Every player has a role. Visitors can look around, chat and copy, builders can also edit
+//! terrain outside protected regions, and admins can edit anywhere and run any console command
+//! from chat. New players get the default role, and admins hand out others with the `role`
+//! command. Players don't have accounts, so roles are remembered by the URL their client listens
+//! on: only a client listening there gets the secret the server signs it in with.
+//!
+//! The default role, the roles handed out and the protected regions are kept in `permissions.json`
+//! in the data directory, which is rewritten whenever they change.
+
+use cgmath::Point3;
+use collision::{Aabb3};
+use serde_json;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::path::PathBuf;
+
+use common::voxel;
+
+use data;
+use entity;
+use server;
+
+/// Where permissions are loaded from and saved to, in the data directory.
+const FILE: &'static str = "permissions.json";
+/// How far the default spawn region reaches from the world spawn, horizontally.
+const SPAWN_RADIUS: i32 = 16;
+/// The default spawn region reaches this far up and down, i.e. the whole height of the world.
+const SPAWN_HEIGHT: i32 = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[allow(missing_docs)]
+pub enum Role {
+  Visitor,
+  Builder,
+  Admin,
+}
+
+impl Role {
+  #[allow(missing_docs)]
+  pub fn name(&self) -> &'static str {
+    match *self {
+      Role::Visitor => "visitor",
+      Role::Builder => "builder",
+      Role::Admin => "admin",
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn parse(name: &str) -> Result<Role, String> {
+    match name {
+      "visitor" => Ok(Role::Visitor),
+      "builder" => Ok(Role::Builder),
+      "admin" => Ok(Role::Admin),
+      _ => Err(format!("bad role {:?}; try visitor, builder or admin", name)),
+    }
+  }
+}
+
+/// A box of terrain only admins can edit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Region {
+  #[allow(missing_docs)]
+  pub name : String,
+  /// Inclusive.
+  pub low  : Point3<i32>,
+  /// Inclusive.
+  pub high : Point3<i32>,
+}
+
+impl Region {
+  /// Whether any of `bounds` is in the region. Voxels on the edge of `bounds` count, since edits
+  /// can reshape the voxels just outside what they fill.
+  fn overlaps(&self, bounds: &Aabb3<i32>) -> bool {
+    bounds.min.x <= self.high.x && bounds.max.x >= self.low.x &&
+    bounds.min.y <= self.high.y && bounds.max.y >= self.low.y &&
+    bounds.min.z <= self.high.z && bounds.max.z >= self.low.z
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// The role new players get.
+  pub default_role : Role,
+  /// Roles admins have handed out, by client URL.
+  pub roles        : BTreeMap<String, Role>,
+  pub regions      : Vec<Region>,
+}
+
+/// Used when there's no permissions file: everyone builds, except near the world spawn.
+fn defaults(world_spawn: &Point3<f32>) -> T {
+  let spawn = Point3::new(world_spawn.x as i32, world_spawn.y as i32, world_spawn.z as i32);
+  T {
+    default_role : Role::Builder,
+    roles        : BTreeMap::new(),
+    regions      : vec!(
+      Region {
+        name : String::from("spawn"),
+        low  : Point3::new(spawn.x - SPAWN_RADIUS, -SPAWN_HEIGHT, spawn.z - SPAWN_RADIUS),
+        high : Point3::new(spawn.x + SPAWN_RADIUS, SPAWN_HEIGHT, spawn.z + SPAWN_RADIUS),
+      },
+    ),
+  }
+}
+
+fn path() -> PathBuf {
+  data::path(FILE)
+}
+
+/// Load the permissions file, falling back to the defaults if there isn't one.
+pub fn load(world_spawn: &Point3<f32>) -> T {
+  let path = path();
+  let mut contents = String::new();
+  match File::open(&path).and_then(|mut file| file.read_to_string(&mut contents)) {
+    Err(ref err) if err.kind() == ErrorKind::NotFound => return defaults(world_spawn),
+    Err(err) => {
+      warn!("Couldn't read {}, using the default permissions: {}", path.display(), err);
+      return defaults(world_spawn)
+    },
+    Ok(_) => {},
+  }
+
+  let permissions =
+    serde_json::from_str(&contents)
+    .map_err(|err| err.to_string())
+    .and_then(decode);
+  match permissions {
+    Ok(permissions) => permissions,
+    Err(err) => {
+      warn!("Couldn't parse {}, using the default permissions: {}", path.display(), err);
+      defaults(world_spawn)
+    },
+  }
+}
+
+fn point(p: &Point3<i32>) -> Value {
+  Value::from(vec!(p.x, p.y, p.z))
+}
+
+fn encode(permissions: &T) -> Value {
+  let regions =
+    permissions.regions.iter()
+    .map(|region| {
+      let mut record = serde_json::Map::new();
+      record.insert(String::from("name"), Value::from(region.name.clone()));
+      record.insert(String::from("low"), point(&region.low));
+      record.insert(String::from("high"), point(&region.high));
+      Value::Object(record)
+    })
+    .collect::<Vec<Value>>();
+
+  let mut roles = serde_json::Map::new();
+  for (url, role) in &permissions.roles {
+    roles.insert(url.clone(), Value::from(role.name()));
+  }
+
+  let mut record = serde_json::Map::new();
+  record.insert(String::from("default_role"), Value::from(permissions.default_role.name()));
+  record.insert(String::from("roles"), Value::Object(roles));
+  record.insert(String::from("regions"), Value::from(regions));
+  Value::Object(record)
+}
+
+fn decode(record: Value) -> Result<T, String> {
+  fn field<'a>(record: &'a Value, name: &str) -> Result<&'a Value, String> {
+    record.get(name).ok_or_else(|| format!("missing {:?}", name))
+  }
+  fn point(value: &Value) -> Result<Point3<i32>, String> {
+    let p: [i32; 3] = try!(serde_json::from_value(value.clone()).map_err(|err| err.to_string()));
+    Ok(Point3::new(p[0], p[1], p[2]))
+  }
+
+  let default_role =
+    match try!(field(&record, "default_role")).as_str() {
+      None => return Err(String::from("default_role should be a string")),
+      Some(role) => try!(Role::parse(role)),
+    };
+
+  // Files from before roles were saved don't have any.
+  let mut roles = BTreeMap::new();
+  match record.get("roles") {
+    None => {},
+    Some(records) => {
+      let records =
+        match records.as_object() {
+          None => return Err(String::from("roles should be an object")),
+          Some(records) => records,
+        };
+      for (url, role) in records {
+        match role.as_str() {
+          None => return Err(String::from("roles should be strings")),
+          Some(role) => { roles.insert(url.clone(), try!(Role::parse(role))); },
+        }
+      }
+    },
+  }
+
+  let mut regions = Vec::new();
+  let records =
+    match try!(field(&record, "regions")).as_array() {
+      None => return Err(String::from("regions should be a list")),
+      Some(records) => records,
+    };
+  for record in records {
+    let name =
+      match try!(field(record, "name")).as_str() {
+        None => return Err(String::from("region names should be strings")),
+        Some(name) => String::from(name),
+      };
+    regions.push(Region {
+      name : name,
+      low  : try!(point(try!(field(record, "low")))),
+      high : try!(point(try!(field(record, "high")))),
+    });
+  }
+
+  Ok(T {
+    default_role : default_role,
+    roles        : roles,
+    regions      : regions,
+  })
+}
+
+impl T {
+  /// Write the permissions file.
+  pub fn save(&self) -> Result<(), String> {
+    let path = path();
+    File::create(&path)
+      .and_then(|mut file| file.write_all(encode(self).to_string().as_bytes()))
+      .map_err(|err| format!("couldn't save {}: {}", path.display(), err))
+  }
+
+  /// The role a player whose client listens on `url` joins with.
+  pub fn role_for(&self, url: &str) -> Role {
+    self.roles.get(url).cloned().unwrap_or(self.default_role)
+  }
+
+  /// Protect a region, replacing any region with the same name.
+  pub fn protect(&mut self, region: Region) {
+    self.unprotect(&region.name);
+    self.regions.push(region);
+  }
+
+  /// Returns false if there was no such region.
+  pub fn unprotect(&mut self, name: &str) -> bool {
+    let before = self.regions.len();
+    self.regions.retain(|region| region.name != name);
+    self.regions.len() < before
+  }
+
+  /// The first protected region that overlaps `bounds`.
+  pub fn protecting(&self, bounds: &Aabb3<i32>) -> Option<&Region> {
+    self.regions.iter().find(|region| region.overlaps(bounds))
+  }
+}
+
+/// The terrain a voxel covers, as a box of the smallest voxels.
+pub fn voxel_box(bounds: &voxel::bounds::T) -> Aabb3<i32> {
+  let (low, high) = bounds.corners();
+  Aabb3::new(
+    Point3::new(low.x as i32, low.y as i32, low.z as i32),
+    Point3::new(high.x as i32, high.y as i32, high.z as i32),
+  )
+}
+
+/// Check that a `role` can change the terrain in every one of `bounds`.
+fn check_edit(permissions: &T, role: Role, bounds: &[Aabb3<i32>]) -> Result<(), String> {
+  match role {
+    Role::Admin => Ok(()),
+    Role::Visitor => Err(String::from("A visitor can't edit terrain.")),
+    Role::Builder => {
+      match bounds.iter().filter_map(|bounds| permissions.protecting(bounds)).next() {
+        None => Ok(()),
+        Some(region) => Err(format!("The {} region is protected.", region.name)),
+      }
+    },
+  }
+}
+
+/// Make a player's terrain edit, if they can change everything in `bounds`. Their role and the
+/// protected regions can't change until the edit's done, so it's made with the permissions it was
+/// checked against.
+pub fn edit<Edit, R>(
+  server : &server::T,
+  player : entity::id::Player,
+  bounds : &[Aabb3<i32>],
+  edit   : Edit,
+) -> Result<R, String> where
+  Edit: FnOnce() -> R,
+{
+  let (role, permissions) = {
+    let world = server.world.lock().unwrap();
+    let role =
+      match world.players.get(player) {
+        None => return Err(String::from("No such player.")),
+        Some(player) => player.role,
+      };
+    // Roles are only changed with `permissions` locked too, so lock it before letting go of `world`.
+    (role, server.permissions.lock().unwrap())
+  };
+  try!(check_edit(&permissions, role, bounds));
+  Ok(edit())
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point3;
+  use collision::{Aabb3};
+
+  use super::{Role, check_edit, decode, defaults, encode};
+
+  #[test]
+  fn spawn_is_protected() {
+    let permissions = defaults(&Point3::new(0.0, 0.0, 0.0));
+    let near = Aabb3::new(Point3::new(10, 0, 10), Point3::new(20, 5, 20));
+    let far = Aabb3::new(Point3::new(17, 0, 17), Point3::new(20, 5, 20));
+    assert_eq!(permissions.protecting(&near).map(|region| &region.name[..]), Some("spawn"));
+    assert!(permissions.protecting(&far).is_none());
+  }
+
+  #[test]
+  fn round_trip() {
+    let mut permissions = defaults(&Point3::new(0.0, 0.0, 0.0));
+    permissions.default_role = Role::Visitor;
+    permissions.roles.insert(String::from("tcp://10.0.0.1:5000"), Role::Admin);
+    let decoded = decode(encode(&permissions)).unwrap();
+    assert_eq!(decoded.default_role, Role::Visitor);
+    assert_eq!(decoded.roles, permissions.roles);
+    assert_eq!(decoded.regions, permissions.regions);
+    assert!(Role::Visitor < Role::Builder && Role::Builder < Role::Admin);
+  }
+
+  #[test]
+  fn edits_check_every_box() {
+    let permissions = defaults(&Point3::new(0.0, 0.0, 0.0));
+    let far = Aabb3::new(Point3::new(40, 0, 40), Point3::new(41, 1, 41));
+    let near = Aabb3::new(Point3::new(0, 0, 0), Point3::new(1, 1, 1));
+    assert!(check_edit(&permissions, Role::Builder, &[far]).is_ok());
+    assert!(check_edit(&permissions, Role::Builder, &[far, near]).is_err());
+    assert!(check_edit(&permissions, Role::Admin, &[far, near]).is_ok());
+    assert!(check_edit(&permissions, Role::Visitor, &[]).is_err());
+  }
+}
@@ -1,75 +1,190 @@
-use cgmath::{Vector3};
-use collision::{Aabb3};
+use cgmath::{Point3, Vector3, InnerSpace};
+use collision::{Aabb3, Ray3};
 
 use common::fnv_map;
+use common::ray;
+use common::voxel;
 
 use entity;
 use octree::Octree;
+use terrain_collision;
 
 pub struct T {
+  world_bounds       : Aabb3<f32>,
   pub terrain_octree : Octree<entity::id::Terrain>,
-  terrain_bounds : fnv_map::T<entity::id::Terrain, Aabb3<f32>>,
+  terrain_shapes : fnv_map::T<entity::id::Terrain, (terrain_collision::Shape, voxel::Material)>,
   pub misc_octree    : Octree<entity::id::Misc>,
   misc_bounds    : fnv_map::T<entity::id::Misc, Aabb3<f32>>,
+  misc_kinds     : fnv_map::T<entity::id::Misc, MiscKind>,
 }
 
 pub enum Collision {
   Misc(entity::id::Misc),
   Terrain(entity::id::Terrain),
+  /// The move would have left the world.
+  OutOfBounds,
+}
+
+/// Categories of physics objects, used to filter queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+  Terrain,
+  /// Solid stand-ins for terrain that hasn't finished loading.
+  Placeholder,
+  Player,
+  Mob,
+}
+
+/// What a misc physics object belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MiscKind {
+  Placeholder,
+  Player(entity::id::Player),
+  Mob(entity::id::Mob),
+}
+
+impl MiscKind {
+  pub fn layer(&self) -> Layer {
+    match *self {
+      MiscKind::Placeholder => Layer::Placeholder,
+      MiscKind::Player(_) => Layer::Player,
+      MiscKind::Mob(_) => Layer::Mob,
+    }
+  }
+}
+
+/// What a ray hit.
+#[derive(Debug, Clone, Copy)]
+pub enum RayTarget {
+  Terrain(entity::id::Terrain, voxel::Material),
+  Misc(entity::id::Misc, MiscKind),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RayHit {
+  pub point    : Point3<f32>,
+  /// Surface normal at `point`, facing back toward the ray.
+  pub normal   : Vector3<f32>,
+  /// Distance from the ray's origin to `point`, in world units.
+  pub distance : f32,
+  pub target   : RayTarget,
 }
 
 impl T {
   pub fn new(world_bounds: Aabb3<f32>) -> T {
     T {
+      world_bounds   : world_bounds,
       terrain_octree : Octree::new(&world_bounds),
-      terrain_bounds : fnv_map::new(),
+      terrain_shapes : fnv_map::new(),
       misc_octree    : Octree::new(&world_bounds),
       misc_bounds    : fnv_map::new(),
+      misc_kinds     : fnv_map::new(),
     }
   }
 
-  pub fn insert_terrain(&mut self, id: entity::id::Terrain, bounds: &Aabb3<f32>) {
-    self.terrain_octree.insert(bounds, id);
-    self.terrain_bounds.insert(id, *bounds);
+  pub fn world_bounds(&self) -> &Aabb3<f32> {
+    &self.world_bounds
+  }
+
+  /// Is `bounds` entirely inside the world?
+  pub fn in_bounds(&self, bounds: &Aabb3<f32>) -> bool {
+    true
+    && self.world_bounds.min.x <= bounds.min.x
+    && self.world_bounds.min.y <= bounds.min.y
+    && self.world_bounds.min.z <= bounds.min.z
+    && bounds.max.x <= self.world_bounds.max.x
+    && bounds.max.y <= self.world_bounds.max.y
+    && bounds.max.z <= self.world_bounds.max.z
+  }
+
+  pub fn insert_terrain(
+    &mut self,
+    id: entity::id::Terrain,
+    shape: &terrain_collision::Shape,
+    material: voxel::Material,
+  ) {
+    let bounds = shape.bounds();
+    if !self.in_bounds(&bounds) {
+      // Nothing can get there anyway.
+      return
+    }
+    self.terrain_octree.insert(&bounds, id);
+    self.terrain_shapes.insert(id, (*shape, material));
   }
 
-  pub fn insert_misc(&mut self, id: entity::id::Misc, bounds: &Aabb3<f32>) {
+  /// Returns false (and inserts nothing) if `bounds` is outside the world.
+  pub fn insert_misc(&mut self, id: entity::id::Misc, bounds: &Aabb3<f32>, kind: MiscKind) -> bool {
+    if !self.in_bounds(bounds) {
+      warn!("Not inserting {:?} {:?} outside the world", kind, bounds);
+      return false
+    }
     self.misc_octree.insert(bounds, id);
     self.misc_bounds.insert(id, *bounds);
+    self.misc_kinds.insert(id, kind);
+    true
   }
 
   pub fn remove_terrain(&mut self, id: entity::id::Terrain) {
-    match self.terrain_bounds.get(&id) {
+    match self.terrain_shapes.remove(&id) {
       None => {},
-      Some(bounds) => {
-        self.terrain_octree.remove(bounds, id);
+      Some((shape, _)) => {
+        self.terrain_octree.remove(&shape.bounds(), id);
       },
     }
   }
 
   pub fn remove_misc(&mut self, id: entity::id::Misc) {
-    match self.misc_bounds.get(&id) {
+    self.misc_kinds.remove(&id);
+    match self.misc_bounds.remove(&id) {
       None => {},
       Some(bounds) => {
-        self.misc_octree.remove(bounds, id);
+        self.misc_octree.remove(&bounds, id);
       },
     }
   }
 
+  /// Move a misc object straight to `new_bounds`, without checking for collisions along the way.
+  /// Returns false (and doesn't move anything) if `new_bounds` is outside the world.
+  pub fn teleport_misc(&mut self, id: entity::id::Misc, new_bounds: &Aabb3<f32>) -> bool {
+    if !self.in_bounds(new_bounds) {
+      return false
+    }
+    let bounds = self.misc_bounds.get_mut(&id).unwrap();
+    self.misc_octree.reinsert(id, bounds, new_bounds);
+    *bounds = *new_bounds;
+    true
+  }
+
   pub fn get_bounds(&self, id: entity::id::Misc) -> Option<&Aabb3<f32>> {
     self.misc_bounds.get(&id)
   }
 
+  pub fn get_kind(&self, id: entity::id::Misc) -> Option<MiscKind> {
+    self.misc_kinds.get(&id).cloned()
+  }
+
   pub fn translate_misc(&mut self, id: entity::id::Misc, amount: Vector3<f32>) -> Option<(Aabb3<f32>, Collision)> {
-    let bounds = self.misc_bounds.get_mut(&id).unwrap();
-    let new_bounds =
+    let new_bounds = {
+      let bounds = &self.misc_bounds[&id];
       Aabb3::new(
         bounds.min + amount,
         bounds.max + amount,
+      )
+    };
+    if !self.in_bounds(&new_bounds) {
+      return Some((self.world_bounds, Collision::OutOfBounds))
+    }
+
+    let bounds = self.misc_bounds.get_mut(&id).unwrap();
+    let terrain_shapes = &self.terrain_shapes;
+    let terrain_collision =
+      self.terrain_octree.intersect_by(
+        &new_bounds,
+        |_, terrain_id| terrain_shapes[&terrain_id].0.intersects(&new_bounds),
       );
-    match self.terrain_octree.intersect(&new_bounds, None) {
-      Some((bounds, terrain_id)) => {
-        Some((bounds, Collision::Terrain(terrain_id)))
+    match terrain_collision {
+      Some((_, terrain_id)) => {
+        Some((terrain_shapes[&terrain_id].0.bounds(), Collision::Terrain(terrain_id)))
       },
       None => {
         match self.misc_octree.intersect(&new_bounds, Some(id)) {
@@ -85,4 +200,83 @@ impl T {
       },
     }
   }
+
+  /// The top of the terrain that `bounds` runs into, i.e. the highest point of any of those shapes
+  /// in the column over `bounds`. None if `bounds` doesn't run into any terrain.
+  pub fn terrain_top(&self, bounds: &Aabb3<f32>) -> Option<f32> {
+    let terrain_shapes = &self.terrain_shapes;
+    let mut top: Option<f32> = None;
+    self.terrain_octree.intersect_by(bounds, |_, terrain_id| {
+      let shape = &terrain_shapes[&terrain_id].0;
+      if shape.intersects(bounds) {
+        shape.top_within(bounds).map(|y| top = Some(top.map_or(y, |top| top.max(y))));
+      }
+      // Keep looking.
+      false
+    });
+    top
+  }
+
+  /// Find the closest object in `layers` along a ray, up to `max_distance` away.
+  /// `ignore` can be used to avoid hitting the object casting the ray.
+  pub fn cast_ray(
+    &self,
+    ray          : &Ray3<f32>,
+    max_distance : f32,
+    layers       : &[Layer],
+    ignore       : Option<entity::id::Misc>,
+  ) -> Option<RayHit> {
+    let length = ray.direction.magnitude();
+    if length == 0.0 {
+      return None
+    }
+    let max_t = max_distance / length;
+
+    let mut nearest: Option<RayHit> = None;
+    {
+      let mut consider = |t: f32, normal: Vector3<f32>, target: RayTarget| {
+        if t > max_t {
+          return
+        }
+        let distance = t * length;
+        if nearest.map(|hit| hit.distance <= distance) == Some(true) {
+          return
+        }
+        nearest =
+          Some(RayHit {
+            point    : ray.origin + ray.direction * t,
+            normal   : normal,
+            distance : distance,
+            target   : target,
+          });
+      };
+
+      if layers.contains(&Layer::Terrain) {
+        let terrain_shapes = &self.terrain_shapes;
+        self.terrain_octree.cast_ray(ray, max_t, &mut |id| {
+          let &(ref shape, material) = &terrain_shapes[&id];
+          shape.cast_ray(ray).map(|(t, normal)| {
+            consider(t, normal, RayTarget::Terrain(id, material))
+          });
+        });
+      }
+
+      let misc_bounds = &self.misc_bounds;
+      let misc_kinds = &self.misc_kinds;
+      self.misc_octree.cast_ray(ray, max_t, &mut |id| {
+        if Some(id) == ignore {
+          return
+        }
+        let kind = misc_kinds[&id];
+        if !layers.contains(&kind.layer()) {
+          return
+        }
+        ray::cast_aabb(ray, &misc_bounds[&id]).map(|hit| {
+          consider(hit.enter, hit.normal, RayTarget::Misc(id, kind))
+        });
+      });
+    }
+
+    nearest
+  }
 }
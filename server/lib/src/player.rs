@@ -1,244 +1,200 @@
 use cgmath;
-use cgmath::{Point3, Matrix3, Vector3, ElementWise};
+use cgmath::{Point3, Matrix3, Vector3};
 use collision::{Aabb3, Ray3};
 use std::f32::consts::PI;
-use std::ops::DerefMut;
-use std::sync::Mutex;
-use stopwatch;
+use time;
 
-use common::id_allocator;
-use common::surroundings_loader;
-use common::voxel;
+use common::protocol;
 
+use body;
+use chat;
+use clipboard;
+use edit_history;
 use entity;
-use lod;
+use loader;
+use permissions;
 use physics;
 use server;
-use update_gaia;
-use update_world::load_placeholders;
-
-const MAX_JUMP_FUEL: u32 = 4;
-const MAX_STEP_HEIGHT: f32 = 1.0;
-
-#[derive(Debug, Clone)]
-pub enum Collision {
-  Terrain(entity::id::Terrain),
-  Misc(entity::id::Misc),
-}
-
+use world;
+
+pub const MAX_HEALTH: u32 = 100;
+/// How big players are.
+pub const SIZE: Vector3<f32> = Vector3 { x: 1.0, y: 2.0, z: 1.0 };
+/// How far around themselves players load terrain.
+const LOAD_DISTANCE: u32 = 8;
+/// Players can land at up to this speed (per tick) without getting hurt.
+const SAFE_FALL_SPEED: f32 = 1.2;
+/// Health lost for each unit of landing speed above `SAFE_FALL_SPEED`.
+const FALL_DAMAGE_PER_SPEED: f32 = 40.0;
+/// Health lost when a mob runs into the player.
+const MOB_DAMAGE: u32 = 10;
+/// How long a player is safe from mobs after being hurt by one.
+const MOB_DAMAGE_COOLDOWN_NS: u64 = 1000000000;
+/// How long a player stays dead before respawning.
+const RESPAWN_DELAY_NS: u64 = 5000000000;
+
+/// The parts of a player that come from its client: input and health.
 pub struct T {
-  pub position: Point3<f32>,
-  // speed; units are world coordinates
-  pub speed: Vector3<f32>,
-  // acceleration; units are world coordinates
-  pub accel: Vector3<f32>,
+  /// The client that controls this player.
+  pub client: protocol::ClientId,
+
   // acceleration; x/z units are relative to player facing
   pub walk_accel: Vector3<f32>,
-  // this is depleted as we jump and replenished as we stand.
-  pub jump_fuel: u32,
-  // are we currently trying to jump? (e.g. holding the key).
-  pub is_jumping: bool,
-  pub entity_id: entity::id::Player,
-  pub physics_id: entity::id::Misc,
+
+  pub health: u32,
+  // when a dead player should respawn; None while the player is alive.
+  pub respawn_at_ns: Option<u64>,
+  // mobs can't hurt the player again until this time.
+  mob_damage_cooldown_ns: u64,
 
   // rotation around the y-axis, in radians
   pub lateral_rotation: f32,
   // "pitch", in radians
   pub vertical_rotation: f32,
 
-  surroundings_loader: surroundings_loader::T,
-  surroundings_owner: lod::OwnerId,
-  // Nearby blocks should be made solid if they aren't loaded yet.
-  solid_boundary: surroundings_loader::T,
-  solid_owner: lod::OwnerId,
+  /// How much more the player can say in chat right now.
+  pub chat: chat::RateLimit,
+  /// Terrain edits the player can undo and redo.
+  pub edits: edit_history::T,
+  /// What the player's selected and copied.
+  pub clipboard: clipboard::T,
+  /// What the player's allowed to do.
+  pub role: permissions::Role,
 }
 
-pub fn new(
-  entity_id: entity::id::Player,
-  physics_id: entity::id::Misc,
-  owner_allocator: &Mutex<id_allocator::T<lod::OwnerId>>,
-) -> T {
-  let surroundings_owner = owner_allocator.lock().unwrap().allocate();
-  let solid_owner = owner_allocator.lock().unwrap().allocate();
+pub fn new(client: protocol::ClientId, role: permissions::Role) -> T {
   T {
-    position            : Point3::new(0.0, 0.0, 0.0),
-    speed               : Vector3::new(0.0, 0.0, 0.0),
-    accel               : Vector3::new(0.0, -0.1, 0.0),
-    walk_accel          : Vector3::new(0.0, 0.0, 0.0),
-    jump_fuel           : 0,
-    is_jumping          : false,
-    entity_id           : entity_id,
-    physics_id          : physics_id,
-    lateral_rotation    : 0.0,
-    vertical_rotation   : 0.0,
-
-    surroundings_loader : surroundings_loader::new(8, Vec::new()),
-    solid_boundary      : surroundings_loader::new(8, Vec::new()),
-    surroundings_owner  : surroundings_owner,
-    solid_owner         : solid_owner,
+    client                 : client,
+    walk_accel             : Vector3::new(0.0, 0.0, 0.0),
+    health                 : MAX_HEALTH,
+    respawn_at_ns          : None,
+    mob_damage_cooldown_ns : 0,
+    lateral_rotation       : 0.0,
+    vertical_rotation      : 0.0,
+    chat                   : chat::rate_limit(time::precise_time_ns()),
+    edits                  : edit_history::new(),
+    clipboard              : clipboard::new(),
+    role                   : role,
   }
 }
 
-impl T {
-  /// Translates the player by a vector.
-  /// If the player collides with something with a small height jump, the player will shift upward.
-  /// Returns the actual amount moved by.
-  fn translate(
-    &mut self,
-    physics: &Mutex<physics::T>,
-    requested_shift: Vector3<f32>,
-  ) -> (Aabb3<f32>, Vec<Collision>)
-  {
-    let mut physics = physics.lock().unwrap();
-    let physics = physics.deref_mut();
-    let init_bounds = *physics.get_bounds(self.physics_id).unwrap();
-    let requested_bounds =
-      Aabb3::new(
-        init_bounds.min + requested_shift,
-        init_bounds.max + requested_shift,
-      );
-
-    let mut shift = requested_shift;
-    let mut collisions = Vec::new();
-    let mut collided = false;
-    loop {
-      match physics.translate_misc(self.physics_id, shift) {
-        None => {
-          break
-        },
-        Some((_, physics::Collision::Misc(id))) => {
-          collided = true;
-          collisions.push(Collision::Misc(id));
-          break
-        },
-        Some((collision_bounds, physics::Collision::Terrain(id))) => {
-          collisions.push(Collision::Terrain(id));
-          collided = true;
-
-          // Step to the top of whatever we hit.
-          let step_height = collision_bounds.max.y - requested_bounds.min.y;
-          assert!(step_height > 0.0);
-
-          if step_height > MAX_STEP_HEIGHT {
-            // Step is too big; we just ran into something.
-            break
-          }
-
-          shift += Vector3::new(0.0, step_height, 0.0);
-        },
-      }
-    }
+/// Put a new player into the world at `bounds` (see `spawn::find_or_fallback`), controlled by
+/// `client`. Returns None if the player couldn't be put there.
+pub fn add(
+  server : &server::T,
+  world  : &mut world::T,
+  client : protocol::ClientId,
+  bounds : &Aabb3<f32>,
+) -> Option<entity::id::Player> {
+  let id = world.create();
+  if !world::embody(server, world, id, bounds, physics::MiscKind::Player(id)) {
+    return None
+  }
+  server.spawn.lock().unwrap().set_respawn_point(id, bounds.min);
+
+  let url = server.clients.lock().unwrap().get(&client).map(|client| client.url.clone());
+  let role = {
+    let permissions = server.permissions.lock().unwrap();
+    url.map_or(permissions.default_role, |url| permissions.role_for(&url))
+  };
+  let mut player = new(client, role);
+  player.rotate_lateral(PI / 2.0);
+  world.players.insert(id, player);
+  world.appearances.insert(id, world::Appearance::Player);
+  world.loaders.insert(id, loader::new(&server.owner_allocator, LOAD_DISTANCE));
+
+  Some(id)
+}
 
-    let shifted = *physics.get_bounds(self.physics_id).unwrap();
-    self.position += shifted.min - init_bounds.min;
+/// Take a player out of the world.
+pub fn remove(server: &server::T, world: &mut world::T, id: entity::id::Player) {
+  world::destroy(server, world, id);
+  server.spawn.lock().unwrap().remove_respawn_point(id);
 
-    if collided {
-      if requested_shift.y < 0.0 {
-        self.jump_fuel = MAX_JUMP_FUEL;
-      }
+  for (_, client) in server.clients.lock().unwrap().iter_mut() {
+    client.send(protocol::ServerToClient::RemovePlayer(id));
+  }
+}
 
-      self.speed.y -= requested_shift.y;
-    } else {
-      if requested_shift.y < 0.0 {
-        self.jump_fuel = 0;
-      }
-    }
+/// Put a player at `bounds` (see `spawn::player`), at rest and fully healed.
+/// Returns false if the player couldn't be moved there.
+#[must_use]
+pub fn respawn(server: &server::T, world: &mut world::T, id: entity::id::Player, bounds: &Aabb3<f32>) -> bool {
+  if !world::teleport(server, world, id, bounds) {
+    return false
+  }
 
-    (shifted, collisions)
+  let player = world.players.get_mut(id).unwrap();
+  player.health = MAX_HEALTH;
+  player.respawn_at_ns = None;
+  true
+}
+
+impl T {
+  /// The acceleration the player is walking with, in world coordinates.
+  pub fn accel(&self) -> Vector3<f32> {
+    if self.is_dead() {
+      return Vector3::new(0.0, 0.0, 0.0)
+    }
+    let y_axis = Vector3::new(0.0, 1.0, 0.0);
+    Matrix3::from_axis_angle(y_axis, cgmath::Rad(self.lateral_rotation)) * self.walk_accel
   }
 
+  /// Take damage from whatever happened to the player's body this tick.
+  pub fn react(&mut self, server: &server::T, step: &body::Step) {
+    match step.landing_speed {
+      Some(impact_speed) if impact_speed > SAFE_FALL_SPEED => {
+        let damage = (impact_speed - SAFE_FALL_SPEED) * FALL_DAMAGE_PER_SPEED;
+        self.damage(damage.ceil() as u32);
+      },
+      _ => {},
+    }
 
-  pub fn update<RequestBlock>(
-    &mut self,
-    server: &server::T,
-    request_block: &mut RequestBlock,
-  ) -> (Aabb3<f32>, Vec<Collision>) where
-    RequestBlock: FnMut(update_gaia::Message),
-  {
-    let player_position =
-      Point3::new(
-        self.position.x as i32,
-        self.position.y as i32,
-        self.position.z as i32,
-      );
-
-    stopwatch::time("update.player.surroundings", || {
-      let owner = self.surroundings_owner;
-      for (pos, load_type) in self.surroundings_loader.updates(&player_position) {
-        let pos = voxel::bounds::new(pos.x, pos.y, pos.z, 0);
-        match load_type {
-          surroundings_loader::LoadType::Load | surroundings_loader::LoadType::Downgrade => {
-            server.terrain_loader.load(
-              &server.misc_allocator,
-              &server.physics,
-              &pos,
-              lod::Full,
-              owner,
-              request_block,
-            );
-          },
-          surroundings_loader::LoadType::Unload => {
-            server.terrain_loader.unload(
-              &server.physics,
-              &pos,
-              owner,
-            );
-          },
+    let hit_by_mob = {
+      let physics = server.physics.lock().unwrap();
+      step.collisions.iter().any(|c| {
+        match c {
+          &body::Collision::Misc(id) =>
+            match physics.get_kind(id) {
+              Some(physics::MiscKind::Mob(_)) => true,
+              _ => false,
+            },
+          _ => false,
         }
-      }
-
-      let owner = self.solid_owner;
-      for (pos, load_type) in self.solid_boundary.updates(&player_position) {
-        let block_position = voxel::bounds::new(pos.x, pos.y, pos.z, 0);
-        load_placeholders(
-          owner,
-          server,
-          request_block,
-          &block_position,
-          load_type,
-        )
-      }
-    });
-
-    if self.is_jumping {
-      if self.jump_fuel > 0 {
-        self.jump_fuel -= 1;
-      } else {
-        // this code is duplicated in a few places
-        self.is_jumping = false;
-        self.accel.y = self.accel.y - 0.3;
+      })
+    };
+    if hit_by_mob {
+      let now = time::precise_time_ns();
+      if now >= self.mob_damage_cooldown_ns {
+        self.mob_damage_cooldown_ns = now + MOB_DAMAGE_COOLDOWN_NS;
+        self.damage(MOB_DAMAGE);
       }
     }
+  }
 
-    let delta_p = self.speed;
-    let mut new_bounds = *server.physics.lock().unwrap().get_bounds(self.physics_id).unwrap();
-    let mut collisions = Vec::new();
-    if delta_p.x != 0.0 {
-      let (b, c) = self.translate(&server.physics, Vector3::new(delta_p.x, 0.0, 0.0));
-      new_bounds = b;
-      collisions.extend_from_slice(c.as_slice());
-    }
-    if delta_p.y != 0.0 {
-      let (b, c) = self.translate(&server.physics, Vector3::new(0.0, delta_p.y, 0.0));
-      new_bounds = b;
-      collisions.extend_from_slice(c.as_slice());
+  pub fn is_dead(&self) -> bool {
+    self.respawn_at_ns.is_some()
+  }
+
+  /// Has this player been dead for long enough to respawn?
+  pub fn should_respawn(&self) -> bool {
+    match self.respawn_at_ns {
+      None => false,
+      Some(t) => time::precise_time_ns() >= t,
     }
-    if delta_p.z != 0.0 {
-      let (b, c) = self.translate(&server.physics, Vector3::new(0.0, 0.0, delta_p.z));
-      new_bounds = b;
-      collisions.extend_from_slice(c.as_slice());
+  }
+
+  /// Take away some of the player's health, killing them if it runs out.
+  /// Dead players can't be hurt.
+  pub fn damage(&mut self, amount: u32) {
+    if self.is_dead() {
+      return
     }
 
-    let y_axis = Vector3::new(0.0, 1.0, 0.0);
-    let walk_v =
-        Matrix3::from_axis_angle(y_axis, cgmath::Rad(self.lateral_rotation))
-        * self.walk_accel;
-    self.speed += walk_v;
-    self.speed += self.accel;
-    // friction
-    self.speed.mul_assign_element_wise(Vector3::new(0.7, 0.99, 0.7 as f32));
-
-    (new_bounds, collisions)
+    self.health = self.health.saturating_sub(amount);
+    if self.health == 0 {
+      self.respawn_at_ns = Some(time::precise_time_ns() + RESPAWN_DELAY_NS);
+    }
   }
 
   /// Changes the player's acceleration by the given `da`.
@@ -284,7 +240,8 @@ impl T {
     transform * forward_orig
   }
 
-  pub fn forward_ray(&self) -> Ray3<f32> {
-    Ray3::new(self.position, self.forward())
+  /// A ray looking forward from `position`.
+  pub fn forward_ray(&self, position: &Point3<f32>) -> Ray3<f32> {
+    Ray3::new(*position, self.forward())
   }
 }
@@ -0,0 +1,106 @@
+//! Raycasts through the world, for tools and AI.
+
+use cgmath::{Point3, Vector3};
+use collision::{Ray3};
+
+use common::voxel;
+
+use entity;
+use physics;
+use server;
+
+pub use physics::Layer;
+
+/// What a ray hit.
+#[derive(Debug, Clone, Copy)]
+pub enum Target {
+  /// The terrain, and which piece of it was hit if it's loaded into physics.
+  Terrain(Option<entity::id::Terrain>),
+  /// Terrain that hasn't finished loading yet.
+  Placeholder,
+  #[allow(missing_docs)]
+  Player(entity::id::Player),
+  #[allow(missing_docs)]
+  Mob(entity::id::Mob),
+}
+
+/// The closest thing along a ray.
+#[derive(Debug, Clone, Copy)]
+pub struct Hit {
+  #[allow(missing_docs)]
+  pub point    : Point3<f32>,
+  /// Surface normal at `point`.
+  pub normal   : Vector3<f32>,
+  /// Distance from the ray's origin to `point`, in world units.
+  pub distance : f32,
+  /// The material hit, if `target` is terrain.
+  pub material : Option<voxel::Material>,
+  #[allow(missing_docs)]
+  pub target   : Target,
+}
+
+/// Find the closest hit along a ray among `layers`, up to `max_distance` away.
+/// Terrain loaded into physics is hit exactly, with its entity id; its shapes are rebuilt as soon as
+/// an edit changes them, so they're never behind the voxel tree. Terrain is also cast against the
+/// voxel tree, so it isn't limited to terrain loaded into physics.
+/// `ignore` can be used to avoid hitting whatever is casting the ray.
+pub fn cast(
+  server       : &server::T,
+  ray          : &Ray3<f32>,
+  max_distance : f32,
+  layers       : &[Layer],
+  ignore       : Option<entity::id::Misc>,
+) -> Option<Hit> {
+  let physics_hit =
+    server.physics.lock().unwrap()
+      .cast_ray(ray, max_distance, layers, ignore)
+      .map(|hit| {
+        let (target, material) =
+          match hit.target {
+            physics::RayTarget::Terrain(id, material) => (Target::Terrain(Some(id)), Some(material)),
+            physics::RayTarget::Misc(_, physics::MiscKind::Placeholder) => (Target::Placeholder, None),
+            physics::RayTarget::Misc(_, physics::MiscKind::Player(id)) => (Target::Player(id), None),
+            physics::RayTarget::Misc(_, physics::MiscKind::Mob(id)) => (Target::Mob(id), None),
+          };
+        Hit {
+          point    : hit.point,
+          normal   : hit.normal,
+          distance : hit.distance,
+          material : material,
+          target   : target,
+        }
+      });
+
+  let terrain_hit =
+    if layers.contains(&Layer::Terrain) {
+      server.terrain_loader.terrain.cast_ray(ray, max_distance).map(|hit| {
+        let (low, high) = hit.bounds.corners();
+        let slack = hit.distance + (high.x - low.x);
+        let hit =
+          Hit {
+            point    : hit.point,
+            normal   : hit.normal,
+            distance : hit.distance,
+            material : Some(hit.material),
+            target   : Target::Terrain(None),
+          };
+        (hit, slack)
+      })
+    } else {
+      None
+    };
+
+  match (terrain_hit, physics_hit) {
+    // The voxel tree only approximates the surface, so where both hit the same terrain, the
+    // physics hit is the exact one. The voxel tree's hit only wins if it's clearly in front.
+    (Some((terrain_hit, terrain_slack)), Some(physics_hit)) => {
+      if terrain_slack < physics_hit.distance {
+        Some(terrain_hit)
+      } else {
+        Some(physics_hit)
+      }
+    },
+    (Some((terrain_hit, _)), None) => Some(terrain_hit),
+    (None, physics_hit) => physics_hit,
+  }
+}
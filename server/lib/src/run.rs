@@ -1,18 +1,22 @@
 use std;
 use std::convert::AsRef;
 use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::time::Duration;
 use bincode;
 use stopwatch;
-use thread_scoped;
 use time;
 use voxel_data;
 
 use common;
-use common::closure_series;
 use common::socket::ReceiveSocket;
 
+use admin;
 use client_recv_thread::apply_client_update;
+use control;
 use server;
+use spawn;
 use update_gaia;
 use update_gaia::update_gaia;
 use update_world::update_world;
@@ -22,72 +26,86 @@ mod terrain {
 }
 
 const SAVE_TERRAIN: bool = false;
+/// How long threads wait for work before checking whether it's time to quit.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How often the terrain's RAM usage is measured for `metrics`. Measuring walks the whole voxel tree.
+const TERRAIN_RAM_INTERVAL: Duration = Duration::from_secs(10);
 
-#[allow(missing_docs)]
-pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
-  let gaia_updates = Mutex::new(std::collections::VecDeque::new());
+/// Where terrain is loaded from and saved to.
+pub fn terrain_path() -> &'static std::path::Path {
+  std::path::Path::new("default.terrain")
+}
 
-  let listen_socket = ReceiveSocket::new(listen_url.as_ref(), None);
-  let listen_socket = Mutex::new(listen_socket);
+/// Run the server until `quit_signal` is set.
+/// Admin commands sent through `admin_requests` are run as they come in.
+/// If there's a `control` config, admin commands are also taken from a local socket (see `control`).
+pub fn run(
+  listen_url     : &str,
+  quit_signal    : &Mutex<bool>,
+  admin_requests : mpsc::Receiver<admin::Request>,
+  control        : Option<control::Config>,
+) {
+  let gaia_updates = update_gaia::new_queue();
+  let gaia_updates = &gaia_updates;
 
   let server = server::new();
   let server = &server;
 
-  let terrain_path = std::path::Path::new("default.terrain");
+  let terrain_path = terrain_path();
 
   println!("Loading terrain from {}", terrain_path.to_str().unwrap());
   load_terrain(&server.terrain_loader.terrain, &terrain_path);
+  spawn::init(server);
 
-  let mut threads = Vec::new();
+  let quit = || *quit_signal.lock().unwrap();
+  let quit = &quit;
 
-  unsafe {
-    threads.push(thread_scoped::scoped(|| {
-      while !*quit_signal.lock().unwrap() {
-        info!("Outstanding gaia updates: {}", gaia_updates.lock().unwrap().len());
-        std::thread::sleep(std::time::Duration::from_secs(1));
-      }
+  std::thread::scope(|scope| {
+    let mut threads = vec!(
+      scope.spawn(move || {
+        let mut last_measured: Option<std::time::Instant> = None;
+        while !quit() {
+          info!("Outstanding gaia updates: {}", gaia_updates.len());
+          if last_measured.map_or(true, |t| t.elapsed() >= TERRAIN_RAM_INTERVAL) {
+            measure_terrain_ram(server);
+            last_measured = Some(std::time::Instant::now());
+          }
+          std::thread::sleep(Duration::from_secs(1));
+        }
 
-      stopwatch::clone()
-    }))
-  }
+        stopwatch::clone()
+      }),
+      scope.spawn(move || {
+        update_world_thread(server, quit, |up| gaia_updates.push(up));
+        stopwatch::clone()
+      }),
+      scope.spawn(move || {
+        network_thread(server, listen_url, quit, gaia_updates);
+        stopwatch::clone()
+      }),
+      scope.spawn(move || {
+        gaia_thread(server, quit, gaia_updates);
+        stopwatch::clone()
+      }),
+      scope.spawn(move || {
+        admin_thread(server, quit, gaia_updates, admin_requests);
+        stopwatch::clone()
+      }),
+    );
 
-  unsafe {
-    let server = &server;
-    let gaia_updates = &gaia_updates;
-    let listen_socket = &listen_socket;
-    threads.push(thread_scoped::scoped(move || {
-      closure_series::new(vec!(
-        quit_upon(&quit_signal),
-        consider_world_update(&server, |up| { gaia_updates.lock().unwrap().push_back(up) }),
-        network_listen(&listen_socket, server, |up| { gaia_updates.lock().unwrap().push_back(up) }),
-        consider_gaia_update(&server, || { gaia_updates.lock().unwrap().pop_front() } ),
-      ))
-      .until_quit();
-
-      stopwatch::clone()
-    }));
-  }
-  unsafe {
-    let server = &server;
-    let gaia_updates = &gaia_updates;
-    let quit_signal = &quit_signal;
-    let listen_socket = &listen_socket;
-    threads.push(thread_scoped::scoped(move || {
-      closure_series::new(vec!(
-        quit_upon(&quit_signal),
-        consider_world_update(&server, |up| { gaia_updates.lock().unwrap().push_back(up) }),
-        network_listen(&listen_socket, server, |up| { gaia_updates.lock().unwrap().push_back(up) }),
-      ))
-      .until_quit();
-
-      stopwatch::clone()
-    }));
-  }
+    if let Some(control) = control {
+      threads.push(
+        scope.spawn(move || {
+          control::run(server, quit, gaia_updates, &control, POLL_INTERVAL);
+          stopwatch::clone()
+        })
+      );
+    }
 
-  for thread in threads {
-    let stopwatch = thread.join();
-    stopwatch.print();
-  }
+    for thread in threads {
+      thread.join().unwrap().print();
+    }
+  });
 
   info!("Voxel takes {} bytes", std::mem::size_of::<common::voxel::T>());
 
@@ -99,77 +117,104 @@ pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
   if SAVE_TERRAIN {
     println!("Saving terrain to {}", terrain_path.to_str().unwrap());
     stopwatch::time("save_terrain", || {
-      save_terrain(&server.terrain_loader.terrain, &terrain_path);
+      save_terrain(&server.terrain_loader.terrain, &terrain_path).unwrap();
     });
   }
 
   stopwatch::clone().print();
 }
 
-fn quit_upon(signal: &Mutex<bool>) -> closure_series::Closure {
-  Box::new(move || {
-    if *signal.lock().unwrap() {
-      closure_series::Quit
-    } else {
-      closure_series::Continue
+/// Update the world once per tick, sleeping in between.
+fn update_world_thread<Quit, ToGaia>(
+  server: &server::T,
+  quit: &Quit,
+  mut to_gaia: ToGaia,
+) where
+  Quit: Fn() -> bool,
+  ToGaia: FnMut(update_gaia::Message),
+{
+  while !quit() {
+    let now = time::precise_time_ns();
+    let next_tick = {
+      let mut update_timer = server.update_timer.lock().unwrap();
+      if update_timer.update(now) > 0 {
+        None
+      } else {
+        Some(update_timer.next())
+      }
+    };
+
+    match next_tick {
+      None => {
+        update_world(server, &mut to_gaia);
+        server.tick_timing.lock().unwrap().record(time::precise_time_ns() - now);
+      },
+      Some(next_tick) => std::thread::sleep(Duration::from_nanos(next_tick - now)),
     }
-  })
+  }
 }
 
-fn consider_world_update<'a, ToGaia>(
-  server: &'a server::T,
-  mut to_gaia: ToGaia,
-) -> closure_series::Closure<'a> where
-  ToGaia: FnMut(update_gaia::Message) + 'a,
+/// Handle messages from clients as they arrive.
+fn network_thread<Quit>(
+  server: &server::T,
+  listen_url: &str,
+  quit: &Quit,
+  gaia_updates: &update_gaia::Queue,
+) where
+  Quit: Fn() -> bool,
 {
-  Box::new(move || {
-    if server.update_timer.lock().unwrap().update(time::precise_time_ns()) > 0 {
-      update_world(
-        server,
-        &mut to_gaia,
-      );
-      closure_series::Restart
-    } else {
-      closure_series::Continue
+  let mut listen_socket = ReceiveSocket::new(listen_url.as_ref(), Some(POLL_INTERVAL));
+  while !quit() {
+    // This times out every `POLL_INTERVAL`, so we notice when it's time to quit.
+    match listen_socket.read() {
+      None => {},
+      Some(up) => {
+        match bincode::deserialize(up.as_ref()) {
+          Ok(up) => apply_client_update(server, gaia_updates, up),
+          Err(err) => warn!("Dropping a message that didn't parse: {:?}", err),
+        }
+      },
     }
-  })
+  }
 }
 
-fn network_listen<'a, ToGaia>(
-  socket: &'a Mutex<ReceiveSocket>,
-  server: &'a server::T,
-  mut to_gaia: ToGaia,
-) -> closure_series::Closure<'a> where
-  ToGaia: FnMut(update_gaia::Message) + 'a,
+/// Generate and apply terrain as it's requested.
+fn gaia_thread<Quit>(
+  server: &server::T,
+  quit: &Quit,
+  gaia_updates: &update_gaia::Queue,
+) where
+  Quit: Fn() -> bool,
 {
-  Box::new(move || {
-    match socket.lock().unwrap().try_read() {
-      common::socket::Result::Empty => closure_series::Continue,
-      common::socket::Result::Terminating => closure_series::Quit,
-      common::socket::Result::Success(up) => {
-        let up = bincode::deserialize(up.as_ref()).unwrap();
-        apply_client_update(server, &mut to_gaia, up);
-        closure_series::Restart
-      },
+  while !quit() {
+    match gaia_updates.pop(POLL_INTERVAL) {
+      None => {},
+      Some(up) => update_gaia(server, up),
     }
-  })
+  }
 }
 
-fn consider_gaia_update<'a, Get>(
-  server: &'a server::T,
-  mut get_update: Get,
-) -> closure_series::Closure<'a> where
-  Get: FnMut() -> Option<update_gaia::Message> + 'a,
+/// Run admin commands as they come in.
+fn admin_thread<Quit>(
+  server: &server::T,
+  quit: &Quit,
+  gaia_updates: &update_gaia::Queue,
+  requests: mpsc::Receiver<admin::Request>,
+) where
+  Quit: Fn() -> bool,
 {
-  Box::new(move || {
-    match get_update() {
-      Some(up) => {
-        update_gaia(server, up);
-        closure_series::Restart
+  while !quit() {
+    match requests.recv_timeout(POLL_INTERVAL) {
+      Ok(request) => {
+        let reply = admin::execute(server, gaia_updates, request.command);
+        // Nobody might be waiting for the reply any more.
+        request.reply.send(reply).unwrap_or(());
       },
-      None => closure_series::Continue,
+      Err(mpsc::RecvTimeoutError::Timeout) => {},
+      // Nobody can send us commands any more.
+      Err(mpsc::RecvTimeoutError::Disconnected) => return,
     }
-  })
+  }
 }
 
 fn load_terrain(terrain: &terrain::T, path: &std::path::Path) {
@@ -194,19 +239,27 @@ fn load_terrain(terrain: &terrain::T, path: &std::path::Path) {
         return
       },
     };
-  *terrain.voxels.lock().unwrap() = loaded;
+  terrain.set_voxels(loaded);
 }
 
-fn save_terrain(terrain: &terrain::T, path: &std::path::Path) {
-  let mut file = std::fs::File::create(path).unwrap();
+/// Write all the terrain generated so far to `path`.
+pub fn save_terrain(terrain: &terrain::T, path: &std::path::Path) -> std::io::Result<()> {
+  let mut file = try!(std::fs::File::create(path));
   bincode::serialize_into(
     &mut file,
     &*terrain.voxels.lock().unwrap(),
     bincode::Infinite,
-  ).unwrap();
+  ).map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, format!("{:?}", err)))
+}
+
+/// Store the terrain's current RAM usage in `server.terrain_bytes`.
+fn measure_terrain_ram(server: &server::T) {
+  let bytes = tree_ram_usage(&server.terrain_loader.terrain.voxels.lock().unwrap());
+  server.terrain_bytes.store(bytes, Ordering::Relaxed);
 }
 
-fn tree_ram_usage(tree: &common::voxel::tree::T) -> usize {
+/// Roughly how many bytes a voxel tree takes up.
+pub fn tree_ram_usage(tree: &common::voxel::tree::T) -> usize {
   fn tree_ram_usage_inner(branches: &common::voxel::tree::Branches, size: &mut usize) {
     *size += std::mem::size_of_val(branches);
     for node in branches.as_flat_array() {
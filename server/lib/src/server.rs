@@ -1,25 +1,30 @@
 //! Server state
 
+use std::sync::atomic::AtomicUsize;
 use cgmath::{Point3};
 use collision::{Aabb3};
 use rand;
-use std::sync::Mutex;
 use time;
 
 use common::protocol;
 use common::fnv_map;
 use common::id_allocator;
 use common::interval_timer::IntervalTimer;
+use common::lock::Mutex;
 use common::socket::SendSocket;
 
 use entity;
-use init_mobs::init_mobs;
+use lock_order;
 use lod;
 use mob;
+use permissions;
 use physics;
-use player;
+use spawn;
 use sun::Sun;
 use terrain_loader;
+use tick_timing;
+use voxel_updates;
+use world;
 
 const UPDATES_PER_SECOND: u64 = 30;
 const SUN_TICK_NS: u64 = 1600000;
@@ -28,6 +33,12 @@ const SUN_TICK_NS: u64 = 1600000;
 pub struct Client {
   /// Socket to the client
   pub socket: SendSocket,
+  /// Where the socket sends to.
+  pub url: String,
+  /// What the client signs its messages with.
+  pub secret: protocol::ClientSecret,
+  /// Where the client has asked for voxels, so it only hears about edits there.
+  pub interest: voxel_updates::Interest,
 }
 
 impl Client {
@@ -44,26 +55,33 @@ impl Client {
 }
 
 // TODO: Audit for s/Mutex/RwLock.
+/// Locks have to be taken in the order given in `lock_order`.
 #[allow(missing_docs)]
 pub struct T {
-  pub players           : Mutex<fnv_map::T<entity::id::Player, player::T>>,
-  pub mobs              : Mutex<fnv_map::T<entity::id::Mob, mob::Mob>>,
+  pub world             : Mutex<world::T>,
+  pub mob_archetypes    : Vec<mob::archetype::T>,
+  pub mob_spawner       : Mutex<mob::spawner::T>,
+  pub mob_scripts       : Mutex<mob::script::T>,
 
-  pub player_allocator  : Mutex<id_allocator::T<entity::id::Player>>,
-  pub mob_allocator     : Mutex<id_allocator::T<entity::id::Mob>>,
   pub terrain_allocator : Mutex<id_allocator::T<entity::id::Terrain>>,
   pub misc_allocator    : Mutex<id_allocator::T<entity::id::Misc>>,
   pub owner_allocator   : Mutex<id_allocator::T<lod::OwnerId>>,
   pub client_allocator  : Mutex<id_allocator::T<protocol::ClientId>>,
 
   pub physics           : Mutex<physics::T>,
+  pub spawn             : Mutex<spawn::T>,
   pub terrain_loader    : terrain_loader::T,
   pub rng               : Mutex<rand::StdRng>,
+  pub permissions       : Mutex<permissions::T>,
+  pub voxel_updates     : Mutex<voxel_updates::T>,
 
   pub clients           : Mutex<fnv_map::T<protocol::ClientId, Client>>,
 
   pub sun               : Mutex<Sun>,
   pub update_timer      : Mutex<IntervalTimer>,
+  pub tick_timing       : Mutex<tick_timing::T>,
+  /// Roughly how many bytes the terrain takes up, as of the last time `run` measured it.
+  pub terrain_bytes     : AtomicUsize,
 }
 
 #[allow(missing_docs)]
@@ -78,37 +96,45 @@ pub fn new() -> T {
       )
     );
 
-  let server = T {
-    players           : Mutex::new(fnv_map::new()),
-    mobs              : Mutex::new(fnv_map::new()),
+  let world_spawn = spawn::load_world_spawn();
+  let mob_archetypes = mob::archetype::load();
+  let mob_scripts = mob::script::new(&mob_archetypes);
 
-    player_allocator  : Mutex::new(id_allocator::new()),
-    mob_allocator     : Mutex::new(id_allocator::new()),
-    terrain_allocator : Mutex::new(id_allocator::new()),
-    misc_allocator    : Mutex::new(id_allocator::new()),
-    owner_allocator   : Mutex::new(id_allocator::new()),
-    client_allocator  : Mutex::new(id_allocator::new()),
+  T {
+    world             : Mutex::new(lock_order::WORLD, world::new()),
+    mob_archetypes    : mob_archetypes,
+    mob_spawner       : Mutex::new(lock_order::MOB_SPAWNER, mob::spawner::new()),
+    mob_scripts       : Mutex::new(lock_order::MOB_SCRIPTS, mob_scripts),
 
-    physics: Mutex::new(physics),
+    terrain_allocator : Mutex::new(lock_order::TERRAIN_ALLOCATOR, id_allocator::new()),
+    misc_allocator    : Mutex::new(lock_order::MISC_ALLOCATOR, id_allocator::new()),
+    owner_allocator   : Mutex::new(lock_order::OWNER_ALLOCATOR, id_allocator::new()),
+    client_allocator  : Mutex::new(lock_order::CLIENT_ALLOCATOR, id_allocator::new()),
+
+    physics: Mutex::new(lock_order::PHYSICS, physics),
+    spawn: Mutex::new(lock_order::SPAWN, spawn::new(world_spawn)),
     terrain_loader: terrain_loader::T::new(),
     rng: {
       let seed = [0];
       let seed: &[usize] = &seed;
-      Mutex::new(rand::SeedableRng::from_seed(seed))
+      Mutex::new(lock_order::RNG, rand::SeedableRng::from_seed(seed))
     },
 
-    clients: Mutex::new(fnv_map::new()),
-    sun: Mutex::new(Sun::new(SUN_TICK_NS)),
+    permissions: Mutex::new(lock_order::PERMISSIONS, permissions::load(&world_spawn)),
+    voxel_updates: Mutex::new(lock_order::VOXEL_UPDATES, voxel_updates::new()),
+
+    clients: Mutex::new(lock_order::CLIENTS, fnv_map::new()),
+    sun: Mutex::new(lock_order::SUN, Sun::new(SUN_TICK_NS)),
 
     update_timer: {
       let now = time::precise_time_ns();
       let nanoseconds_per_second = 1000000000;
       Mutex::new(
+        lock_order::UPDATE_TIMER,
         IntervalTimer::new(nanoseconds_per_second / UPDATES_PER_SECOND, now)
       )
-    }
-  };
-
-  init_mobs(&server);
-  server
+    },
+    tick_timing: Mutex::new(lock_order::TICK_TIMING, tick_timing::new()),
+    terrain_bytes: AtomicUsize::new(0),
+  }
 }
@@ -0,0 +1,456 @@
+//! Find safe places to put players and mobs into the world.
+
+use cgmath::{Point3, Vector3};
+use collision::{Aabb3};
+use serde_json;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+
+use common::fnv_map;
+use common::fnv_set;
+use common::protocol;
+use common::voxel;
+
+use chat;
+use data;
+use entity;
+use mob;
+use player;
+use server;
+use terrain;
+use terrain_collision;
+use update_gaia;
+use world;
+use world::center;
+
+/// Where the world spawn is kept, in the data directory. If there's no such file, the world spawn
+/// is the origin.
+const FILE: &'static str = "spawn.json";
+/// How far above and below the requested point we look for ground.
+const SEARCH_HEIGHT: i32 = 64;
+/// How far (horizontally) from the requested point we'll look for a safe spot.
+const SEARCH_RADIUS: i32 = 16;
+/// Horizontal spacing between the columns we try.
+const SEARCH_STEP: i32 = 2;
+/// How many times we'll nudge a spot upward to get out of the terrain surface before giving up on it.
+const MAX_NUDGES: i32 = 3;
+/// How many voxels one search can look at before it gives up.
+const SEARCH_BUDGET: u32 = 1 << 15;
+
+/// Spawn points for the world and for individual players.
+pub struct T {
+  /// Where new players appear, before being moved to safe ground.
+  pub world_spawn: Point3<f32>,
+  /// The low corner of a spot near the world spawn where a player was found to fit (see `init`).
+  /// Anything that can't find a spot of its own goes here, if it fits.
+  pub safe_spawn: Option<Point3<f32>>,
+  respawn_points: fnv_map::T<entity::id::Player, Point3<f32>>,
+  /// Requests waiting on the gaia thread.
+  pending: fnv_set::T<Request>,
+}
+
+#[allow(missing_docs)]
+pub fn new(world_spawn: Point3<f32>) -> T {
+  T {
+    world_spawn    : world_spawn,
+    safe_spawn     : None,
+    respawn_points : fnv_map::new(),
+    pending        : fnv_set::new(),
+  }
+}
+
+/// Read the world spawn from `FILE`, e.g. `{"world_spawn": [0, 40, 0]}`.
+pub fn load_world_spawn() -> Point3<f32> {
+  let origin = Point3::new(0.0, 0.0, 0.0);
+  let path = data::path(FILE);
+  let mut contents = String::new();
+  match File::open(&path).and_then(|mut file| file.read_to_string(&mut contents)) {
+    Err(ref err) if err.kind() == ErrorKind::NotFound => return origin,
+    Err(err) => {
+      warn!("Couldn't read {}, spawning at the origin: {}", path.display(), err);
+      return origin
+    },
+    Ok(_) => {},
+  }
+
+  let world_spawn =
+    serde_json::from_str::<Value>(&contents)
+    .map_err(|err| err.to_string())
+    .and_then(|record| {
+      let p = try!(record.get("world_spawn").ok_or_else(|| String::from("missing \"world_spawn\"")));
+      serde_json::from_value::<[f32; 3]>(p.clone()).map_err(|err| err.to_string())
+    });
+  match world_spawn {
+    Ok(p) if p.iter().all(|x| x.is_finite()) => Point3::new(p[0], p[1], p[2]),
+    Ok(p) => {
+      warn!("Bad world spawn {:?} in {}, spawning at the origin", p, path.display());
+      origin
+    },
+    Err(err) => {
+      warn!("Couldn't parse {}, spawning at the origin: {}", path.display(), err);
+      origin
+    },
+  }
+}
+
+/// Something waiting for a place in the world. Places are found on the gaia thread (see `place`),
+/// so searching doesn't hold up ticks or the network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Request {
+  /// A new player for a client.
+  Join(protocol::ClientId),
+  /// A player who died, or left the world.
+  Respawn(entity::id::Player),
+  /// A mob that left the world.
+  Relocate(entity::id::Mob),
+}
+
+impl T {
+  /// Where a player should respawn. This is the world spawn unless the player has their own.
+  pub fn respawn_point(&self, player: entity::id::Player) -> Point3<f32> {
+    self.respawn_points.get(&player).cloned().unwrap_or(self.world_spawn)
+  }
+
+  /// Give a player their own respawn point.
+  pub fn set_respawn_point(&mut self, player: entity::id::Player, point: Point3<f32>) {
+    self.respawn_points.insert(player, point);
+  }
+
+  /// Forget a player's own respawn point.
+  pub fn remove_respawn_point(&mut self, player: entity::id::Player) {
+    self.respawn_points.remove(&player);
+  }
+}
+
+/// A limit on how much terrain a search can look at.
+pub struct Search<'a> {
+  terrain  : &'a terrain::T,
+  /// Whether to generate voxels that haven't been yet. Otherwise, a search only looks at voxels
+  /// that are already there, i.e. around where things have been.
+  generate : bool,
+  /// How many more voxels the search can look at.
+  budget   : u32,
+}
+
+/// A search that only looks at voxels that are already generated, up to `SEARCH_BUDGET` of them.
+pub fn search(terrain: &terrain::T) -> Search {
+  Search {
+    terrain  : terrain,
+    generate : false,
+    budget   : SEARCH_BUDGET,
+  }
+}
+
+impl<'a> Search<'a> {
+  /// The voxel at `bounds`, unless it isn't generated or the budget has run out.
+  fn voxel(&mut self, bounds: &voxel::bounds::T) -> Option<voxel::T> {
+    if self.budget == 0 {
+      return None
+    }
+    self.budget -= 1;
+    if self.generate {
+      Some(self.terrain.load(bounds))
+    } else {
+      self.terrain.get(bounds)
+    }
+  }
+}
+
+fn is_empty(voxel: &voxel::T) -> bool {
+  match *voxel {
+    voxel::Volume(voxel::Material::Empty) => true,
+    _ => false,
+  }
+}
+
+/// The height of the top of the highest ground in a column between `bottom` and `top`, and what it's made of.
+/// Columns with voxels we can't see (see `Search`) have no ground.
+fn ground(search: &mut Search, x: i32, z: i32, top: i32, bottom: i32) -> Option<(i32, voxel::Material)> {
+  let mut load = |y| search.voxel(&voxel::bounds::new(x, y, z, 0));
+
+  // Anything already buried at the top of the search range isn't a usable surface.
+  match load(top) {
+    Some(ref voxel) if is_empty(voxel) => {},
+    _ => return None,
+  }
+  let mut y = top - 1;
+  while y >= bottom {
+    let voxel =
+      match load(y) {
+        None => return None,
+        Some(voxel) => voxel,
+      };
+    if !is_empty(&voxel) {
+      // The surface voxel might have an empty low corner; the one beneath it won't.
+      let mut material = voxel::material_of(&voxel);
+      if material == voxel::Material::Empty {
+        material =
+          match load(y - 1) {
+            None => return None,
+            Some(below) => voxel::material_of(&below),
+          };
+      }
+      return Some((y + 1, material))
+    }
+    y -= 1;
+  }
+  None
+}
+
+/// Might a box overlap the terrain (surface triangles or solid voxels)? It might if we can't see
+/// all of the terrain around it.
+fn overlaps_terrain(search: &mut Search, bounds: &Aabb3<f32>) -> bool {
+  // Surface triangles can stretch into the neighbouring voxels, so look one voxel further out.
+  let low = Point3::new(bounds.min.x.floor() as i32 - 1, bounds.min.y.floor() as i32 - 1, bounds.min.z.floor() as i32 - 1);
+  let high = Point3::new(bounds.max.x.ceil() as i32, bounds.max.y.ceil() as i32, bounds.max.z.ceil() as i32);
+  for x in low.x .. high.x + 1 {
+  for y in low.y .. high.y + 1 {
+  for z in low.z .. high.z + 1 {
+    let voxel = voxel::bounds::new(x, y, z, 0);
+    match terrain_collision::shapes_from(&mut |bounds| search.voxel(bounds), &voxel) {
+      None => return true,
+      Some(shapes) => {
+        if shapes.iter().any(|&(ref shape, _)| shape.intersects(bounds)) {
+          return true
+        }
+      },
+    }
+  }}}
+  false
+}
+
+/// Try to stand a box of the given size on the highest ground in a column, between heights `bottom` and `top`.
+/// Returns the box's bounds and the material it's standing on.
+pub fn on_ground(
+  search : &mut Search,
+  x      : i32,
+  z      : i32,
+  top    : i32,
+  bottom : i32,
+  size   : &Vector3<f32>,
+) -> Option<(Aabb3<f32>, voxel::Material)> {
+  let (ground, material) =
+    match ground(search, x, z, top, bottom) {
+      None => return None,
+      Some(ground) => ground,
+    };
+
+  for nudge in 0 .. MAX_NUDGES + 1 {
+    let low = Point3::new(x as f32, (ground + nudge) as f32, z as f32);
+    let bounds = Aabb3::new(low, low + *size);
+    if !overlaps_terrain(search, &bounds) {
+      return Some((bounds, material))
+    }
+  }
+
+  None
+}
+
+/// Find a spot near `near` where a box of size `size` is standing on the ground and not inside the terrain.
+/// Columns are tried in rings of increasing distance from `near`, until the search's budget runs out.
+/// Returns the bounds of the box.
+pub fn find(search: &mut Search, near: &Point3<f32>, size: &Vector3<f32>) -> Option<Aabb3<f32>> {
+  let center_x = near.x.floor() as i32;
+  let center_z = near.z.floor() as i32;
+  let top = near.y.floor() as i32 + SEARCH_HEIGHT;
+  let bottom = near.y.floor() as i32 - SEARCH_HEIGHT;
+
+  let mut radius = 0;
+  while radius <= SEARCH_RADIUS && search.budget > 0 {
+    let mut dx = -radius;
+    while dx <= radius {
+      let mut dz = -radius;
+      while dz <= radius {
+        // Only visit the edge of this ring; the inside was covered by smaller rings.
+        if dx.abs() == radius || dz.abs() == radius {
+          match on_ground(search, center_x + dx, center_z + dz, top, bottom, size) {
+            None => {},
+            Some((bounds, _)) => return Some(bounds),
+          }
+        }
+        dz += SEARCH_STEP;
+      }
+      dx += SEARCH_STEP;
+    }
+    radius += SEARCH_STEP;
+  }
+
+  None
+}
+
+/// Find the safe spawn (see `T::safe_spawn`). The spawn region is protected, so it should stay safe.
+/// This runs before the server starts, so unlike other searches, it generates the terrain it needs.
+pub fn init(server: &server::T) {
+  let world_spawn = server.spawn.lock().unwrap().world_spawn;
+  let mut search =
+    Search {
+      terrain  : &server.terrain_loader.terrain,
+      generate : true,
+      budget   : SEARCH_BUDGET,
+    };
+  let safe_spawn = find(&mut search, &world_spawn, &player::SIZE);
+  match safe_spawn {
+    None => error!("No safe ground near the world spawn {:?}; players can't join until there is", world_spawn),
+    Some(bounds) => info!("Safe spawn is {:?}", bounds.min),
+  }
+  server.spawn.lock().unwrap().safe_spawn = safe_spawn.map(|bounds| bounds.min);
+}
+
+/// Find where to put a box of size `size` near `near`.
+/// If there's no safe ground nearby, the box goes to the safe spawn instead, as long as it fits.
+pub fn find_or_fallback(server: &server::T, near: &Point3<f32>, size: &Vector3<f32>) -> Option<Aabb3<f32>> {
+  let mut search = search(&server.terrain_loader.terrain);
+  match find(&mut search, near, size) {
+    Some(bounds) => Some(bounds),
+    None => {
+      debug!("No safe spawn point found near {:?}", near);
+      let safe_spawn = server.spawn.lock().unwrap().safe_spawn;
+      // Check the safe spawn with a fresh budget.
+      let mut search = self::search(&server.terrain_loader.terrain);
+      safe_spawn
+        .map(|low| Aabb3::new(low, low + *size))
+        .and_then(|bounds| if overlaps_terrain(&mut search, &bounds) { None } else { Some(bounds) })
+    },
+  }
+}
+
+/// Find where a player should (re)spawn.
+pub fn player(server: &server::T, player: entity::id::Player, size: &Vector3<f32>) -> Option<Aabb3<f32>> {
+  let near = server.spawn.lock().unwrap().respawn_point(player);
+  find_or_fallback(server, &near, size)
+}
+
+/// Ask the gaia thread to find a place for something, unless it's already looking.
+/// Returns whether this was a new request.
+pub fn request<Push>(server: &server::T, request: Request, push: &mut Push) -> bool
+  where Push: FnMut(update_gaia::Message)
+{
+  let is_new = server.spawn.lock().unwrap().pending.insert(request);
+  if is_new {
+    push(update_gaia::Message::Spawn(request));
+  }
+  is_new
+}
+
+/// Find a place for something, and put it there. Runs on the gaia thread.
+pub fn place(server: &server::T, request: Request) {
+  match request {
+    Request::Join(client) => join(server, client),
+    Request::Respawn(player) => respawn(server, player),
+    Request::Relocate(mob) => relocate(server, mob),
+  }
+  server.spawn.lock().unwrap().pending.remove(&request);
+}
+
+fn broadcast(server: &server::T, messages: &[protocol::ServerToClient]) {
+  for (_, client) in server.clients.lock().unwrap().iter_mut() {
+    for message in messages {
+      client.send(message.clone());
+    }
+  }
+}
+
+/// The size of an entity's bounds, if it's still around.
+fn size_of(server: &server::T, id: entity::id::Entity) -> Option<Vector3<f32>> {
+  server.world.lock().unwrap().bounds(server, id).map(|bounds| bounds.max - bounds.min)
+}
+
+fn join(server: &server::T, client: protocol::ClientId) {
+  if !server.clients.lock().unwrap().contains_key(&client) {
+    return
+  }
+
+  let near = server.spawn.lock().unwrap().world_spawn;
+  let added =
+    find_or_fallback(server, &near, &player::SIZE).and_then(|bounds| {
+      let mut world = server.world.lock().unwrap();
+      player::add(server, &mut world, client, &bounds).map(|id| {
+        (id, center(&bounds), world.players.get(id).unwrap().health)
+      })
+    });
+  let (id, position, health) =
+    match added {
+      None => {
+        warn!("Nowhere to put a player for client {:?}", client);
+        chat::notice(server, client, String::from("There's nowhere safe to spawn right now. Try again later."));
+        return
+      },
+      Some(added) => added,
+    };
+
+  let mut clients = server.clients.lock().unwrap();
+  clients.get_mut(&client).map(|client| {
+    client.send(protocol::ServerToClient::PlayerAdded(id, position));
+    client.send(
+      protocol::ServerToClient::UpdateHealth {
+        player     : id,
+        health     : health,
+        max_health : player::MAX_HEALTH,
+      }
+    );
+  });
+}
+
+fn respawn(server: &server::T, id: entity::id::Player) {
+  let size =
+    match size_of(server, id) {
+      None => return,
+      Some(size) => size,
+    };
+  let bounds =
+    match player(server, id, &size) {
+      None => {
+        // It'll be asked for again next tick.
+        warn!("Nowhere to respawn player {:?}", id);
+        return
+      },
+      Some(bounds) => bounds,
+    };
+
+  {
+    let mut world = server.world.lock().unwrap();
+    if !player::respawn(server, &mut world, id, &bounds) {
+      warn!("Couldn't respawn player {:?} at {:?}", id, bounds.min);
+      return
+    }
+  }
+  info!("Respawned player {:?} at {:?}", id, bounds.min);
+
+  broadcast(server, &[
+    protocol::ServerToClient::PlayerRespawned(id, center(&bounds)),
+    protocol::ServerToClient::UpdatePlayer(id, bounds),
+    protocol::ServerToClient::UpdateHealth {
+      player     : id,
+      health     : player::MAX_HEALTH,
+      max_health : player::MAX_HEALTH,
+    },
+  ]);
+}
+
+fn relocate(server: &server::T, id: entity::id::Mob) {
+  let size =
+    match size_of(server, id) {
+      None => return,
+      Some(size) => size,
+    };
+  let near = server.spawn.lock().unwrap().world_spawn;
+  let bounds = find_or_fallback(server, &near, &size);
+
+  let mut world = server.world.lock().unwrap();
+  // Don't go by `world.mobs`: `update_world` takes mobs out of it while they decide where to go.
+  if world.appearances.get(id) != Some(&world::Appearance::Mob) {
+    return
+  }
+  let relocated = bounds.map_or(false, |bounds| world::teleport(server, &mut world, id, &bounds));
+  match bounds {
+    Some(bounds) if relocated => {
+      info!("Mob {:?} left the world; relocated to {:?}", id, bounds.min);
+      broadcast(server, &[protocol::ServerToClient::UpdateMob(id, bounds)]);
+    },
+    _ => {
+      warn!("Nowhere to relocate mob {:?}; removing it", id);
+      mob::remove(server, &mut world, id);
+    },
+  }
+}
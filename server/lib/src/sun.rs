@@ -27,10 +27,7 @@ impl Sun {
 
     self.position = (std::num::Wrapping(self.position) + std::num::Wrapping(ticks as u16)).0;
 
-    // Fraction completed of a full cycle.
-    let fraction = (self.position as f32) / 65536.0;
-    // Longer day, shorter night.
-    let fraction = fraction * fraction;
+    let fraction = self.fraction();
 
     if self.print_timer.update(time::precise_time_ns()) > 0 {
       debug!("Sun is at {:.1}%.", fraction * 100.0);
@@ -38,4 +35,23 @@ impl Sun {
 
     Some(fraction)
   }
+
+  /// The sun as a [0, 1) portion of its cycle.
+  pub fn fraction(&self) -> f32 {
+    // Fraction completed of a full cycle.
+    let fraction = (self.position as f32) / 65536.0;
+    // Longer day, shorter night.
+    fraction * fraction
+  }
+
+  /// Move the sun to a [0, 1) portion of its cycle, as returned by `fraction`.
+  pub fn set_fraction(&mut self, fraction: f32) {
+    let fraction = f32::max(0.0, f32::min(fraction, 1.0));
+    self.position = (fraction.sqrt() * 65535.0) as u16;
+  }
+
+  /// Is the sun above the horizon?
+  pub fn is_day(&self) -> bool {
+    self.fraction() < 0.5
+  }
 }
@@ -0,0 +1,193 @@
+//! Build terrain collision geometry from the same dual-contoured surface the client renders.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+use collision::{Aabb3, Ray3};
+use isosurface_extraction::dual_contouring;
+
+use common::ray;
+use common::voxel;
+
+use terrain;
+use triangle;
+
+mod voxel_storage {
+  use isosurface_extraction::dual_contouring;
+
+  use common::voxel;
+
+  /// Voxel storage that gets its voxels from a function, and notes whether any were missing.
+  pub struct T<'a, Load: 'a> {
+    pub load    : &'a mut Load,
+    pub missing : bool,
+  }
+
+  impl<'a, Load> T<'a, Load> where Load: FnMut(&voxel::bounds::T) -> Option<voxel::T> {
+    fn get(&mut self, bounds: &voxel::bounds::T) -> Option<voxel::T> {
+      let voxel = (self.load)(bounds);
+      self.missing |= voxel.is_none();
+      voxel
+    }
+  }
+
+  impl<'a, Load> dual_contouring::voxel_storage::T<voxel::Material> for T<'a, Load>
+    where Load: FnMut(&voxel::bounds::T) -> Option<voxel::T>
+  {
+    fn get_material(&mut self, bounds: &voxel::bounds::T) -> Option<voxel::Material> {
+      match self.get(bounds) {
+        None => None,
+        Some(voxel::Surface(voxel)) => Some(voxel.corner),
+        Some(voxel::Volume(material)) => Some(material),
+      }
+    }
+
+    fn get_voxel_data(&mut self, bounds: &voxel::bounds::T) -> Option<dual_contouring::voxel_storage::VoxelData> {
+      match self.get(bounds) {
+        None => None,
+        Some(voxel::Volume(_)) => None,
+        Some(voxel::Surface(voxel)) =>
+          Some({
+            dual_contouring::voxel_storage::VoxelData {
+              bounds: *bounds,
+              vertex: voxel.surface_vertex.to_world_vertex(bounds),
+              normal: voxel.normal.to_float_normal(),
+            }
+          }),
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// A piece of solid terrain.
+pub enum Shape {
+  /// A voxel that's solid all the way through.
+  Solid(Aabb3<f32>),
+  /// Part of the terrain surface.
+  Triangle(triangle::T),
+}
+
+impl Shape {
+  #[allow(missing_docs)]
+  pub fn bounds(&self) -> Aabb3<f32> {
+    match *self {
+      Shape::Solid(bounds) => bounds,
+      Shape::Triangle(ref triangle) => triangle.bounds(),
+    }
+  }
+
+  /// Does this shape overlap an AABB? Touching doesn't count.
+  pub fn intersects(&self, bounds: &Aabb3<f32>) -> bool {
+    match *self {
+      Shape::Solid(ref solid) => {
+        true
+        && solid.min.x < bounds.max.x
+        && solid.min.y < bounds.max.y
+        && solid.min.z < bounds.max.z
+        && bounds.min.x < solid.max.x
+        && bounds.min.y < solid.max.y
+        && bounds.min.z < solid.max.z
+      },
+      Shape::Triangle(ref triangle) => triangle.intersects(bounds),
+    }
+  }
+
+  /// The highest point of this shape in the column over `bounds`, ignoring the height of `bounds`.
+  pub fn top_within(&self, bounds: &Aabb3<f32>) -> Option<f32> {
+    match *self {
+      Shape::Solid(ref solid) => {
+        let overlaps =
+          true
+          && solid.min.x < bounds.max.x
+          && solid.min.z < bounds.max.z
+          && bounds.min.x < solid.max.x
+          && bounds.min.z < solid.max.z;
+        if overlaps {
+          Some(solid.max.y)
+        } else {
+          None
+        }
+      },
+      Shape::Triangle(ref triangle) => triangle.top_within(bounds),
+    }
+  }
+
+  /// Cast a ray against this shape.
+  /// Returns the distance (in units of the ray's direction) and the normal facing the ray.
+  pub fn cast_ray(&self, ray: &Ray3<f32>) -> Option<(f32, Vector3<f32>)> {
+    match *self {
+      Shape::Solid(ref solid) => {
+        ray::cast_aabb(ray, solid).map(|hit| (hit.enter, hit.normal))
+      },
+      Shape::Triangle(ref triangle) => {
+        triangle.cast_ray(ray).map(|t| {
+          let normal = triangle.normal().normalize();
+          if normal.dot(ray.direction) > 0.0 {
+            (t, -normal)
+          } else {
+            (t, normal)
+          }
+        })
+      },
+    }
+  }
+}
+
+/// The collision shapes (and their materials) for the terrain in a voxel.
+/// Surface voxels produce the triangles meshed around their low corner, exactly like a client chunk;
+/// solid voxels are boxes. Any voxels that haven't been generated yet are generated.
+pub fn shapes(terrain: &terrain::T, bounds: &voxel::bounds::T) -> Vec<(Shape, voxel::Material)> {
+  shapes_from(&mut |bounds| Some(terrain.load(bounds)), bounds).unwrap()
+}
+
+/// Like `shapes`, but with voxels from `load`. Returns None if `load` was missing any voxels the
+/// shapes depend on.
+pub fn shapes_from<Load>(load: &mut Load, bounds: &voxel::bounds::T) -> Option<Vec<(Shape, voxel::Material)>>
+  where Load: FnMut(&voxel::bounds::T) -> Option<voxel::T>
+{
+  match load(bounds) {
+    None => return None,
+    Some(voxel::Volume(voxel::Material::Empty)) => {},
+    Some(voxel::Volume(material)) => {
+      let (low, high) = bounds.corners();
+      return Some(vec!((Shape::Solid(Aabb3::new(low, high)), material)))
+    },
+    Some(voxel::Surface(_)) => {},
+  }
+
+  let mut shapes = Vec::new();
+  let mut storage = voxel_storage::T { load: load, missing: false };
+
+  let directions = [
+    dual_contouring::edge::Direction::X,
+    dual_contouring::edge::Direction::Y,
+    dual_contouring::edge::Direction::Z,
+  ];
+  for direction in directions.iter() {
+    let edge =
+      dual_contouring::edge::T {
+        low_corner: Point3::new(bounds.x, bounds.y, bounds.z),
+        direction: *direction,
+        lg_size: bounds.lg_size,
+      };
+
+    let _ =
+      dual_contouring::edge::extract(
+        &mut storage,
+        &edge,
+        &mut |polygon: dual_contouring::polygon::T<voxel::Material>| {
+          shapes.push((
+            Shape::Triangle(
+              triangle::new(polygon.vertices[0], polygon.vertices[1], polygon.vertices[2])
+            ),
+            polygon.material,
+          ));
+        }
+      );
+  }
+
+  if storage.missing {
+    None
+  } else {
+    Some(shapes)
+  }
+}
@@ -1,17 +1,19 @@
-use collision::{Aabb3};
-use std::sync::Mutex;
 use stopwatch;
 use time;
 
 use common::fnv_map;
+use common::fnv_set;
 use common::id_allocator;
+use common::lock::Mutex;
 use common::voxel;
 
 use entity;
 use in_progress_terrain;
+use lock_order;
 use lod;
 use physics;
 use terrain;
+use terrain_collision;
 use update_gaia;
 use update_gaia::LoadDestination;
 
@@ -30,10 +32,10 @@ pub struct T {
 impl T {
   pub fn new() -> T {
     T {
-      terrain             : terrain::T::new(terrain::Seed::new(0)),
-      in_progress_terrain : Mutex::new(in_progress_terrain::T::new()),
-      lod_map             : Mutex::new(lod::Map::new()),
-      loaded              : Mutex::new(fnv_map::new()),
+      terrain             : terrain::T::new(terrain::Seed::new(0), lock_order::TERRAIN_VOXELS, lock_order::TERRAIN_MOSAIC),
+      in_progress_terrain : Mutex::new(lock_order::IN_PROGRESS_TERRAIN, in_progress_terrain::T::new()),
+      lod_map             : Mutex::new(lock_order::LOD_MAP, lod::Map::new()),
+      loaded              : Mutex::new(lock_order::LOADED, fnv_map::new()),
     }
   }
 
@@ -139,9 +141,9 @@ impl T {
 
     stopwatch::time("terrain_loader.load.physics", || {
       let mut physics = physics.lock().unwrap();
-      let mut ids = Vec::with_capacity(block.bounds.len());
-      for &(ref id, ref bounds) in &block.bounds {
-        physics.insert_terrain(*id, bounds);
+      let mut ids = Vec::with_capacity(block.shapes.len());
+      for &(ref id, ref shape, material) in &block.shapes {
+        physics.insert_terrain(*id, shape, material);
         ids.push(*id);
       }
       let prev = loaded.insert(*position, ids);
@@ -149,6 +151,70 @@ impl T {
     });
   }
 
+  /// Rebuild the collision shapes of the loaded blocks whose surface goes through any of `changed`,
+  /// e.g. after an edit, so physics matches the terrain again.
+  pub fn reload_shapes(
+    &self,
+    terrain_allocator : &Mutex<id_allocator::T<entity::id::Terrain>>,
+    physics           : &Mutex<physics::T>,
+    changed           : &[voxel::bounds::T],
+  ) {
+    // A block's shapes come from the edges at its low corner, which touch the voxels on either side.
+    let mut blocks = fnv_set::new();
+    for bounds in changed {
+      if bounds.lg_size != 0 {
+        continue
+      }
+      for dx in -1 .. 2 {
+      for dy in -1 .. 2 {
+      for dz in -1 .. 2 {
+        blocks.insert(voxel::bounds::new(bounds.x + dx, bounds.y + dy, bounds.z + dz, 0));
+      }}}
+    }
+
+    // Holding `loaded` keeps the blocks from being unloaded underneath us.
+    let mut loaded = self.loaded.lock().unwrap();
+    for block in blocks {
+      let ids =
+        match loaded.get_mut(&block) {
+          None => continue,
+          Some(ids) => ids,
+        };
+
+      let shapes =
+        stopwatch::time("terrain_loader.reload_shapes", || {
+          terrain_collision::shapes(&self.terrain, &block)
+        });
+      let shapes: Vec<_> = {
+        let mut terrain_allocator = terrain_allocator.lock().unwrap();
+        shapes.into_iter()
+          .map(|(shape, material)| (terrain_allocator.allocate(), shape, material))
+          .collect()
+      };
+
+      let mut physics = physics.lock().unwrap();
+      for id in ids.drain(..) {
+        physics.remove_terrain(id);
+      }
+      for (id, shape, material) in shapes {
+        physics.insert_terrain(id, &shape, material);
+        ids.push(id);
+      }
+    }
+  }
+
+  /// Release all of an owner's handles.
+  pub fn unload_owner(
+    &self,
+    physics : &Mutex<physics::T>,
+    owner   : lod::OwnerId,
+  ) {
+    let positions = self.lod_map.lock().unwrap().owned_by(owner);
+    for position in &positions {
+      self.unload(physics, position, owner);
+    }
+  }
+
   pub fn unload(
     &self,
     physics  : &Mutex<physics::T>,
@@ -187,5 +253,5 @@ impl T {
 }
 
 pub struct LoadedTerrain {
-  pub bounds: Vec<(entity::id::Terrain, Aabb3<f32>)>,
+  pub shapes: Vec<(entity::id::Terrain, terrain_collision::Shape, voxel::Material)>,
 }
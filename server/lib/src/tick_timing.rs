@@ -0,0 +1,43 @@
+//! How long world updates are taking.
+
+use std;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// How many ticks have run.
+  pub ticks   : u64,
+  /// How long the most recent tick took.
+  pub last_ns : u64,
+  /// How long the slowest tick took.
+  pub max_ns  : u64,
+  total_ns    : u64,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    ticks    : 0,
+    last_ns  : 0,
+    max_ns   : 0,
+    total_ns : 0,
+  }
+}
+
+impl T {
+  /// Record a tick that took `ns` nanoseconds.
+  pub fn record(&mut self, ns: u64) {
+    self.ticks += 1;
+    self.last_ns = ns;
+    self.max_ns = std::cmp::max(self.max_ns, ns);
+    self.total_ns += ns;
+  }
+
+  /// How long ticks take on average.
+  pub fn mean_ns(&self) -> u64 {
+    if self.ticks == 0 {
+      0
+    } else {
+      self.total_ns / self.ticks
+    }
+  }
+}
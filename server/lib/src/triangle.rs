@@ -0,0 +1,248 @@
+//! Triangles for physics, and their intersection tests.
+
+use cgmath::{Point3, Vector3, EuclideanSpace, InnerSpace};
+use collision::{Aabb3, Ray3};
+
+#[derive(Debug, Clone, Copy)]
+#[allow(missing_docs)]
+pub struct T {
+  pub v1: Point3<f32>,
+  pub v2: Point3<f32>,
+  pub v3: Point3<f32>,
+}
+
+#[allow(missing_docs)]
+pub fn new(v1: Point3<f32>, v2: Point3<f32>, v3: Point3<f32>) -> T {
+  T {
+    v1: v1,
+    v2: v2,
+    v3: v3,
+  }
+}
+
+fn min3(a: f32, b: f32, c: f32) -> f32 {
+  f32::min(a, f32::min(b, c))
+}
+
+fn max3(a: f32, b: f32, c: f32) -> f32 {
+  f32::max(a, f32::max(b, c))
+}
+
+// Is there a gap between the projections of the triangle (relative to the box center)
+// and the box onto `axis`? Touching doesn't count as overlapping.
+fn separated_on(
+  axis: &Vector3<f32>,
+  v1: &Vector3<f32>,
+  v2: &Vector3<f32>,
+  v3: &Vector3<f32>,
+  half_extents: &Vector3<f32>,
+) -> bool {
+  // Parallel edges produce degenerate axes, which can't separate anything.
+  if axis.magnitude2() < 1e-12 {
+    return false
+  }
+
+  let p1 = v1.dot(*axis);
+  let p2 = v2.dot(*axis);
+  let p3 = v3.dot(*axis);
+  let r =
+    half_extents.x * axis.x.abs() +
+    half_extents.y * axis.y.abs() +
+    half_extents.z * axis.z.abs();
+
+  min3(p1, p2, p3) >= r || max3(p1, p2, p3) <= -r
+}
+
+// Clip a convex polygon to where `distance` is nonnegative (Sutherland-Hodgman).
+fn clip<F>(polygon: &[Point3<f32>], distance: F) -> Vec<Point3<f32>>
+  where F: Fn(&Point3<f32>) -> f32
+{
+  let mut clipped = Vec::new();
+  for i in 0..polygon.len() {
+    let p = polygon[i];
+    let q = polygon[(i + 1) % polygon.len()];
+    let dp = distance(&p);
+    let dq = distance(&q);
+    if dp >= 0.0 {
+      clipped.push(p);
+    }
+    if (dp >= 0.0) != (dq >= 0.0) {
+      clipped.push(p + (q - p) * (dp / (dp - dq)));
+    }
+  }
+  clipped
+}
+
+impl T {
+  /// The smallest AABB containing this triangle.
+  pub fn bounds(&self) -> Aabb3<f32> {
+    Aabb3::new(
+      Point3::new(
+        min3(self.v1.x, self.v2.x, self.v3.x),
+        min3(self.v1.y, self.v2.y, self.v3.y),
+        min3(self.v1.z, self.v2.z, self.v3.z),
+      ),
+      Point3::new(
+        max3(self.v1.x, self.v2.x, self.v3.x),
+        max3(self.v1.y, self.v2.y, self.v3.y),
+        max3(self.v1.z, self.v2.z, self.v3.z),
+      ),
+    )
+  }
+
+  /// The unnormalized face normal, following the winding order.
+  pub fn normal(&self) -> Vector3<f32> {
+    (self.v2 - self.v1).cross(self.v3 - self.v1)
+  }
+
+  /// The highest point of this triangle in the column over `bounds` (i.e. anywhere inside its x
+  /// and z ranges, at any height), or None if the triangle doesn't pass through that column.
+  pub fn top_within(&self, bounds: &Aabb3<f32>) -> Option<f32> {
+    // Height is linear across the triangle, so the top is one of the corners of the clipped polygon.
+    let polygon = [self.v1, self.v2, self.v3];
+    let polygon = clip(&polygon, |p| p.x - bounds.min.x);
+    let polygon = clip(&polygon, |p| bounds.max.x - p.x);
+    let polygon = clip(&polygon, |p| p.z - bounds.min.z);
+    let polygon = clip(&polygon, |p| bounds.max.z - p.z);
+    polygon.iter().fold(None, |top, p| Some(top.map_or(p.y, |top: f32| top.max(p.y))))
+  }
+
+  /// Möller-Trumbore intersection, from either side of the triangle.
+  /// Returns the distance to the hit, in units of the ray's direction.
+  pub fn cast_ray(&self, ray: &Ray3<f32>) -> Option<f32> {
+    let e1 = self.v2 - self.v1;
+    let e2 = self.v3 - self.v1;
+    let p = ray.direction.cross(e2);
+    let det = e1.dot(p);
+    if det.abs() < 1e-12 {
+      return None
+    }
+    let inv_det = 1.0 / det;
+
+    let s = ray.origin - self.v1;
+    let u = s.dot(p) * inv_det;
+    if u < 0.0 || u > 1.0 {
+      return None
+    }
+
+    let q = s.cross(e1);
+    let v = ray.direction.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+      return None
+    }
+
+    let t = e2.dot(q) * inv_det;
+    if t < 0.0 {
+      None
+    } else {
+      Some(t)
+    }
+  }
+
+  /// Separating axis test between this triangle and a box (Akenine-Möller).
+  pub fn intersects(&self, aabb: &Aabb3<f32>) -> bool {
+    let center = (aabb.min + aabb.max.to_vec()) * 0.5;
+    let half_extents = (aabb.max - aabb.min) * 0.5;
+
+    let v1 = self.v1 - center;
+    let v2 = self.v2 - center;
+    let v3 = self.v3 - center;
+
+    let edges = [v2 - v1, v3 - v2, v1 - v3];
+    let box_axes = [
+      Vector3::new(1.0, 0.0, 0.0),
+      Vector3::new(0.0, 1.0, 0.0),
+      Vector3::new(0.0, 0.0, 1.0),
+    ];
+
+    for axis in &box_axes {
+      if separated_on(axis, &v1, &v2, &v3, &half_extents) {
+        return false
+      }
+    }
+
+    if separated_on(&edges[0].cross(edges[1]), &v1, &v2, &v3, &half_extents) {
+      return false
+    }
+
+    for edge in &edges {
+      for axis in &box_axes {
+        if separated_on(&axis.cross(*edge), &v1, &v2, &v3, &half_extents) {
+          return false
+        }
+      }
+    }
+
+    true
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3};
+  use collision::{Aabb3, Ray3};
+
+  use super::new;
+
+  fn unit_box(x: f32, y: f32, z: f32) -> Aabb3<f32> {
+    Aabb3::new(Point3::new(x, y, z), Point3::new(x + 1.0, y + 1.0, z + 1.0))
+  }
+
+  #[test]
+  fn slope_through_box() {
+    let t =
+      new(
+        Point3::new(-2.0, -2.0, -2.0),
+        Point3::new(3.0, 3.0, -2.0),
+        Point3::new(-2.0, -2.0, 3.0),
+      );
+    assert!(t.intersects(&unit_box(0.0, 0.0, 0.0)));
+    assert!(!t.intersects(&unit_box(-1.0, 1.5, 0.0)));
+  }
+
+  #[test]
+  fn flat_floor() {
+    let t =
+      new(
+        Point3::new(-4.0, 0.5, -4.0),
+        Point3::new(4.0, 0.5, 4.0),
+        Point3::new(4.0, 0.5, -4.0),
+      );
+    assert!(t.intersects(&unit_box(0.0, 0.0, 0.0)));
+    // Resting exactly on top of the floor isn't a collision.
+    assert!(!t.intersects(&unit_box(0.0, 0.5, 0.0)));
+    assert!(!t.intersects(&unit_box(5.0, 0.0, 0.0)));
+  }
+
+  #[test]
+  fn top_of_slope() {
+    // Rises one unit for every two along x.
+    let t =
+      new(
+        Point3::new(-8.0, -4.0, -8.0),
+        Point3::new(8.0, 4.0, 0.0),
+        Point3::new(-8.0, -4.0, 8.0),
+      );
+    let near = |top: Option<f32>, y: f32| top.map_or(false, |top| (top - y).abs() < 1e-5);
+    assert!(near(t.top_within(&unit_box(0.0, 0.0, -0.5)), 0.5));
+    // Only the column matters, not the height of the box.
+    assert!(near(t.top_within(&unit_box(2.0, 10.0, -0.5)), 1.5));
+    assert_eq!(t.top_within(&unit_box(9.0, 0.0, -0.5)), None);
+  }
+
+  #[test]
+  fn ray_through_floor() {
+    let t =
+      new(
+        Point3::new(-4.0, 0.5, -4.0),
+        Point3::new(4.0, 0.5, 4.0),
+        Point3::new(4.0, 0.5, -4.0),
+      );
+    let down = Ray3::new(Point3::new(1.0, 2.5, -1.0), Vector3::new(0.0, -1.0, 0.0));
+    assert_eq!(t.cast_ray(&down), Some(2.0));
+    let up = Ray3::new(Point3::new(1.0, 2.5, -1.0), Vector3::new(0.0, 1.0, 0.0));
+    assert_eq!(t.cast_ray(&up), None);
+    let beside = Ray3::new(Point3::new(-1.0, 2.5, 1.0), Vector3::new(0.0, -1.0, 0.0));
+    assert_eq!(t.cast_ray(&beside), None);
+  }
+}
@@ -1,14 +1,26 @@
 //! Creator of the earth.
 
+use cgmath::Point3;
 use collision::{Aabb3};
+use std::collections::VecDeque;
+use std::time::Duration;
 use stopwatch;
 
-use common;
+use common::lock;
 use common::protocol;
 use common::voxel;
 
+use brush;
+use chat;
+use clipboard;
+use edit_history;
+use entity;
+use lock_order;
 use lod;
+use permissions;
 use server;
+use spawn;
+use terrain_collision;
 use terrain_loader;
 use voxel_data;
 
@@ -28,7 +40,57 @@ pub enum Message {
   /// Load some voxels
   Load(u64, Vec<voxel::bounds::T>, LoadDestination),
   /// Apply a brush operation
-  Brush(voxel_data::brush::T<Box<voxel_data::mosaic::T<common::voxel::Material> + Send>>),
+  Brush(brush::Stroke),
+  /// Undo a player's latest terrain edit
+  Undo(entity::id::Player),
+  /// Redo a player's latest undone terrain edit
+  Redo(entity::id::Player),
+  /// Copy a player's selection to their clipboard
+  Copy(entity::id::Player),
+  /// Paste a player's clipboard with its bottom middle at a voxel
+  Paste(entity::id::Player, Point3<i32>, protocol::Placement),
+  /// Save a player's clipboard as a named prefab
+  SavePrefab(entity::id::Player, String),
+  /// Load a named prefab into a player's clipboard
+  LoadPrefab(entity::id::Player, String),
+  /// Find a place for a player or mob, and put it there
+  Spawn(spawn::Request),
+}
+
+/// Messages waiting for the gaia thread.
+pub struct Queue {
+  messages : lock::Mutex<VecDeque<Message>>,
+  pushed   : lock::Condvar,
+}
+
+#[allow(missing_docs)]
+pub fn new_queue() -> Queue {
+  Queue {
+    messages : lock::Mutex::new(lock_order::GAIA_UPDATES, VecDeque::new()),
+    pushed   : lock::Condvar::new(),
+  }
+}
+
+impl Queue {
+  #[allow(missing_docs)]
+  pub fn push(&self, message: Message) {
+    self.messages.lock().unwrap().push_back(message);
+    self.pushed.notify_one();
+  }
+
+  /// Take the oldest message, waiting up to `timeout` for one to arrive.
+  pub fn pop(&self, timeout: Duration) -> Option<Message> {
+    let mut messages = self.messages.lock().unwrap();
+    if messages.is_empty() {
+      messages = self.pushed.wait_timeout(messages, timeout).unwrap();
+    }
+    messages.pop_front()
+  }
+
+  #[allow(missing_docs)]
+  pub fn len(&self) -> usize {
+    self.messages.lock().unwrap().len()
+  }
 }
 
 // TODO: Consider adding terrain loads to a thread pool instead of having one monolithic separate thread.
@@ -44,30 +106,192 @@ pub fn update_gaia(
           load(server, time_requested, voxel_bounds, load_reason);
         });
       },
-      Message::Brush(mut brush) => {
-        let mut updates = Vec::new();
-        server.terrain_loader.terrain.brush(
-          &mut brush,
-          |block, bounds| {
-            trace!("update bounds {:?}", bounds);
-            updates.push((*bounds, *block));
-          },
+      Message::Brush(stroke) => {
+        let reach = [brush::reach(&stroke)];
+        match brush::build(server, &stroke) {
+          brush::Edit::Brush(mut brush) => edit(server, stroke.player, &reach, || apply_brush(server, &mut brush)),
+          brush::Edit::Voxels(voxels) => edit(server, stroke.player, &reach, || apply_voxels(server, &voxels)),
+        }
+      },
+      Message::Undo(player) => {
+        // An undo that isn't allowed goes back on the undo stack, which is where `redone` puts edits.
+        replay(
+          server, player,
+          edit_history::T::undo, edit_history::T::redone, edit_history::T::undone,
+          "Nothing to undo.",
         );
-
-        let mut clients = server.clients.lock().unwrap();
-        for (_, client) in clients.iter_mut() {
-          client.send(
-            protocol::ServerToClient::Voxels {
-              voxels : updates.clone(),
-              reason : protocol::VoxelReason::Updated,
-            }
-          );
+      },
+      Message::Redo(player) => {
+        replay(
+          server, player,
+          edit_history::T::redo, edit_history::T::undone, edit_history::T::redone,
+          "Nothing to redo.",
+        );
+      },
+      Message::Copy(player) => {
+        let result = clipboard::copy(server, player);
+        reply(server, player, result);
+      },
+      Message::Paste(player, target, placement) => {
+        match clipboard::paste(server, player, target, &placement) {
+          Err(err) => reply(server, player, Err(err)),
+          Ok((bounds, voxels)) => edit(server, player, &[bounds], || apply_voxels(server, &voxels)),
         }
       },
+      Message::SavePrefab(player, name) => {
+        let result = clipboard::save(server, player, &name);
+        reply(server, player, result);
+      },
+      Message::LoadPrefab(player, name) => {
+        let result = clipboard::load(server, player, &name);
+        reply(server, player, result);
+      },
+      Message::Spawn(request) => {
+        stopwatch::time("spawn", || {
+          spawn::place(server, request);
+        });
+      },
     };
   })
 }
 
+/// Make an edit for a player if they can change everything in `bounds`, so they can undo it, and
+/// tell every client about it.
+fn edit<Apply>(server: &server::T, player: entity::id::Player, bounds: &[Aabb3<i32>], apply: Apply) where
+  Apply: FnOnce() -> edit_history::Edit,
+{
+  match permissions::edit(server, player, bounds, apply) {
+    Err(err) => reply(server, player, Err(err)),
+    Ok(changes) => record_edit(server, player, changes),
+  }
+}
+
+/// Apply a brush, returning what it changed.
+fn apply_brush(server: &server::T, brush: &mut voxel_data::brush::T<brush::Mosaic>) -> edit_history::Edit {
+  let mut changes = Vec::new();
+  server.terrain_loader.terrain.brush(
+    brush,
+    |bounds, old, new| {
+      trace!("update bounds {:?}", bounds);
+      changes.push((*bounds, *old, *new));
+    },
+  );
+  changes
+}
+
+/// Set voxels, e.g. to paste or replace materials, returning what changed. The coarser voxels over
+/// them are brushed to match, so clients see the change at every LOD.
+fn apply_voxels(server: &server::T, voxels: &[(voxel::bounds::T, voxel::T)]) -> edit_history::Edit {
+  let terrain = &server.terrain_loader.terrain;
+  let mut changes = Vec::new();
+  terrain.restore(
+    voxels,
+    |bounds, old, new| {
+      changes.push((*bounds, *old, *new));
+    },
+  );
+  match brush::coarsen(terrain, &changes) {
+    None => {},
+    Some(mut coarse) => {
+      terrain.brush(
+        &mut coarse,
+        |bounds, old, new| {
+          changes.push((*bounds, *old, *new));
+        },
+      );
+    },
+  }
+  changes
+}
+
+/// Rebuild the physics for some changes, and send the new voxels to every client.
+fn publish(server: &server::T, changes: &edit_history::Edit) {
+  let changed: Vec<_> = changes.iter().map(|&(bounds, _, _)| bounds).collect();
+  server.terrain_loader.reload_shapes(&server.terrain_allocator, &server.physics, &changed);
+  let updates = changes.iter().map(|&(bounds, _, new)| (bounds, new)).collect();
+  server.voxel_updates.lock().unwrap().push(updates);
+}
+
+/// Remember an edit, so the player can undo it, and publish its changes.
+fn record_edit(server: &server::T, player: entity::id::Player, changes: edit_history::Edit) {
+  publish(server, &changes);
+  server.world.lock().unwrap().players.get_mut(player)
+    .map(|player| player.edits.record(changes));
+}
+
+/// Tell a player how a request went.
+fn reply(server: &server::T, player: entity::id::Player, result: Result<String, String>) {
+  let text =
+    match result {
+      Ok(text) => text,
+      Err(text) => text,
+    };
+  let client = server.world.lock().unwrap().players.get(player).map(|player| player.client);
+  client.map(|client| chat::notice(server, client, text));
+}
+
+/// Revert one of a player's saved edits, and save the changes that makes on the other stack, so
+/// undo can be redone and vice versa. The edit is checked like any other, and if the player can't
+/// make it, it's put back where it was taken from.
+fn replay<Take, PutBack, Save>(
+  server   : &server::T,
+  player   : entity::id::Player,
+  take     : Take,
+  put_back : PutBack,
+  save     : Save,
+  nothing  : &str,
+) where
+  Take: FnOnce(&mut edit_history::T) -> Option<edit_history::Edit>,
+  PutBack: FnOnce(&mut edit_history::T, edit_history::Edit),
+  Save: FnOnce(&mut edit_history::T, edit_history::Edit),
+{
+  let (client, edit) = {
+    let mut world = server.world.lock().unwrap();
+    // The player might have left since asking.
+    match world.players.get_mut(player) {
+      None => return,
+      Some(player) => (player.client, take(&mut player.edits)),
+    }
+  };
+
+  let edit =
+    match edit {
+      None => {
+        chat::notice(server, client, String::from(nothing));
+        return
+      },
+      Some(edit) => edit,
+    };
+
+  let bounds: Vec<_> = edit.iter().map(|&(ref bounds, _, _)| permissions::voxel_box(bounds)).collect();
+  let reverted =
+    permissions::edit(server, player, &bounds, || {
+      // Voxels someone else has changed since are left alone.
+      let mut reverted = Vec::new();
+      server.terrain_loader.terrain.revert(
+        &edit,
+        |bounds, old, new| {
+          reverted.push((*bounds, *old, *new));
+        },
+      );
+      reverted
+    });
+  let reverted =
+    match reverted {
+      Err(err) => {
+        server.world.lock().unwrap().players.get_mut(player)
+          .map(|player| put_back(&mut player.edits, edit));
+        chat::notice(server, client, err);
+        return
+      },
+      Ok(reverted) => reverted,
+    };
+
+  publish(server, &reverted);
+  server.world.lock().unwrap().players.get_mut(player)
+    .map(|player| save(&mut player.edits, reverted));
+}
+
 #[inline(never)]
 fn load(
   server: &server::T,
@@ -87,20 +311,20 @@ fn load(
     },
     LoadDestination::Local(owner) => {
       for voxel_bounds in voxel_bounds {
-        let block = server.terrain_loader.terrain.load(&voxel_bounds);
-        let bounds =
-          match block {
-            voxel::Volume(voxel::Material::Empty) => Vec::new(),
-            _ => {
-              let (low, high) = voxel_bounds.corners();
-              let id = server.terrain_allocator.lock().unwrap().allocate();
-              vec!((id, Aabb3::new(low, high)))
-            },
-          };
+        let shapes =
+          stopwatch::time("terrain.load.collision_shapes", || {
+            terrain_collision::shapes(&server.terrain_loader.terrain, &voxel_bounds)
+          });
+        let shapes = {
+          let mut terrain_allocator = server.terrain_allocator.lock().unwrap();
+          shapes.into_iter()
+            .map(|(shape, material)| (terrain_allocator.allocate(), shape, material))
+            .collect()
+        };
         // TODO: Check that this block isn't stale, i.e. should still be loaded.
         // Maybe this should just ping the original thread, same as we ping the client.
         terrain_loader::T::insert_block(
-          &terrain_loader::LoadedTerrain { bounds: bounds },
+          &terrain_loader::LoadedTerrain { shapes: shapes },
           &voxel_bounds,
           owner,
           &server.physics,
@@ -117,14 +341,16 @@ fn load(
         voxels.push((voxel_bounds, voxel));
       }
 
+      // The client might have been kicked while we were loading.
       let mut clients = server.clients.lock().unwrap();
-      let client = clients.get_mut(&id).unwrap();
-      client.send(
-        protocol::ServerToClient::Voxels {
-          voxels : voxels,
-          reason : protocol::VoxelReason::Requested { at: time_requested },
-        }
-      );
+      clients.get_mut(&id).map(|client| {
+        client.send(
+          protocol::ServerToClient::Voxels {
+            voxels : voxels,
+            reason : protocol::VoxelReason::Requested { at: time_requested },
+          }
+        );
+      });
     },
   }
 }
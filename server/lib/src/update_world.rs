@@ -1,19 +1,30 @@
-use cgmath::{Point3, Vector3};
-use std::ops::Neg;
+use cgmath::{Point3};
+use collision::{Aabb3};
 use stopwatch;
 
+use common::fnv_map;
+use common::fnv_set;
 use common::protocol;
-use common::surroundings_loader::LoadType;
-use common::voxel;
 
-use lod;
+use body;
+use entity;
+use loader;
 use mob;
 use player;
 use server;
+use spawn;
 use update_gaia;
+use voxel_updates;
+use world;
+use world::center;
 
 // TODO: Consider removing the IntervalTimer.
 
+/// Run one tick of the world.
+///
+/// WORLD is only held while entity state is read or written. Terrain loading, mob scripts and
+/// pathfinding happen without it, so they don't hold up other threads (e.g. adding players) for
+/// the whole tick. Spawn searches happen on the gaia thread (see `spawn::request`).
 pub fn update_world<RequestBlock>(
   server: &server::T,
   request_block: &mut RequestBlock,
@@ -21,73 +32,73 @@ pub fn update_world<RequestBlock>(
   RequestBlock: FnMut(update_gaia::Message),
 {
   stopwatch::time("update_world", || {
-    stopwatch::time("update_world.player", || {
-      let mut updates = Vec::new();
-
-      for (_, player) in server.players.lock().unwrap().iter_mut() {
-        let (bounds, collisions) = player.update(server, request_block);
-        updates.push(protocol::ServerToClient::UpdatePlayer(player.entity_id, bounds));
-        updates.extend(
-          collisions.into_iter()
-          .map(|c| {
-            match c {
-              player::Collision::Terrain(_) => protocol::Collision::PlayerTerrain(player.entity_id),
-              player::Collision::Misc(_)    => protocol::Collision::PlayerMisc(player.entity_id),
-            }
-          })
-          .map(|c| {
-            protocol::ServerToClient::Collision(c)
-          })
-        );
+    let mut updates = Vec::new();
+
+    stopwatch::time("update_world.loaders", || {
+      load_surroundings(server, request_block);
+    });
+
+    stopwatch::time("update_world.mobs", || {
+      steer_mobs(server);
+    });
+
+    let mut moved = fnv_set::new();
+    let (respawns, relocations) = {
+      let mut world = server.world.lock().unwrap();
+      let world = &mut *world;
+      let mut steps = fnv_map::new();
+
+      stopwatch::time("update_world.player", || {
+        steer_players(world);
+      });
+
+      stopwatch::time("update_world.bodies", || {
+        move_bodies(server, world, &mut steps, &mut moved);
+      });
+
+      let respawns =
+        stopwatch::time("update_world.player_events", || {
+          update_players(server, world, &steps, &mut moved, &mut updates)
+        });
+
+      let relocations =
+        stopwatch::time("update_world.mob_events", || {
+          update_mobs(world, &steps)
+        });
+
+      (respawns, relocations)
+    };
+
+    // The gaia thread finds places for them, so the searches don't hold up the tick.
+    for (id, out_of_world) in respawns {
+      let requested = spawn::request(server, spawn::Request::Respawn(id), request_block);
+      if requested && out_of_world {
+        info!("Player {:?} left the world", id);
       }
+    }
+    for id in relocations {
+      spawn::request(server, spawn::Request::Relocate(id), request_block);
+    }
 
+    {
+      let world = server.world.lock().unwrap();
+      broadcast_moves(server, &world, &moved, &mut updates);
+    }
+    {
       let mut clients = server.clients.lock().unwrap();
       for (_, client) in &mut *clients {
         for update in &updates {
           client.send(update.clone());
         }
       }
-    });
-
-    stopwatch::time("update_world.mobs", || {
-      for (_, mob) in server.mobs.lock().unwrap().iter_mut() {
-        let position =
-          Point3::new(
-            mob.position.x as i32,
-            mob.position.y as i32,
-            mob.position.z as i32,
-          );
-
-        let owner_id = mob.owner_id;
-        for (position, load_type) in mob.surroundings_loader.updates(&position) {
-          load_placeholders(
-            owner_id,
-            server,
-            request_block,
-            &voxel::bounds::new(position.x, position.y, position.z, 0),
-            load_type,
-          )
-        }
-
-        {
-          let behavior = mob.behavior;
-          (behavior)(server, mob);
-        }
+    }
 
-        mob.speed = mob.speed + -Vector3::new(0.0, 0.1, 0.0 as f32);
+    stopwatch::time("update_world.voxel_updates", || {
+      voxel_updates::flush(server);
+    });
 
-        // TODO: This logic is dumb (isolating along components shouldn't be a thing). Change it.
-        let delta_p = mob.speed;
-        if delta_p.x != 0.0 {
-          translate_mob(server, mob, &Vector3::new(delta_p.x, 0.0, 0.0));
-        }
-        if delta_p.y != 0.0 {
-          translate_mob(server, mob, &Vector3::new(0.0, delta_p.y, 0.0));
-        }
-        if delta_p.z != 0.0 {
-          translate_mob(server, mob, &Vector3::new(0.0, 0.0, delta_p.z));
-        }
-      }
+    stopwatch::time("update_world.mob_spawner", || {
+      mob::spawner::update(server);
     });
 
     server.sun.lock().unwrap().update().map(|fraction| {
@@ -98,57 +109,221 @@ pub fn update_world<RequestBlock>(
   });
 }
 
-fn translate_mob(
+fn left_world(step: &body::Step) -> bool {
+  step.collisions.iter().any(|c| {
+    match c {
+      &body::Collision::OutOfBounds => true,
+      _ => false,
+    }
+  })
+}
+
+/// Keep the terrain loaded around everything that has a loader.
+fn load_surroundings<RequestBlock>(
   server: &server::T,
-  mob: &mut mob::Mob,
-  delta_p: &Vector3<f32>,
-) {
-  let bounds;
+  request_block: &mut RequestBlock,
+) where
+  RequestBlock: FnMut(update_gaia::Message),
+{
+  // Take the loaders out of the world while they load terrain, and put them back afterward.
+  let mut loaders: Vec<(entity::id::Entity, loader::T, Point3<f32>)> = {
+    let mut world = server.world.lock().unwrap();
+    let mut loaders = Vec::new();
+    for id in world.loaders.ids() {
+      let position =
+        match world.positions.get(id) {
+          None => continue,
+          Some(&position) => position,
+        };
+      world.loaders.remove(id).map(|loader| loaders.push((id, loader, position)));
+    }
+    loaders
+  };
+
+  for &mut (_, ref mut loader, ref position) in &mut loaders {
+    loader.update(server, request_block, position);
+  }
+
+  let mut destroyed = Vec::new();
   {
-    let mut physics = server.physics.lock().unwrap();
-    if physics.translate_misc(mob.physics_id, *delta_p).is_some() {
-      mob.speed += delta_p.neg();
-      return;
-    } else {
-      bounds = *physics.get_bounds(mob.physics_id).unwrap();
+    let mut world = server.world.lock().unwrap();
+    for (id, loader, _) in loaders {
+      if world.positions.get(id).is_some() {
+        world.loaders.insert(id, loader);
+      } else {
+        destroyed.push(loader);
+      }
     }
   }
+  // `world::destroy` couldn't release these while we had them.
+  for loader in destroyed {
+    loader.release(server);
+  }
+}
+
+/// Turn players' input into acceleration.
+fn steer_players(world: &mut world::T) {
+  for (&id, player) in world.players.iter() {
+    world.bodies.get_mut(id).map(|body| body.accel = player.accel());
+  }
+}
 
-  mob.position += *delta_p;
+/// Let the mobs decide where to go.
+fn steer_mobs(server: &server::T) {
+  server.mob_scripts.lock().unwrap().start_tick();
+
+  // Take the mobs' minds and bodies out of the world while they think, and put them back afterward.
+  let (players, mut mobs) = {
+    let mut world = server.world.lock().unwrap();
+    let players: Vec<Aabb3<f32>> =
+      world.players.ids().into_iter()
+      .filter(|&id| world.is_living_player(id))
+      .filter_map(|id| world.bounds(server, id))
+      .collect();
+
+    let mut mobs = Vec::new();
+    for id in world.mobs.ids() {
+      let physics_id = *world.physics_ids.get(id).unwrap();
+      let mob = world.mobs.remove(id).unwrap();
+      let body = world.bodies.remove(id).unwrap();
+      mobs.push((id, physics_id, mob, body));
+    }
+    (players, mobs)
+  };
+
+  for &mut (_, physics_id, ref mut mob, ref mut body) in &mut mobs {
+    let surroundings =
+      mob::Surroundings {
+        bounds     : *server.physics.lock().unwrap().get_bounds(physics_id).unwrap(),
+        physics_id : physics_id,
+        players    : &players,
+      };
+    mob::behave(server, mob, body, &surroundings);
+  }
 
-  for (_, client) in server.clients.lock().unwrap().iter_mut() {
-    client.send(
-      protocol::ServerToClient::UpdateMob(mob.entity_id, bounds),
-    );
+  let mut world = server.world.lock().unwrap();
+  for (id, _, mob, body) in mobs {
+    // Skip mobs that were removed in the meantime.
+    if world.physics_ids.get(id).is_some() {
+      world.mobs.insert(id, mob);
+      world.bodies.insert(id, body);
+    }
   }
 }
 
-pub fn load_placeholders<RequestBlock>(
-  owner: lod::OwnerId,
+/// Move every body. Records what happened to each one in `steps`, and which ones moved in `moved`.
+fn move_bodies(
   server: &server::T,
-  request_block: &mut RequestBlock,
-  pos: &voxel::bounds::T,
-  load_type: LoadType,
-) where
-  RequestBlock: FnMut(update_gaia::Message),
-{
-  match load_type {
-    LoadType::Load | LoadType::Downgrade => {
-      server.terrain_loader.load(
-        &server.misc_allocator,
-        &server.physics,
-        &pos,
-        lod::Placeholder,
-        owner,
-        request_block,
+  world: &mut world::T,
+  steps: &mut fnv_map::T<entity::id::Entity, body::Step>,
+  moved: &mut fnv_set::T<entity::id::Entity>,
+) {
+  for (&id, body) in world.bodies.iter_mut() {
+    // Dead players stay where they fell until they respawn.
+    if world.players.get(id).map_or(false, |player| player.is_dead()) {
+      continue
+    }
+
+    let physics_id = *world.physics_ids.get(id).unwrap();
+    let step = body.step(&server.physics, physics_id);
+    let position = center(&step.bounds);
+    if world.positions.get(id) != Some(&position) {
+      moved.insert(id);
+    }
+    world.positions.insert(id, position);
+    steps.insert(id, step);
+  }
+}
+
+/// Hurt and kill players, depending on what happened to them.
+/// Returns the players who need to respawn, and whether each of them left the world.
+fn update_players(
+  server: &server::T,
+  world: &mut world::T,
+  steps: &fnv_map::T<entity::id::Entity, body::Step>,
+  moved: &mut fnv_set::T<entity::id::Entity>,
+  updates: &mut Vec<protocol::ServerToClient>,
+) -> Vec<(entity::id::Player, bool)> {
+  let mut respawns = Vec::new();
+  for id in world.players.ids() {
+    let step = steps.get(&id);
+
+    let (health, was_dead, is_dead, should_respawn) = {
+      let player = world.players.get_mut(id).unwrap();
+      let health = player.health;
+      let was_dead = player.is_dead();
+      step.map(|step| player.react(server, step));
+      (health, was_dead, player.is_dead(), player.should_respawn())
+    };
+
+    let out_of_world = step.map_or(false, left_world);
+    if out_of_world || should_respawn {
+      respawns.push((id, out_of_world));
+    } else if is_dead && !was_dead {
+      info!("Player {:?} died", id);
+      updates.push(protocol::ServerToClient::PlayerDied(id));
+    }
+
+    let new_health = world.players.get(id).unwrap().health;
+    if new_health != health {
+      updates.push(
+        protocol::ServerToClient::UpdateHealth {
+          player     : id,
+          health     : new_health,
+          max_health : player::MAX_HEALTH,
+        }
       );
-    },
-    LoadType::Unload => {
-      server.terrain_loader.unload(
-        &server.physics,
-        &pos,
-        owner,
+    }
+
+    // Players are always sent, so new clients find out about the ones standing still.
+    moved.insert(id);
+
+    step.map(|step| {
+      updates.extend(
+        step.collisions.iter()
+        .filter_map(|c| {
+          match c {
+            &body::Collision::Terrain(_)  => Some(protocol::Collision::PlayerTerrain(id)),
+            &body::Collision::Misc(_)     => Some(protocol::Collision::PlayerMisc(id)),
+            &body::Collision::OutOfBounds => None,
+          }
+        })
+        .map(|c| {
+          protocol::ServerToClient::Collision(c)
+        })
       );
-    },
+    });
+  }
+  respawns
+}
+
+/// The mobs that left the world.
+fn update_mobs(
+  world: &world::T,
+  steps: &fnv_map::T<entity::id::Entity, body::Step>,
+) -> Vec<entity::id::Mob> {
+  world.mobs.ids().into_iter()
+    .filter(|id| steps.get(id).map_or(false, left_world))
+    .collect()
+}
+
+/// Tell clients where the entities in `moved` are now.
+fn broadcast_moves(
+  server: &server::T,
+  world: &world::T,
+  moved: &fnv_set::T<entity::id::Entity>,
+  updates: &mut Vec<protocol::ServerToClient>,
+) {
+  for &id in moved {
+    let bounds =
+      match world.bounds(server, id) {
+        None => continue,
+        Some(bounds) => bounds,
+      };
+    match world.appearances.get(id) {
+      None => {},
+      Some(&world::Appearance::Player) => updates.push(protocol::ServerToClient::UpdatePlayer(id, bounds)),
+      Some(&world::Appearance::Mob) => updates.push(protocol::ServerToClient::UpdateMob(id, bounds)),
+    }
   }
 }
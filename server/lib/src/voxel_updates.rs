@@ -0,0 +1,171 @@
+//! Terrain edits on their way to clients.
+//!
+//! Edits are collected over a tick, so a voxel that changes several times is only sent once. Then
+//! each client gets one message with just the edits in regions it's watching: regions it has asked
+//! for voxels in, and hasn't unwatched since. Clients find out about edits anywhere else when they
+//! ask for that area, since loading reads the edited terrain.
+
+use cgmath::Point3;
+use std::mem;
+
+use common::fnv_map;
+use common::fnv_set;
+use common::protocol;
+use common::voxel;
+
+use server;
+
+/// Regions are cubes 2^this world units wide.
+const LG_REGION_WIDTH: i32 = 5;
+
+/// A cube of the world, in units of its width.
+pub type Region = Point3<i32>;
+
+/// How many times a client is watching each region: once for each batch of voxels it asked to
+/// watch there and hasn't unwatched since.
+pub type Interest = fnv_map::T<Region, u32>;
+
+/// The regions a voxel overlaps.
+fn regions(bounds: &voxel::bounds::T) -> Vec<Region> {
+  let lg_size = bounds.lg_size as i32;
+  if lg_size <= LG_REGION_WIDTH {
+    let shift = LG_REGION_WIDTH - lg_size;
+    return vec!(Point3::new(bounds.x >> shift, bounds.y >> shift, bounds.z >> shift))
+  }
+
+  let shift = lg_size - LG_REGION_WIDTH;
+  let width: i32 = 1 << shift;
+  let low = Point3::new(bounds.x << shift, bounds.y << shift, bounds.z << shift);
+  let mut regions = Vec::with_capacity((width * width * width) as usize);
+  for x in 0 .. width {
+  for y in 0 .. width {
+  for z in 0 .. width {
+    regions.push(Point3::new(low.x + x, low.y + y, low.z + z));
+  }}}
+  regions
+}
+
+/// The regions a batch of voxels overlaps, each once.
+fn all_regions(voxels: &[voxel::bounds::T]) -> fnv_set::T<Region> {
+  let mut all = fnv_set::new();
+  for bounds in voxels {
+    all.extend(regions(bounds));
+  }
+  all
+}
+
+/// Watch the regions around a batch of voxels a client asked for.
+pub fn watch(interest: &mut Interest, voxels: &[voxel::bounds::T]) {
+  for region in all_regions(voxels) {
+    *interest.entry(region).or_insert(0) += 1;
+  }
+}
+
+/// Undo `watch` for the same batch of voxels. Regions nothing else is watching are forgotten.
+pub fn unwatch(interest: &mut Interest, voxels: &[voxel::bounds::T]) {
+  for region in all_regions(voxels) {
+    let watchers =
+      match interest.get_mut(&region) {
+        // Clients can only unwatch what they've watched.
+        None => continue,
+        Some(watchers) => {
+          *watchers -= 1;
+          *watchers
+        },
+      };
+    if watchers == 0 {
+      interest.remove(&region);
+    }
+  }
+}
+
+/// Edits that haven't been sent yet.
+pub struct T {
+  pending: fnv_map::T<voxel::bounds::T, voxel::T>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    pending: fnv_map::new(),
+  }
+}
+
+impl T {
+  /// Queue some changed voxels. They replace any earlier changes to the same voxels.
+  pub fn push(&mut self, updates: Vec<(voxel::bounds::T, voxel::T)>) {
+    self.pending.extend(updates);
+  }
+}
+
+/// Send every client the queued edits it's interested in.
+pub fn flush(server: &server::T) {
+  let pending = mem::replace(&mut server.voxel_updates.lock().unwrap().pending, fnv_map::new());
+  if pending.is_empty() {
+    return
+  }
+
+  let pending: Vec<(voxel::bounds::T, voxel::T, Vec<Region>)> =
+    pending.into_iter()
+    .map(|(bounds, voxel)| (bounds, voxel, regions(&bounds)))
+    .collect();
+
+  let mut clients = server.clients.lock().unwrap();
+  for (_, client) in clients.iter_mut() {
+    let voxels: Vec<(voxel::bounds::T, voxel::T)> =
+      pending.iter()
+      .filter(|&&(_, _, ref regions)| regions.iter().any(|region| client.interest.contains_key(region)))
+      .map(|&(bounds, voxel, _)| (bounds, voxel))
+      .collect();
+    if voxels.is_empty() {
+      continue
+    }
+    client.send(
+      protocol::ServerToClient::Voxels {
+        voxels : voxels,
+        reason : protocol::VoxelReason::Updated,
+      }
+    );
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point3;
+
+  use common::fnv_map;
+  use common::voxel;
+
+  use super::{regions, watch, unwatch, Interest};
+
+  #[test]
+  fn voxel_regions() {
+    // Small voxels are in one region, even on the negative side.
+    assert_eq!(regions(&voxel::bounds::new(31, 0, -1, 0)), vec!(Point3::new(0, 0, -1)));
+    assert_eq!(regions(&voxel::bounds::new(8, 0, 0, 2)), vec!(Point3::new(1, 0, 0)));
+    // Big ones cover several.
+    let big = regions(&voxel::bounds::new(1, 0, 0, 6));
+    assert_eq!(big.len(), 8);
+    assert!(big.contains(&Point3::new(2, 0, 0)) && big.contains(&Point3::new(3, 1, 1)));
+  }
+
+  #[test]
+  fn unwatching() {
+    let mut interest: Interest = fnv_map::new();
+    let chunk = [voxel::bounds::new(0, 0, 0, 0), voxel::bounds::new(1, 0, 0, 0)];
+    let neighbor = [voxel::bounds::new(1, 0, 0, 0), voxel::bounds::new(40, 0, 0, 0)];
+    watch(&mut interest, &chunk);
+    watch(&mut interest, &neighbor);
+    assert_eq!(interest.get(&Point3::new(0, 0, 0)), Some(&2));
+
+    // The neighbor still needs the region they share.
+    unwatch(&mut interest, &chunk);
+    assert_eq!(interest.get(&Point3::new(0, 0, 0)), Some(&1));
+    unwatch(&mut interest, &neighbor);
+    assert!(interest.is_empty());
+
+    // Unwatching again doesn't underflow.
+    unwatch(&mut interest, &neighbor);
+    assert!(interest.is_empty());
+  }
+}
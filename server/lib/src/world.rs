@@ -0,0 +1,187 @@
+//! The things in the world, stored as components of entities.
+//!
+//! An entity is just an ID. What it is and what it does comes from which components it has, and
+//! each system (see `update_world`) works over the entities that have the components it needs.
+
+use cgmath::{Point3, EuclideanSpace};
+use collision::{Aabb3};
+use std::collections::hash_map;
+
+use common::fnv_map;
+use common::id_allocator;
+
+use body;
+use entity;
+use loader;
+use mob;
+use physics;
+use player;
+use server;
+
+/// One kind of component, for any number of entities.
+pub struct Components<C> {
+  by_entity: fnv_map::T<entity::id::Entity, C>,
+}
+
+#[allow(missing_docs)]
+impl<C> Components<C> {
+  fn new() -> Components<C> {
+    Components {
+      by_entity: fnv_map::new(),
+    }
+  }
+
+  pub fn insert(&mut self, id: entity::id::Entity, component: C) {
+    self.by_entity.insert(id, component);
+  }
+
+  pub fn remove(&mut self, id: entity::id::Entity) -> Option<C> {
+    self.by_entity.remove(&id)
+  }
+
+  pub fn get(&self, id: entity::id::Entity) -> Option<&C> {
+    self.by_entity.get(&id)
+  }
+
+  pub fn get_mut(&mut self, id: entity::id::Entity) -> Option<&mut C> {
+    self.by_entity.get_mut(&id)
+  }
+
+  pub fn iter(&self) -> hash_map::Iter<entity::id::Entity, C> {
+    self.by_entity.iter()
+  }
+
+  pub fn iter_mut(&mut self) -> hash_map::IterMut<entity::id::Entity, C> {
+    self.by_entity.iter_mut()
+  }
+
+  pub fn ids(&self) -> Vec<entity::id::Entity> {
+    self.by_entity.keys().cloned().collect()
+  }
+}
+
+/// How clients should draw an entity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum Appearance {
+  Player,
+  Mob,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  allocator       : id_allocator::T<entity::id::Entity>,
+
+  /// The entity's object in physics.
+  pub physics_ids : Components<entity::id::Misc>,
+  /// The center of the entity's bounds.
+  pub positions   : Components<Point3<f32>>,
+  pub bodies      : Components<body::T>,
+  pub appearances : Components<Appearance>,
+  /// Terrain kept loaded around the entity.
+  pub loaders     : Components<loader::T>,
+  /// Input and health, for entities controlled by a client.
+  pub players     : Components<player::T>,
+  /// AI, for entities that move by themselves.
+  pub mobs        : Components<mob::Mob>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    allocator   : id_allocator::new(),
+    physics_ids : Components::new(),
+    positions   : Components::new(),
+    bodies      : Components::new(),
+    appearances : Components::new(),
+    loaders     : Components::new(),
+    players     : Components::new(),
+    mobs        : Components::new(),
+  }
+}
+
+/// The point halfway between a box's corners.
+pub fn center(bounds: &Aabb3<f32>) -> Point3<f32> {
+  (bounds.min + bounds.max.to_vec()) * 0.5
+}
+
+impl T {
+  /// A new entity, with no components yet.
+  pub fn create(&mut self) -> entity::id::Entity {
+    self.allocator.allocate()
+  }
+
+  /// The bounds of an entity's physics object, if it has one.
+  pub fn bounds(&self, server: &server::T, id: entity::id::Entity) -> Option<Aabb3<f32>> {
+    self.physics_ids.get(id).and_then(|&physics_id| {
+      server.physics.lock().unwrap().get_bounds(physics_id).cloned()
+    })
+  }
+
+  /// Every mob, including any whose minds are out of the world while `update_world` runs them.
+  pub fn mob_ids(&self) -> Vec<entity::id::Mob> {
+    self.appearances.iter()
+      .filter(|&(_, &appearance)| appearance == Appearance::Mob)
+      .map(|(&id, _)| id)
+      .collect()
+  }
+
+  /// Whether an entity is a living player.
+  pub fn is_living_player(&self, id: entity::id::Entity) -> bool {
+    self.players.get(id).map_or(false, |player| !player.is_dead())
+  }
+}
+
+/// Put an entity into physics with the given bounds, and give it a position and a body to move it around.
+/// Returns false if the bounds are outside the world.
+pub fn embody(
+  server : &server::T,
+  world  : &mut T,
+  id     : entity::id::Entity,
+  bounds : &Aabb3<f32>,
+  kind   : physics::MiscKind,
+) -> bool {
+  let physics_id = server.misc_allocator.lock().unwrap().allocate();
+  if !server.physics.lock().unwrap().insert_misc(physics_id, bounds, kind) {
+    return false
+  }
+  world.physics_ids.insert(id, physics_id);
+  world.positions.insert(id, center(bounds));
+  world.bodies.insert(id, body::new());
+  true
+}
+
+/// Move an entity straight to `bounds`, and bring it to rest.
+/// Returns false (and doesn't move anything) if the entity isn't in physics, or `bounds` are
+/// outside the world.
+#[must_use]
+pub fn teleport(server: &server::T, world: &mut T, id: entity::id::Entity, bounds: &Aabb3<f32>) -> bool {
+  let physics_id =
+    match world.physics_ids.get(id) {
+      None => return false,
+      Some(&physics_id) => physics_id,
+    };
+  if !server.physics.lock().unwrap().teleport_misc(physics_id, bounds) {
+    return false
+  }
+  world.positions.insert(id, center(bounds));
+  world.bodies.get_mut(id).map(|body| body.stop());
+  true
+}
+
+/// Take an entity out of the world: drop all its components, and let go of its physics object
+/// and the terrain it was keeping loaded.
+pub fn destroy(server: &server::T, world: &mut T, id: entity::id::Entity) {
+  world.positions.remove(id);
+  world.bodies.remove(id);
+  world.appearances.remove(id);
+  world.players.remove(id);
+  world.mobs.remove(id);
+
+  world.physics_ids.remove(id).map(|physics_id| {
+    server.physics.lock().unwrap().remove_misc(physics_id);
+  });
+  world.loaders.remove(id).map(|loader| {
+    loader.release(server);
+  });
+}
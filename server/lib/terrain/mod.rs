@@ -23,24 +23,84 @@ pub mod tree;
 
 pub use noise::Seed;
 
-use std::sync::Mutex;
+use cgmath::{Point3, Vector3, InnerSpace};
+use collision::{Aabb3, Ray3};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+use common::lock;
+use common::lock::Mutex;
+use common::ray;
 use common::voxel;
 
+/// Where a ray hit the terrain.
+#[derive(Debug, Clone, Copy)]
+pub struct RayHit {
+  /// The voxel that was hit.
+  pub bounds: voxel::bounds::T,
+  /// Where the ray crossed the voxel's surface, or entered it if it's solid.
+  pub point: Point3<f32>,
+  /// The surface normal for surface voxels, facing the ray; the normal of the entry face for solid ones.
+  pub normal: Vector3<f32>,
+  /// The voxel's material. For surface voxels, this is the material at the voxel's low corner.
+  pub material: voxel::Material,
+  /// The distance along the ray to `point`, in world units.
+  pub distance: f32,
+}
+
 /// This struct contains and lazily generates the world's terrain.
 #[allow(missing_docs)]
 pub struct T {
   pub mosaic: Mutex<cache_mosaic::T<voxel::Material>>,
   pub voxels: Mutex<voxel::tree::T>,
+  /// How many voxels `voxels` holds, so it can be read without walking the tree.
+  stored: AtomicUsize,
+}
+
+/// How many voxels a tree holds.
+fn stored_in(tree: &voxel::tree::T) -> usize {
+  fn count(branches: &voxel::tree::Branches) -> usize {
+    branches.as_flat_array().iter()
+      .map(|node| {
+        let here = if node.data.is_some() { 1 } else { 0 };
+        match node.next {
+          voxel_data::tree::Inner::Empty => here,
+          voxel_data::tree::Inner::Branches(ref branches) => here + count(branches),
+        }
+      })
+      .sum()
+  }
+  count(&tree.contents)
 }
 
 impl T {
-  #[allow(missing_docs)]
-  pub fn new(terrain_seed: Seed) -> T {
+  /// `voxels` is locked before `mosaic`, so it has to come first in the lock hierarchy.
+  pub fn new(terrain_seed: Seed, voxels_level: lock::Level, mosaic_level: lock::Level) -> T {
+    assert!(voxels_level.rank < mosaic_level.rank);
     T {
-      mosaic: Mutex::new(cache_mosaic::new(Box::new(biome::demo::new(terrain_seed)))),
-      voxels: Mutex::new(voxel::tree::new()),
+      mosaic: Mutex::new(mosaic_level, cache_mosaic::new(Box::new(biome::demo::new(terrain_seed)))),
+      voxels: Mutex::new(voxels_level, voxel::tree::new()),
+      stored: AtomicUsize::new(0),
+    }
+  }
+
+  /// Replace all the stored voxels, e.g. with ones loaded from disk.
+  pub fn set_voxels(&self, voxels: voxel::tree::T) {
+    let mut current = self.voxels.lock().unwrap();
+    self.stored.store(stored_in(&voxels), Ordering::Relaxed);
+    *current = voxels;
+  }
+
+  /// How many voxels have been generated or edited so far.
+  pub fn voxel_count(&self) -> usize {
+    self.stored.load(Ordering::Relaxed)
+  }
+
+  /// Put a voxel in a node, counting it if the node was empty.
+  fn store(&self, data: &mut Option<voxel::T>, voxel: voxel::T) {
+    if data.is_none() {
+      self.stored.fetch_add(1, Ordering::Relaxed);
     }
+    *data = Some(voxel);
   }
 
   /// Load the block of terrain at a given position.
@@ -50,11 +110,9 @@ impl T {
     let node = voxels.get_mut_or_create(bounds);
     match node.data {
       None => {
-        let mut mosaic = self.mosaic.lock().unwrap();
-        let voxel = voxel::unwrap(voxel::of_field(&mut *mosaic, bounds));
-        let r = voxel;
-        node.data = Some(voxel);
-        r
+        let voxel = self.generate(bounds);
+        self.store(&mut node.data, voxel);
+        voxel
       },
       Some(data) => {
         data
@@ -62,28 +120,212 @@ impl T {
     }
   }
 
+  /// The voxel at `bounds`, if it's been generated already. Unlike `load`, this never generates.
+  pub fn get(&self, bounds: &voxel::bounds::T) -> Option<voxel::T> {
+    self.voxels.lock().unwrap().get(bounds).cloned()
+  }
+
+  /// Find where a ray first hits the terrain, up to `max_distance` away.
+  /// A surface voxel's surface is taken to be the plane through its vertex, so rays that pass
+  /// through a surface voxel without crossing that plane inside it carry on.
+  pub fn cast_ray(&self, ray: &Ray3<f32>, max_distance: f32) -> Option<RayHit> {
+    let length = ray.direction.magnitude();
+    let voxels = self.voxels.lock().unwrap();
+    let hit =
+      voxels.cast_ray(
+        ray,
+        &mut |bounds, voxel| {
+          let (low, high) = bounds.corners();
+          let entry =
+            match ray::cast_aabb(ray, &Aabb3::new(low, high)) {
+              None => return None,
+              Some(entry) => entry,
+            };
+
+          if entry.enter * length > max_distance {
+            // Voxels are visited front-to-back, so nothing else is in range either.
+            return Some(None)
+          }
+
+          let (t, normal, material) =
+            match voxel {
+              &voxel::Volume(voxel::Material::Empty) => return None,
+              &voxel::Volume(material) => (entry.enter, entry.normal, material),
+              &voxel::Surface(ref voxel) => {
+                let vertex = voxel.surface_vertex.to_world_vertex(&bounds);
+                let normal = voxel.normal.to_float_normal();
+                let facing = normal.dot(ray.direction);
+                if facing == 0.0 {
+                  return None
+                }
+                let t = normal.dot(vertex - ray.origin) / facing;
+                if t < entry.enter || t > entry.exit {
+                  return None
+                }
+                let normal = if facing > 0.0 { -normal } else { normal };
+                (t, normal, voxel.corner)
+              },
+            };
+
+          let distance = t * length;
+          if distance > max_distance {
+            // This voxel's surface is past `max_distance`, and the ones after it are further still.
+            return Some(None)
+          }
+
+          Some(Some(RayHit {
+            bounds: bounds,
+            point: ray.origin + ray.direction * t,
+            normal: normal,
+            material: material,
+            distance: distance,
+          }))
+        }
+      );
+    hit.and_then(|hit| hit)
+  }
+
+  /// What `bounds` would be if nobody had edited it.
+  fn generate(&self, bounds: &voxel::bounds::T) -> voxel::T {
+    let mut mosaic = self.mosaic.lock().unwrap();
+    voxel::unwrap(voxel::of_field(&mut *mosaic, bounds))
+  }
+
   /// Apply a voxel brush to the terrain.
+  /// `voxel_changed` gets the bounds of each voxel the brush changed, what it was before, and what it is now.
   pub fn brush<VoxelChanged, Mosaic>(
     &self,
     brush: &mut voxel::brush::T<Mosaic>,
     mut voxel_changed: VoxelChanged,
   ) where
-    VoxelChanged: FnMut(&voxel::T, &voxel::bounds::T),
+    VoxelChanged: FnMut(&voxel::bounds::T, &voxel::T, &voxel::T),
     Mosaic: voxel::mosaic::T<voxel::Material>,
   {
     let mut voxels = self.voxels.lock().unwrap();
-    voxels.brush(
-      brush,
-      // TODO: Put a max size on this
-      &mut |bounds| {
-        if bounds.lg_size > 3 {
-          None
-        } else {
-          let mut mosaic = self.mosaic.lock().unwrap();
-          Some(voxel::unwrap(voxel::of_field(&mut *mosaic, bounds)))
-        }
-      },
-      &mut voxel_changed,
-    );
+
+    // The brush only tells us about voxels after it's changed them, so it works on a scratch tree
+    // that gets voxels from the terrain as the brush asks for them. The terrain itself is changed
+    // afterwards, which is when each old voxel is read.
+    // Voxels of every size are brushed, so the coarse ones clients load far away show edits too.
+    let mut scratch = voxel::tree::new();
+    let mut changed = Vec::new();
+    {
+      let voxels = &*voxels;
+      scratch.brush(
+        brush,
+        &mut |bounds| {
+          Some(voxels.get(bounds).cloned().unwrap_or_else(|| self.generate(bounds)))
+        },
+        &mut |after, bounds| {
+          changed.push((*bounds, *after));
+        },
+      );
+    }
+
+    for (bounds, after) in changed {
+      let node = voxels.get_mut_or_create(&bounds);
+      let before =
+        match node.data {
+          None => self.generate(&bounds),
+          Some(data) => data,
+        };
+      if before != after {
+        self.store(&mut node.data, after);
+        voxel_changed(&bounds, &before, &after);
+      }
+    }
+  }
+
+  /// Undo voxel changes from an earlier edit, i.e. put back what was there before them. Each
+  /// change is a voxel's bounds, what it was, and what the edit made it; voxels that aren't what the
+  /// edit made them any more have been changed since, and stay as they are.
+  /// `voxel_changed` gets the same arguments as it does in `brush`, for the voxels that went back.
+  pub fn revert<VoxelChanged>(
+    &self,
+    changes: &[(voxel::bounds::T, voxel::T, voxel::T)],
+    mut voxel_changed: VoxelChanged,
+  ) where
+    VoxelChanged: FnMut(&voxel::bounds::T, &voxel::T, &voxel::T),
+  {
+    let mut voxels = self.voxels.lock().unwrap();
+    for &(ref bounds, ref before, ref after) in changes {
+      let node = voxels.get_mut_or_create(bounds);
+      let current =
+        match node.data {
+          None => self.generate(bounds),
+          Some(data) => data,
+        };
+      if current != *after {
+        continue
+      }
+      self.store(&mut node.data, *before);
+      voxel_changed(bounds, &current, before);
+    }
+  }
+
+  /// Put voxels in as they are, e.g. to paste a copy.
+  /// `voxel_changed` gets the same arguments as it does in `brush`.
+  pub fn restore<VoxelChanged>(
+    &self,
+    saved: &[(voxel::bounds::T, voxel::T)],
+    mut voxel_changed: VoxelChanged,
+  ) where
+    VoxelChanged: FnMut(&voxel::bounds::T, &voxel::T, &voxel::T),
+  {
+    let mut voxels = self.voxels.lock().unwrap();
+    for &(ref bounds, ref voxel) in saved {
+      let node = voxels.get_mut_or_create(bounds);
+      let before =
+        match node.data {
+          None => self.generate(bounds),
+          Some(data) => data,
+        };
+      if before == *voxel {
+        continue
+      }
+      self.store(&mut node.data, *voxel);
+      voxel_changed(bounds, &before, voxel);
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, EuclideanSpace};
+  use collision::{Aabb3};
+
+  use common::lock;
+  use common::voxel;
+
+  #[test]
+  fn edits_reach_coarse_voxels() {
+    let terrain =
+      super::T::new(
+        super::Seed::new(0),
+        lock::Level { rank: 1, name: "voxels" },
+        lock::Level { rank: 2, name: "mosaic" },
+      );
+    // High enough to be empty sky, and 16 voxels wide.
+    let coarse = voxel::bounds::new(0, 62, 0, 4);
+    let before = terrain.load(&coarse);
+
+    let center = Point3::new(8.0, 1000.0, 8.0);
+    let mut brush =
+      voxel::brush::T {
+        bounds: Aabb3::new(Point3::new(-12, 980, -12), Point3::new(28, 1020, 28)),
+        mosaic: voxel::mosaic::solid::T {
+          field: voxel::field::translation::T {
+            translation: center.to_vec(),
+            field: voxel::field::sphere::T { radius: 20.0 },
+          },
+          material: voxel::Material::Stone,
+        },
+        min_lg_size: 0,
+      };
+    let mut changed = Vec::new();
+    terrain.brush(&mut brush, |bounds, _, _| changed.push(*bounds));
+
+    assert!(changed.contains(&coarse));
+    assert!(terrain.get(&coarse) != Some(before));
   }
 }
@@ -23,11 +23,13 @@ fn main() {
   let server_url = String::from("ipc:///tmp/server.ipc");
 
   let quit_signal = Mutex::new(false);
+  // There's no admin console when the server's bundled with the client.
+  let (_, admin_inbox) = std::sync::mpsc::channel();
 
   unsafe {
     let server_thread =
       thread_scoped::scoped(|| {
-        server_lib::run(server_url.borrow(), &quit_signal);
+        server_lib::run(server_url.borrow(), &quit_signal, admin_inbox, None);
       });
 
     #[cfg(feature = "dummy-client")]